@@ -6,11 +6,13 @@ use custom_sui_sdk::SuiClient;
 use custom_sui_sdk::programmable_transaction_sui_json::ProgrammableTransactionArg;
 use custom_sui_sdk::transaction_builder::{
     TransactionBuilder,
-    // ProgrammableObsArg
+    ProgrammableObjectArg
 };
 
 use ethnum::I256;
 
+use rayon::prelude::*;
+
 use move_core_types::language_storage::TypeTag;
 
 use shared_crypto::intent::Intent;
@@ -18,31 +20,80 @@ use shared_crypto::intent::Intent;
 use sui_keys::keystore::{Keystore, AccountKeystore};
 use sui_sdk::rpc_types::{
     SuiTransactionBlockEffectsAPI,
+    SuiTransactionBlockResponse,
     SuiTransactionBlockResponseOptions,
     SuiExecutionStatus
 };
 use sui_sdk::SUI_COIN_TYPE;
 use sui_sdk::types::{
     base_types::{SuiAddress, ObjectID},
-    transaction::TransactionData,
+    transaction::{TransactionData, TransactionDataAPI},
     programmable_transaction_builder::ProgrammableTransactionBuilder,
-    transaction::Transaction,
+    transaction::{Argument, Transaction},
     quorum_driver_types::ExecuteTransactionRequestType
 };
 
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Error, Formatter};
 use std::str::FromStr;
 
-use crate::markets::Market;
+use crate::markets::{Market, ConstantProductCurve};
 use crate::market_graph::MarketGraph;
+use crate::gas::{self, GasEstimator};
+use crate::simulation::SimulationContext;
+
+const BPS_DENOMINATOR: u128 = 10_000;
+
+// Chunks a hop's input is split into across parallel pools. More chunks get
+// closer to the optimal split at the cost of more swap simulations.
+const SPLIT_CHUNKS: u128 = 16;
+
+// How far either side of a curve fitted optimum we search when the fit
+// only holds locally. Optima outside of it fall back to the full range.
+const BRACKET_FACTOR: u128 = 4;
+
+// How many of the most valuable results are selected from jointly.
+// The rest are only taken if they don't touch a pool the selection uses.
+const MAX_JOINT_CANDIDATES: usize = 16;
+
+// Caps the branch and bound over the joint candidates. The first
+// selection it reaches is the greedy one so there's always an answer.
+const MAX_SELECTION_NODES: usize = 4096;
 
 #[derive(Debug, Clone)]
 pub struct OptimizedResult<'a> {
-    pub path: Vec<DirectedLeg<'a>>,
+    // The pools of every hop. A hop's input is split across all of its pools.
+    pub route: Vec<Vec<DirectedLeg<'a>>>,
+    // The coin the cycle starts and ends at. Amounts, profit and gas are in it.
+    pub source_coin: &'a TypeTag,
     pub amount_in: u128,
     pub amount_out: u128,
-    pub profit: I256
+    pub profit: I256,
+    // Expected gas in the source coin
+    pub gas_cost: u128,
+    // Net profit in the profit coin, to compare results across source coins
+    pub net_profit_value: I256,
+}
+
+impl<'a> OptimizedResult<'a> {
+    pub fn net_profit(&self) -> I256 {
+        self.profit - I256::from(self.gas_cost)
+    }
+
+    // Moves the result to another amount in. The source coin keeps
+    // the same price in the profit coin as it had before.
+    fn resize(&mut self, amount_in: u128, amount_out: u128) {
+        let net_profit = self.net_profit();
+
+        self.amount_in = amount_in;
+        self.amount_out = amount_out;
+        self.profit = I256::from(amount_out) - I256::from(amount_in);
+
+        if net_profit > I256::ZERO {
+            self.net_profit_value = self.net_profit_value * self.net_profit() / net_profit;
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -59,6 +110,15 @@ pub struct DirectedLegResult<'a> {
     pub amount_out: u128,
 }
 
+impl<'a> DirectedLegResult<'a> {
+    pub fn directed_leg(&self) -> DirectedLeg<'a> {
+        DirectedLeg {
+            x_to_y: self.x_to_y,
+            market: self.market,
+        }
+    }
+}
+
 impl<'a> Debug for DirectedLeg<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         f
@@ -77,47 +137,54 @@ impl<'a> Debug for DirectedLeg<'a> {
 
 // } -> Result<Optimized, Result >
 
-// For a single path
+// For a single coin path.
+// Every combination of one pool per edge is optimized on its own. When an
+// edge has several viable pools, flow split across all of them is tried as well.
 // Objective is maximizing profit
 pub fn optimize_starting_amount_in<'a>(
     path: &'a [TypeTag], 
-    market_graph: &'a MarketGraph<'a>
+    market_graph: &'a MarketGraph
 ) -> Result<OptimizedResult<'a>, anyhow::Error> {
 
-    // let expanded_paths = 
-    let mut starting_amount_in = 0;
-    let mut delta = 0;
-
-    let mut expanded_paths = Vec::<Vec::<DirectedLeg>>::new();
-    // println!("Expanded paths: {:#?}", expanded_paths);
-    expanded_paths.push(vec![]);
-    // println!("Expanded paths: {:#?}", expanded_paths);
+    // Every pool that can take each hop of the path
+    let mut hops = Vec::<Vec::<DirectedLeg>>::new();
 
     for pair in path[..].windows(2) {
         let orig = &pair[0];
         let dest = &pair[1];
 
         let orig_to_dest_markets = market_graph
-            .graph
-            .edge_weight(&orig, &dest)
-            .unwrap();
+            .markets_between(orig, dest)
+            .context(format!("Missing markets for pair ({}, {})", orig, dest))?;
 
-        let mut expanded_paths_extended = Vec::<Vec::<DirectedLeg>>::new();
+        hops.push(
+            orig_to_dest_markets
+                .map(|market| {
+                    let x_to_y = (orig, dest) == (market.coin_x(), market.coin_y());
+
+                    DirectedLeg {
+                        x_to_y,
+                        market
+                    }
+                })
+                .collect()
+        );
+    }
 
+    let mut expanded_paths = Vec::<Vec::<DirectedLeg>>::new();
+    // println!("Expanded paths: {:#?}", expanded_paths);
+    expanded_paths.push(vec![]);
+    // println!("Expanded paths: {:#?}", expanded_paths);
+
+    for hop in hops.iter() {
+        let mut expanded_paths_extended = Vec::<Vec::<DirectedLeg>>::new();
 
         for expanded_path in expanded_paths {
-            for (_, market_info) in orig_to_dest_markets.iter() {
+            for leg in hop.iter() {
                 let mut expanded_path_extended = expanded_path
                     .clone();
 
-                let x_to_y = (orig, dest) == (market_info.market.coin_x(), market_info.market.coin_y());
-
-                expanded_path_extended.push(
-                        DirectedLeg {
-                            x_to_y,
-                            market: &market_info.market
-                        }
-                    );
+                expanded_path_extended.push(leg.clone());
 
                 expanded_paths_extended.push(expanded_path_extended);
             }
@@ -128,55 +195,83 @@ pub fn optimize_starting_amount_in<'a>(
 
     // println!("Expanded paths: {:#?}", expanded_paths);
 
-    // Golden section search:
-    // - for unimodal functions
-    // - does not get caught in local extrema
-
-    let mut optimized_results = Vec::new();
-
-    let gr_num = 121393u128;
-    let gr_den = 75025u128;
-
-    for expanded_path in expanded_paths {
-        let mut a = 0u128;
-        let mut b = u64::MAX as u128;
-
-        let mut c = b - (((b - a) * gr_den) / gr_num);
-        let mut d = a + (((b - a) * gr_den) / gr_num);
+    // A single pool per hop
+    let mut routes = expanded_paths
+        .into_iter()
+        .map(|expanded_path| {
+            expanded_path
+                .into_iter()
+                .map(|leg| {
+                    vec![leg]
+                })
+                .collect::<Vec<Vec<DirectedLeg>>>()
+        })
+        .collect::<Vec<Vec<Vec<DirectedLeg>>>>();
+
+    // Every viable pool per hop
+    let split_route = hops
+        .into_iter()
+        .map(|hop| {
+            hop
+                .into_iter()
+                .filter(|leg| {
+                    leg.market.viable()
+                })
+                .collect::<Vec<DirectedLeg>>()
+        })
+        .collect::<Vec<Vec<DirectedLeg>>>();
+
+    let splits = split_route.iter().any(|legs| legs.len() > 1);
+    let dead_end = split_route.iter().any(|legs| legs.is_empty());
+
+    if splits && !dead_end {
+        routes.push(split_route);
+    }
 
-        if b < a {
-            println!("b: {}, a: {}", b , a);
-        }
+    // Priced against the market graph as it is
+    let simulation_context = SimulationContext::new();
 
-        while (I256::from(b) - I256::from(a)).abs() > 1 {
-            let amount_out_c = amount_out(&expanded_path, c)?;
-            let amount_out_d = amount_out(&expanded_path, d)?;
-            let profit_c = I256::from(amount_out_c) - I256::from(c);
-            let profit_d = I256::from(amount_out_d) - I256::from(d);
+    let mut optimized_results = routes
+        .into_iter()
+        .map(|route| {
+            let (amount_in, amount_out, profit) = optimize_route_amount_in(&route, &simulation_context)?;
 
-            if profit_c > profit_d {
-                b = d;
+            // Drop the pools that aren't worth splitting into at the optimum
+            let route = if amount_in > 0 {
+                route_leg_results(&route, amount_in, &simulation_context)?
+                    .into_iter()
+                    .map(|leg_results| {
+                        leg_results
+                            .into_iter()
+                            .filter(|leg_result| {
+                                leg_result.amount_in > 0
+                            })
+                            .map(|leg_result| {
+                                DirectedLeg {
+                                    x_to_y: leg_result.x_to_y,
+                                    market: leg_result.market
+                                }
+                            })
+                            .collect::<Vec<DirectedLeg>>()
+                    })
+                    .collect::<Vec<Vec<DirectedLeg>>>()
             } else {
-                a = c;
-            }
-
-            c = b - (((b - a) * gr_den) / gr_num);
-            d = a + (((b - a) * gr_den) / gr_num);
-        }
-
-        let optimized_amount_in = (b + a) / 2;
-        let optimized_amount_out = amount_out(&expanded_path, optimized_amount_in)?;
-        let optimized_profit = I256::from(optimized_amount_out) - I256::from(optimized_amount_in);
+                route
+            };
 
-        optimized_results.push(
-            OptimizedResult{
-                path: expanded_path,
-                amount_in: optimized_amount_in,
-                amount_out: optimized_amount_out,
-                profit: optimized_profit
-            }
-        )
-    }
+            Ok(
+                OptimizedResult{
+                    route,
+                    source_coin: &path[0],
+                    amount_in,
+                    amount_out,
+                    profit,
+                    gas_cost: 0,
+                    net_profit_value: I256::ZERO
+                }
+            )
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
     
     // println!("optimized_results: {:#?}", optimized_results);
 
@@ -202,6 +297,585 @@ pub fn optimize_starting_amount_in<'a>(
     Ok(profit_maximized_result)
 }
 
+// Returns the amount in, amount out and profit at the optimum.
+// Routes where every hop is a single pool get the closed form optimum
+// over each pool's constant product curve. It's exact when every pool is
+// a constant product pool and brackets the search otherwise.
+// Routes that come back through a pool are searched in full.
+fn optimize_route_amount_in(
+    route: &[Vec<DirectedLeg>],
+    simulation_context: &SimulationContext
+) -> Result<(u128, u128, I256), anyhow::Error> {
+    let amount_out = |amount_in| {
+        route_amount_out(route, amount_in, simulation_context)
+    };
+
+    if repeats_pool(route) {
+        return maximize_profit(amount_out, 0, u64::MAX as u128);
+    }
+
+    let curves = simulation_context
+        .route(route)
+        .iter()
+        .map(|legs| {
+            match legs.as_slice() {
+                [leg] => leg.market.constant_product_curve(leg.x_to_y),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<ConstantProductCurve>>>();
+
+    let curves = if let Some(curves) = curves {
+        curves
+    } else {
+        return maximize_profit(amount_out, 0, u64::MAX as u128);
+    };
+
+    let seed_amount_in = constant_product_amount_in(&curves)
+        .min(u64::MAX as f64) as u128;
+
+    // Output is concave in input so a cycle that loses at the margin loses at any size.
+    // Both hold for the exact curves and for the local curves at the current price.
+    if seed_amount_in == 0 || curves.iter().all(|curve| curve.exact) {
+        let seed_amount_out = amount_out(seed_amount_in)?;
+
+        return Ok((seed_amount_in, seed_amount_out, I256::from(seed_amount_out) - I256::from(seed_amount_in)));
+    }
+
+    let lower = seed_amount_in / BRACKET_FACTOR;
+    let upper = cmp::min(seed_amount_in.saturating_mul(BRACKET_FACTOR), u64::MAX as u128);
+
+    let optimum = maximize_profit(amount_out, lower, upper)?;
+
+    // The search ran into the edge of the bracket so the optimum may be past it
+    if optimum.0 <= lower + 1 || optimum.0 + 1 >= upper {
+        maximize_profit(amount_out, 0, u64::MAX as u128)
+    } else {
+        Ok(optimum)
+    }
+}
+
+// The amount in that maximizes profit through a chain of constant product curves.
+// Chaining the curves gives amount_out = a * x / (b + c * x) which peaks
+// at x = (sqrt(a * b) - b) / c. Zero when the chain loses at the margin.
+pub fn constant_product_amount_in(curves: &[ConstantProductCurve]) -> f64 {
+    let mut a = 1f64;
+    let mut c = 0f64;
+
+    // Kept normalized so that b = 1 and long paths don't overflow
+    for curve in curves {
+        let next_a = curve.fee_multiplier * curve.reserve_out * a;
+        let next_c = curve.reserve_in * c + curve.fee_multiplier * a;
+
+        a = next_a / curve.reserve_in;
+        c = next_c / curve.reserve_in;
+    }
+
+    if a <= 1.0 || c <= 0.0 {
+        return 0.0;
+    }
+
+    (a.sqrt() - 1.0) / c
+}
+
+// Golden section search over amount_in in [lower, upper] for the most profit.
+// - for unimodal functions
+// - does not get caught in local extrema
+// Returns the amount in, amount out and profit at the optimum.
+fn maximize_profit(
+    amount_out: impl Fn(u128) -> Result<u128, anyhow::Error>,
+    lower: u128,
+    upper: u128
+) -> Result<(u128, u128, I256), anyhow::Error> {
+    let gr_num = 121393u128;
+    let gr_den = 75025u128;
+
+    let mut a = lower;
+    let mut b = upper;
+
+    let mut c = b - (((b - a) * gr_den) / gr_num);
+    let mut d = a + (((b - a) * gr_den) / gr_num);
+
+    while (I256::from(b) - I256::from(a)).abs() > 1 {
+        let amount_out_c = amount_out(c)?;
+        let amount_out_d = amount_out(d)?;
+        let profit_c = I256::from(amount_out_c) - I256::from(c);
+        let profit_d = I256::from(amount_out_d) - I256::from(d);
+
+        if profit_c > profit_d {
+            b = d;
+        } else {
+            a = c;
+        }
+
+        c = b - (((b - a) * gr_den) / gr_num);
+        d = a + (((b - a) * gr_den) / gr_num);
+    }
+
+    let optimized_amount_in = (b + a) / 2;
+    let optimized_amount_out = amount_out(optimized_amount_in)?;
+    let optimized_profit = I256::from(optimized_amount_out) - I256::from(optimized_amount_in);
+
+    Ok((optimized_amount_in, optimized_amount_out, optimized_profit))
+}
+
+// Splits amount_in across the parallel legs of a hop. Chunks are handed out
+// one at a time to whichever leg gives the most for them. Every pool's output
+// is concave in its input so this converges on equal marginal prices.
+// Returns the amount in and amount out of every leg.
+pub fn split_amount_in(legs: &[DirectedLeg], amount_in: u128) -> Result<Vec<(u128, u128)>, anyhow::Error> {
+    if legs.len() == 1 {
+        return Ok(vec![(amount_in, amount_out(legs, amount_in)?)]);
+    }
+
+    let mut leg_amounts = vec![(0u128, 0u128); legs.len()];
+
+    let chunk = cmp::max(amount_in / SPLIT_CHUNKS, 1);
+    let mut remaining = amount_in;
+
+    while remaining > 0 {
+        let chunk_amount_in = cmp::min(chunk, remaining);
+
+        let mut best: Option<(usize, u128, u128)> = None;
+
+        for (index, leg) in legs.iter().enumerate() {
+            let (leg_amount_in, leg_amount_out) = leg_amounts[index];
+            let new_leg_amount_out = amount_out(&[leg.clone()], leg_amount_in + chunk_amount_in)?;
+            let gain = new_leg_amount_out.saturating_sub(leg_amount_out);
+
+            if best.map_or(true, |(_, best_gain, _)| gain > best_gain) {
+                best = Some((index, gain, new_leg_amount_out));
+            }
+        }
+
+        let (index, _, new_leg_amount_out) = best.context("No legs to split across.")?;
+
+        leg_amounts[index].0 += chunk_amount_in;
+        leg_amounts[index].1 = new_leg_amount_out;
+        remaining -= chunk_amount_in;
+    }
+
+    Ok(leg_amounts)
+}
+
+// The amount in and predicted amount out of every leg of every hop,
+// priced against the pools as simulation_context has them. A route that
+// comes back through a pool sees the price impact of its earlier hops.
+pub fn route_leg_results<'a>(
+    route: &[Vec<DirectedLeg<'a>>],
+    mut amount_in: u128,
+    simulation_context: &SimulationContext
+) -> Result<Vec<Vec<DirectedLegResult<'a>>>, anyhow::Error> {
+    let mut hop_results = Vec::with_capacity(route.len());
+
+    // Pools only have to move when the route comes back through one
+    let mut forked_simulation_context = if repeats_pool(route) {
+        Some(simulation_context.clone())
+    } else {
+        None
+    };
+
+    for legs in route {
+        let leg_amounts = split_amount_in(
+            &forked_simulation_context
+                .as_ref()
+                .unwrap_or(simulation_context)
+                .legs(legs),
+            amount_in
+        )?;
+
+        let mut leg_results = Vec::with_capacity(legs.len());
+
+        for (leg, (leg_amount_in, mut leg_amount_out)) in legs.iter().zip(leg_amounts) {
+            if let Some(forked_simulation_context) = forked_simulation_context.as_mut() {
+                leg_amount_out = forked_simulation_context.apply_swap(leg, leg_amount_in);
+            }
+
+            leg_results.push(
+                DirectedLegResult {
+                    x_to_y: leg.x_to_y,
+                    market: leg.market,
+                    amount_in: leg_amount_in,
+                    amount_out: leg_amount_out,
+                }
+            );
+        }
+
+        amount_in = leg_results
+            .iter()
+            .map(|leg_result| {
+                leg_result.amount_out
+            })
+            .sum();
+
+        hop_results.push(leg_results);
+    }
+
+    Ok(hop_results)
+}
+
+pub fn route_amount_out(
+    route: &[Vec<DirectedLeg>],
+    amount_in: u128,
+    simulation_context: &SimulationContext
+) -> Result<u128, anyhow::Error> {
+    let amount_out = route_leg_results(route, amount_in, simulation_context)?
+        .last()
+        .map(|leg_results| {
+            leg_results
+                .iter()
+                .map(|leg_result| {
+                    leg_result.amount_out
+                })
+                .sum()
+        })
+        .unwrap_or(amount_in);
+
+    Ok(amount_out)
+}
+
+// Whether a pool comes up more than once along the route
+fn repeats_pool(route: &[Vec<DirectedLeg>]) -> bool {
+    let mut pool_ids = HashSet::new();
+
+    !route
+        .iter()
+        .flatten()
+        .all(|leg| {
+            pool_ids.insert(leg.market.pool_id())
+        })
+}
+
+// Whether the route can be opened with a flash swap. The first hop has to be a
+// single pool that supports it and the pool can't come up again before we repay.
+pub fn flash_swappable(route: &[Vec<DirectedLeg>]) -> bool {
+    let (first_hop, hops) = if let Some(split) = route.split_first() {
+        split
+    } else {
+        return false;
+    };
+
+    let flash_leg = if let [flash_leg] = first_hop.as_slice() {
+        flash_leg
+    } else {
+        return false;
+    };
+
+    flash_leg.market.supports_flash_swap() &&
+        !hops
+            .iter()
+            .flatten()
+            .any(|leg| {
+                leg.market.pool_id() == flash_leg.market.pool_id()
+            })
+}
+
+// Optimizes every cycle and prices in its gas. Cycles can start from different
+// source coins so results are valued by their net profit in the profit coin.
+// Returns the set of results worth the most together in the order they have to go out.
+// A result may be chained behind an earlier one through a pool they share.
+pub fn select_opportunities<'a>(
+    cycles: &'a [Vec<TypeTag>],
+    market_graph: &'a MarketGraph,
+    profit_coin: &TypeTag,
+    // What the trades from each source coin can put in together
+    source_coin_to_allowance: &HashMap<TypeTag, u128>,
+    gas_estimator: &GasEstimator,
+    atomic: bool,
+    // Only routes we can open with a flash swap. They go out as one transaction.
+    flash: bool
+) -> Result<Vec<OptimizedResult<'a>>, anyhow::Error> {
+    let optimized_results = cycles
+        .par_iter()
+        .map(|cycle| {
+            optimize_starting_amount_in(cycle, market_graph)
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+    let atomic = atomic || flash;
+
+    // Gas doesn't depend on amount_in so it doesn't change the optimum.
+    // Results whose gas we can't price in the source coin are dropped.
+    let optimized_results = optimized_results
+        .into_iter()
+        .filter(|optimized_result| {
+            !flash || flash_swappable(&optimized_result.route)
+        })
+        .filter_map(|mut optimized_result| {
+            let gas = gas_estimator.estimate_route_gas(&optimized_result.route, atomic);
+            optimized_result.gas_cost = gas::gas_in_source_coin(market_graph, optimized_result.source_coin, gas)?;
+
+            println!("profit: {}, gas: {}", optimized_result.profit, optimized_result.gas_cost);
+
+            Some(optimized_result)
+        })
+        .filter(|optimized_result| {
+            optimized_result.net_profit() > I256::ZERO
+        })
+        .filter_map(|mut optimized_result| {
+            // Results we can't value in the profit coin are dropped too
+            let net_profit_value = market_graph.convert_amount(
+                optimized_result.net_profit().as_u128(),
+                optimized_result.source_coin,
+                profit_coin
+            )?;
+
+            optimized_result.net_profit_value = I256::from(net_profit_value);
+
+            Some(optimized_result)
+        })
+        .collect::<Vec<_>>();
+
+    // Each result was optimized on its own and may not fit our allowance
+    let mut candidates = vec![];
+    for mut optimized_result in optimized_results {
+        let allowance = source_coin_to_allowance
+            .get(optimized_result.source_coin)
+            .copied()
+            .unwrap_or(0);
+
+        if fit_to_allowance(&mut optimized_result, allowance)? {
+            candidates.push(optimized_result);
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        b.net_profit_value.cmp(&a.net_profit_value)
+    });
+
+    let rest = if candidates.len() > MAX_JOINT_CANDIDATES {
+        candidates.split_off(MAX_JOINT_CANDIDATES)
+    } else {
+        vec![]
+    };
+
+    let mut joint_selection = JointSelection::new(&candidates);
+
+    joint_selection.search(
+        0,
+        &SimulationContext::new(),
+        source_coin_to_allowance,
+        &mut vec![],
+        I256::ZERO
+    )?;
+
+    let mut selected_results = joint_selection.best_results;
+
+    // Candidates past the joint search are taken greedily when they
+    // don't touch a pool the selection uses and still fit the allowance
+    let mut used_pool_ids = selected_results
+        .iter()
+        .flat_map(|optimized_result| {
+            optimized_result.route.iter().flatten()
+        })
+        .map(|leg| {
+            leg.market.pool_id().clone()
+        })
+        .collect::<HashSet<ObjectID>>();
+
+    let mut source_coin_to_remaining_allowance = source_coin_to_allowance.clone();
+    for optimized_result in selected_results.iter() {
+        if let Some(remaining_allowance) = source_coin_to_remaining_allowance.get_mut(optimized_result.source_coin) {
+            *remaining_allowance = remaining_allowance.saturating_sub(optimized_result.amount_in);
+        }
+    }
+
+    for optimized_result in rest {
+        let touches_used_pool = optimized_result.route
+            .iter()
+            .flatten()
+            .any(|leg| {
+                used_pool_ids.contains(leg.market.pool_id())
+            });
+
+        let remaining_allowance = if let Some(remaining_allowance) = source_coin_to_remaining_allowance.get_mut(optimized_result.source_coin) {
+            remaining_allowance
+        } else {
+            continue;
+        };
+
+        if touches_used_pool || optimized_result.amount_in > *remaining_allowance {
+            continue;
+        }
+
+        *remaining_allowance -= optimized_result.amount_in;
+
+        used_pool_ids.extend(
+            optimized_result.route
+                .iter()
+                .flatten()
+                .map(|leg| {
+                    leg.market.pool_id().clone()
+                })
+        );
+
+        selected_results.push(optimized_result);
+    }
+
+    Ok(selected_results)
+}
+
+// Branch and bound over which candidates to take. Trades that share a pool
+// are chained: a candidate is re-optimized against the pools as the trades
+// taken before it leave them instead of being dropped. The trades of a source
+// coin together can't put in more than its allowance so they can all go out
+// without waiting on each other.
+struct JointSelection<'a, 'c> {
+    // Most valuable first so the first selection we reach is the greedy one
+    candidates: &'c [OptimizedResult<'a>],
+    // Upper bound on what the candidates from each index on can add.
+    // Assumes chaining never makes a candidate worth more than on its own.
+    remaining_values: Vec<I256>,
+    num_nodes: usize,
+    best_results: Vec<OptimizedResult<'a>>,
+    best_value: I256,
+}
+
+impl<'a, 'c> JointSelection<'a, 'c> {
+    fn new(candidates: &'c [OptimizedResult<'a>]) -> Self {
+        let mut remaining_values = vec![I256::ZERO; candidates.len() + 1];
+
+        for index in (0..candidates.len()).rev() {
+            remaining_values[index] = remaining_values[index + 1] + candidates[index].net_profit_value;
+        }
+
+        JointSelection {
+            candidates,
+            remaining_values,
+            num_nodes: 0,
+            best_results: vec![],
+            best_value: I256::ZERO,
+        }
+    }
+
+    // simulation_context has the pools as the selected trades leave them
+    fn search(
+        &mut self,
+        index: usize,
+        simulation_context: &SimulationContext,
+        source_coin_to_allowance: &HashMap<TypeTag, u128>,
+        selected_results: &mut Vec<OptimizedResult<'a>>,
+        value: I256
+    ) -> Result<(), anyhow::Error> {
+        if value > self.best_value {
+            self.best_value = value;
+            self.best_results = selected_results.clone();
+        }
+
+        if index == self.candidates.len() || value + self.remaining_values[index] <= self.best_value {
+            return Ok(());
+        }
+
+        self.num_nodes += 1;
+        if self.num_nodes > MAX_SELECTION_NODES {
+            return Ok(());
+        }
+
+        let candidates = self.candidates;
+        let candidate = &candidates[index];
+
+        let allowance = source_coin_to_allowance
+            .get(candidate.source_coin)
+            .copied()
+            .unwrap_or(0);
+
+        if let Some(chained_result) = chain_result(candidate, simulation_context, allowance)? {
+            let mut next_simulation_context = simulation_context.clone();
+            next_simulation_context.apply_route(&chained_result.route, chained_result.amount_in)?;
+
+            let mut next_source_coin_to_allowance = source_coin_to_allowance.clone();
+            next_source_coin_to_allowance.insert(candidate.source_coin.clone(), allowance - chained_result.amount_in);
+
+            let next_value = value + chained_result.net_profit_value;
+
+            selected_results.push(chained_result);
+            self.search(
+                index + 1,
+                &next_simulation_context,
+                &next_source_coin_to_allowance,
+                selected_results,
+                next_value
+            )?;
+            selected_results.pop();
+        }
+
+        self.search(
+            index + 1,
+            simulation_context,
+            source_coin_to_allowance,
+            selected_results,
+            value
+        )
+    }
+}
+
+// The candidate as it trades after the selected trades in simulation_context,
+// within the allowance that's left. None if it's no longer profitable.
+// Its gas doesn't change since it goes through the same pools.
+fn chain_result<'a>(
+    candidate: &OptimizedResult<'a>,
+    simulation_context: &SimulationContext,
+    allowance: u128
+) -> Result<Option<OptimizedResult<'a>>, anyhow::Error> {
+    let shares_pool = candidate.route
+        .iter()
+        .flatten()
+        .any(|leg| {
+            simulation_context.contains(leg.market.pool_id())
+        });
+
+    if !shares_pool {
+        if candidate.amount_in <= allowance {
+            return Ok(Some(candidate.clone()));
+        }
+
+        let mut chained_result = candidate.clone();
+
+        return if fit_to_allowance(&mut chained_result, allowance)? {
+            Ok(Some(chained_result))
+        } else {
+            Ok(None)
+        };
+    }
+
+    let (mut amount_in, mut amount_out, _) = optimize_route_amount_in(&candidate.route, simulation_context)?;
+
+    if amount_in > allowance {
+        amount_in = allowance;
+        amount_out = route_amount_out(&candidate.route, allowance, simulation_context)?;
+    }
+
+    let mut chained_result = candidate.clone();
+    chained_result.resize(amount_in, amount_out);
+
+    if chained_result.net_profit() > I256::ZERO {
+        Ok(Some(chained_result))
+    } else {
+        Ok(None)
+    }
+}
+
+// Shrinks a result down to what we can afford to put in.
+// False if it's no longer profitable after gas at that size.
+pub fn fit_to_allowance(optimized_result: &mut OptimizedResult, allowance: u128) -> Result<bool, anyhow::Error> {
+    if optimized_result.amount_in <= allowance {
+        return Ok(true);
+    }
+
+    println!("profitable optimized result amount_in: {}", optimized_result.amount_in);
+
+    let amount_out = route_amount_out(&optimized_result.route, allowance, &SimulationContext::new())?;
+    let profit = I256::from(amount_out) - I256::from(allowance);
+
+    if profit > I256::from(optimized_result.gas_cost) {
+        optimized_result.resize(allowance, amount_out);
+
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
 pub fn amount_out(path: &[DirectedLeg], mut amount_in: u128) -> Result<u128, anyhow::Error> {
 
     for leg in path {
@@ -237,231 +911,1006 @@ pub fn amount_out(path: &[DirectedLeg], mut amount_in: u128) -> Result<u128, any
     Ok(amount_in)
 }
 
+// The least we'll accept for a predicted amount out.
+// slippage_bps is in basis points. 50 bps = 0.5%.
+pub fn min_amount_out(predicted_amount_out: u128, slippage_bps: u64) -> u128 {
+    let slippage_bps = (slippage_bps as u128).min(BPS_DENOMINATOR);
+
+    (predicted_amount_out * (BPS_DENOMINATOR - slippage_bps)) / BPS_DENOMINATOR
+}
+
+// Legs are predicted against the pools as simulation_context has them.
+// The swaps of the legs that land are applied to it so the trades that go
+// out after this one are predicted against what this one leaves behind.
 pub async fn execute_arb<'a>(
     sui_client: &SuiClient,
     optimized_result: OptimizedResult<'a>,
     signer_address: &SuiAddress,
-    keystore: &Keystore
+    keystore: &Keystore,
+    slippage_bps: u64,
+    gas_estimator: &mut GasEstimator,
+    simulation_context: &mut SimulationContext
 ) -> Result<(), anyhow::Error> {
     // We'll want to work with the actual amounts out we get!
     let mut amount_in = optimized_result.amount_in;
 
-    for leg in optimized_result.path {
-        let mut dry_run_pt_builder = ProgrammableTransactionBuilder::new();
+    for legs in optimized_result.route {
+        // Split what we actually got out of the last hop
+        let mut hop_amount_out = 0;
 
-        let (orig_coin_type, dest_coin_type) = if leg.x_to_y {
-            (leg.market.coin_x(), leg.market.coin_y())
-        } else {
-            (leg.market.coin_y(), leg.market.coin_x())
-        };
+        let leg_amounts = split_amount_in(&simulation_context.legs(&legs), amount_in)?;
 
-        let orig_coin_string = format!("{}", orig_coin_type);
+        for (leg, (leg_amount_in, _)) in legs.iter().zip(leg_amounts) {
+            if leg_amount_in == 0 {
+                continue;
+            }
 
-        // Yields SuiRpcResult<Vec<Coin>>
-        let coins = sui_client
-            .coin_read_api()
-            .select_coins(
-                signer_address.clone(),
-                Some(orig_coin_string),
-                amount_in,
-                vec![]
+            let predicted_amount_out = amount_out(&[simulation_context.leg(leg)], leg_amount_in)?;
+
+            let leg_amount_out = execute_leg(
+                sui_client,
+                leg,
+                leg_amount_in,
+                predicted_amount_out,
+                signer_address,
+                keystore,
+                slippage_bps,
+                gas_estimator
             )
             .await?;
 
-        // println!("{:#?}", coins);
+            // Without an amount out of the destination coin there's nothing to size
+            // the next hop with. Whatever the earlier legs got out stays with us.
+            let leg_amount_out = if let Some(leg_amount_out) = leg_amount_out {
+                leg_amount_out
+            } else {
+                println!("Leg through pool {} got nothing out. Stopping the route.", leg.market.pool_id());
+                return Ok(());
+            };
 
-        // None if our orig_cion is Sui - we'll be splitting off of gas_coin
-        let coin_object_ids = if *orig_coin_type == TypeTag::from_str(SUI_COIN_TYPE)? {
-            None
-        } else {
-            Some(
-                coins
-                    .into_iter()
-                    .map(|coin| {
-                        coin.coin_object_id
-                    })
-                    .collect::<Vec<ObjectID>>()
-            )
-        };
+            simulation_context.apply_swap(leg, leg_amount_in);
 
-        let predicted_amount_out = if leg.x_to_y {
-            leg.market
-                .compute_swap_x_to_y(amount_in).1
-        } else {
-            leg.market
-                .compute_swap_y_to_x(amount_in).0
+            // Set amount_in for next leg
+            hop_amount_out += leg_amount_out;
+        }
+
+        amount_in = hop_amount_out;
+    }
+
+    Ok(())
+}
+
+// Executes a single leg as its own transaction.
+// Returns how much of the destination coin we got if our balance of it went up.
+async fn execute_leg<'a>(
+    sui_client: &SuiClient,
+    leg: &DirectedLeg<'a>,
+    amount_in: u128,
+    predicted_amount_out: u128,
+    signer_address: &SuiAddress,
+    keystore: &Keystore,
+    slippage_bps: u64,
+    gas_estimator: &mut GasEstimator
+) -> Result<Option<u128>, anyhow::Error> {
+    let (orig_coin_type, dest_coin_type) = if leg.x_to_y {
+        (leg.market.coin_x(), leg.market.coin_y())
+    } else {
+        (leg.market.coin_y(), leg.market.coin_x())
+    };
+
+    // println!("predicted amount out: {}", predicted_amount_out);
+
+    let leg_min_amount_out = min_amount_out(predicted_amount_out, slippage_bps);
+
+    let package_ids = [leg.market.package_id().clone()];
+
+    let mut dry_run_pt_builder = ProgrammableTransactionBuilder::new();
+
+    add_leg_to_programmable_transaction(
+        sui_client,
+        &mut dry_run_pt_builder,
+        leg,
+        amount_in,
+        leg_min_amount_out,
+        signer_address
+    )
+    .await?;
+
+    let reference_gas_price = sui_client
+        .read_api()
+        .get_reference_gas_price()
+        .await?
+        * 20000;
+
+    // Initial dry run transaction to get gas
+    let dry_run_transaction = finish_building_programmable_transaction(
+        sui_client,
+        dry_run_pt_builder,
+        orig_coin_type,
+        signer_address,
+        reference_gas_price
+    )
+    .await?;
+
+    // The builder picks the gas price so learn from what it picked
+    let dry_run_gas_price = dry_run_transaction.gas_price();
+
+    let dry_run_result = sui_client
+        .read_api()
+        .dry_run_transaction_block(
+            dry_run_transaction
+        )
+        .await?;
+
+    let gcs = dry_run_result.effects.gas_cost_summary();
+    gas_estimator.observe(&package_ids, dry_run_gas_price, gcs);
+    let gas_budget = (gcs.computation_cost + gcs.storage_cost + gcs.non_refundable_storage_fee) * 3;
+
+    // // println!("Gas Budget: {}", gas_budget);
+    // // panic!();
+    // println!("DRY RUN RESULT: {:#?}", dry_run_result);
+    // panic!();
+
+    let mut pt_builder = ProgrammableTransactionBuilder::new();
+
+    add_leg_to_programmable_transaction(
+        sui_client,
+        &mut pt_builder,
+        leg,
+        amount_in,
+        leg_min_amount_out,
+        signer_address
+    )
+    .await?;
+
+    let transaction = finish_building_programmable_transaction(
+        sui_client,
+        pt_builder,
+        orig_coin_type,
+        signer_address,
+        gas_budget
+    )
+    .await?;
+
+    let gas_price = transaction.gas_price();
+
+    let result = sign_and_execute_transaction(
+        sui_client,
+        transaction,
+        signer_address,
+        keystore
+    )
+    .await?;
+
+    // println!("RESULT: {:#?}", result);
+        
+    if let Some(effects) = result.effects {
+        gas_estimator.observe(&package_ids, gas_price, effects.gas_cost_summary());
+
+        match effects.into_status() {
+            SuiExecutionStatus::Success => {
+                if let Some(balance_changes) = result.balance_changes {
+                    let coin_type_to_balance_change_amount = balance_changes
+                        .into_iter()
+                        .map(|balance_change| {
+                            (balance_change.coin_type, balance_change.amount)
+                        })
+                        .collect::<HashMap<TypeTag, i128>>();
+
+                    let dest_coin_balance_change_amount = coin_type_to_balance_change_amount
+                        .get(dest_coin_type)
+                        .context(format!("Balance change for orig_coin {} not available", dest_coin_type))?;
+
+                    // DESTINATION COIN
+                    if *dest_coin_balance_change_amount > 0 {
+                        return Ok(Some(*dest_coin_balance_change_amount as u128));
+                    }
+                } else {
+                    return Err(anyhow!("result.balance_changes missing."))
+                }
+            },
+            SuiExecutionStatus::Failure { error } => return Err(anyhow!(error)),
         };
 
-        // println!("predicted amount out: {}", predicted_amount_out);
+    }
+
+    Ok(None)
+}
+
+// Executes every leg of the cycle in a single programmable transaction.
+// Each leg's output coin is fed to the next leg so the cycle either
+// lands as a whole or reverts as a whole.
+// Predicted against simulation_context and applied to it once it lands.
+pub async fn execute_arb_atomic<'a>(
+    sui_client: &SuiClient,
+    optimized_result: OptimizedResult<'a>,
+    signer_address: &SuiAddress,
+    keystore: &Keystore,
+    slippage_bps: u64,
+    gas_estimator: &mut GasEstimator,
+    simulation_context: &mut SimulationContext
+) -> Result<(), anyhow::Error> {
+    execute_programmable_route(
+        sui_client,
+        optimized_result,
+        signer_address,
+        keystore,
+        slippage_bps,
+        gas_estimator,
+        simulation_context,
+        false,
+        false
+    )
+    .await
+}
+
+// Like execute_arb_atomic but the first hop is a flash swap that the last
+// hop repays, so amount_in doesn't have to come out of our balance.
+// The route has to be flash_swappable.
+// With exact_repay the last hop buys exactly what we owe when its market can,
+// leaving the profit in the coin it's paid for with.
+pub async fn execute_arb_flash<'a>(
+    sui_client: &SuiClient,
+    optimized_result: OptimizedResult<'a>,
+    signer_address: &SuiAddress,
+    keystore: &Keystore,
+    slippage_bps: u64,
+    gas_estimator: &mut GasEstimator,
+    simulation_context: &mut SimulationContext,
+    exact_repay: bool
+) -> Result<(), anyhow::Error> {
+    if !flash_swappable(&optimized_result.route) {
+        return Err(anyhow!("Route can't be opened with a flash swap."));
+    }
+
+    execute_programmable_route(
+        sui_client,
+        optimized_result,
+        signer_address,
+        keystore,
+        slippage_bps,
+        gas_estimator,
+        simulation_context,
+        true,
+        exact_repay
+    )
+    .await
+}
+
+async fn execute_programmable_route<'a>(
+    sui_client: &SuiClient,
+    optimized_result: OptimizedResult<'a>,
+    signer_address: &SuiAddress,
+    keystore: &Keystore,
+    slippage_bps: u64,
+    gas_estimator: &mut GasEstimator,
+    simulation_context: &mut SimulationContext,
+    flash: bool,
+    exact_repay: bool
+) -> Result<(), anyhow::Error> {
+    let source_coin_type = source_coin_type(&optimized_result.route)?;
+
+    let package_ids = optimized_result.route
+        .iter()
+        .flatten()
+        .map(|leg| {
+            leg.market.package_id().clone()
+        })
+        .collect::<Vec<ObjectID>>();
+
+    let hop_results = route_leg_results(&optimized_result.route, optimized_result.amount_in, simulation_context)?;
+
+    let mut dry_run_pt_builder = ProgrammableTransactionBuilder::new();
+
+    add_route_to_programmable_transaction(
+        sui_client,
+        &mut dry_run_pt_builder,
+        &hop_results,
+        optimized_result.amount_in,
+        optimized_result.gas_cost,
+        slippage_bps,
+        signer_address,
+        simulation_context,
+        flash,
+        exact_repay
+    )
+    .await?;
+
+    let reference_gas_price = sui_client
+        .read_api()
+        .get_reference_gas_price()
+        .await?
+        * 20000;
+
+    // Initial dry run transaction to get gas
+    let dry_run_transaction = finish_building_programmable_transaction(
+        sui_client,
+        dry_run_pt_builder,
+        source_coin_type,
+        signer_address,
+        reference_gas_price
+    )
+    .await?;
+
+    // The builder picks the gas price so learn from what it picked
+    let dry_run_gas_price = dry_run_transaction.gas_price();
+
+    let dry_run_result = sui_client
+        .read_api()
+        .dry_run_transaction_block(
+            dry_run_transaction
+        )
+        .await?;
+
+    // Failed dry runs still tell us what the legs cost
+    let gcs = dry_run_result.effects.gas_cost_summary();
+    gas_estimator.observe(&package_ids, dry_run_gas_price, gcs);
+
+    // The state moved out from under us. Nothing is lost by not sending it.
+    if let SuiExecutionStatus::Failure { error } = dry_run_result.effects.status() {
+        println!("| DRY RUN FAILED: {}", error);
+        return Ok(());
+    }
+
+    let gas_budget = (gcs.computation_cost + gcs.storage_cost + gcs.non_refundable_storage_fee) * 3;
+
+    let mut pt_builder = ProgrammableTransactionBuilder::new();
+
+    add_route_to_programmable_transaction(
+        sui_client,
+        &mut pt_builder,
+        &hop_results,
+        optimized_result.amount_in,
+        optimized_result.gas_cost,
+        slippage_bps,
+        signer_address,
+        simulation_context,
+        flash,
+        exact_repay
+    )
+    .await?;
+
+    let transaction = finish_building_programmable_transaction(
+        sui_client,
+        pt_builder,
+        source_coin_type,
+        signer_address,
+        gas_budget
+    )
+    .await?;
+
+    let gas_price = transaction.gas_price();
+
+    let result = sign_and_execute_transaction(
+        sui_client,
+        transaction,
+        signer_address,
+        keystore
+    )
+    .await?;
+
+    if let Some(effects) = result.effects {
+        gas_estimator.observe(&package_ids, gas_price, effects.gas_cost_summary());
+
+        if let SuiExecutionStatus::Failure { error } = effects.into_status() {
+            return Err(anyhow!(error));
+        }
+
+        // Only a landed route moved the pools
+        simulation_context.apply_route(&optimized_result.route, optimized_result.amount_in)?;
+    }
+
+    Ok(())
+}
+
+fn source_coin_type<'a>(route: &[Vec<DirectedLeg<'a>>]) -> Result<&'a TypeTag, anyhow::Error> {
+    let first_leg = route
+        .first()
+        .and_then(|legs| legs.first())
+        .context("Route has no legs.")?;
+
+    let market: &'a Box<dyn Market> = first_leg.market;
+
+    if first_leg.x_to_y {
+        Ok(market.coin_x())
+    } else {
+        Ok(market.coin_y())
+    }
+}
+
+async fn add_leg_to_programmable_transaction<'a>(
+    sui_client: &SuiClient,
+    pt_builder: &mut ProgrammableTransactionBuilder,
+    leg: &DirectedLeg<'a>,
+    amount_in: u128,
+    min_amount_out: u128,
+    signer_address: &SuiAddress
+) -> Result<(), anyhow::Error> {
+    let orig_coin_type = if leg.x_to_y {
+        leg.market.coin_x()
+    } else {
+        leg.market.coin_y()
+    };
+
+    let orig_coin = split_orig_coin(
+        sui_client,
+        pt_builder,
+        orig_coin_type,
+        amount_in,
+        signer_address
+    )
+    .await?;
+
+    let dest_coin = leg
+        .market
+        .add_swap_to_programmable_transaction(
+            sui_client.transaction_builder(),
+            pt_builder,
+            orig_coin,
+            leg.x_to_y,
+            amount_in,
+            min_amount_out,
+            signer_address.clone()
+        )
+        .await?;
+
+    pt_builder.transfer_arg(signer_address.clone(), dest_coin);
+
+    Ok(())
+}
+
+async fn add_route_to_programmable_transaction<'a>(
+    sui_client: &SuiClient,
+    pt_builder: &mut ProgrammableTransactionBuilder,
+    hop_results: &[Vec<DirectedLegResult<'a>>],
+    amount_in: u128,
+    // What the cycle has to make in the source coin to pay for its gas
+    min_profit: u128,
+    slippage_bps: u64,
+    signer_address: &SuiAddress,
+    simulation_context: &SimulationContext,
+    flash: bool,
+    exact_repay: bool
+) -> Result<(), anyhow::Error> {
+    // Every leg is built against its pool as the legs before it leave it
+    let mut simulation_context = simulation_context.clone();
+
+    if flash {
+        add_flash_cycle_to_programmable_transaction(
+            sui_client,
+            pt_builder,
+            hop_results,
+            amount_in,
+            min_profit,
+            slippage_bps,
+            signer_address,
+            &mut simulation_context,
+            exact_repay
+        )
+        .await
+    } else {
+        add_cycle_to_programmable_transaction(
+            sui_client,
+            pt_builder,
+            hop_results,
+            amount_in,
+            min_profit,
+            slippage_bps,
+            signer_address,
+            &mut simulation_context
+        )
+        .await
+    }
+}
+
+async fn add_cycle_to_programmable_transaction<'a>(
+    sui_client: &SuiClient,
+    pt_builder: &mut ProgrammableTransactionBuilder,
+    hop_results: &[Vec<DirectedLegResult<'a>>],
+    amount_in: u128,
+    min_profit: u128,
+    slippage_bps: u64,
+    signer_address: &SuiAddress,
+    simulation_context: &mut SimulationContext
+) -> Result<(), anyhow::Error> {
+    let transaction_builder = sui_client.transaction_builder();
+
+    let first_leg = hop_results
+        .first()
+        .and_then(|leg_results| leg_results.first())
+        .context("Route has no legs.")?;
+
+    let source_coin_type = if first_leg.x_to_y {
+        first_leg.market.coin_x()
+    } else {
+        first_leg.market.coin_y()
+    };
+
+    let coin = split_orig_coin(
+        sui_client,
+        pt_builder,
+        source_coin_type,
+        amount_in,
+        signer_address
+    )
+    .await?;
+
+    let coin = add_hops_to_programmable_transaction(
+        transaction_builder,
+        pt_builder,
+        hop_results,
+        coin,
+        amount_in,
+        slippage_bps,
+        signer_address,
+        simulation_context
+    )
+    .await?;
+
+    // Profit assertion. Abort the whole cycle if what we get back doesn't cover
+    // what we put in and the gas. Breaking even would still lose the gas.
+    transaction_builder
+        .programmable_assert_min_value(
+            pt_builder,
+            coin,
+            (amount_in + min_profit) as u64
+        )
+        .await?;
+
+    pt_builder.transfer_arg(signer_address.clone(), coin);
+
+    Ok(())
+}
+
+// Borrows the first hop from its pool and repays it out of what the last hop
+// brings back. Repaying aborts the whole cycle if we got back less than we owe.
+async fn add_flash_cycle_to_programmable_transaction<'a>(
+    sui_client: &SuiClient,
+    pt_builder: &mut ProgrammableTransactionBuilder,
+    hop_results: &[Vec<DirectedLegResult<'a>>],
+    amount_in: u128,
+    min_profit: u128,
+    slippage_bps: u64,
+    signer_address: &SuiAddress,
+    simulation_context: &mut SimulationContext,
+    exact_repay: bool
+) -> Result<(), anyhow::Error> {
+    let transaction_builder = sui_client.transaction_builder();
+
+    let (first_hop, hop_results) = hop_results
+        .split_first()
+        .context("Route has no legs.")?;
+
+    let flash_leg = if let [flash_leg] = first_hop.as_slice() {
+        flash_leg
+    } else {
+        return Err(anyhow!("A flash swap opens a hop with a single leg."));
+    };
+
+    let (coin, receipt) = simulation_context
+        .leg(&flash_leg.directed_leg())
+        .market
+        .add_flash_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            flash_leg.x_to_y,
+            amount_in,
+            signer_address.clone()
+        )
+        .await?;
+
+    simulation_context.apply_swap(&flash_leg.directed_leg(), amount_in);
 
-        leg
+    // Flash swaps take no min out so we enforce it on the output coin ourselves
+    let flash_min_amount_out = min_amount_out(flash_leg.amount_out, slippage_bps);
+
+    transaction_builder
+        .programmable_assert_min_value(
+            pt_builder,
+            coin,
+            flash_min_amount_out as u64
+        )
+        .await?;
+
+    // A single leg last hop that can swap for an exact amount out buys exactly what we owe
+    let exact_repay_leg = match hop_results.last().map(|leg_results| leg_results.as_slice()) {
+        Some([last_leg]) if exact_repay && last_leg.market.supports_exact_output() => Some(last_leg),
+        _ => None,
+    };
+
+    let coin = if let Some(last_leg) = exact_repay_leg {
+        let coin = add_hops_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            &hop_results[..hop_results.len() - 1],
+            coin,
+            flash_min_amount_out,
+            slippage_bps,
+            signer_address,
+            simulation_context
+        )
+        .await?;
+
+        // Buys what we owe and the gas on top so the profit check below holds
+        let (repay_coin, profit_coin) = simulation_context
+            .leg(&last_leg.directed_leg())
             .market
-            .add_swap_to_programmable_transaction(
-                sui_client.transaction_builder(),
-                &mut dry_run_pt_builder,
-                coin_object_ids.clone(),
-                leg.x_to_y,
-                amount_in,
-                0, // predicted_amount_out
+            .add_exact_output_swap_to_programmable_transaction(
+                transaction_builder,
+                pt_builder,
+                coin,
+                last_leg.x_to_y,
+                amount_in + min_profit,
                 signer_address.clone()
             )
             .await?;
 
-        let reference_gas_price = sui_client
-            .read_api()
-            .get_reference_gas_price()
-            .await?
-            * 20000;
+        // The rest of the profit stays in the coin the last hop pays with
+        pt_builder.transfer_arg(signer_address.clone(), profit_coin);
+
+        repay_coin
+    } else {
+        add_hops_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            hop_results,
+            coin,
+            flash_min_amount_out,
+            slippage_bps,
+            signer_address,
+            simulation_context
+        )
+        .await?
+    };
+
+    flash_leg
+        .market
+        .add_repay_flash_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            receipt,
+            coin,
+            flash_leg.x_to_y,
+            amount_in
+        )
+        .await?;
 
-        // Initial dry run transaction to get gas
-        let dry_run_transaction = if coin_object_ids.is_some() {
-            sui_client
-            .transaction_builder()
-            .finish_building_programmable_transaction(
-                dry_run_pt_builder,
-                signer_address.clone(),
-                None,
-                reference_gas_price
+    // What's left after repaying is the profit. Abort the whole cycle if it
+    // doesn't cover the gas. Breaking even would still lose the gas.
+    if min_profit > 0 {
+        transaction_builder
+            .programmable_assert_min_value(
+                pt_builder,
+                coin,
+                min_profit as u64
             )
-            .await?
+            .await?;
+    }
+
+    pt_builder.transfer_arg(signer_address.clone(), coin);
+
+    Ok(())
+}
+
+// Chains the hops. The output coin of every hop is the input coin of the next.
+// Returns the output coin of the last hop.
+async fn add_hops_to_programmable_transaction<'a>(
+    transaction_builder: &TransactionBuilder,
+    pt_builder: &mut ProgrammableTransactionBuilder,
+    hop_results: &[Vec<DirectedLegResult<'a>>],
+    mut coin: Argument,
+    // The least the previous hop guarantees us
+    mut hop_min_amount_in: u128,
+    slippage_bps: u64,
+    signer_address: &SuiAddress,
+    // Pools as the hops before leave them. The swaps of these hops are applied to it.
+    simulation_context: &mut SimulationContext
+) -> Result<Argument, anyhow::Error> {
+    for leg_results in hop_results {
+        let leg_results = leg_results
+            .iter()
+            .filter(|leg_result| {
+                leg_result.amount_in > 0
+            })
+            .collect::<Vec<&DirectedLegResult>>();
+
+        if leg_results.is_empty() {
+            return Err(anyhow!("Hop has no leg with an amount in."));
+        }
+
+        let hop_predicted_amount_in = leg_results
+            .iter()
+            .map(|leg_result| {
+                leg_result.amount_in
+            })
+            .sum::<u128>();
+
+        let mut leg_coins = Vec::with_capacity(leg_results.len());
+
+        // Every leg but the last gets its share of what we're guaranteed.
+        // The last leg swaps whatever is left so nothing is stranded.
+        for (index, leg_result) in leg_results.iter().enumerate() {
+            let leg_coin = if index + 1 == leg_results.len() {
+                coin
+            } else {
+                let leg_amount_in = if hop_predicted_amount_in > 0 {
+                    (leg_result.amount_in * hop_min_amount_in) / hop_predicted_amount_in
+                } else {
+                    0
+                };
+
+                transaction_builder
+                    .programmable_split_coin(
+                        pt_builder,
+                        ProgrammableObjectArg::Argument(coin),
+                        leg_amount_in as u64
+                    )
+                    .await?
+            };
+
+            leg_coins.push(
+                simulation_context
+                    .leg(&leg_result.directed_leg())
+                    .market
+                    .add_swap_to_programmable_transaction(
+                        transaction_builder,
+                        pt_builder,
+                        leg_coin,
+                        leg_result.x_to_y,
+                        leg_result.amount_in,
+                        min_amount_out(leg_result.amount_out, slippage_bps),
+                        signer_address.clone()
+                    )
+                    .await?
+            );
+
+            simulation_context.apply_swap(&leg_result.directed_leg(), leg_result.amount_in);
+        }
+
+        hop_min_amount_in = leg_results
+            .iter()
+            .map(|leg_result| {
+                min_amount_out(leg_result.amount_out, slippage_bps)
+            })
+            .sum();
+
+        let primary_coin = leg_coins.remove(0);
+
+        coin = if leg_coins.is_empty() {
+            primary_coin
         } else {
-            sui_client
-            .transaction_builder()
-            .finish_building_programmable_transaction_select_all_gas(
-                dry_run_pt_builder,
-                signer_address.clone(),
-                reference_gas_price
-            )
-            .await?
+            transaction_builder
+                .programmable_merge_coins(
+                    pt_builder,
+                    ProgrammableObjectArg::Argument(primary_coin),
+                    leg_coins
+                        .into_iter()
+                        .map(|leg_coin| {
+                            ProgrammableObjectArg::Argument(leg_coin)
+                        })
+                        .collect()
+                )
+                .await?
         };
+    }
 
-        let dry_run_result = sui_client
-            .read_api()
-            .dry_run_transaction_block(
-                dry_run_transaction
-            )
-            .await?;
-
-        let gcs = dry_run_result.effects.gas_cost_summary();
-        let gas_budget = (gcs.computation_cost + gcs.storage_cost + gcs.non_refundable_storage_fee) * 3;
+    Ok(coin)
+}
 
-        // // println!("Gas Budget: {}", gas_budget);
-        // // panic!();
-        // println!("DRY RUN RESULT: {:#?}", dry_run_result);
-        // panic!();
+// Splits amount_in off of the coins we hold of orig_coin_type.
+// Sui is split off of the gas coin.
+async fn split_orig_coin(
+    sui_client: &SuiClient,
+    pt_builder: &mut ProgrammableTransactionBuilder,
+    orig_coin_type: &TypeTag,
+    amount_in: u128,
+    signer_address: &SuiAddress
+) -> Result<Argument, anyhow::Error> {
+    let transaction_builder = sui_client.transaction_builder();
+
+    if *orig_coin_type == TypeTag::from_str(SUI_COIN_TYPE)? {
+        return Ok(
+            transaction_builder
+                .programmable_split_gas_coin(pt_builder, amount_in as u64)
+                .await
+        );
+    }
 
-        let mut pt_builder = ProgrammableTransactionBuilder::new();
+    // Yields SuiRpcResult<Vec<Coin>>
+    let mut coin_object_ids = sui_client
+        .coin_read_api()
+        .select_coins(
+            signer_address.clone(),
+            Some(format!("{}", orig_coin_type)),
+            amount_in,
+            vec![]
+        )
+        .await?
+        .into_iter()
+        .map(|coin| {
+            coin.coin_object_id
+        })
+        .collect::<Vec<ObjectID>>();
+
+    let primary_coin_id = coin_object_ids.pop().context("No primary coin id to pop.")?;
+
+    // Remaining coins to merge
+    let primary_coin = if coin_object_ids.len() > 0 {
+        ProgrammableObjectArg::Argument(
+            transaction_builder
+                .programmable_merge_coins(
+                    pt_builder,
+                    ProgrammableObjectArg::ObjectID(primary_coin_id),
+                    coin_object_ids
+                        .into_iter()
+                        .map(|coin_object_id| {
+                            ProgrammableObjectArg::ObjectID(coin_object_id)
+                        })
+                        .collect::<Vec<ProgrammableObjectArg>>()
+                )
+                .await?
+        )
+    } else {
+        ProgrammableObjectArg::ObjectID(primary_coin_id)
+    };
 
-        leg
-            .market
-            .add_swap_to_programmable_transaction(
-                sui_client.transaction_builder(),
-                &mut pt_builder,
-                coin_object_ids.clone(),
-                leg.x_to_y,
-                amount_in,
-                0, // Predicted amount out 
-                signer_address.clone()
-            )
-            .await?;
+    transaction_builder
+        .programmable_split_coin(pt_builder, primary_coin, amount_in as u64)
+        .await
+}
 
-        // If our base coin is sui, select all gas
-        // We gotta encapsulate a little better ...
-        let transaction = if coin_object_ids.is_some() {
-            sui_client
+// If our orig coin is sui we split off of gas and select all gas.
+// We gotta encapsulate a little better ...
+async fn finish_building_programmable_transaction(
+    sui_client: &SuiClient,
+    pt_builder: ProgrammableTransactionBuilder,
+    orig_coin_type: &TypeTag,
+    signer_address: &SuiAddress,
+    gas_budget: u64
+) -> Result<TransactionData, anyhow::Error> {
+    if *orig_coin_type == TypeTag::from_str(SUI_COIN_TYPE)? {
+        sui_client
             .transaction_builder()
-            .finish_building_programmable_transaction(
+            .finish_building_programmable_transaction_select_all_gas(
                 pt_builder,
                 signer_address.clone(),
-                None,
-                gas_budget
+                gas_budget * 2
             )
-            .await?
-        } else {
-            sui_client
+            .await
+    } else {
+        sui_client
             .transaction_builder()
-            .finish_building_programmable_transaction_select_all_gas(
+            .finish_building_programmable_transaction(
                 pt_builder,
                 signer_address.clone(),
-                gas_budget * 2
+                None,
+                gas_budget
             )
-            .await?
-        };
+            .await
+    }
+}
 
-        let signature = keystore.sign_secure(
-            &signer_address,
-            &transaction,
-            Intent::sui_transaction()
-        )?;
+async fn sign_and_execute_transaction(
+    sui_client: &SuiClient,
+    transaction: TransactionData,
+    signer_address: &SuiAddress,
+    keystore: &Keystore
+) -> Result<SuiTransactionBlockResponse, anyhow::Error> {
+    let signature = keystore.sign_secure(
+        &signer_address,
+        &transaction,
+        Intent::sui_transaction()
+    )?;
+
+    let result = sui_client
+        .quorum_driver_api()
+        .execute_transaction_block(
+            Transaction::from_data(
+                transaction,
+                Intent::sui_transaction(),
+                vec![signature]
+            ),
+            SuiTransactionBlockResponseOptions::full_content(),
+            Some(ExecuteTransactionRequestType::WaitForLocalExecution),
+        ).await?;
+
+    Ok(result)
+}
 
-        let result = sui_client
-            .quorum_driver_api()
-            .execute_transaction_block(
-                Transaction::from_data(
-                    transaction,
-                    Intent::sui_transaction(),
-                    vec![signature]
-                ),
-                SuiTransactionBlockResponseOptions::full_content(),
-                Some(ExecuteTransactionRequestType::WaitForLocalExecution),
-            ).await?;
-
-        // println!("RESULT: {:#?}", result);
-            
-        // Set amount_in for next leg
-        if let Some(effects) = result.effects {
-            match effects.into_status() {
-                SuiExecutionStatus::Success => {
-                    if let Some(balance_changes) = result.balance_changes {
-                        let coin_type_to_balance_change_amount = balance_changes
-                            .into_iter()
-                            .map(|balance_change| {
-                                (balance_change.coin_type, balance_change.amount)
-                            })
-                            .collect::<HashMap<TypeTag, i128>>();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_amount_out() {
+        assert_eq!(min_amount_out(1_000_000, 0), 1_000_000);
+        assert_eq!(min_amount_out(1_000_000, 50), 995_000);
+        assert_eq!(min_amount_out(1_000_000, 10_000), 0);
+        // Tolerances past 100% are clamped
+        assert_eq!(min_amount_out(1_000_000, 20_000), 0);
+        // Rounds down
+        assert_eq!(min_amount_out(999, 1), 998);
+    }
 
-                        let dest_coin_balance_change_amount = coin_type_to_balance_change_amount
-                            .get(dest_coin_type)
-                            .context(format!("Balance change for orig_coin {} not available", dest_coin_type))?;
+    #[test]
+    fn test_maximize_profit() {
+        // profit = 1_000_000 * x / (x + 1_000) - x / 2
+        // peaks where (x + 1_000)^2 = 2_000_000_000
+        let (amount_in, amount_out, profit) = maximize_profit(|amount_in| {
+            Ok(amount_in + (amount_in * 1_000_000) / (amount_in + 1_000) - amount_in / 2)
+        }, 0, u64::MAX as u128)
+        .unwrap();
+
+        assert!((43_700..=43_750).contains(&amount_in), "amount_in: {}", amount_in);
+        assert_eq!(profit, I256::from(amount_out) - I256::from(amount_in));
+        assert!(profit > I256::from(934_000), "profit: {}", profit);
+    }
 
-                        // DESTINATION COIN
-                        if *dest_coin_balance_change_amount > 0 {
-                            amount_in = *dest_coin_balance_change_amount as u128;
-                        }
+    #[test]
+    fn test_maximize_profit_bracketed() {
+        let (amount_in, _, _) = maximize_profit(|amount_in| {
+            Ok(amount_in + (amount_in * 1_000_000) / (amount_in + 1_000) - amount_in / 2)
+        }, 10_000, 100_000)
+        .unwrap();
 
-                        // let balance_change_a = balance_changes
-                        //     .get(0)
-                        //     .context("Balance change 0 missing.")?;
-    
-                        // let balance_change_b = balance_changes
-                        //     .get(1)
-                        //     .context("Balance change 1 missing")?;
-    
-                        // if (&balance_change_a.coin_type, &balance_change_b.coin_type) == (leg.market.coin_x(), leg.market.coin_y()) {
-                        //     if leg.x_to_y {
-                        //         amount_in = balance_change_b.amount as u128
-                        //     } else {
-                        //         amount_in = balance_change_a.amount as u128
-                        //     }
-                        // } else if (&balance_change_b.coin_type, &balance_change_a.coin_type) == (leg.market.coin_x(), leg.market.coin_y()){
-                        //     if leg.x_to_y {
-                        //         amount_in = balance_change_a.amount as u128
-                        //     } else {
-                        //         amount_in = balance_change_b.amount as u128
-                        //     }
-                        // } else {
-                        //     return Err(anyhow!("Balance change coin types do not match leg's coin types."));
-                        // }
-
-                    } else {
-                        return Err(anyhow!("result.balance_changes missing."))
-                    }
-                },
-                SuiExecutionStatus::Failure { error } => return Err(anyhow!(error)),
-            };
-    
+        assert!((43_700..=43_750).contains(&amount_in), "amount_in: {}", amount_in);
+    }
+
+    fn curve(reserve_in: f64, reserve_out: f64, fee_multiplier: f64) -> ConstantProductCurve {
+        ConstantProductCurve {
+            reserve_in,
+            reserve_out,
+            fee_multiplier,
+            exact: true,
         }
     }
 
-    Ok(())
-}
\ No newline at end of file
+    fn curves_profit(curves: &[ConstantProductCurve], amount_in: f64) -> f64 {
+        let amount_out = curves
+            .iter()
+            .fold(amount_in, |amount_in, curve| {
+                let amount_in_after_fee = amount_in * curve.fee_multiplier;
+                curve.reserve_out * amount_in_after_fee / (curve.reserve_in + amount_in_after_fee)
+            });
+
+        amount_out - amount_in
+    }
+
+    #[test]
+    fn test_constant_product_amount_in() {
+        let curves = [
+            curve(1_000_000_000_000.0, 2_000_000_000_000.0, 0.997),
+            curve(3_000_000_000_000.0, 1_000_000_000_000.0, 0.997),
+            curve(500_000_000_000.0, 900_000_000_000.0, 0.9975),
+        ];
+
+        let amount_in = constant_product_amount_in(&curves);
+        let profit = curves_profit(&curves, amount_in);
+
+        assert!(amount_in > 0.0);
+        assert!(profit > 0.0);
+        assert!(profit >= curves_profit(&curves, amount_in * 0.99));
+        assert!(profit >= curves_profit(&curves, amount_in * 1.01));
+    }
+
+    #[test]
+    fn test_constant_product_amount_in_unprofitable() {
+        let curves = [
+            curve(1_000_000_000_000.0, 1_000_000_000_000.0, 0.997),
+            curve(1_000_000_000_000.0, 1_000_000_000_000.0, 0.997),
+        ];
+
+        assert_eq!(constant_product_amount_in(&curves), 0.0);
+    }
+
+    #[test]
+    fn test_maximize_profit_unprofitable() {
+        let (amount_in, _, profit) = maximize_profit(|amount_in| {
+            Ok(amount_in / 2)
+        }, 0, u64::MAX as u128)
+        .unwrap();
+
+        assert!(amount_in <= 1, "amount_in: {}", amount_in);
+        assert_eq!(profit, I256::ZERO - I256::from(amount_in - amount_in / 2));
+    }
+}
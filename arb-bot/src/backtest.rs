@@ -0,0 +1,322 @@
+use anyhow::Context;
+
+use ethnum::I256;
+
+use move_core_types::language_storage::TypeTag;
+
+use std::collections::{HashMap, HashSet};
+
+use sui_sdk::rpc_types::SuiEvent;
+use sui_sdk::types::base_types::ObjectID;
+
+use crate::arbitrage;
+use crate::config::{CycleDetector, SourceCoin};
+use crate::cycles_for_pool;
+use crate::gas::GasEstimator;
+use crate::market_graph::MarketGraph;
+use crate::markets::{pool_id_from_event, EventUpdate, Exchange};
+use crate::simulation::SimulationContext;
+
+// How opportunities are searched for and paid for during a replay
+#[derive(Debug, Clone)]
+pub struct BacktestParams {
+    pub source_coins: Vec<SourceCoin>,
+    pub profit_coin: TypeTag,
+    pub cycle_detector: CycleDetector,
+    // Keep these pools in sync but don't search on their events
+    pub skip_event_pools: HashSet<ObjectID>,
+    // Price gas as if every leg of a cycle were one programmable transaction
+    pub atomic: bool,
+    // Only take routes that open with a flash swap. They aren't capped by the balance.
+    pub flash: bool,
+}
+
+// Replays events through the market graph and books what we would have traded.
+// Booked trades are applied to the graph so later events see the pools as we left them.
+pub struct Backtest {
+    pub market_graph: MarketGraph,
+    params: BacktestParams,
+    gas_estimator: GasEstimator,
+    // There's no network to refetch a pool that falls out of sync.
+    // It stays out of the search for the rest of the replay.
+    synced_pool_ids: HashSet<ObjectID>,
+    pub source_coin_to_balance: HashMap<TypeTag, u128>,
+    pub source_coin_to_pnl: HashMap<TypeTag, I256>,
+    // Net profit of every trade in the profit coin
+    pub pnl_value: I256,
+    pub num_events: usize,
+    pub num_opportunities: usize,
+    pub num_out_of_sync: usize,
+}
+
+impl Backtest {
+    // Starting balances are in the order of params.source_coins
+    pub fn new(
+        market_graph: MarketGraph,
+        params: BacktestParams,
+        gas_estimator: GasEstimator,
+        starting_balances: &[u128]
+    ) -> Self {
+        let synced_pool_ids = market_graph
+            .pool_id_to_market
+            .iter()
+            .filter(|(_, market_info)| {
+                market_info.market.market_record().pool_state.is_some()
+            })
+            .map(|(pool_id, _)| {
+                pool_id.clone()
+            })
+            .collect::<HashSet<ObjectID>>();
+
+        let source_coin_to_balance = params
+            .source_coins
+            .iter()
+            .zip(starting_balances.iter())
+            .map(|(source_coin, starting_balance)| {
+                (source_coin.coin_type.clone(), *starting_balance)
+            })
+            .collect::<HashMap<TypeTag, u128>>();
+
+        Backtest {
+            market_graph,
+            params,
+            gas_estimator,
+            synced_pool_ids,
+            source_coin_to_balance,
+            source_coin_to_pnl: HashMap::new(),
+            pnl_value: I256::ZERO,
+            num_events: 0,
+            num_opportunities: 0,
+            num_out_of_sync: 0,
+        }
+    }
+
+    pub fn replay_event(&mut self, exchanges: &[Box<dyn Exchange>], event: &SuiEvent) -> Result<(), anyhow::Error> {
+        self.num_events += 1;
+
+        let pool_id = if let Some(pool_id) = pool_id_from_event(exchanges, event)? {
+            pool_id
+        } else {
+            return Ok(());
+        };
+
+        // Pools we filtered out of the graph
+        if !self.market_graph.pool_id_to_coin_pair.contains_key(&pool_id) {
+            return Ok(());
+        }
+
+        if !self.synced_pool_ids.contains(&pool_id) {
+            return Ok(());
+        }
+
+        match self.market_graph.update_market_with_event(&pool_id, event) {
+            Ok(EventUpdate::Applied) | Ok(EventUpdate::Ignored) => {},
+            Ok(EventUpdate::Inconsistent) | Err(_) => {
+                println!("Pool {} out of sync with event {}", pool_id, event.type_);
+                self.market_graph.update_market_with_pool_state(&pool_id, None)?;
+                self.synced_pool_ids.remove(&pool_id);
+                self.num_out_of_sync += 1;
+                return Ok(());
+            }
+        }
+
+        if self.params.skip_event_pools.contains(&pool_id) {
+            return Ok(());
+        }
+
+        let cycles = if let Some(cycles) = cycles_for_pool(&self.market_graph, &pool_id, &self.params.source_coins, self.params.cycle_detector) {
+            cycles
+        } else {
+            return Ok(());
+        };
+
+        let source_coin_to_allowance = self
+            .params
+            .source_coins
+            .iter()
+            .map(|source_coin| {
+                let balance = self.source_coin_to_balance[&source_coin.coin_type];
+
+                let allowance = if self.params.flash {
+                    u128::MAX
+                } else {
+                    (balance * source_coin.allowance_bps as u128) / 10_000
+                };
+
+                (source_coin.coin_type.clone(), allowance)
+            })
+            .collect::<HashMap<TypeTag, u128>>();
+
+        let optimized_results = arbitrage::select_opportunities(
+            &cycles,
+            &self.market_graph,
+            &self.params.profit_coin,
+            &source_coin_to_allowance,
+            &self.gas_estimator,
+            self.params.atomic,
+            self.params.flash
+        )?;
+
+        // Every trade is applied in the order it would go out, like they're predicted
+        let mut simulation_context = SimulationContext::new();
+
+        for optimized_result in optimized_results {
+            let source_coin = optimized_result.source_coin;
+
+            let balance = self
+                .source_coin_to_balance
+                .get_mut(source_coin)
+                .context(format!("No balance for {}", source_coin))?;
+
+            self.num_opportunities += 1;
+
+            let net_profit = optimized_result.net_profit();
+            *self.source_coin_to_pnl.entry(source_coin.clone()).or_insert(I256::ZERO) += net_profit;
+            self.pnl_value += optimized_result.net_profit_value;
+            *balance = (I256::from(*balance) + net_profit).as_u128();
+
+            println!("+-----------------------------------------------------");
+            println!("| TIMESTAMP: {}", event.timestamp_ms.unwrap_or_default());
+            println!("| TRIGGER POOL: {}", pool_id);
+            println!("| AMOUNT IN: {} {}", optimized_result.amount_in, source_coin);
+            println!("| AMOUNT OUT: {} {}", optimized_result.amount_out, source_coin);
+            println!("| RAW PROFIT: {} {}", optimized_result.profit, source_coin);
+            println!("| EXPECTED GAS: {} {}", optimized_result.gas_cost, source_coin);
+            println!("| NET PROFIT: {} {}", net_profit, source_coin);
+            println!("| NET PROFIT VALUE: {} {}", optimized_result.net_profit_value, self.params.profit_coin);
+            for leg in optimized_result.route.iter().flatten() {
+                println!("|    [POOL: {}, X_TO_Y: {}]", leg.market.pool_id(), leg.x_to_y);
+            }
+            println!("+-----------------------------------------------------");
+
+            simulation_context.apply_route(&optimized_result.route, optimized_result.amount_in)?;
+        }
+
+        self.market_graph.apply_simulation(simulation_context)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use std::str::FromStr;
+
+    use move_core_types::identifier::Identifier;
+    use move_core_types::language_storage::StructTag;
+
+    use sui_sdk::SUI_COIN_TYPE;
+    use sui_sdk::types::base_types::{SuiAddress, TransactionDigest};
+    use sui_sdk::types::event::EventID;
+
+    use pool_state::PoolState;
+
+    use crate::kriyadex::KriyaDex;
+    use crate::markets::MarketRecord;
+
+    const KRIYA_PACKAGE: &str = "0xa0eba10b173538c8fecca1dff298e488402cc9ff374f8a12ca7758eebe830b66";
+
+    fn market_record(pool_id: &str, coin_x: &TypeTag, coin_y: &TypeTag, reserve_x: u64, reserve_y: u64) -> MarketRecord {
+        MarketRecord {
+            package_id: ObjectID::from_str(KRIYA_PACKAGE).unwrap(),
+            pool_id: ObjectID::from_str(pool_id).unwrap(),
+            coin_x: coin_x.clone(),
+            coin_y: coin_y.clone(),
+            extra_type_args: vec![],
+            pool_state: Some(
+                PoolState::ConstantProduct {
+                    reserve_x,
+                    reserve_y,
+                    protocol_fee: 0,
+                    lp_fee: 3_000,
+                    unlocked: true,
+                }
+            ),
+        }
+    }
+
+    // Leaves the pool as it was
+    fn empty_liquidity_event(pool_id: &str, event_seq: u64) -> SuiEvent {
+        let package_id = ObjectID::from_str(KRIYA_PACKAGE).unwrap();
+
+        SuiEvent {
+            id: EventID {
+                tx_digest: TransactionDigest::random(),
+                event_seq,
+            },
+            package_id,
+            transaction_module: Identifier::new("spot_dex").unwrap(),
+            sender: SuiAddress::ZERO,
+            type_: StructTag::from_str(&format!("{}::spot_dex::LiquidityAddedEvent", package_id)).unwrap(),
+            parsed_json: json!({
+                "pool_id": pool_id,
+                "amount_x": "0",
+                "amount_y": "0",
+            }),
+            bcs: vec![],
+            timestamp_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_mispricing_is_booked_once() {
+        let sui = TypeTag::from_str(SUI_COIN_TYPE).unwrap();
+        let coin_a = TypeTag::from_str("0x1111::a::A").unwrap();
+        let coin_b = TypeTag::from_str("0x2222::b::B").unwrap();
+
+        let (pool_sui_a, pool_a_b, pool_b_sui) = ("0x101", "0x102", "0x103");
+
+        // SUI buys 10% more SUI around the triangle than it started with
+        let exchange = KriyaDex::new(ObjectID::from_str(KRIYA_PACKAGE).unwrap());
+        let markets = [
+            market_record(pool_sui_a, &sui, &coin_a, 1_000_000_000_000, 1_000_000_000_000),
+            market_record(pool_a_b, &coin_a, &coin_b, 1_000_000_000_000, 1_000_000_000_000),
+            market_record(pool_b_sui, &coin_b, &sui, 1_000_000_000_000, 1_100_000_000_000),
+        ]
+        .iter()
+        .map(|market_record| {
+            exchange.market_from_record(market_record).unwrap()
+        })
+        .collect::<Vec<_>>();
+
+        let mut market_graph = MarketGraph::new(markets).unwrap();
+        market_graph.add_cycles(&sui, 2).unwrap();
+
+        let mut gas_estimator = GasEstimator::new();
+        gas_estimator.set_reference_gas_price(750);
+
+        let params = BacktestParams {
+            source_coins: vec![
+                SourceCoin {
+                    coin_type: sui.clone(),
+                    allowance_bps: 10_000,
+                }
+            ],
+            profit_coin: sui.clone(),
+            cycle_detector: CycleDetector::SimplePaths,
+            skip_event_pools: HashSet::new(),
+            atomic: true,
+            flash: false,
+        };
+
+        let exchanges: Vec<Box<dyn Exchange>> = vec![Box::new(exchange)];
+        let mut backtest = Backtest::new(market_graph, params, gas_estimator, &[u64::MAX as u128]);
+
+        backtest.replay_event(&exchanges, &empty_liquidity_event(pool_sui_a, 0)).unwrap();
+
+        assert_eq!(backtest.num_opportunities, 1);
+        let pnl = backtest.source_coin_to_pnl[&sui];
+        assert!(pnl > I256::ZERO, "pnl: {}", pnl);
+
+        // Same mispricing on paper but our own trade already closed it
+        backtest.replay_event(&exchanges, &empty_liquidity_event(pool_sui_a, 1)).unwrap();
+
+        assert_eq!(backtest.num_events, 2);
+        assert_eq!(backtest.num_opportunities, 1);
+        assert_eq!(backtest.source_coin_to_pnl[&sui], pnl);
+    }
+}
@@ -0,0 +1,144 @@
+use arb_bot::*;
+use arb_bot::recording::{self, Record};
+
+use anyhow::{anyhow, Context};
+
+use clap::Parser;
+
+use ethnum::I256;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use sui_sdk::types::base_types::ObjectID;
+
+#[derive(Parser)]
+#[clap(
+    name = "backtest",
+    about = "Replays a recording through the market graph and reports what we would have traded",
+    rename_all = "kebab-case"
+)]
+struct BacktestOpts {
+    #[clap(long, default_value = "config.toml")]
+    config_path: PathBuf,
+    #[clap(long)]
+    recording_path: PathBuf,
+    // Simulated balance of each source coin, in the order they're configured.
+    // Repeat once per source coin.
+    #[clap(long, required = true)]
+    starting_balance: Vec<u128>,
+    // Gas price in MIST to price transactions at
+    #[clap(long, default_value = "750")]
+    reference_gas_price: u64,
+    // Price gas as if every leg of a cycle were one programmable transaction
+    #[clap(long)]
+    atomic: bool,
+    // Only take routes that open with a flash swap. They aren't capped by the balance.
+    #[clap(long)]
+    flash: bool,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let backtest_opts = BacktestOpts::parse();
+
+    let config = config::Config::from_file(&backtest_opts.config_path)?;
+
+    let source_coins = config.source_coins()?;
+    let profit_coin = config.profit_coin()?;
+
+    if backtest_opts.starting_balance.len() != source_coins.len() {
+        return Err(
+            anyhow!(
+                "Expected a starting balance for each of the {} source coins, got {}",
+                source_coins.len(),
+                backtest_opts.starting_balance.len()
+            )
+        );
+    }
+
+    let exchanges = config.exchanges();
+
+    let package_id_to_exchange = exchanges
+        .iter()
+        .map(|exchange| {
+            (exchange.package_id().clone(), exchange)
+        })
+        .collect::<HashMap<ObjectID, _>>();
+
+    // Every market comes before the first event so we can build the graph up front
+    let mut markets = vec![];
+    for record in recording::read_records(&backtest_opts.recording_path)? {
+        match record? {
+            Record::Market(market_record) => {
+                let exchange = package_id_to_exchange
+                    .get(&market_record.package_id)
+                    .context(format!("No exchange configured for package {}", market_record.package_id))?;
+
+                markets.push(exchange.market_from_record(&market_record)?);
+            },
+            Record::Event(_) => {
+                break;
+            }
+        }
+    }
+
+    let markets = config.filter_markets(markets);
+
+    println!("markets.len(): {}", markets.len());
+
+    let mut market_graph = MarketGraph::new(markets)?;
+
+    if config.search.cycle_detector == config::CycleDetector::SimplePaths {
+        for source_coin in source_coins.iter() {
+            market_graph.add_cycles(
+                &source_coin.coin_type,
+                config.thresholds.max_intermediate_nodes
+            )?;
+        }
+    }
+
+    let mut gas_estimator = gas::GasEstimator::new();
+    gas_estimator.set_reference_gas_price(backtest_opts.reference_gas_price);
+
+    let params = backtest::BacktestParams {
+        source_coins: source_coins.clone(),
+        profit_coin: profit_coin.clone(),
+        cycle_detector: config.search.cycle_detector,
+        skip_event_pools: config.skip_event_pool_ids(),
+        atomic: backtest_opts.atomic,
+        flash: backtest_opts.flash,
+    };
+
+    let mut backtest = backtest::Backtest::new(
+        market_graph,
+        params,
+        gas_estimator,
+        &backtest_opts.starting_balance
+    );
+
+    for record in recording::read_records(&backtest_opts.recording_path)? {
+        let event = match record? {
+            Record::Event(event) => event,
+            Record::Market(_) => continue,
+        };
+
+        backtest.replay_event(&exchanges, &event)?;
+    }
+
+    println!("EVENTS REPLAYED: {}", backtest.num_events);
+    println!("OPPORTUNITIES: {}", backtest.num_opportunities);
+    println!("POOLS OUT OF SYNC: {}", backtest.num_out_of_sync);
+    for source_coin in source_coins.iter() {
+        let pnl = backtest
+            .source_coin_to_pnl
+            .get(&source_coin.coin_type)
+            .copied()
+            .unwrap_or(I256::ZERO);
+
+        println!("SIMULATED PNL: {} {}", pnl, source_coin.coin_type);
+        println!("END BALANCE: {} {}", backtest.source_coin_to_balance[&source_coin.coin_type], source_coin.coin_type);
+    }
+    println!("SIMULATED PNL VALUE: {} {}", backtest.pnl_value, profit_coin);
+
+    Ok(())
+}
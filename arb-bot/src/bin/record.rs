@@ -0,0 +1,134 @@
+use custom_sui_sdk::SuiClientBuilder;
+
+use arb_bot::*;
+use arb_bot::recording::{Record, RecordWriter};
+
+use anyhow::Context;
+use clap::Parser;
+
+use futures::{future, StreamExt};
+
+use custom_sui_sdk::rate_limiter::RequestLimiter;
+use governor::Quota;
+use nonzero_ext::*;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use sui_sdk::rpc_types::EventFilter;
+use sui_sdk::types::base_types::ObjectID;
+
+#[derive(Parser)]
+#[clap(
+    name = "record",
+    about = "Records pool state and events to replay with backtest",
+    rename_all = "kebab-case"
+)]
+struct RecordOpts {
+    #[clap(long = "wss-url", default_values = &["wss://sui-mainnet.blastapi.io:443/338f3a96-cd39-41f8-88d9-4faefe2eee21", "wss://fullnode.mainnet.sui.io:443"])]
+    wss_urls: Vec<String>,
+    #[clap(long = "rpc-url", default_values = &["https://sui-mainnet.blastapi.io:443/338f3a96-cd39-41f8-88d9-4faefe2eee21", "https://fullnode.mainnet.sui.io:443"])]
+    rpc_urls: Vec<String>,
+    #[clap(long, default_value = "config.toml")]
+    config_path: PathBuf,
+    #[clap(long)]
+    recording_path: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let record_opts = RecordOpts::parse();
+
+    let config = config::Config::from_file(&record_opts.config_path)?;
+
+    let mut exchanges = config.exchanges();
+
+    // Never executes anything so the priority lane gets next to nothing
+    let rate_limiter = Arc::new(
+        RequestLimiter::new(
+            Quota::per_second(nonzero!(44u32)),
+            Quota::per_second(nonzero!(1u32))
+        )
+    );
+
+    let (rpc_url, fallback_rpc_urls) = record_opts.rpc_urls
+        .split_first()
+        .context("At least one --rpc-url is needed")?;
+
+    let sui_client = SuiClientBuilder::default()
+        .ws_urls(&record_opts.wss_urls)
+        .http_urls(fallback_rpc_urls)
+        .build(rpc_url, &rate_limiter)
+        .await?;
+
+    let mut markets = vec![];
+    for exchange in exchanges.iter_mut() {
+        markets.extend(exchange.get_all_markets(&sui_client).await?);
+    }
+
+    let mut markets = config.filter_markets(markets);
+
+    // Subscribe before fetching state so there's no gap between the two.
+    // Events that land while we fetch may already be in the fetched state.
+    // Replaying those shows up as the pool going out of sync.
+    let mut events = sui_client
+        .event_api()
+        .subscribe_event_resilient(
+            EventFilter::Any(
+                exchanges
+                    .iter()
+                    .flat_map(|exchange| {
+                        exchange.event_filters()
+                    })
+                    .collect()
+            ),
+            None
+        )
+        .await?;
+
+    let pool_ids = markets
+        .iter()
+        .map(|market| {
+            market.pool_id().clone()
+        })
+        .collect::<Vec<ObjectID>>();
+
+    let pool_id_to_object_response = sui_sdk_utils::get_object_id_to_object_response(
+        &sui_client,
+        &pool_ids
+    ).await?;
+
+    future::try_join_all(
+        markets
+            .iter_mut()
+            .filter_map(|market| {
+                let object_response = pool_id_to_object_response.get(market.pool_id())?;
+
+                Some(market.update_with_object_response(&sui_client, object_response))
+            })
+    ).await?;
+
+    let mut record_writer = RecordWriter::create(&record_opts.recording_path)?;
+
+    for market in markets.iter() {
+        record_writer.write(&Record::Market(market.market_record()))?;
+    }
+    record_writer.flush()?;
+
+    println!("Recorded {} markets", markets.len());
+
+    let mut num_events = 0;
+
+    while let Some(event) = events.next().await {
+        record_writer.write(&Record::Event(event))?;
+        // Flush every event so a crash doesn't lose the tail of the recording
+        record_writer.flush()?;
+
+        num_events += 1;
+        if num_events % 100 == 0 {
+            println!("Recorded {} events", num_events);
+        }
+    }
+
+    Ok(())
+}
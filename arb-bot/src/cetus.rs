@@ -13,7 +13,7 @@ use custom_sui_sdk::{
         QueryEventsRequest,
         GetDynamicFieldsRequest
     },
-    transaction_builder::{TransactionBuilder, ProgrammableObjectArg},
+    transaction_builder::{TransactionBuilder, ProgrammableObjectArg, programmable_nested_result},
     programmable_transaction_sui_json::ProgrammableTransactionArg
 };
 
@@ -25,6 +25,7 @@ use sui_sdk::rpc_types::{EventFilter, SuiEvent, SuiMoveValue, SuiObjectDataOptio
  
 // use sui_sdk::json_types::SuiTypeTag;
 use sui_sdk::types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_sdk::types::transaction::Argument;
 
 use move_core_types::language_storage::{StructTag, TypeTag};
 use move_core_types::value::MoveValue;
@@ -32,11 +33,14 @@ use move_core_types::value::MoveValue;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::format;
 
-use crate::markets::{Exchange, Market};
+use crate::markets::{Exchange, Market, EventUpdate, MarketRecord, ConstantProductCurve, try_parse_pool_id_from_event};
 use crate::sui_sdk_utils::{self, sui_move_value};
 // use crate::{cetus_pool, cetus};
+use crate::arbitrage;
 use crate::fast_v3_pool;
-use crate::sui_json_utils::move_value_to_json;
+use crate::sui_json_utils::{move_value_to_json, parsed_json};
+
+use pool_state::PoolState;
 
 // const GLOBAL: &str = "0xdaa46292632c3c4d8f31f23ea0f9b36a28ff3677e9684980e4438403a67a3d8f";
 // const POOLS: &str = "0xf699e7f2276f5c9a75944b37a0c5b5d9ddfd2471bf6242483b03ab2887d198d0";
@@ -47,23 +51,29 @@ pub struct Cetus {
     package_id: ObjectID,
     periphery_id: ObjectID,
     global_config_id: ObjectID,
+    // How far past the predicted price a swap may push the pool, in basis points of price
+    price_limit_tolerance_bps: u64,
     event_struct_tag_to_pool_field: HashMap<StructTag, String>
 }
 
 impl Cetus {
-    pub fn new(package_id: ObjectID, periphery_id: ObjectID, global_config_id: ObjectID) -> Self {
+    pub fn new(package_id: ObjectID, periphery_id: ObjectID, global_config_id: ObjectID, price_limit_tolerance_bps: u64) -> Self {
         let mut event_struct_tag_to_pool_field = HashMap::new();
-        event_struct_tag_to_pool_field.insert(
-            StructTag::from_str(
-                &format!("{}::pool::SwapEvent", package_id)
-            ).expect("Cetus: failed to create event struct tag"),
-            "pool".to_string()
-        );
+        // Everything that changes the state of a pool
+        for event_name in ["SwapEvent", "AddLiquidityEvent", "RemoveLiquidityEvent", "UpdateFeeRateEvent"] {
+            event_struct_tag_to_pool_field.insert(
+                StructTag::from_str(
+                    &format!("{}::pool::{}", package_id, event_name)
+                ).expect("Cetus: failed to create event struct tag"),
+                "pool".to_string()
+            );
+        }
         
         Cetus {
             package_id,
             periphery_id,
             global_config_id,
+            price_limit_tolerance_bps,
             event_struct_tag_to_pool_field
         }
     }
@@ -111,9 +121,7 @@ impl Cetus {
             .pages(
                 QueryEventsRequest {
                     query: EventFilter::MoveEventType(
-                        StructTag::from_str(
-                            &format!("{}::factory::CreatePoolEvent", self.package_id)
-                        )?
+                        self.pool_created_event_struct_tag()
                     ),
                     cursor: None,
                     limit: None,
@@ -129,43 +137,66 @@ impl Cetus {
         let markets = pool_created_events
             .iter()
             .map(|pool_created_event| {
-                let parsed_json = &pool_created_event.parsed_json;
-                if let (
-                    Value::String(coin_x_value), 
-                    Value::String(coin_y_value), 
-                    Value::String(pool_id_value)
-                ) = 
-                    (
-                        parsed_json.get("coin_type_a").context("Failed to get coin_type_a for a CetusMarket")?,
-                        parsed_json.get("coin_type_b").context("Failed to get coin_type_b for a CetusMarket")?,
-                        parsed_json.get("pool_id").context(format!("Failed to get pool_id for a CetusMarket: {:#?}", parsed_json))?
-                    ) {
-                        let coin_x = TypeTag::from_str(&format!("0x{}", coin_x_value))?;
-                        let coin_y = TypeTag::from_str(&format!("0x{}", coin_y_value))?;
-                        let pool_id = ObjectID::from_str(&format!("0x{}", pool_id_value))?;
-
-                        Ok(
-                            Box::new(
-                                CetusMarket {
-                                    parent_exchange: self.clone(),
-                                    coin_x,
-                                    coin_y,
-                                    pool_id,
-                                    coin_x_sqrt_price: None,
-                                    coin_y_sqrt_price: None,
-                                    computing_pool: None
-                                }
-                            ) as Box<dyn Market>
-                        )
-                    } else {
-                        Err(anyhow!("Failed to match pattern."))
-                    }
+                self.pool_created_event_to_market(pool_created_event)
             })
             .collect::<Result<Vec<Box<dyn Market>> ,anyhow::Error>>()?;
 
         Ok(markets)
     }
 
+    fn pool_created_event_struct_tag(&self) -> StructTag {
+        StructTag::from_str(
+            &format!("{}::factory::CreatePoolEvent", self.package_id)
+        ).expect("Cetus: failed to create pool created event struct tag")
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        vec![EventFilter::MoveEventType(self.pool_created_event_struct_tag())]
+    }
+
+    // Everything we need is in the event
+    fn pool_created_event_to_market(&self, pool_created_event: &SuiEvent) -> Result<Box<dyn Market>, anyhow::Error> {
+        let parsed_json = &pool_created_event.parsed_json;
+        if let (
+            Value::String(coin_x_value), 
+            Value::String(coin_y_value), 
+            Value::String(pool_id_value)
+        ) = 
+            (
+                parsed_json.get("coin_type_a").context("Failed to get coin_type_a for a CetusMarket")?,
+                parsed_json.get("coin_type_b").context("Failed to get coin_type_b for a CetusMarket")?,
+                parsed_json.get("pool_id").context(format!("Failed to get pool_id for a CetusMarket: {:#?}", parsed_json))?
+            ) {
+                let coin_x = TypeTag::from_str(&format!("0x{}", coin_x_value))?;
+                let coin_y = TypeTag::from_str(&format!("0x{}", coin_y_value))?;
+                let pool_id = ObjectID::from_str(&format!("0x{}", pool_id_value))?;
+
+                Ok(
+                    Box::new(
+                        CetusMarket {
+                            parent_exchange: self.clone(),
+                            coin_x,
+                            coin_y,
+                            pool_id,
+                            coin_x_sqrt_price: None,
+                            coin_y_sqrt_price: None,
+                            computing_pool: None
+                        }
+                    ) as Box<dyn Market>
+                )
+            } else {
+                Err(anyhow!("Failed to match pattern."))
+            }
+    }
+
+    fn market_from_pool_created_event_(&self, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        if event.type_ == self.pool_created_event_struct_tag() {
+            Ok(Some(self.pool_created_event_to_market(event)?))
+        } else {
+            Ok(None)
+        }
+    }
+
     async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
         let pool_ids = markets
             .iter()
@@ -370,6 +401,22 @@ impl Cetus {
 
     }
 
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        let mut market = CetusMarket {
+            parent_exchange: self.clone(),
+            coin_x: market_record.coin_x.clone(),
+            coin_y: market_record.coin_y.clone(),
+            pool_id: market_record.pool_id.clone(),
+            coin_x_sqrt_price: None,
+            coin_y_sqrt_price: None,
+            computing_pool: None
+        };
+
+        market.update_with_pool_state(market_record.pool_state.as_ref())?;
+
+        Ok(Box::new(market))
+    }
 }
 
 #[async_trait]
@@ -391,6 +438,10 @@ impl Exchange for Cetus {
         self.event_struct_tag_to_pool_field()
     }
 
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error> {
+        try_parse_pool_id_from_event(self.event_struct_tag_to_pool_field(), event)
+    }
+
     // Cetus has us query for events
     async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
         self.get_all_markets_(sui_client).await
@@ -400,6 +451,17 @@ impl Exchange for Cetus {
         self.get_pool_id_to_object_response(sui_client, markets).await
     }
 
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        self.market_from_record(market_record)
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        self.pool_created_event_filters()
+    }
+
+    async fn market_from_pool_created_event(&mut self, _sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        self.market_from_pool_created_event_(event)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -462,7 +524,7 @@ impl CetusMarket {
         Ok(())
     }
 
-    fn update_with_event(&mut self, event: &SuiEvent) -> Result<(), anyhow::Error> {
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
         let type_ = &event.type_;
         let event_parsed_json = &event.parsed_json;
         let computing_pool = self
@@ -487,127 +549,92 @@ impl CetusMarket {
                 &format!("{}::pool::UpdateFeeRateEvent", &self.parent_exchange.package_id)
             ).context("Cetus: failed to create event struct tag")?;
 
-        match type_ {
-            swap_event_type => {
-                let amount_in = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_in").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_out = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_out").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
-                let fee_amount = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("fee_amount").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent fee_amount is not Value::String."))
-                    }
-                )?;
-                let atob = *if let serde_json::Value::Bool(bool_inner) = event_parsed_json.get("atob").context("")? {
-                    bool_inner
-                } else {
-                    return Err(anyhow!("SwapEvent atob is not Value::Bool."))
-                };
+        if type_ == &swap_event_type {
+            let atob = parsed_json::get_bool(event_parsed_json, "atob")?;
+            let before_sqrt_price = parsed_json::get_u128(event_parsed_json, "before_sqrt_price")?;
+            let after_sqrt_price = parsed_json::get_u128(event_parsed_json, "after_sqrt_price")?;
 
-                let amount_specified = amount_in + fee_amount;
+            // We missed something if we weren't where the swap started
+            if computing_pool.sqrt_price != before_sqrt_price || !computing_pool.liquidity_sanity_check() {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-                let sqrt_price_limit = if atob {
-                    fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1
-                } else {
-                    fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1
-                };
+            // Walk the price to where the swap ended. Crosses the same ticks
+            // the swap did so liquidity ends up right as well.
+            computing_pool.apply_swap(
+                atob,
+                u64::MAX,
+                true,
+                after_sqrt_price
+            );
+
+            let coin_x_sqrt_price = U64F64::from_bits(computing_pool.sqrt_price);
+            self.coin_x_sqrt_price = Some(coin_x_sqrt_price);
+            self.coin_y_sqrt_price = Some(U64F64::from_num(1) / coin_x_sqrt_price);
+        } else if type_ == &add_liq_event_type {
+            let tick_lower = parsed_json::get_i32(event_parsed_json, "tick_lower")?;
+            let tick_upper = parsed_json::get_i32(event_parsed_json, "tick_upper")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity")?;
+
+            computing_pool.apply_add_liquidity(
+                tick_lower, 
+                tick_upper, 
+                liquidity_delta
+            );
+        } else if type_ == &remove_liq_event_type {
+            let tick_lower = parsed_json::get_i32(event_parsed_json, "tick_lower")?;
+            let tick_upper = parsed_json::get_i32(event_parsed_json, "tick_upper")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity")?;
+
+            if !computing_pool.ticks.contains_key(&tick_lower) || !computing_pool.ticks.contains_key(&tick_upper) {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-                computing_pool.apply_swap(
-                    atob,
-                    amount_specified, 
-                    true, 
-                    sqrt_price_limit
-                );
+            computing_pool.apply_remove_liquidity(
+                tick_lower, 
+                tick_upper, 
+                liquidity_delta
+            );
+        } else if type_ == &update_fee_rate_event_type {
+            let new_fee_rate = parsed_json::get_u64(event_parsed_json, "new_fee_rate")?;
 
-            },
-            add_liq_event_type => {
-                let tick_lower = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper_index is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity is not Value::String."))
-                    }
-                )?;
+            computing_pool.apply_update_fee(new_fee_rate);
+        } else {
+            return Ok(EventUpdate::Ignored);
+        }
 
-                computing_pool.apply_add_liquidity(
-                    tick_lower, 
-                    tick_upper, 
-                    liquidity_delta
-                );
-            },
-            remove_liq_event_type => {
-                let tick_lower_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity is not Value::String."))
-                    }
-                )?;
+        Ok(EventUpdate::Applied)
+    }
 
-                computing_pool.apply_remove_liquidity(
-                    tick_lower_index, 
-                    tick_upper_index, 
-                    liquidity_delta
-                );
-            },
-            update_fee_rate_event_type => {
-                let new_fee_rate = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("new_fee_rate").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent new_fee_rate is not Value::String."))
-                    }
-                )?;
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.computing_pool = pool_state
+            .map(|pool_state| {
+                fast_v3_pool::Pool::from_pool_state(self.pool_id.clone(), pool_state)
+            })
+            .transpose()?;
 
-                computing_pool.apply_update_fee(new_fee_rate);
-            },
-            _ => {
-                // do nothing
-            }
+        if let Some(computing_pool) = &self.computing_pool {
+            let coin_x_sqrt_price = U64F64::from_bits(computing_pool.sqrt_price);
+
+            self.coin_x_sqrt_price = Some(coin_x_sqrt_price);
+            self.coin_y_sqrt_price = Some(U64F64::from_num(1) / coin_x_sqrt_price);
+        } else {
+            self.coin_x_sqrt_price = None;
+            self.coin_y_sqrt_price = None;
         }
 
         Ok(())
+    }
 
+    fn market_record(&self) -> MarketRecord {
+        MarketRecord {
+            package_id: self.package_id().clone(),
+            pool_id: self.pool_id.clone(),
+            coin_x: self.coin_x.clone(),
+            coin_y: self.coin_y.clone(),
+            extra_type_args: vec![],
+            pool_state: self.computing_pool.as_ref().map(|cp| cp.to_pool_state()),
+        }
     }
 
     fn pool_id(&self) -> &ObjectID {
@@ -645,6 +672,46 @@ impl CetusMarket {
         (swap_state.amount_a as u128, swap_state.amount_b as u128)
     }
 
+    // Moves the pool as if the swap had landed. Returns the same as compute_swap_x_to_y.
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        // Nothing to move until the pool has synced
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_x_to_y(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            computing_pool.apply_swap(
+                true, 
+                amount_specified as u64, 
+                true, 
+                fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1,
+            );
+        }
+
+        amounts
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_y_to_x(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            computing_pool.apply_swap(
+                false, 
+                amount_specified as u64, 
+                true, 
+                fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1,
+            );
+        }
+
+        amounts
+    }
+
     fn viable(&self) -> bool {
         if let Some(cp) = &self.computing_pool {
             // println!("liquidity: {}", cp.liquidity);
@@ -658,143 +725,510 @@ impl CetusMarket {
         }
     }
 
+    // Only holds until the price crosses into the next tick
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        let cp = self.computing_pool.as_ref()?;
+
+        if cp.liquidity == 0 {
+            return None;
+        }
+
+        let (virtual_reserve_x, virtual_reserve_y) = cp.virtual_reserves();
+        let (reserve_in, reserve_out) = if x_to_y {
+            (virtual_reserve_x, virtual_reserve_y)
+        } else {
+            (virtual_reserve_y, virtual_reserve_x)
+        };
+
+        Some(
+            ConstantProductCurve {
+                reserve_in,
+                reserve_out,
+                fee_multiplier: cp.fee_multiplier(),
+                exact: false,
+            }
+        )
+    }
+
+    // The limit the swap is protected by. Past where we predict it leaves the
+    // price by the exchange's tolerance or, without a prediction, anywhere.
+    // A swap that reaches it stops there and brings out less than we predicted,
+    // so the hops after it would be built on an amount that never came out.
+    // min_amount_out_within_limit sizes the leg's min out to catch that.
+    fn sqrt_price_limit(&self, x_to_y: bool, amount_specified: u128, amount_specified_is_input: bool) -> u128 {
+        // Amounts past u64 can't be predicted so they get the widest limit
+        let amount_specified = u64::try_from(amount_specified).unwrap_or(0);
+
+        match &self.computing_pool {
+            Some(cp) if amount_specified > 0 => {
+                cp.sqrt_price_limit(
+                    x_to_y,
+                    amount_specified,
+                    amount_specified_is_input,
+                    self.parent_exchange.price_limit_tolerance_bps
+                )
+            },
+            _ => {
+                if x_to_y {
+                    fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1
+                } else {
+                    fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1
+                }
+            }
+        }
+    }
+
+    // The least a swap of amount_in brings out while staying within the price limit.
+    // Every price inside the limit is at most the tolerance worse than predicted.
+    fn min_amount_out_within_limit(&self, x_to_y: bool, amount_in: u128) -> u128 {
+        if self.computing_pool.is_none() || amount_in == 0 || u64::try_from(amount_in).is_err() {
+            return 0;
+        }
+
+        let predicted_amount_out = if x_to_y {
+            self.compute_swap_x_to_y(amount_in).1
+        } else {
+            self.compute_swap_y_to_x(amount_in).0
+        };
+
+        arbitrage::min_amount_out(predicted_amount_out, self.parent_exchange.price_limit_tolerance_bps)
+    }
+
+    // amount_in is what we predict orig_coin holds. It sets the price limit and the min out with it.
     async fn add_swap_to_programmable_trasaction(
         &self,
         transaction_builder: &TransactionBuilder,
         pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
-        // orig_coin_is_gas_coin: bool,
+        orig_coin: Argument, // the coin we swap in its entirety
         x_to_y: bool,
         amount_in: u128,
         amount_out: u128,
-    ) -> Result<(), anyhow::Error> {
-        // Very rough but lets do thisss
-        // We can't add to a result unless theres a function that exists..
+        recipient: SuiAddress
+    ) -> Result<Argument, anyhow::Error> {
+        let orig_coin_type = if x_to_y {
+            self.coin_x.clone()
+        } else {
+            self.coin_y.clone()
+        };
+
+        // Whatever is in the coin we were handed. We may not know it ahead of time.
+        let amount_specified = ProgrammableTransactionArg::Argument(
+            transaction_builder
+                .programmable_coin_value(
+                    pt_builder,
+                    orig_coin_type,
+                    orig_coin
+                ).await?
+        );
+
+        let (orig_coin_out, dest_coin_out) = self.add_router_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            orig_coin,
+            x_to_y,
+            true,
+            amount_specified,
+            self.sqrt_price_limit(x_to_y, amount_in, true)
+        ).await?;
+
+        // The swap stops at the price limit and hands back what it didn't swap.
+        // Whatever is left over is ours.
+        pt_builder.transfer_arg(recipient, orig_coin_out);
+
+        // Cetus' router has no min out so we enforce it on the output coin ourselves.
+        // A swap cut short by the limit comes out under it and aborts.
+        let min_amount_out = amount_out.max(self.min_amount_out_within_limit(x_to_y, amount_in));
+
+        if min_amount_out > 0 {
+            transaction_builder
+                .programmable_assert_min_value(
+                    pt_builder,
+                    dest_coin_out,
+                    min_amount_out as u64
+                ).await?;
+        }
+
+        Ok(dest_coin_out)
+    }
+
+    // Swaps for exactly amount_out. orig_coin has to cover whatever that costs.
+    // Returns the output coin and what's left of orig_coin.
+    async fn add_exact_output_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument,
+        x_to_y: bool,
+        amount_out: u128,
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        let amount_specified = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::U64(amount_out as u64)
+                )
+                .context("failed to convert MoveValue for amount to JSON")?
+            )?
+        );
+
+        let (orig_coin_out, dest_coin_out) = self.add_router_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            orig_coin,
+            x_to_y,
+            false,
+            amount_specified,
+            self.sqrt_price_limit(x_to_y, amount_out, false)
+        ).await?;
+
+        Ok((dest_coin_out, orig_coin_out))
+    }
+
+    // router::swap takes and returns both coins of the pair.
+    // The side we are not swapping in is filled with a zero coin.
+    // Returns (what's left of orig_coin, the output coin).
+    async fn add_router_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument,
+        x_to_y: bool,
+        by_amount_in: bool,
+        amount_specified: ProgrammableTransactionArg,
+        sqrt_price_limit: u128,
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        let dest_coin_type = if x_to_y {
+            self.coin_y.clone()
+        } else {
+            self.coin_x.clone()
+        };
 
-        // println!("x_to_y: {}", x_to_y);
+        let zero_coin = transaction_builder
+            .programmable_coin_zero(
+                pt_builder,
+                dest_coin_type
+            ).await?;
+
+        let (coin_a, coin_b) = if x_to_y {
+            (orig_coin, zero_coin)
+        } else {
+            (zero_coin, orig_coin)
+        };
 
         // Arg0: &GlobalConfig
         let global_config = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::from_object_id(self.parent_exchange.global_config_id.clone())
         );
 
-        // swap_a_b and swap_b_c arguments
-        // Arg1: &mut Pool<Ty0, Ty1, Ty2>
+        // Arg1: &mut Pool<Ty0, Ty1>
         let pool = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::from_object_id(self.pool_id.clone())
         );
 
-        // Arg2: vector<Coin<Ty0 or Ty1>>
+        // Arg2: Coin<Ty0>
+        let coin_a_arg = ProgrammableTransactionArg::Argument(coin_a);
 
-        let orig_coins_args_vec = if let Some(oc) = orig_coins {
-            oc
-                .into_iter()
-                .map(|orig_coin| {
-                    ProgrammableObjectArg::ObjectID(orig_coin)
-                })
-                .collect::<Vec<ProgrammableObjectArg>>()
-        } else {
-            vec![
-                ProgrammableObjectArg::Argument(
-                    transaction_builder.programmable_split_gas_coin(pt_builder, amount_in as u64).await
+        // Arg3: Coin<Ty1>
+        let coin_b_arg = ProgrammableTransactionArg::Argument(coin_b);
+
+        // Arg4: bool
+        let a2b = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::Bool(x_to_y)
+                )
+                .context("failed to convert MoveValue for a2b to JSON")?
+            )?
+        );
+
+        // Arg5: bool
+        // The amount is what goes in if true and what comes out if false
+        let by_amount_in = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::Bool(by_amount_in)
+                )
+                .context("failed to convert MoveValue for by_amount_in to JSON")?
+            )?
+        );
+
+        // Arg6: u64
+        // amount_specified
+
+        // Arg7: u128
+        // The pool stops swapping once the price gets here. It doesn't abort.
+        // Whatever wasn't swapped comes back in what's left of orig_coin.
+        let sqrt_price_limit = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::U128(sqrt_price_limit)
+                )
+                .context("failed to convert MoveValue for sqrt_price_limit to JSON")?
+            )?
+        );
+
+        // Arg8: bool
+        let use_coin_value = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::Bool(false)
                 )
-            ]
+                .context("failed to convert MoveValue for use_coin_value to JSON")?
+            )?
+        );
+
+        // Arg9: &Clock
+        let clock = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(
+                ObjectID::from_str(CLOCK_OBJECT_ID)?
+            )
+        );
+
+        let call_args = vec![
+            global_config,
+            pool,
+            coin_a_arg,
+            coin_b_arg,
+            a2b,
+            by_amount_in,
+            amount_specified,
+            sqrt_price_limit,
+            use_coin_value,
+            clock
+        ];
+
+        let type_args = vec![
+            SuiTypeTag::new(format!("{}", self.coin_x)), 
+            SuiTypeTag::new(format!("{}", self.coin_y)), 
+        ];
+
+        let swap_result = transaction_builder.programmable_move_call(
+            pt_builder,
+            self.parent_exchange.periphery_id.clone(),
+            "router",
+            "swap",
+            type_args,
+            call_args
+        ).await?;
+
+        // Returns (Coin<Ty0>, Coin<Ty1>)
+        if x_to_y {
+            Ok((programmable_nested_result(swap_result, 0)?, programmable_nested_result(swap_result, 1)?))
+        } else {
+            Ok((programmable_nested_result(swap_result, 1)?, programmable_nested_result(swap_result, 0)?))
+        }
+    }
+
+    // pool::flash_swap pays out before we pay in. The receipt it returns
+    // has no drop so the transaction can't succeed without repaying it.
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        x_to_y: bool,
+        amount_in: u128
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        let (orig_coin_type, dest_coin_type) = if x_to_y {
+            (self.coin_x.clone(), self.coin_y.clone())
+        } else {
+            (self.coin_y.clone(), self.coin_x.clone())
         };
 
-        let orig_coins_arg = ProgrammableTransactionArg::Argument(
-            transaction_builder
-                .programmable_make_object_vec(
-                    pt_builder,
-                    orig_coins_args_vec
-                ).await?
+        // Arg0: &GlobalConfig
+        let global_config = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.parent_exchange.global_config_id.clone())
         );
 
-        // Arg3: bool
-        let amount_specified_is_input = ProgrammableTransactionArg::SuiJsonValue(
+        // Arg1: &mut Pool<Ty0, Ty1>
+        let pool = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.pool_id.clone())
+        );
+
+        // Arg2: bool
+        let a2b = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::new(
                 move_value_to_json(
-                    &MoveValue::Bool(true)
+                    &MoveValue::Bool(x_to_y)
                 )
-                .context("failed to convert MoveValue for amount_specified_is_input to JSON")?
+                .context("failed to convert MoveValue for a2b to JSON")?
             )?
         );
 
-        // Arg4: u64
-        let amount_specified = ProgrammableTransactionArg::SuiJsonValue(
+        // Arg3: bool
+        let by_amount_in = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::new(
                 move_value_to_json(
-                    &MoveValue::U64((amount_in) as u64)
+                    &MoveValue::Bool(true)
                 )
-                .context("failed to convert MoveValue for amount_specified to JSON")?
+                .context("failed to convert MoveValue for by_amount_in to JSON")?
             )?
         );
 
-        // Arg5: u65
-        let amount_limit = ProgrammableTransactionArg::SuiJsonValue(
+        // Arg4: u64
+        let amount = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::new(
                 move_value_to_json(
-                    &MoveValue::U64((amount_out) as u64)
+                    &MoveValue::U64(amount_in as u64)
                 )
-                .context("failed to convert MoveValue for amount_limit to JSON")?
+                .context("failed to convert MoveValue for amount to JSON")?
             )?
         );
 
-        // Arg6: u64
+        // Arg5: u128
+        // The pool stops short of this. The min out we assert on the flash coin catches it.
         let sqrt_price_limit = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::new(
                 move_value_to_json(
                     &MoveValue::U128(
-                        if x_to_y {
-                            fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1
-                        } else {
-                            fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1
-                        }
+                        self.sqrt_price_limit(x_to_y, amount_in, true)
                     )
                 )
                 .context("failed to convert MoveValue for sqrt_price_limit to JSON")?
             )?
         );
 
-        // Arg7: &Clock
+        // Arg6: &Clock
         let clock = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::from_object_id(
                 ObjectID::from_str(CLOCK_OBJECT_ID)?
             )
         );
 
-        let function = if x_to_y {
-            "swap_a2b"
-        } else {
-            "swap_b2a"
-        };
-
         let call_args = vec![
-            global_config, 
+            global_config,
             pool,
-            orig_coins_arg,
-            amount_specified_is_input,
-            amount_specified,
-            amount_limit,
+            a2b,
+            by_amount_in,
+            amount,
             sqrt_price_limit,
             clock
         ];
 
         let type_args = vec![
-            SuiTypeTag::new(format!("{}", self.coin_x)), 
-            SuiTypeTag::new(format!("{}", self.coin_y)), 
+            SuiTypeTag::new(format!("{}", self.coin_x)),
+            SuiTypeTag::new(format!("{}", self.coin_y)),
         ];
 
-        // println!("{:#?}", type_args);
+        let flash_swap_result = transaction_builder.programmable_move_call(
+            pt_builder,
+            self.parent_exchange.package_id.clone(),
+            "pool",
+            "flash_swap",
+            type_args,
+            call_args
+        ).await?;
+
+        // Returns (Balance<Ty0>, Balance<Ty1>, FlashSwapReceipt<Ty0, Ty1>)
+        let (orig_balance_out, dest_balance_out) = if x_to_y {
+            (programmable_nested_result(flash_swap_result, 0)?, programmable_nested_result(flash_swap_result, 1)?)
+        } else {
+            (programmable_nested_result(flash_swap_result, 1)?, programmable_nested_result(flash_swap_result, 0)?)
+        };
+        let receipt = programmable_nested_result(flash_swap_result, 2)?;
+
+        // Nothing of the side we owe is paid out
+        transaction_builder
+            .programmable_balance_destroy_zero(
+                pt_builder,
+                orig_coin_type,
+                orig_balance_out
+            ).await?;
+
+        let dest_coin_out = transaction_builder
+            .programmable_coin_from_balance(
+                pt_builder,
+                dest_coin_type,
+                dest_balance_out
+            ).await?;
+
+        Ok((dest_coin_out, receipt))
+    }
+
+    // Splits exactly what the receipt says we owe off of pay_coin.
+    // repay_flash_swap aborts on anything else.
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        receipt: Argument,
+        pay_coin: Argument,
+        x_to_y: bool
+    ) -> Result<(), anyhow::Error> {
+        let (orig_coin_type, dest_coin_type) = if x_to_y {
+            (self.coin_x.clone(), self.coin_y.clone())
+        } else {
+            (self.coin_y.clone(), self.coin_x.clone())
+        };
+
+        let pay_amount = transaction_builder.programmable_move_call(
+            pt_builder,
+            self.parent_exchange.package_id.clone(),
+            "pool",
+            "swap_pay_amount",
+            vec![
+                SuiTypeTag::new(format!("{}", self.coin_x)),
+                SuiTypeTag::new(format!("{}", self.coin_y)),
+            ],
+            vec![ProgrammableTransactionArg::Argument(receipt)]
+        ).await?;
+
+        let repay_coin = transaction_builder
+            .programmable_split_coin_by_result(
+                pt_builder,
+                pay_coin,
+                pay_amount
+            ).await?;
+
+        let repay_balance = transaction_builder
+            .programmable_coin_into_balance(
+                pt_builder,
+                orig_coin_type,
+                repay_coin
+            ).await?;
+
+        let zero_balance = transaction_builder
+            .programmable_balance_zero(
+                pt_builder,
+                dest_coin_type
+            ).await?;
+
+        let (balance_a, balance_b) = if x_to_y {
+            (repay_balance, zero_balance)
+        } else {
+            (zero_balance, repay_balance)
+        };
+
+        // Arg0: &GlobalConfig
+        let global_config = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.parent_exchange.global_config_id.clone())
+        );
+
+        // Arg1: &mut Pool<Ty0, Ty1>
+        let pool = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.pool_id.clone())
+        );
+
+        let call_args = vec![
+            global_config,
+            pool,
+            ProgrammableTransactionArg::Argument(balance_a), // Arg2: Balance<Ty0>
+            ProgrammableTransactionArg::Argument(balance_b), // Arg3: Balance<Ty1>
+            ProgrammableTransactionArg::Argument(receipt)    // Arg4: FlashSwapReceipt<Ty0, Ty1>
+        ];
+
+        let type_args = vec![
+            SuiTypeTag::new(format!("{}", self.coin_x)),
+            SuiTypeTag::new(format!("{}", self.coin_y)),
+        ];
 
         transaction_builder.programmable_move_call(
             pt_builder,
-            self.parent_exchange.periphery_id.clone(),
-            "pool_script",
-            function,
+            self.parent_exchange.package_id.clone(),
+            "pool",
+            "repay_flash_swap",
             type_args,
             call_args
         ).await?;
-        
+
         Ok(())
     }
 
@@ -822,6 +1256,18 @@ impl Market for CetusMarket {
         self.update_with_object_response(sui_client, object_response).await
     }
 
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
+
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.update_with_pool_state(pool_state)
+    }
+
+    fn market_record(&self) -> MarketRecord {
+        self.market_record()
+    }
+
     fn pool_id(&self) -> &ObjectID {
         self.pool_id()
     }
@@ -854,32 +1300,100 @@ impl Market for CetusMarket {
         self.compute_swap_y_to_x(amount_specified)
     }
 
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_x_to_y(amount_specified)
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_y_to_x(amount_specified)
+    }
+
     fn viable(&self) -> bool {
         self.viable()
     }
 
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        self.constant_product_curve(x_to_y)
+    }
+
     async fn add_swap_to_programmable_transaction(
         &self,
         transaction_builder: &TransactionBuilder,
         pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
+        orig_coin: Argument, // split off of our own coins or the output coin of the previous leg
         x_to_y: bool,
         amount_in: u128,
         amount_out: u128,
         recipient: SuiAddress
-    ) -> Result<(), anyhow::Error> {
-
-        // if !recipient.is_none() {
-        //     return Err(anyhow!("recipient should be none"));
-        // }
-
+    ) -> Result<Argument, anyhow::Error> {
         self.add_swap_to_programmable_trasaction(
             transaction_builder,
             pt_builder,
-            orig_coins,
+            orig_coin,
             x_to_y,
             amount_in,
+            amount_out,
+            recipient
+        ).await
+    }
+
+    fn supports_exact_output(&self) -> bool {
+        true
+    }
+
+    async fn add_exact_output_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument,
+        x_to_y: bool,
+        amount_out: u128,
+        recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        self.add_exact_output_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            orig_coin,
+            x_to_y,
             amount_out
         ).await
     }
+
+    fn supports_flash_swap(&self) -> bool {
+        true
+    }
+
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        x_to_y: bool,
+        amount_in: u128,
+        recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        self.add_flash_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            x_to_y,
+            amount_in
+        ).await
+    }
+
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        receipt: Argument,
+        pay_coin: Argument,
+        x_to_y: bool,
+        amount_in: u128
+    ) -> Result<(), anyhow::Error> {
+        self.add_repay_flash_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            receipt,
+            pay_coin,
+            x_to_y
+        ).await
+    }
 }
@@ -0,0 +1,303 @@
+use anyhow::{anyhow, Context};
+
+use move_core_types::language_storage::TypeTag;
+
+use serde::Deserialize;
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use sui_sdk::SUI_COIN_TYPE;
+use sui_sdk::types::base_types::ObjectID;
+
+use local_orderbooks::DeepBook;
+
+use crate::cetus::Cetus;
+use crate::flameswap::FlameSwap;
+use crate::kriyadex::KriyaDex;
+use crate::markets::{Exchange, Market};
+use crate::turbos::Turbos;
+
+// Everything that changes when a package is upgraded or we retarget the bot.
+// Loaded from TOML alongside RunDataOpts.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub exchanges: ExchangesConfig,
+    #[serde(default)]
+    pub pools: PoolsConfig,
+    // The coins cycles start and end at. We need to hold each of them.
+    pub source_coins: Vec<SourceCoinConfig>,
+    // Profits from different source coins are compared in this coin. SUI when unset.
+    #[serde(default)]
+    pub profit_coin: Option<String>,
+    pub thresholds: ThresholdsConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+}
+
+// An exchange is only traded on when its section is present
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExchangesConfig {
+    pub cetus: Option<CetusConfig>,
+    pub turbos: Option<TurbosConfig>,
+    pub kriyadex: Option<KriyaDexConfig>,
+    pub flameswap: Option<FlameSwapConfig>,
+    pub deepbook: Option<DeepBookConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CetusConfig {
+    pub package_id: ObjectID,
+    pub router_id: ObjectID,
+    pub global_config_id: ObjectID,
+    // How far past the predicted post-swap price a swap may move the pool before it aborts
+    #[serde(default = "default_price_limit_tolerance_bps")]
+    pub price_limit_tolerance_bps: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TurbosConfig {
+    // Events keep the type of the package that first published them
+    pub original_package_id: ObjectID,
+    pub package_id: ObjectID,
+    pub versioned_id: ObjectID,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KriyaDexConfig {
+    pub package_id: ObjectID,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlameSwapConfig {
+    pub package_id: ObjectID,
+    pub global_id: ObjectID,
+    // Bag of pools keyed by LP name
+    pub pools_id: ObjectID,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeepBookConfig {
+    pub package_id: ObjectID,
+    // An AccountCap we own. Swaps take one even though taker orders never rest.
+    pub account_cap_id: ObjectID,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceCoinConfig {
+    pub coin_type: String,
+    // Overrides thresholds.allowance_bps for this coin
+    #[serde(default)]
+    pub allowance_bps: Option<u64>,
+}
+
+// A source coin with its allowance resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceCoin {
+    pub coin_type: TypeTag,
+    pub allowance_bps: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PoolsConfig {
+    // Only trade these pools. Every pool when empty.
+    #[serde(default)]
+    pub allow: Vec<ObjectID>,
+    // Never trade these pools
+    #[serde(default)]
+    pub deny: Vec<ObjectID>,
+    // Keep these pools in sync but don't search on their events
+    #[serde(default)]
+    pub skip_events: Vec<ObjectID>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThresholdsConfig {
+    pub max_intermediate_nodes: usize,
+    // Tolerance in basis points applied to every leg's predicted amount out
+    #[serde(default = "default_slippage_bps")]
+    pub slippage_bps: u64,
+    // Share of a source coin's balance a single trade may use, in basis points.
+    // The default for source coins that don't set their own.
+    #[serde(default = "default_allowance_bps")]
+    pub allowance_bps: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SearchConfig {
+    #[serde(default)]
+    pub cycle_detector: CycleDetector,
+}
+
+// How the cycles to optimize are found when a pool changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CycleDetector {
+    // Every simple cycle through the source coin, enumerated up front.
+    // Bounded by max_intermediate_nodes.
+    #[default]
+    SimplePaths,
+    // Negative cycles of -ln(price) found from the changed pool's coins.
+    // Any length, but only loops through the source coin can be traded.
+    NegativeCycles,
+}
+
+impl PoolsConfig {
+    pub fn allows(&self, pool_id: &ObjectID) -> bool {
+        (self.allow.is_empty() || self.allow.contains(pool_id)) && !self.deny.contains(pool_id)
+    }
+}
+
+fn default_price_limit_tolerance_bps() -> u64 {
+    100
+}
+
+fn default_slippage_bps() -> u64 {
+    50
+}
+
+fn default_allowance_bps() -> u64 {
+    8_000
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read config {}", path.display()))?;
+
+        let config: Config = toml::from_str(&contents)
+            .context(format!("Failed to parse config {}", path.display()))?;
+
+        // Catches bad coin types and allowances before we connect to anything
+        config.source_coins()?;
+        config.profit_coin()?;
+
+        Ok(config)
+    }
+
+    pub fn exchanges(&self) -> Vec<Box<dyn Exchange>> {
+        let mut exchanges: Vec<Box<dyn Exchange>> = vec![];
+
+        if let Some(cetus) = &self.exchanges.cetus {
+            exchanges.push(
+                Box::new(
+                    Cetus::new(
+                        cetus.package_id.clone(),
+                        cetus.router_id.clone(),
+                        cetus.global_config_id.clone(),
+                        cetus.price_limit_tolerance_bps
+                    )
+                )
+            );
+        }
+
+        if let Some(turbos) = &self.exchanges.turbos {
+            exchanges.push(
+                Box::new(
+                    Turbos::new(
+                        turbos.original_package_id.clone(),
+                        turbos.package_id.clone(),
+                        turbos.versioned_id.clone()
+                    )
+                )
+            );
+        }
+
+        if let Some(kriyadex) = &self.exchanges.kriyadex {
+            exchanges.push(
+                Box::new(
+                    KriyaDex::new(
+                        kriyadex.package_id.clone()
+                    )
+                )
+            );
+        }
+
+        if let Some(flameswap) = &self.exchanges.flameswap {
+            exchanges.push(
+                Box::new(
+                    FlameSwap::new(
+                        flameswap.package_id.clone(),
+                        flameswap.global_id.clone(),
+                        flameswap.pools_id.clone()
+                    )
+                )
+            );
+        }
+
+        if let Some(deepbook) = &self.exchanges.deepbook {
+            exchanges.push(
+                Box::new(
+                    DeepBook::new(
+                        deepbook.package_id.clone(),
+                        deepbook.account_cap_id.clone()
+                    )
+                )
+            );
+        }
+
+        exchanges
+    }
+
+    pub fn source_coins(&self) -> Result<Vec<SourceCoin>, anyhow::Error> {
+        if self.source_coins.is_empty() {
+            return Err(anyhow!("At least one source coin is required"));
+        }
+
+        let source_coins = self.source_coins
+            .iter()
+            .map(|source_coin| {
+                let coin_type = TypeTag::from_str(&source_coin.coin_type)
+                    .context(format!("Invalid source coin {}", source_coin.coin_type))?;
+
+                let allowance_bps = source_coin.allowance_bps.unwrap_or(self.thresholds.allowance_bps);
+
+                if allowance_bps > 10_000 {
+                    return Err(anyhow!("allowance_bps of {} can't be more than 10000", source_coin.coin_type));
+                }
+
+                Ok(
+                    SourceCoin {
+                        coin_type,
+                        allowance_bps,
+                    }
+                )
+            })
+            .collect::<Result<Vec<SourceCoin>, anyhow::Error>>()?;
+
+        let unique_coin_types = source_coins
+            .iter()
+            .map(|source_coin| {
+                &source_coin.coin_type
+            })
+            .collect::<HashSet<&TypeTag>>();
+
+        if unique_coin_types.len() != source_coins.len() {
+            return Err(anyhow!("Source coins can't be listed more than once"));
+        }
+
+        Ok(source_coins)
+    }
+
+    pub fn profit_coin(&self) -> Result<TypeTag, anyhow::Error> {
+        let profit_coin = self.profit_coin.as_deref().unwrap_or(SUI_COIN_TYPE);
+
+        TypeTag::from_str(profit_coin)
+            .context(format!("Invalid profit coin {}", profit_coin))
+    }
+
+    pub fn skip_event_pool_ids(&self) -> HashSet<ObjectID> {
+        self.pools.skip_events.iter().cloned().collect()
+    }
+
+    // Applies the pool allow and deny lists
+    pub fn filter_markets(&self, markets: Vec<Box<dyn Market>>) -> Vec<Box<dyn Market>> {
+        markets
+            .into_iter()
+            .filter(|market| {
+                self.pools.allows(market.pool_id())
+            })
+            .collect()
+    }
+}
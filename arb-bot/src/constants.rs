@@ -1 +1,6 @@
-pub const OBJECT_REQUEST_LIMIT: usize = 50;
\ No newline at end of file
+use std::time::Duration;
+
+pub const OBJECT_REQUEST_LIMIT: usize = 50;
+
+// How long a market refresh may keep retrying before the round is skipped
+pub const MARKET_REFRESH_BUDGET: Duration = Duration::from_secs(10);
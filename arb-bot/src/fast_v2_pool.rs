@@ -91,6 +91,11 @@ impl Pool {
         }
     }
 
+    // Share of the amount in left after the protocol and lp fees
+    pub fn fee_multiplier(&self) -> f64 {
+        (1.0 - self.protocol_fee as f64 / 1_000_000.0) * (1.0 - self.lp_fee as f64 / 1_000_000.0)
+    }
+
     // Calculate
     pub fn calc_swap_exact_amount_in(
         &self,
@@ -6,6 +6,8 @@ use sui_sdk::types::base_types::ObjectID;
 
 use anyhow::anyhow;
 
+use pool_state::{PoolState, TickState};
+
 // INITIALIZED BY DEFAULT
 #[derive(Debug, Clone)]
 pub struct Tick {
@@ -48,6 +50,84 @@ pub struct ComputeSwapState {
 }
 
 impl Pool {
+    // A pool from the state served by a librarian
+    pub fn from_pool_state(id: ObjectID, pool_state: &PoolState) -> Result<Self, anyhow::Error> {
+        if let PoolState::ConcentratedLiquidity {
+            sqrt_price,
+            tick_current_index,
+            tick_spacing,
+            fee,
+            unlocked,
+            liquidity,
+            ticks
+        } = pool_state {
+            Ok(
+                Pool {
+                    id,
+                    sqrt_price: *sqrt_price,
+                    tick_current_index: *tick_current_index,
+                    tick_spacing: *tick_spacing,
+                    fee: *fee,
+                    unlocked: *unlocked,
+                    liquidity: *liquidity,
+                    ticks: ticks
+                        .iter()
+                        .map(|tick| {
+                            (
+                                tick.index,
+                                Tick {
+                                    index: tick.index,
+                                    sqrt_price: tick.sqrt_price,
+                                    liquidity_gross: tick.liquidity_gross,
+                                    liquidity_net: tick.liquidity_net,
+                                }
+                            )
+                        })
+                        .collect(),
+                }
+            )
+        } else {
+            Err(anyhow!("Expected concentrated liquidity pool state for pool {}", id))
+        }
+    }
+
+    pub fn to_pool_state(&self) -> PoolState {
+        PoolState::ConcentratedLiquidity {
+            sqrt_price: self.sqrt_price,
+            tick_current_index: self.tick_current_index,
+            tick_spacing: self.tick_spacing,
+            fee: self.fee,
+            unlocked: self.unlocked,
+            liquidity: self.liquidity,
+            ticks: self
+                .ticks
+                .values()
+                .map(|tick| {
+                    TickState {
+                        index: tick.index,
+                        sqrt_price: tick.sqrt_price,
+                        liquidity_net: tick.liquidity_net,
+                        liquidity_gross: tick.liquidity_gross,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    // Reserves of the constant product pool that prices the same
+    // as this one until the price leaves the current tick.
+    // x = L / sqrt(P), y = L * sqrt(P)
+    pub fn virtual_reserves(&self) -> (f64, f64) {
+        let sqrt_price = self.sqrt_price as f64 / 2f64.powi(64);
+        let liquidity = self.liquidity as f64;
+
+        (liquidity / sqrt_price, liquidity * sqrt_price)
+    }
+
+    pub fn fee_multiplier(&self) -> f64 {
+        1.0 - self.fee as f64 / 1_000_000.0
+    }
+
     pub fn apply_update_fee(
         &mut self,
         fee: u64 // rate
@@ -72,6 +152,7 @@ impl Pool {
     ) {
         let liquidity_delta_i128 = liquidity_delta as i128;
 
+        // Newly initialized ticks start empty. The deltas are added below.
         let tick_lower = self
             .ticks
             .entry(tick_lower_index)
@@ -79,8 +160,8 @@ impl Pool {
                 Tick {
                     index: tick_lower_index,
                     sqrt_price: tick_math::sqrt_price_from_tick_index(tick_lower_index),
-                    liquidity_gross: liquidity_delta,
-                    liquidity_net: -liquidity_delta_i128
+                    liquidity_gross: 0,
+                    liquidity_net: 0
                 }
             );
 
@@ -94,13 +175,18 @@ impl Pool {
                 Tick {
                     index: tick_upper_index,
                     sqrt_price: tick_math::sqrt_price_from_tick_index(tick_upper_index),
-                    liquidity_gross: liquidity_delta,
-                    liquidity_net: liquidity_delta_i128
+                    liquidity_gross: 0,
+                    liquidity_net: 0
                 }
             );
 
         tick_upper.liquidity_gross += liquidity_delta;
         tick_upper.liquidity_net -= liquidity_delta_i128;
+
+        // Position is in range so it adds to the active liquidity
+        if tick_lower_index <= self.tick_current_index && self.tick_current_index < tick_upper_index {
+            self.liquidity += liquidity_delta;
+        }
     }
 
     pub fn apply_remove_liquidity(
@@ -138,6 +224,10 @@ impl Pool {
                 .ticks
                 .remove(&tick_upper_index);
         }
+
+        if tick_lower_index <= self.tick_current_index && self.tick_current_index < tick_upper_index {
+            self.liquidity -= liquidity_delta;
+        }
     }
 
     pub fn apply_swap(
@@ -392,6 +482,28 @@ impl Pool {
     
     }
 
+    // Where we predict the swap leaves the price, widened by tolerance_bps of price
+    pub fn sqrt_price_limit(
+        &self,
+        a_to_b: bool,
+        amount_specified: u64,
+        amount_specified_is_input: bool,
+        tolerance_bps: u64,
+    ) -> u128 {
+        let swap_result = self.compute_swap_result(
+            a_to_b,
+            amount_specified,
+            amount_specified_is_input,
+            if a_to_b {
+                tick_math::MIN_SQRT_PRICE_X64 + 1
+            } else {
+                tick_math::MAX_SQRT_PRICE_X64 - 1
+            }
+        );
+
+        sqrt_price_limit_with_tolerance(swap_result.sqrt_price, a_to_b, tolerance_bps)
+    }
+
     pub fn liquidity_sanity_check(
         &self
     ) -> bool {
@@ -537,6 +649,22 @@ pub fn cross_by_swap(
     Ok(liquidity)
 }
 
+// a_to_b pushes the price down so the limit sits below sqrt_price. b_to_a the opposite.
+// Price moves by the square of sqrt price so the tolerance is square rooted.
+pub fn sqrt_price_limit_with_tolerance(sqrt_price: u128, a_to_b: bool, tolerance_bps: u64) -> u128 {
+    let tolerance = (tolerance_bps.min(10_000) as f64) / 10_000.0;
+
+    if a_to_b {
+        let limit = (sqrt_price as f64 * (1.0 - tolerance).sqrt()) as u128;
+
+        limit.max(tick_math::MIN_SQRT_PRICE_X64 + 1)
+    } else {
+        let limit = (sqrt_price as f64 * (1.0 + tolerance).sqrt()) as u128;
+
+        limit.min(tick_math::MAX_SQRT_PRICE_X64 - 1)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
@@ -640,6 +768,73 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_add_then_remove_liquidity() {
+        let mut pool = Pool {
+            id: ObjectID::from_str("0x1").unwrap(),
+            sqrt_price: tick_math::sqrt_price_from_tick_index(100),
+            tick_current_index: 100,
+            tick_spacing: 10,
+            fee: 0,
+            unlocked: true,
+            liquidity: 0,
+            ticks: BTreeMap::new(),
+        };
+
+        // In range
+        pool.apply_add_liquidity(0, 200, 1_000);
+        assert_eq!(pool.liquidity, 1_000);
+        assert_eq!(pool.ticks[&0].liquidity_gross, 1_000);
+        assert_eq!(pool.ticks[&0].liquidity_net, 1_000);
+        assert_eq!(pool.ticks[&200].liquidity_gross, 1_000);
+        assert_eq!(pool.ticks[&200].liquidity_net, -1_000);
+
+        // Out of range. Shares the lower tick.
+        pool.apply_add_liquidity(200, 300, 500);
+        assert_eq!(pool.liquidity, 1_000);
+        assert_eq!(pool.ticks[&200].liquidity_gross, 1_500);
+        assert_eq!(pool.ticks[&200].liquidity_net, -500);
+
+        pool.apply_remove_liquidity(0, 200, 1_000);
+        assert_eq!(pool.liquidity, 0);
+        assert!(!pool.ticks.contains_key(&0));
+        assert_eq!(pool.ticks[&200].liquidity_gross, 500);
+        assert_eq!(pool.ticks[&200].liquidity_net, 500);
+
+        pool.apply_remove_liquidity(200, 300, 500);
+        assert!(pool.ticks.is_empty());
+    }
+
+    #[test]
+    fn test_sqrt_price_limit_with_tolerance() {
+        let sqrt_price = 1304381782533278269440;
+
+        // 1% of price either way
+        let lower = sqrt_price_limit_with_tolerance(sqrt_price, true, 100);
+        let upper = sqrt_price_limit_with_tolerance(sqrt_price, false, 100);
+
+        let price_ratio = |limit: u128| (limit as f64 / sqrt_price as f64).powi(2);
+
+        assert!((price_ratio(lower) - 0.99).abs() < 1e-9, "lower: {}", lower);
+        assert!((price_ratio(upper) - 1.01).abs() < 1e-9, "upper: {}", upper);
+
+        // No tolerance leaves the limit at the price either way. A price of 1 survives the float round trip.
+        let one = 1u128 << 64;
+        assert_eq!(sqrt_price_limit_with_tolerance(one, true, 0), one);
+        assert_eq!(sqrt_price_limit_with_tolerance(one, false, 0), one);
+
+        // Never past the bounds the pools accept
+        assert_eq!(sqrt_price_limit_with_tolerance(sqrt_price, true, 10_000), tick_math::MIN_SQRT_PRICE_X64 + 1);
+        assert_eq!(sqrt_price_limit_with_tolerance(tick_math::MIN_SQRT_PRICE_X64, true, 100), tick_math::MIN_SQRT_PRICE_X64 + 1);
+        assert_eq!(sqrt_price_limit_with_tolerance(tick_math::MAX_SQRT_PRICE_X64, false, 100), tick_math::MAX_SQRT_PRICE_X64 - 1);
+
+        // Tolerances past 100% are clamped
+        assert_eq!(
+            sqrt_price_limit_with_tolerance(sqrt_price, false, 20_000),
+            sqrt_price_limit_with_tolerance(sqrt_price, false, 10_000)
+        );
+        assert!(sqrt_price_limit_with_tolerance(sqrt_price, false, 10_000) > upper);
+    }
 }
 
 mod clmm_math {
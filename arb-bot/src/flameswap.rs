@@ -1,124 +1,799 @@
 use std::str::FromStr;
-use std::cmp;
 use async_trait::async_trait;
 use anyhow::{anyhow, Context};
 
 use futures::TryStreamExt;
 use page_turner::PageTurner;
+use fixed::types::U64F64;
+
 use custom_sui_sdk::{
     SuiClient,
-    apis::GetDynamicFieldsRequest
+    apis::GetDynamicFieldsRequest,
+    transaction_builder::TransactionBuilder,
+    programmable_transaction_sui_json::ProgrammableTransactionArg
 };
 
-use sui_sdk::types::base_types::{ObjectID, ObjectType};
-use sui_sdk::rpc_types::{SuiObjectDataOptions};
-use sui_sdk::types::dynamic_field::DynamicFieldInfo;
-// use sui_sdk::error::{Error, SuiRpcResult};
+use sui_sdk::types::base_types::{ObjectID, ObjectType, SuiAddress};
+use sui_sdk::types::dynamic_field::{self, DynamicFieldInfo};
+use sui_sdk::types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_sdk::types::transaction::Argument;
+use sui_sdk::rpc_types::{
+    SuiObjectResponse,
+    EventFilter,
+    SuiEvent,
+    SuiTypeTag
+};
 
-use crate::markets::Exchange;
+use sui_sdk::json::SuiJsonValue;
 
+use move_core_types::language_storage::{StructTag, TypeTag};
+use move_core_types::value::MoveValue;
+use std::collections::HashMap;
 
-use move_core_types::language_storage::{TypeTag};
+use crate::markets::{Exchange, Market, EventUpdate, MarketRecord, ConstantProductCurve};
+use crate::sui_sdk_utils::{self, sui_move_value};
+use crate::fast_v2_pool;
+use crate::sui_json_utils::{move_value_to_json, parsed_json};
 
-const EXCHANGE_ADDRESS: &str = "0x6b84da4f5dc051759382e60352377fea9d59bc6ec92dc60e0b6387e05274415f";
-const GLOBAL: &str = "0x3083e3d751360c9084ba33f6d9e1ad38fb2a11cffc151f2ee4a5c03da61fb1e2";
-const POOLS: &str = "0x6edec171d3b4c6669ac748f6de77f78635b72aac071732b184677db19eefd9e8";
+use pool_state::PoolState;
 
-pub struct FlameSwap;
+// 0.3% of the amount in, in fast_v2_pool's fee units.
+// Extra per-coin fees configured in Global's coin_map aren't modelled.
+const LP_FEE: u64 = 3_000;
 
-#[async_trait]
-impl Exchange for FlameSwap {
-    fn package_id(&self) -> Result<ObjectID, anyhow::Error> {
-        ObjectID::from_str(EXCHANGE_ADDRESS).map_err(|err| err.into())
+// Pools aren't objects of their own. They're values in a Bag owned by Global
+// keyed by their LP name, so a market's pool id is the id of its dynamic field.
+// Events name the pool by its LP name and we derive the field id from that.
+#[derive(Debug, Clone)]
+pub struct FlameSwap {
+    package_id: ObjectID,
+    global_id: ObjectID,
+    pools_id: ObjectID,
+    event_struct_tag_to_pool_field: HashMap<StructTag, String>,
+}
+
+impl FlameSwap {
+    pub fn new(package_id: ObjectID, global_id: ObjectID, pools_id: ObjectID) -> Self {
+        let mut event_struct_tag_to_pool_field = HashMap::new();
+        // Everything that changes the state of a pool
+        for event_name in ["SwappedEvent", "AddedEvent", "RemovedEvent"] {
+            event_struct_tag_to_pool_field.insert(
+                StructTag::from_str(
+                    &format!("{}::event::{}", package_id, event_name)
+                ).expect("FlameSwap: failed to create event struct tag"),
+                "lp_name".to_string()
+            );
+        }
+
+        FlameSwap {
+            package_id,
+            global_id,
+            pools_id,
+            event_struct_tag_to_pool_field,
+        }
     }
+}
 
-    async fn get_all_markets(&self, sui_client: &SuiClient) -> Result<(), anyhow::Error> {
+impl FlameSwap {
+    pub fn package_id(&self) -> &ObjectID {
+        &self.package_id
+    }
 
-        let pools_dynamic_fields_data = sui_client
+    fn event_package_id(&self) -> &ObjectID {
+        &self.package_id
+    }
+
+    fn event_filters(&self) -> Vec<EventFilter> {
+        self
+            .event_struct_tag_to_pool_field()
+            .keys()
+            .cloned()
+            .map(|event_struct_tag| {
+                EventFilter::MoveEventType(
+                    event_struct_tag
+                )
+            })
+            .collect::<Vec<_>>()
+    }
+
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
+        &self.event_struct_tag_to_pool_field
+    }
+
+    // The id of the dynamic field holding the pool named lp_name
+    fn pool_id_from_lp_name(&self, lp_name: &str) -> Result<ObjectID, anyhow::Error> {
+        let key_type_tag = TypeTag::from_str("0x1::string::String")?;
+
+        dynamic_field::derive_dynamic_field_id(
+            self.pools_id,
+            &key_type_tag,
+            &bcs::to_bytes(lp_name)?
+        )
+        .context(format!("FlameSwap: failed to derive pool id for {}", lp_name))
+    }
+
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error> {
+        let pool_field = if let Some(pool_field) = self.event_struct_tag_to_pool_field.get(&event.type_) {
+            pool_field
+        } else {
+            return Ok(None);
+        };
+
+        let lp_name = parsed_json::get_string(&event.parsed_json, pool_field)?;
+
+        Ok(Some(self.pool_id_from_lp_name(&lp_name)?))
+    }
+
+    // Every pool is a dynamic field of the pools Bag
+    async fn get_all_markets_(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
+        let pool_ids = sui_client
             .read_api()
             .pages(
                 GetDynamicFieldsRequest {
-                    object_id: ObjectID::from_str(POOLS)?,
+                    object_id: self.pools_id.clone(),
                     cursor: None,
                     limit: None,
                 }
             )
             .items()
             .try_collect::<Vec<DynamicFieldInfo>>()
-            .await?;
-
-        let pool_object_ids = pools_dynamic_fields_data
-            .iter()
+            .await?
+            .into_iter()
             .map(|field| {
                 field.object_id
             })
             .collect::<Vec<ObjectID>>();
 
-        println!(
-            "Number of pools: {:#?}", 
-            pool_object_ids.len()
-        );
+        println!("flameswap len pools: {}", pool_ids.len());
 
-        // Considering the request limit might make sense to do a page at a time hahahha
-        let mut pools = Vec::new();
+        let pool_id_to_object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await?;
 
-        for i in 0..(pool_object_ids.len() / 50) + 1 {
+        let markets = pool_id_to_object_response
+            .into_iter()
+            .map(|(pool_id, object_response)| {
+                self.market_from_object_response(pool_id, &object_response)
+            })
+            .collect::<Result<Vec<Box<dyn Market>>, anyhow::Error>>()?;
 
-            // println!("i*50: {}, pool_object_ids length:{}", i*50, pool_object_ids.len());
-            pools.extend(
-                sui_client
-                    .read_api()
-                    .multi_get_object_with_options(
-                        pool_object_ids[i*50..cmp::min(i*50 + 50, pool_object_ids.len())].to_vec(),
-                        SuiObjectDataOptions::full_content()
-                    )
-                    .await?
-            )
+        Ok(markets)
+    }
+
+    fn pool_created_event_struct_tag(&self) -> StructTag {
+        StructTag::from_str(
+            &format!("{}::event::PoolCreatedEvent", self.package_id)
+        ).expect("FlameSwap: failed to create pool created event struct tag")
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        vec![EventFilter::MoveEventType(self.pool_created_event_struct_tag())]
+    }
+
+    // The coin types are only in the pool's type so the pool has to be fetched
+    async fn market_from_pool_created_event_(&self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        if event.type_ != self.pool_created_event_struct_tag() {
+            return Ok(None);
         }
 
-        // TODO: Refactor
-        let coin_pairs = pools
-            .into_iter()
-            .map(|pool| {
-                if let Some(data) = pool.data {
-                    if let Some(type_) = data.type_ {
-                        if let ObjectType::Struct(move_object_type) = type_ {
-                                if let TypeTag::Struct(box_struct_tag) = move_object_type
-                                .type_params()
-                                .get(1).context("Missing coin pair type parameter")? 
-                            {
-                                println!("{:#?}", box_struct_tag);
-                                Ok(
-                                    FlameswapMarket{
-                                        coin_x: box_struct_tag.type_params.get(0).context("Missing coin_x")?.clone(),
-                                        coin_y: box_struct_tag.type_params.get(0).context("Missing coin_y")?.clone(),
-                                    }
-                                )
-                            } else {
-                                Err(anyhow!("Does not match the TypeTag::Struct variant"))
-                            }
-                        } else {
-                            Err(anyhow!("Does not match the ObjectType::Struct variant"))
-                        }
-                    } else {
-                        Err(anyhow!("Expected Some"))
-                    }
-                } else {
-                    Err(anyhow!("Expected Some"))
+        let lp_name = parsed_json::get_string(&event.parsed_json, "lp_name")?;
+        let pool_id = self.pool_id_from_lp_name(&lp_name)?;
+
+        let object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &[pool_id])
+            .await?
+            .remove(&pool_id)
+            .context(format!("Missing object response for new pool {}", pool_id))?;
+
+        Ok(Some(self.market_from_object_response(pool_id, &object_response)?))
+    }
+
+    fn market_from_object_response(&self, pool_id: ObjectID, object_response: &SuiObjectResponse) -> Result<Box<dyn Market>, anyhow::Error> {
+        let (coin_x, coin_y) = get_coin_pair_from_object_response(object_response)?;
+
+        Ok(
+            Box::new(
+                FlameSwapMarket {
+                    parent_exchange: self.clone(),
+                    coin_x,
+                    coin_y,
+                    pool_id,
+                    computing_pool: None    // We'll grab this later so we don't have to deal with async stuff
                 }
+            ) as Box<dyn Market>
+        )
+    }
+
+    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
+        let pool_ids = markets
+            .iter()
+            .map(|market| {
+                *market.pool_id()
             })
-            .collect::<Result<Vec<FlameswapMarket>, anyhow::Error>>()?;
+            .collect::<Vec<ObjectID>>();
 
-        println!("Number of markets: {}", coin_pairs.len());
-        // coin_pairs.iter().for_each(|market| println!("{:#?}", market));
+        sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await
+    }
 
-        
-        Ok(())
+    // Fees sit in their own balances so the reserves are just coin_x and coin_y
+    pub fn computing_pool_from_object_response(&self, response: &SuiObjectResponse) -> Result<fast_v2_pool::Pool, anyhow::Error> {
+        let fields = sui_sdk_utils::read_fields_from_object_response(response).context("missing fields")?;
+
+        let id = response.data.as_ref().context("data field from object response is None")?.object_id;
+
+        // Field<String, Pool<Ty0, Ty1>>
+        let pool_fields = sui_move_value::get_struct(&fields, "value")?;
+
+        let coin_x = u64::from_str(
+            &sui_move_value::get_string(
+                &pool_fields,
+                "coin_x"
+            )?
+        )?;
+
+        let coin_y = u64::from_str(
+            &sui_move_value::get_string(
+                &pool_fields,
+                "coin_y"
+            )?
+        )?;
+
+        Ok(
+            fast_v2_pool::Pool {
+                id,
+                reserve_x: coin_x,
+                reserve_y: coin_y,
+                protocol_fee: 0,
+                lp_fee: LP_FEE,
+                // Pausing is global and only stops adding liquidity
+                unlocked: true,
+            }
+        )
+    }
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        let mut market = FlameSwapMarket {
+            parent_exchange: self.clone(),
+            coin_x: market_record.coin_x.clone(),
+            coin_y: market_record.coin_y.clone(),
+            pool_id: market_record.pool_id.clone(),
+            computing_pool: None
+        };
+
+        market.update_with_pool_state(market_record.pool_state.as_ref())?;
+
+        Ok(Box::new(market))
+    }
+}
+
+#[async_trait]
+impl Exchange for FlameSwap {
+    fn package_id(&self) -> &ObjectID {
+        self.package_id()
+    }
+
+    fn event_package_id(&self) -> &ObjectID {
+        self.event_package_id()
+    }
+
+    fn event_filters(&self) -> Vec<EventFilter> {
+        self.event_filters()
+    }
+
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
+        self.event_struct_tag_to_pool_field()
+    }
+
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error> {
+        self.pool_id_from_event(event)
+    }
+
+    async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
+        self.get_all_markets_(sui_client).await
+    }
+
+    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
+        self.get_pool_id_to_object_response(sui_client, markets).await
+    }
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        self.market_from_record(market_record)
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        self.pool_created_event_filters()
+    }
+
+    async fn market_from_pool_created_event(&mut self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        self.market_from_pool_created_event_(sui_client, event).await
     }
 }
 
-#[derive(Debug)]
-struct FlameswapMarket {
+#[derive(Debug, Clone)]
+struct FlameSwapMarket {
+    parent_exchange: FlameSwap,
     coin_x: TypeTag,
     coin_y: TypeTag,
+    pool_id: ObjectID,
+    computing_pool: Option<fast_v2_pool::Pool>
+}
+
+impl FlameSwapMarket {
+    fn coin_x(&self) -> &TypeTag {
+        &self.coin_x
+    }
+
+    fn coin_y(&self) -> &TypeTag {
+        &self.coin_y
+    }
+
+    fn coin_x_price(&self) -> Option<U64F64> {
+        let cp = self.computing_pool.as_ref()?;
+
+        if cp.reserve_y == 0 {
+            return None;
+        }
+
+        Some(U64F64::from_num(cp.reserve_x) / U64F64::from_num(cp.reserve_y))
+    }
+
+    fn coin_y_price(&self) -> Option<U64F64> {
+        let cp = self.computing_pool.as_ref()?;
+
+        if cp.reserve_x == 0 {
+            return None;
+        }
+
+        Some(U64F64::from_num(cp.reserve_y) / U64F64::from_num(cp.reserve_x))
+    }
+
+    async fn update_with_object_response(&mut self, _sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
+        self.computing_pool = Some(self.parent_exchange.computing_pool_from_object_response(object_response)?);
+        Ok(())
+    }
+
+    // Every pool shares the same events so the exchange already matched the LP name to us
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        let type_ = &event.type_;
+        let event_parsed_json = &event.parsed_json;
+        let computing_pool = self
+            .computing_pool
+            .as_mut()
+            .context("computing_pool is None")?;
+
+        let package_id = &self.parent_exchange.package_id;
+
+        let swapped_event_type = StructTag::from_str(
+                &format!("{}::event::SwappedEvent", package_id)
+            ).context("FlameSwap: failed to create event struct tag")?;
+
+        let added_event_type = StructTag::from_str(
+                &format!("{}::event::AddedEvent", package_id)
+            ).context("FlameSwap: failed to create event struct tag")?;
+
+        let removed_event_type = StructTag::from_str(
+                &format!("{}::event::RemovedEvent", package_id)
+            ).context("FlameSwap: failed to create event struct tag")?;
+
+        if type_ == &swapped_event_type {
+            let coin_x_in = parsed_json::get_u64(event_parsed_json, "coin_x_in")?;
+            let coin_x_out = parsed_json::get_u64(event_parsed_json, "coin_x_out")?;
+            let coin_y_in = parsed_json::get_u64(event_parsed_json, "coin_y_in")?;
+            let coin_y_out = parsed_json::get_u64(event_parsed_json, "coin_y_out")?;
+
+            // Only one side goes in
+            let x_to_y = coin_x_in > 0;
+            let (amount_in, amount_out, reserve_out) = if x_to_y {
+                (coin_x_in, coin_y_out, computing_pool.reserve_y)
+            } else {
+                (coin_y_in, coin_x_out, computing_pool.reserve_x)
+            };
+
+            // Can't pay out more than we think the pool holds
+            if amount_out > reserve_out {
+                return Ok(EventUpdate::Inconsistent);
+            }
+
+            computing_pool.apply_swap_effects(
+                x_to_y,
+                amount_in,
+                amount_out
+            );
+        } else if type_ == &added_event_type {
+            let coin_x_val = parsed_json::get_u64(event_parsed_json, "coin_x_val")?;
+            let coin_y_val = parsed_json::get_u64(event_parsed_json, "coin_y_val")?;
+
+            computing_pool.apply_add_liquidity_effects(
+                coin_x_val,
+                coin_y_val
+            );
+        } else if type_ == &removed_event_type {
+            let coin_x_val = parsed_json::get_u64(event_parsed_json, "coin_x_val")?;
+            let coin_y_val = parsed_json::get_u64(event_parsed_json, "coin_y_val")?;
+
+            if coin_x_val > computing_pool.reserve_x || coin_y_val > computing_pool.reserve_y {
+                return Ok(EventUpdate::Inconsistent);
+            }
+
+            computing_pool.apply_remove_liquidity_effects(
+                coin_x_val,
+                coin_y_val
+            );
+        } else {
+            return Ok(EventUpdate::Ignored);
+        }
+
+        Ok(EventUpdate::Applied)
+    }
+
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.computing_pool = pool_state
+            .map(|pool_state| {
+                match pool_state {
+                    PoolState::ConstantProduct { reserve_x, reserve_y, protocol_fee, lp_fee, unlocked } => {
+                        Ok(
+                            fast_v2_pool::Pool {
+                                id: self.pool_id.clone(),
+                                reserve_x: *reserve_x,
+                                reserve_y: *reserve_y,
+                                protocol_fee: *protocol_fee,
+                                lp_fee: *lp_fee,
+                                unlocked: *unlocked,
+                            }
+                        )
+                    },
+                    _ => {
+                        Err(anyhow!("FlameSwap pool {} can only take constant product pool state", self.pool_id))
+                    }
+                }
+            })
+            .transpose()?;
+
+        Ok(())
+    }
+
+    fn market_record(&self) -> MarketRecord {
+        MarketRecord {
+            package_id: self.package_id().clone(),
+            pool_id: self.pool_id.clone(),
+            coin_x: self.coin_x.clone(),
+            coin_y: self.coin_y.clone(),
+            extra_type_args: vec![],
+            pool_state: self.computing_pool.as_ref().map(|cp| {
+                PoolState::ConstantProduct {
+                    reserve_x: cp.reserve_x,
+                    reserve_y: cp.reserve_y,
+                    protocol_fee: cp.protocol_fee,
+                    lp_fee: cp.lp_fee,
+                    unlocked: cp.unlocked,
+                }
+            }),
+        }
+    }
+
+    fn pool_id(&self) -> &ObjectID {
+        &self.pool_id
+    }
+
+    fn package_id(&self) -> &ObjectID {
+        &self.parent_exchange.package_id
+    }
+
+    fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
+        let (amount_x_delta, amount_y_delta) = self
+            .computing_pool
+            .as_ref()
+            .unwrap()
+            .calc_swap_exact_amount_in(
+                amount_specified as u64,
+                true
+            );
+
+        (amount_x_delta as u128, amount_y_delta as u128)
+    }
+
+    fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
+        let (amount_x_delta, amount_y_delta) = self
+            .computing_pool
+            .as_ref()
+            .unwrap()
+            .calc_swap_exact_amount_in(
+                amount_specified as u64,
+                false
+            );
+
+        (amount_x_delta as u128, amount_y_delta as u128)
+    }
+
+    // Moves the pool as if the swap had landed. Returns the same as compute_swap_x_to_y.
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        // Nothing to move until the pool has synced
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_x_to_y(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            // Fees are taken out of the full amount in
+            computing_pool.apply_swap_effects(
+                true,
+                amount_specified as u64,
+                amounts.1 as u64
+            );
+        }
+
+        amounts
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_y_to_x(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            computing_pool.apply_swap_effects(
+                false,
+                amount_specified as u64,
+                amounts.0 as u64
+            );
+        }
+
+        amounts
+    }
+
+    // The interface module's entry functions transfer the output to the sender.
+    // implements::swap_out hands it back so legs can be chained.
+    async fn add_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument, // the coin we swap in its entirety
+        x_to_y: bool,
+        amount_out: u128,
+    ) -> Result<Argument, anyhow::Error> {
+        let (orig_coin_type, dest_coin_type) = if x_to_y {
+            (self.coin_x.clone(), self.coin_y.clone())
+        } else {
+            (self.coin_y.clone(), self.coin_x.clone())
+        };
+
+        // Arg0: &mut Global
+        let global_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.parent_exchange.global_id.clone())
+        );
+
+        // Arg1: Coin<Ty0>
+        let orig_coin_arg = ProgrammableTransactionArg::Argument(orig_coin);
+
+        // Arg2: u64
+        // The minimum amount out we're expecting
+        let amount_threshold_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::U64(amount_out as u64)
+                )
+                .context("failed to convert MoveValue for coin_out_min to JSON")?
+            )?
+        );
+
+        // Arg3: bool
+        // Whether the type arguments are in the pool's order
+        let is_order_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::Bool(x_to_y)
+                )
+                .context("failed to convert MoveValue for is_order to JSON")?
+            )?
+        );
+
+        let call_args = vec![
+            global_arg,
+            orig_coin_arg,
+            amount_threshold_arg,
+            is_order_arg
+        ];
+
+        // Swaps are typed by the coin going in then the coin coming out
+        let type_args = vec![
+            SuiTypeTag::new(format!("{}", orig_coin_type)),
+            SuiTypeTag::new(format!("{}", dest_coin_type)),
+        ];
+
+        let dest_coin_out = transaction_builder.programmable_move_call(
+            pt_builder,
+            self.parent_exchange.package_id.clone(),
+            "implements",
+            "swap_out",
+            type_args,
+            call_args
+        ).await?;
+
+        Ok(dest_coin_out)
+    }
+
+    fn viable(&self) -> bool {
+        if let Some(cp) = &self.computing_pool {
+            cp.reserve_x > 0 && cp.reserve_y > 0 && cp.unlocked
+        } else {
+            false
+        }
+    }
+
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        let cp = self.computing_pool.as_ref()?;
+
+        let (reserve_in, reserve_out) = if x_to_y {
+            (cp.reserve_x, cp.reserve_y)
+        } else {
+            (cp.reserve_y, cp.reserve_x)
+        };
+
+        Some(
+            ConstantProductCurve {
+                reserve_in: reserve_in as f64,
+                reserve_out: reserve_out as f64,
+                fee_multiplier: cp.fee_multiplier(),
+                exact: true,
+            }
+        )
+    }
+}
+
+#[async_trait]
+impl Market for FlameSwapMarket {
+    fn coin_x(&self) -> &TypeTag {
+        self.coin_x()
+    }
+
+    fn coin_y(&self) -> &TypeTag {
+        self.coin_y()
+    }
+
+    fn coin_x_price(&self) -> Option<U64F64> {
+        self.coin_x_price()
+    }
+
+    fn coin_y_price(&self) -> Option<U64F64> {
+        self.coin_y_price()
+    }
+
+    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
+        self.update_with_object_response(sui_client, object_response).await
+    }
+
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
+
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.update_with_pool_state(pool_state)
+    }
+
+    fn market_record(&self) -> MarketRecord {
+        self.market_record()
+    }
+
+    fn pool_id(&self) -> &ObjectID {
+        self.pool_id()
+    }
+
+    fn package_id(&self) -> &ObjectID {
+        self.package_id()
+    }
+
+    fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
+        self.compute_swap_x_to_y(amount_specified)
+    }
+
+    fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
+        self.compute_swap_y_to_x(amount_specified)
+    }
+
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_x_to_y(amount_specified)
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_y_to_x(amount_specified)
+    }
+
+    fn viable(&self) -> bool {
+        self.viable()
+    }
+
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        self.constant_product_curve(x_to_y)
+    }
+
+    async fn add_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument, // split off of our own coins or the output coin of the previous leg
+        x_to_y: bool,
+        _amount_in: u128,
+        amount_out: u128,
+        _recipient: SuiAddress
+    ) -> Result<Argument, anyhow::Error> {
+        self.add_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            orig_coin,
+            x_to_y,
+            amount_out,
+        )
+        .await
+    }
+
+    fn supports_exact_output(&self) -> bool {
+        false
+    }
+
+    async fn add_exact_output_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _orig_coin: Argument,
+        _x_to_y: bool,
+        _amount_out: u128,
+        _recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        Err(anyhow!("FlameSwap pools don't support exact output swaps"))
+    }
+
+    // FlameSwap pools only swap coins we already hold
+    fn supports_flash_swap(&self) -> bool {
+        false
+    }
+
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _x_to_y: bool,
+        _amount_in: u128,
+        _recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        Err(anyhow!("FlameSwap pools don't support flash swaps"))
+    }
+
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _receipt: Argument,
+        _pay_coin: Argument,
+        _x_to_y: bool,
+        _amount_in: u128
+    ) -> Result<(), anyhow::Error> {
+        Err(anyhow!("FlameSwap pools don't support flash swaps"))
+    }
+}
+
+// Pools are dynamic fields so the object is a Field<String, Pool<Ty0, Ty1>>
+fn get_coin_pair_from_object_response(
+    object_response: &SuiObjectResponse
+) -> Result<(TypeTag, TypeTag), anyhow::Error> {
+    let data = object_response.data.as_ref().context("Expected Some")?;
+    let type_ = data.type_.as_ref().context("Expected Some")?;
+
+    if let ObjectType::Struct(move_object_type) = type_ {
+        if let TypeTag::Struct(pool_struct_tag) = move_object_type
+            .type_params()
+            .get(1)
+            .context("Missing pool type parameter")?
+        {
+            Ok(
+                (
+                    pool_struct_tag.type_params.get(0).context("Missing coin_x")?.clone(),
+                    pool_struct_tag.type_params.get(1).context("Missing coin_y")?.clone(),
+                )
+            )
+        } else {
+            Err(anyhow!("Does not match the TypeTag::Struct variant"))
+        }
+    } else {
+        Err(anyhow!("Does not match the ObjectType::Struct variant"))
+    }
 }
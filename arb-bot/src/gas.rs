@@ -0,0 +1,302 @@
+use custom_sui_sdk::SuiClient;
+
+use move_core_types::language_storage::TypeTag;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use sui_sdk::SUI_COIN_TYPE;
+use sui_sdk::types::base_types::ObjectID;
+use sui_sdk::types::gas::GasCostSummary;
+
+use crate::arbitrage::DirectedLeg;
+use crate::market_graph::MarketGraph;
+
+// The reference gas price only changes once an epoch
+const REFERENCE_GAS_PRICE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Weight given to a new observation
+const LEARNING_RATE: f64 = 0.2;
+
+// Starting guesses until we've seen a transaction land.
+// Roughly what the old 7_000_000 MIST per leg threshold assumed.
+const DEFAULT_TRANSACTION_COST: GasCost = GasCost {
+    computation_units: 1_000.,
+    net_storage_cost: 1_000_000.,
+};
+const DEFAULT_LEG_COST: GasCost = GasCost {
+    computation_units: 5_000.,
+    net_storage_cost: 1_000_000.,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct GasCost {
+    // Computation is charged per unit at the reference gas price
+    computation_units: f64,
+    // Storage cost less the rebate, in MIST. Independent of the gas price.
+    net_storage_cost: f64,
+}
+
+// Learns what a transaction and a swap on each exchange cost from our own
+// dry runs and executions so that opportunities can be ranked by net profit.
+#[derive(Debug, Clone)]
+pub struct GasEstimator {
+    reference_gas_price: u64,
+    reference_gas_price_updated_at: Option<Instant>,
+    // Fixed overhead of a transaction: gas coin, coin splits and merges, transfers
+    transaction_cost: GasCost,
+    // Per leg cost keyed by the package id of the market
+    package_id_to_leg_cost: HashMap<ObjectID, GasCost>,
+}
+
+impl GasEstimator {
+    pub fn new() -> Self {
+        GasEstimator {
+            reference_gas_price: 0,
+            reference_gas_price_updated_at: None,
+            transaction_cost: DEFAULT_TRANSACTION_COST,
+            package_id_to_leg_cost: HashMap::new(),
+        }
+    }
+
+    pub fn reference_gas_price(&self) -> u64 {
+        self.reference_gas_price
+    }
+
+    // For when there's no network to ask, like backtesting
+    pub fn set_reference_gas_price(&mut self, reference_gas_price: u64) {
+        self.reference_gas_price = reference_gas_price;
+        self.reference_gas_price_updated_at = Some(Instant::now());
+    }
+
+    pub async fn update_reference_gas_price(&mut self, sui_client: &SuiClient) -> Result<(), anyhow::Error> {
+        if let Some(updated_at) = self.reference_gas_price_updated_at {
+            if updated_at.elapsed() < REFERENCE_GAS_PRICE_REFRESH_INTERVAL {
+                return Ok(());
+            }
+        }
+
+        self.reference_gas_price = sui_client
+            .governance_api()
+            .get_reference_gas_price()
+            .await?;
+        self.reference_gas_price_updated_at = Some(Instant::now());
+
+        Ok(())
+    }
+
+    fn leg_cost(&self, package_id: &ObjectID) -> GasCost {
+        *self.package_id_to_leg_cost
+            .get(package_id)
+            .unwrap_or(&DEFAULT_LEG_COST)
+    }
+
+    fn predicted_cost(&self, package_ids: &[ObjectID]) -> GasCost {
+        package_ids
+            .iter()
+            .fold(self.transaction_cost, |cost, package_id| {
+                let leg_cost = self.leg_cost(package_id);
+
+                GasCost {
+                    computation_units: cost.computation_units + leg_cost.computation_units,
+                    net_storage_cost: cost.net_storage_cost + leg_cost.net_storage_cost,
+                }
+            })
+    }
+
+    // Expected gas in MIST for a transaction swapping through markets of the given packages
+    pub fn estimate_transaction_gas(&self, package_ids: &[ObjectID]) -> u128 {
+        let cost = self.predicted_cost(package_ids);
+
+        let gas = cost.computation_units * self.reference_gas_price as f64 + cost.net_storage_cost;
+
+        gas.max(0.) as u128
+    }
+
+    // Expected gas in MIST to execute a route.
+    // Atomic execution is a single transaction. Otherwise every leg is its own transaction.
+    pub fn estimate_route_gas(&self, route: &[Vec<DirectedLeg>], atomic: bool) -> u128 {
+        let package_ids = route
+            .iter()
+            .flatten()
+            .map(|leg| {
+                leg.market.package_id().clone()
+            })
+            .collect::<Vec<ObjectID>>();
+
+        if atomic {
+            self.estimate_transaction_gas(&package_ids)
+        } else {
+            package_ids
+                .iter()
+                .map(|package_id| {
+                    self.estimate_transaction_gas(&[package_id.clone()])
+                })
+                .sum()
+        }
+    }
+
+    // Learn from the gas used by a dry run or an executed transaction.
+    // The cost is linear in the overhead and the number of legs on each package,
+    // so the difference from our prediction is split by a normalized least squares step.
+    // Single package observations pin down that package, mixed ones separate the rest.
+    pub fn observe(&mut self, package_ids: &[ObjectID], gas_price: u64, gas_cost_summary: &GasCostSummary) {
+        if gas_price == 0 {
+            return;
+        }
+
+        let observed_computation_units = gas_cost_summary.computation_cost as f64 / gas_price as f64;
+        // Negative when the rebate outweighs what the transaction stored
+        let observed_net_storage_cost = gas_cost_summary.storage_cost as f64 - gas_cost_summary.storage_rebate as f64;
+
+        let predicted = self.predicted_cost(package_ids);
+
+        let computation_residual = observed_computation_units - predicted.computation_units;
+        let storage_residual = observed_net_storage_cost - predicted.net_storage_cost;
+
+        let mut package_id_to_count: HashMap<ObjectID, f64> = HashMap::new();
+        for package_id in package_ids {
+            *package_id_to_count.entry(package_id.clone()).or_insert(0.) += 1.;
+        }
+
+        // The overhead is in every transaction
+        let norm = 1. + package_id_to_count
+            .values()
+            .map(|count| {
+                count * count
+            })
+            .sum::<f64>();
+
+        // Clamped so a large rebate can't make a swap look like it pays for itself
+        let learn = |cost: GasCost, weight: f64| {
+            GasCost {
+                computation_units: (cost.computation_units + LEARNING_RATE * computation_residual * weight / norm).max(0.),
+                net_storage_cost: (cost.net_storage_cost + LEARNING_RATE * storage_residual * weight / norm).max(0.),
+            }
+        };
+
+        let leg_costs = package_id_to_count
+            .iter()
+            .map(|(package_id, count)| {
+                (package_id.clone(), learn(self.leg_cost(package_id), *count))
+            })
+            .collect::<Vec<(ObjectID, GasCost)>>();
+
+        self.package_id_to_leg_cost.extend(leg_costs);
+        self.transaction_cost = learn(self.transaction_cost, 1.);
+    }
+}
+
+// Converts a gas amount in MIST to the source coin using the deepest
+// direct SUI market. None if the source coin can't be priced in SUI.
+pub fn gas_in_source_coin(market_graph: &MarketGraph, source_coin: &TypeTag, gas: u128) -> Option<u128> {
+    let sui = TypeTag::from_str(SUI_COIN_TYPE).ok()?;
+
+    market_graph.convert_amount(gas, &sui, source_coin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAS_PRICE: u64 = 750;
+
+    fn gas_cost_summary(computation_units: u64, storage_cost: u64, storage_rebate: u64) -> GasCostSummary {
+        GasCostSummary {
+            computation_cost: computation_units * GAS_PRICE,
+            storage_cost,
+            storage_rebate,
+            non_refundable_storage_fee: 0,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= expected.abs() * 0.01 + 1., "actual: {}, expected: {}", actual, expected);
+    }
+
+    #[test]
+    fn test_predicted_cost_sums_overhead_and_legs() {
+        let cetus = ObjectID::from_single_byte(1);
+        let kriya = ObjectID::from_single_byte(2);
+
+        let mut gas_estimator = GasEstimator::new();
+        gas_estimator.set_reference_gas_price(GAS_PRICE);
+        gas_estimator.package_id_to_leg_cost.insert(
+            cetus,
+            GasCost {
+                computation_units: 30_000.,
+                net_storage_cost: 2_000_000.,
+            }
+        );
+
+        let cost = gas_estimator.predicted_cost(&[cetus, cetus, kriya]);
+
+        assert_close(
+            cost.computation_units,
+            DEFAULT_TRANSACTION_COST.computation_units + 2. * 30_000. + DEFAULT_LEG_COST.computation_units
+        );
+        assert_close(
+            cost.net_storage_cost,
+            DEFAULT_TRANSACTION_COST.net_storage_cost + 2. * 2_000_000. + DEFAULT_LEG_COST.net_storage_cost
+        );
+
+        assert_eq!(
+            gas_estimator.estimate_transaction_gas(&[cetus, cetus, kriya]),
+            (cost.computation_units * GAS_PRICE as f64 + cost.net_storage_cost) as u128
+        );
+    }
+
+    #[test]
+    fn test_observe_converges_per_package() {
+        let cetus = ObjectID::from_single_byte(1);
+        let kriya = ObjectID::from_single_byte(2);
+
+        // Overhead 2_000 units, Cetus legs 30_000 units, Kriya legs 4_000 units
+        let cetus_only = gas_cost_summary(32_000, 5_000_000, 1_000_000);
+        let kriya_only = gas_cost_summary(6_000, 2_500_000, 1_000_000);
+        let mixed = gas_cost_summary(36_000, 5_500_000, 1_000_000);
+
+        let mut gas_estimator = GasEstimator::new();
+
+        for _ in 0..500 {
+            gas_estimator.observe(&[cetus], GAS_PRICE, &cetus_only);
+            gas_estimator.observe(&[kriya], GAS_PRICE, &kriya_only);
+            gas_estimator.observe(&[cetus, kriya], GAS_PRICE, &mixed);
+        }
+
+        assert_close(gas_estimator.transaction_cost.computation_units, 2_000.);
+        assert_close(gas_estimator.leg_cost(&cetus).computation_units, 30_000.);
+        assert_close(gas_estimator.leg_cost(&kriya).computation_units, 4_000.);
+
+        assert_close(gas_estimator.transaction_cost.net_storage_cost, 1_000_000.);
+        assert_close(gas_estimator.leg_cost(&cetus).net_storage_cost, 3_000_000.);
+        assert_close(gas_estimator.leg_cost(&kriya).net_storage_cost, 500_000.);
+    }
+
+    #[test]
+    fn test_observe_rebate_keeps_costs_non_negative() {
+        let cetus = ObjectID::from_single_byte(1);
+
+        let mut gas_estimator = GasEstimator::new();
+        gas_estimator.set_reference_gas_price(GAS_PRICE);
+
+        // Rebate larger than what was stored
+        let rebated = gas_cost_summary(10_000, 1_000_000, 50_000_000);
+
+        for _ in 0..100 {
+            gas_estimator.observe(&[cetus], GAS_PRICE, &rebated);
+
+            assert!(gas_estimator.transaction_cost.net_storage_cost >= 0.);
+            assert!(gas_estimator.leg_cost(&cetus).net_storage_cost >= 0.);
+        }
+
+        assert_eq!(gas_estimator.transaction_cost.net_storage_cost, 0.);
+        assert_eq!(gas_estimator.leg_cost(&cetus).net_storage_cost, 0.);
+
+        // Computation is still learned and the estimate is never below it
+        let computation_units = gas_estimator.predicted_cost(&[cetus]).computation_units;
+        assert_close(computation_units, 10_000.);
+        assert!(gas_estimator.estimate_transaction_gas(&[cetus]) >= (computation_units * GAS_PRICE as f64) as u128);
+    }
+}
@@ -22,6 +22,7 @@ use custom_sui_sdk::{
 use sui_sdk::types::{base_types::{ObjectID, ObjectIDParseError, ObjectType, SuiAddress}, object::Object};
 use sui_sdk::types::dynamic_field::DynamicFieldInfo;
 use sui_sdk::types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_sdk::types::transaction::Argument;
 use sui_sdk::rpc_types::{
     SuiObjectResponse, 
     EventFilter, 
@@ -37,11 +38,13 @@ use move_core_types::account_address::AccountAddress;
 use move_core_types::value::MoveValue;
 use std::collections::{BTreeMap, HashMap, HashSet};
 
-use crate::markets::{Exchange, Market};
+use crate::markets::{Exchange, Market, EventUpdate, MarketRecord, ConstantProductCurve, try_parse_pool_id_from_event};
 use crate::sui_sdk_utils::{self, sui_move_value};
 use crate::fast_v2_pool;
 use crate::fast_cronje_pool;
-use crate::sui_json_utils::move_value_to_json;
+use crate::sui_json_utils::{move_value_to_json, parsed_json};
+
+use pool_state::PoolState;
 
 #[derive(Debug, Clone)]
 pub struct KriyaDex {
@@ -51,10 +54,20 @@ pub struct KriyaDex {
 
 impl KriyaDex {
     pub fn new(package_id: ObjectID) -> Self {
+        let mut event_struct_tag_to_pool_field = HashMap::new();
+        // Swap events are typed by coin so they're added once we know the markets
+        for event_name in ["LiquidityAddedEvent", "LiquidityRemovedEvent"] {
+            event_struct_tag_to_pool_field.insert(
+                StructTag::from_str(
+                    &format!("{}::spot_dex::{}", package_id, event_name)
+                ).expect("KriyaDEX: failed to create event struct tag"),
+                "pool_id".to_string()
+            );
+        }
 
         KriyaDex {
             package_id,
-            event_struct_tag_to_pool_field: HashMap::new(),
+            event_struct_tag_to_pool_field,
         }
     }
 }
@@ -94,9 +107,7 @@ impl KriyaDex {
             .pages(
                 QueryEventsRequest {
                     query: EventFilter::MoveEventType(
-                        StructTag::from_str(
-                            &format!("{}::spot_dex::PoolCreatedEvent", self.package_id)
-                        )?
+                        self.pool_created_event_struct_tag()
                     ),
                     cursor: None,
                     limit: None,
@@ -112,13 +123,7 @@ impl KriyaDex {
         let pool_ids = pool_created_events
             .into_iter()
             .map(|pool_created_event| {
-                let parsed_json = pool_created_event.parsed_json;
-                if let Value::String(pool_id_value) = parsed_json.get("pool_id").context("Failed to get pool_id for a CetusMarket")? {
-                    // println!("turbos: pool_id: {}", pool_id_value);
-                    Ok(ObjectID::from_str(&format!("0x{}", pool_id_value))?)
-                } else {
-                    Err(anyhow!("Failed to match pattern."))
-                }
+                pool_id_from_pool_created_event(&pool_created_event)
             })
             .collect::<Result<Vec<ObjectID>, anyhow::Error>>()?;
 
@@ -127,46 +132,71 @@ impl KriyaDex {
             let markets = pool_id_to_object_response
                 .into_iter()
                 .map(|(pool_id, object_response)| {
-    
-                    let fields = sui_sdk_utils::read_fields_from_object_response(&object_response).context(format!("Missing fields for pool {}.", pool_id))?;
-    
-                    let (coin_x, coin_y) = get_coin_pair_from_object_response(&object_response)?;
-    
-                    // Add event filter struct tags
-                    self.event_struct_tag_to_pool_field.insert(
-                        StructTag::from_str(
-                            &format!("{}::spot_dex::SwapEvent<{}>", self.package_id, coin_x)
-                        )?,
-                        "pool_id".to_string()
-                    );
-
-                    self.event_struct_tag_to_pool_field.insert(
-                        StructTag::from_str(
-                            &format!("{}::spot_dex::SwapEvent<{}>", self.package_id, coin_y)
-                        )?,
-                        "pool_id".to_string()
-                    );
-
-                    Ok(
-                        Some(
-                            Box::new(
-                                KriyaDexMarket {
-                                    parent_exchange: self.clone(),  // reevaluate clone
-                                    coin_x,
-                                    coin_y,
-                                    pool_id,
-                                    computing_pool: None    // We'll grab this later so we don't have to deal with async stuff
-                                }
-                            ) as Box<dyn Market>
-                        )
-                    )
+                    self.market_from_object_response(pool_id, &object_response)
                 })
-                .filter_map(|x| x.transpose())
                 .collect::<Result<Vec<Box<dyn Market>>, anyhow::Error>>()?;
     
             Ok(markets)
     }
 
+    fn pool_created_event_struct_tag(&self) -> StructTag {
+        StructTag::from_str(
+            &format!("{}::spot_dex::PoolCreatedEvent", self.package_id)
+        ).expect("KriyaDEX: failed to create pool created event struct tag")
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        vec![EventFilter::MoveEventType(self.pool_created_event_struct_tag())]
+    }
+
+    // The coin types are only in the pool's type so the pool has to be fetched
+    async fn market_from_pool_created_event_(&mut self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        if event.type_ != self.pool_created_event_struct_tag() {
+            return Ok(None);
+        }
+
+        let pool_id = pool_id_from_pool_created_event(event)?;
+
+        let object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &[pool_id])
+            .await?
+            .remove(&pool_id)
+            .context(format!("Missing object response for new pool {}", pool_id))?;
+
+        Ok(Some(self.market_from_object_response(pool_id, &object_response)?))
+    }
+
+    // Swap events are typed by coin so this adds the market's to our events
+    fn market_from_object_response(&mut self, pool_id: ObjectID, object_response: &SuiObjectResponse) -> Result<Box<dyn Market>, anyhow::Error> {
+        let (coin_x, coin_y) = get_coin_pair_from_object_response(object_response)?;
+
+        // Add event filter struct tags
+        self.event_struct_tag_to_pool_field.insert(
+            StructTag::from_str(
+                &format!("{}::spot_dex::SwapEvent<{}>", self.package_id, coin_x)
+            )?,
+            "pool_id".to_string()
+        );
+
+        self.event_struct_tag_to_pool_field.insert(
+            StructTag::from_str(
+                &format!("{}::spot_dex::SwapEvent<{}>", self.package_id, coin_y)
+            )?,
+            "pool_id".to_string()
+        );
+
+        Ok(
+            Box::new(
+                KriyaDexMarket {
+                    parent_exchange: self.clone(),  // reevaluate clone
+                    coin_x,
+                    coin_y,
+                    pool_id,
+                    computing_pool: None    // We'll grab this later so we don't have to deal with async stuff
+                }
+            ) as Box<dyn Market>
+        )
+    }
+
     async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
         let pool_ids = markets
             .iter()
@@ -268,6 +298,20 @@ impl KriyaDex {
             pool
         )
     }
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        let mut market = KriyaDexMarket {
+            parent_exchange: self.clone(),
+            coin_x: market_record.coin_x.clone(),
+            coin_y: market_record.coin_y.clone(),
+            pool_id: market_record.pool_id.clone(),
+            computing_pool: None
+        };
+
+        market.update_with_pool_state(market_record.pool_state.as_ref())?;
+
+        Ok(Box::new(market))
+    }
 }
 
 #[async_trait]
@@ -288,6 +332,10 @@ impl Exchange for KriyaDex {
         self.event_struct_tag_to_pool_field()
     }
 
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error> {
+        try_parse_pool_id_from_event(self.event_struct_tag_to_pool_field(), event)
+    }
+
     async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
         self.get_all_markets_(sui_client).await
     }
@@ -295,6 +343,18 @@ impl Exchange for KriyaDex {
     async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
         self.get_pool_id_to_object_response(sui_client, markets).await
     }
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        self.market_from_record(market_record)
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        self.pool_created_event_filters()
+    }
+
+    async fn market_from_pool_created_event(&mut self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        self.market_from_pool_created_event_(sui_client, event).await
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -304,6 +364,70 @@ pub enum KriyaComputingPool {
 }
 
 impl KriyaComputingPool {
+    // A pool from the state served by a librarian
+    fn from_pool_state(id: ObjectID, pool_state: &PoolState) -> Result<Self, anyhow::Error> {
+        match pool_state {
+            PoolState::ConstantProduct { reserve_x, reserve_y, protocol_fee, lp_fee, unlocked } => {
+                Ok(
+                    KriyaComputingPool::Uncorrelated(
+                        fast_v2_pool::Pool {
+                            id,
+                            reserve_x: *reserve_x,
+                            reserve_y: *reserve_y,
+                            protocol_fee: *protocol_fee,
+                            lp_fee: *lp_fee,
+                            unlocked: *unlocked,
+                        }
+                    )
+                )
+            },
+            PoolState::Stable { reserve_x, reserve_y, protocol_fee, lp_fee, scale_x, scale_y, unlocked } => {
+                Ok(
+                    KriyaComputingPool::Stable(
+                        fast_cronje_pool::Pool {
+                            id,
+                            reserve_x: *reserve_x,
+                            reserve_y: *reserve_y,
+                            protocol_fee: *protocol_fee,
+                            lp_fee: *lp_fee,
+                            scale_x: *scale_x,
+                            scale_y: *scale_y,
+                            unlocked: *unlocked,
+                        }
+                    )
+                )
+            },
+            PoolState::ConcentratedLiquidity { .. } => {
+                Err(anyhow!("KriyaDex pool {} can't take concentrated liquidity pool state", id))
+            }
+        }
+    }
+
+    fn to_pool_state(&self) -> PoolState {
+        match self {
+            KriyaComputingPool::Uncorrelated(cp) => {
+                PoolState::ConstantProduct {
+                    reserve_x: cp.reserve_x,
+                    reserve_y: cp.reserve_y,
+                    protocol_fee: cp.protocol_fee,
+                    lp_fee: cp.lp_fee,
+                    unlocked: cp.unlocked,
+                }
+            },
+            KriyaComputingPool::Stable(cp) => {
+                PoolState::Stable {
+                    reserve_x: cp.reserve_x,
+                    reserve_y: cp.reserve_y,
+                    protocol_fee: cp.protocol_fee,
+                    lp_fee: cp.lp_fee,
+                    scale_x: cp.scale_x,
+                    scale_y: cp.scale_y,
+                    unlocked: cp.unlocked,
+                }
+            },
+        }
+    }
+
     fn coin_x_price(&self) -> U64F64 {
         match self {
             KriyaComputingPool::Uncorrelated(cp) => {
@@ -475,7 +599,7 @@ impl KriyaDexMarket {
         Ok(())
     }
 
-    fn update_with_event(&mut self, event: &SuiEvent) -> Result<(), anyhow::Error> {
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
         let type_ = &event.type_;
         let event_parsed_json = &event.parsed_json;
         let computing_pool = self
@@ -504,109 +628,74 @@ impl KriyaDexMarket {
         //         &format!("{}::spot_dex::ConfigUpdatedEvent", &self.parent_exchange.package_id)
         //     ).context("KriyaDEX: failed to create event struct tag")?;
 
-        match type_ {
-            swap_coin_x_event_type => {
-                let amount_in = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_in").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_out = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_out").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
-
-                computing_pool.apply_swap_effects(
-                    true,
-                    amount_in,
-                    amount_out
-                );
+        if type_ == &swap_coin_x_event_type || type_ == &swap_coin_y_event_type {
+            // The event is typed by the coin going in
+            let x_to_y = type_ == &swap_coin_x_event_type;
+            let amount_in = parsed_json::get_u64(event_parsed_json, "amount_in")?;
+            let amount_out = parsed_json::get_u64(event_parsed_json, "amount_out")?;
 
-            },
-            swap_coin_y_event_type => {
-                let amount_in = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_in").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_out = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_out").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
-
-                // coin x
-                let x_to_y = false;
-                
-                computing_pool.apply_swap_effects(
-                    x_to_y,
-                    amount_in,
-                    amount_out
-                );
+            let reserve_out = if x_to_y {
+                computing_pool.reserve_y()
+            } else {
+                computing_pool.reserve_x()
+            };
 
-            },
-            add_liq_event_type => {
-                let amount_x = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_x").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_y = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_y").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
-
-                computing_pool.apply_add_liquidity_effects(
-                    amount_x,
-                    amount_y
-                );
-            },
-            remove_liq_event_type => {
-                let amount_x = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_x").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_y = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_y").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
-
-                computing_pool.apply_remove_liquidity_effects(
-                    amount_x,
-                    amount_y
-                );
-            },
-            // update_config_event_type => {
-            //     // return Err(anyhow!());
-            //     // This is an update to all pools....
-            // },
-            _ => {
-                // do nothing
+            // Can't pay out more than we think the pool holds
+            if amount_out > reserve_out {
+                return Ok(EventUpdate::Inconsistent);
+            }
+
+            computing_pool.apply_swap_effects(
+                x_to_y,
+                amount_in,
+                amount_out
+            );
+        } else if type_ == &add_liq_event_type {
+            let amount_x = parsed_json::get_u64(event_parsed_json, "amount_x")?;
+            let amount_y = parsed_json::get_u64(event_parsed_json, "amount_y")?;
+
+            computing_pool.apply_add_liquidity_effects(
+                amount_x,
+                amount_y
+            );
+        } else if type_ == &remove_liq_event_type {
+            let amount_x = parsed_json::get_u64(event_parsed_json, "amount_x")?;
+            let amount_y = parsed_json::get_u64(event_parsed_json, "amount_y")?;
+
+            if amount_x > computing_pool.reserve_x() || amount_y > computing_pool.reserve_y() {
+                return Ok(EventUpdate::Inconsistent);
             }
+
+            computing_pool.apply_remove_liquidity_effects(
+                amount_x,
+                amount_y
+            );
+        } else {
+            return Ok(EventUpdate::Ignored);
         }
 
+        Ok(EventUpdate::Applied)
+    }
+
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.computing_pool = pool_state
+            .map(|pool_state| {
+                KriyaComputingPool::from_pool_state(self.pool_id.clone(), pool_state)
+            })
+            .transpose()?;
+
         Ok(())
+    }
 
+    fn market_record(&self) -> MarketRecord {
+        MarketRecord {
+            package_id: self.package_id().clone(),
+            pool_id: self.pool_id.clone(),
+            coin_x: self.coin_x.clone(),
+            coin_y: self.coin_y.clone(),
+            extra_type_args: vec![],
+            pool_state: self.computing_pool.as_ref().map(|cp| cp.to_pool_state()),
+        }
     }
 
     fn pool_id(&self) -> &ObjectID {
@@ -651,76 +740,87 @@ impl KriyaDexMarket {
         (amount_x_delta as u128, amount_y_delta as u128)
     }
 
+    // Moves the pool as if the swap had landed. Returns the same as compute_swap_x_to_y.
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        // Nothing to move until the pool has synced
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_x_to_y(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            // Fees are taken out of the full amount in
+            computing_pool.apply_swap_effects(
+                true,
+                amount_specified as u64,
+                amounts.1 as u64
+            );
+        }
+
+        amounts
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_y_to_x(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            computing_pool.apply_swap_effects(
+                false,
+                amount_specified as u64,
+                amounts.0 as u64
+            );
+        }
+
+        amounts
+    }
+
     async fn add_swap_to_programmable_transaction(
         &self,
         transaction_builder: &TransactionBuilder,
         pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
+        orig_coin: Argument, // the coin we swap in its entirety
         x_to_y: bool,
-        amount_in: u128,
         amount_out: u128,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<Argument, anyhow::Error> {
 
         // Arg0: &mut Pool
-        // Arg1: Coin<> as single coin (we need to merge)
+        // Arg1: Coin<> as single coin
         // Arg2: u64 amount_in
         // Arg3: u64 min_amount_out
 
         let coin_x_sui_type_tag = SuiTypeTag::new(format!("{}", self.coin_x));
         let coin_y_sui_type_tag = SuiTypeTag::new(format!("{}", self.coin_y));
 
+        let orig_coin_type = if x_to_y {
+            self.coin_x.clone()
+        } else {
+            self.coin_y.clone()
+        };
+
         let pool_arg = ProgrammableTransactionArg::SuiJsonValue(
             SuiJsonValue::from_object_id(self.pool_id.clone())
         );
 
-        // Arg1: &mut Pool
-        // The coins to merge into ther first coin
-        let orig_coin_arg = if let Some(mut orig_coins_to_merge) = orig_coins {
-            
-            let primary_coin_id = orig_coins_to_merge.pop().context("No primary coin id to pop.")?;
-
-            // Remaining coins to merge
-            if orig_coins_to_merge.len() > 0 {
-                let coins_to_merge_obj_args = orig_coins_to_merge
-                    .into_iter()
-                    .map(|coin_to_merge| {
-                        ProgrammableObjectArg::ObjectID(coin_to_merge.clone())
-                    })
-                    .collect::<Vec<ProgrammableObjectArg>>();
-
-                let primary_coin_obj_arg = ProgrammableObjectArg::ObjectID(
-                    primary_coin_id
-                );
-
-                ProgrammableTransactionArg::Argument(
-                    transaction_builder.programmable_merge_coins(
-                        pt_builder,
-                        primary_coin_obj_arg.clone(),
-                        coins_to_merge_obj_args,
-                    ).await?
-                )
-            } else {
-                ProgrammableTransactionArg::SuiJsonValue(
-                    SuiJsonValue::from_object_id(primary_coin_id)
-                )
-            }
-        } else {
-            ProgrammableTransactionArg::Argument(
-                transaction_builder.programmable_split_gas_coin(pt_builder, amount_in as u64).await
-            )
-        };
-
         // Arg2: u64
-        // The amount in
-        let amount_specified_arg = ProgrammableTransactionArg::SuiJsonValue(
-            SuiJsonValue::new(
-                move_value_to_json(
-                    &MoveValue::U64(amount_in as u64)
-                )
-                .context("failed to convert MoveValue for amount_specified to JSON")?
-            )?
+        // The amount in. Whatever is in the coin we were handed.
+        // Read before the coin is moved into the swap below.
+        let amount_specified_arg = ProgrammableTransactionArg::Argument(
+            transaction_builder
+                .programmable_coin_value(
+                    pt_builder,
+                    orig_coin_type,
+                    orig_coin
+                ).await?
         );
 
+        // Arg1: Coin<>
+        let orig_coin_arg = ProgrammableTransactionArg::Argument(orig_coin);
+
         // Arg3: u64
         // The minimum amount out we're expecting 
         let amount_threshold_arg = ProgrammableTransactionArg::SuiJsonValue(
@@ -744,13 +844,14 @@ impl KriyaDexMarket {
             coin_y_sui_type_tag
         ];
 
+        // The non entry variants return the output coin
         let function = if x_to_y {
-            "swap_token_x_"
+            "swap_token_x"
         } else {
-            "swap_token_y_"
+            "swap_token_y"
         };
 
-        transaction_builder.programmable_move_call(
+        let dest_coin_out = transaction_builder.programmable_move_call(
             pt_builder,
             self.parent_exchange.package_id.clone(),
             "spot_dex",
@@ -759,7 +860,7 @@ impl KriyaDexMarket {
             call_args
         ).await?;
 
-        Ok(())
+        Ok(dest_coin_out)
     }
 
     fn viable(&self) -> bool {
@@ -769,6 +870,28 @@ impl KriyaDexMarket {
             false
         }
     }
+
+    // Stable pools follow a different invariant
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        if let Some(KriyaComputingPool::Uncorrelated(cp)) = &self.computing_pool {
+            let (reserve_in, reserve_out) = if x_to_y {
+                (cp.reserve_x, cp.reserve_y)
+            } else {
+                (cp.reserve_y, cp.reserve_x)
+            };
+
+            Some(
+                ConstantProductCurve {
+                    reserve_in: reserve_in as f64,
+                    reserve_out: reserve_out as f64,
+                    fee_multiplier: cp.fee_multiplier(),
+                    exact: true,
+                }
+            )
+        } else {
+            None
+        }
+    }
 }
 
 #[async_trait]
@@ -793,6 +916,18 @@ impl Market for KriyaDexMarket {
         self.update_with_object_response(sui_client, object_response).await
     }
 
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
+
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.update_with_pool_state(pool_state)
+    }
+
+    fn market_record(&self) -> MarketRecord {
+        self.market_record()
+    }
+
     fn pool_id(&self) -> &ObjectID {
         self.pool_id()
     }
@@ -809,30 +944,94 @@ impl Market for KriyaDexMarket {
         self.compute_swap_y_to_x(amount_specified)
     }
 
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_x_to_y(amount_specified)
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_y_to_x(amount_specified)
+    }
+
     fn viable(&self) -> bool {
         self.viable()
     }
 
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        self.constant_product_curve(x_to_y)
+    }
+
     async fn add_swap_to_programmable_transaction(
         &self,
         transaction_builder: &TransactionBuilder,
         pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
+        orig_coin: Argument, // split off of our own coins or the output coin of the previous leg
         x_to_y: bool,
-        amount_in: u128,
+        _amount_in: u128,
         amount_out: u128,
         recipient: SuiAddress
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<Argument, anyhow::Error> {
         self.add_swap_to_programmable_transaction(
             transaction_builder,
             pt_builder,
-            orig_coins,
+            orig_coin,
             x_to_y,
-            amount_in,
             amount_out,
         )
         .await
     }
+
+    fn supports_exact_output(&self) -> bool {
+        false
+    }
+
+    async fn add_exact_output_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _orig_coin: Argument,
+        _x_to_y: bool,
+        _amount_out: u128,
+        _recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        Err(anyhow!("KriyaDex pools don't support exact output swaps"))
+    }
+
+    // Kriya pools only swap coins we already hold
+    fn supports_flash_swap(&self) -> bool {
+        false
+    }
+
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _x_to_y: bool,
+        _amount_in: u128,
+        _recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        Err(anyhow!("KriyaDex pools don't support flash swaps"))
+    }
+
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _receipt: Argument,
+        _pay_coin: Argument,
+        _x_to_y: bool,
+        _amount_in: u128
+    ) -> Result<(), anyhow::Error> {
+        Err(anyhow!("KriyaDex pools don't support flash swaps"))
+    }
+}
+
+fn pool_id_from_pool_created_event(pool_created_event: &SuiEvent) -> Result<ObjectID, anyhow::Error> {
+    let parsed_json = &pool_created_event.parsed_json;
+    if let Value::String(pool_id_value) = parsed_json.get("pool_id").context("Failed to get pool_id for a KriyaDexMarket")? {
+        Ok(ObjectID::from_str(&format!("0x{}", pool_id_value))?)
+    } else {
+        Err(anyhow!("Failed to match pattern."))
+    }
 }
 
 fn get_coin_pair_from_object_response (
@@ -862,4 +1061,30 @@ fn get_coin_pair_from_object_response (
     } else {
         Err(anyhow!("Expected Some"))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_swap_unsynced() {
+        let exchange = KriyaDex::new(ObjectID::from_single_byte(1));
+
+        let mut market = exchange.market_from_record(
+            &MarketRecord {
+                package_id: exchange.package_id().clone(),
+                pool_id: ObjectID::from_single_byte(2),
+                coin_x: TypeTag::U8,
+                coin_y: TypeTag::U16,
+                extra_type_args: vec![],
+                pool_state: None,
+            }
+        )
+        .unwrap();
+
+        assert_eq!(market.apply_swap_x_to_y(1_000), (0, 0));
+        assert_eq!(market.apply_swap_y_to_x(1_000), (0, 0));
+        assert!(market.market_record().pool_state.is_none());
+    }
+}
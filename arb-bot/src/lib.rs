@@ -1,8 +1,8 @@
 // #![feature(async_fn_in_trait)]
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 use fixed::consts::E;
-use sui_sdk::rpc_types::EventFilter;
+use sui_sdk::rpc_types::{EventFilter, SuiEvent};
 
 use clap::Parser;
 
@@ -11,41 +11,53 @@ use custom_sui_sdk::SuiClient;
 
 use ethnum::I256;
 
-use futures::{StreamExt, future, FutureExt};
+use futures::{Stream, StreamExt, future, FutureExt};
 
 use move_core_types::language_storage::{TypeTag, StructTag};
 
 use rayon::prelude::*;
 
-use serde_json::Value;
 use sui_sdk::rpc_types::SuiObjectResponse;
 
 // use std::task::{Context, Poll};
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
 use std::time::{Instant, Duration};
 use std::collections::HashSet;
 use std::sync::mpsc;
 use std::thread;
 
 use sui_keys::keystore::{Keystore, AccountKeystore};
+
+use librarian::ipc::{Message, Subscription};
+use pool_state::PoolState;
+
+use crate::config::CycleDetector;
+use crate::simulation::SimulationContext;
+use crate::constants::MARKET_REFRESH_BUDGET;
 use sui_sdk::types::object::Object;
 use sui_sdk::sui_client_config;
 use sui_sdk::rpc_types::SuiObjectDataOptions;
-use sui_sdk::types::base_types::ObjectID;
+use sui_sdk::types::base_types::{ObjectID, SuiAddress};
+use sui_sdk::types::event::EventID;
 
 pub mod markets;
 pub mod market_graph;
 pub mod cetus;
 pub mod kriyadex;
 pub mod turbos;
+pub mod flameswap;
 pub mod constants;
 pub mod sui_sdk_utils;
 pub mod sui_json_utils;
 pub mod turbos_pool;
 // pub mod cetus_pool; 
 pub mod arbitrage;
+pub mod backtest;
+pub mod gas;
+pub mod config;
+pub mod recording;
+pub mod simulation;
 pub mod fast_v2_pool;
 pub mod fast_v3_pool;
 pub mod fast_cronje_pool;
@@ -54,6 +66,7 @@ pub use crate::market_graph::*;
 pub use crate::cetus::*;
 pub use crate::turbos::*;
 pub use crate::kriyadex::*;
+pub use crate::flameswap::*;
 
 #[derive(Parser)]
 #[clap(
@@ -62,29 +75,57 @@ pub use crate::kriyadex::*;
     rename_all = "kebab-case"
 )]
 pub struct RunDataOpts {
-    #[clap(long, default_value = "wss://sui-mainnet.blastapi.io:443/338f3a96-cd39-41f8-88d9-4faefe2eee21")]
-    pub wss_url: String,
-    #[clap(long, default_value = "https://sui-mainnet.blastapi.io:443/338f3a96-cd39-41f8-88d9-4faefe2eee21")]
-    pub rpc_url: String,
+    // Each can be given more than once. Requests go to the fastest healthy fullnode.
+    #[clap(long = "wss-url", default_values = &["wss://sui-mainnet.blastapi.io:443/338f3a96-cd39-41f8-88d9-4faefe2eee21", "wss://fullnode.mainnet.sui.io:443"])]
+    pub wss_urls: Vec<String>,
+    #[clap(long = "rpc-url", default_values = &["https://sui-mainnet.blastapi.io:443/338f3a96-cd39-41f8-88d9-4faefe2eee21", "https://fullnode.mainnet.sui.io:443"])]
+    pub rpc_urls: Vec<String>,
     #[clap(long)]
     pub keystore_path: PathBuf,
     #[clap(long)]
     pub key_index: usize, 
+    // Exchanges, pools, source coins and thresholds
+    #[clap(long, default_value = "config.toml")]
+    pub config_path: PathBuf,
+    // Execute every leg of a cycle in a single programmable transaction
+    #[clap(long)]
+    pub atomic: bool,
+    // Open every cycle with a flash swap and repay it in the same transaction.
+    // Trades aren't capped by our balance but only flash swappable routes are taken.
+    #[clap(long)]
+    pub flash: bool,
+    // With --flash, have the last hop buy exactly what the flash swap owes
+    // where its market can, keeping the profit in the coin it pays with
     #[clap(long)]
-    pub max_intermediate_nodes: usize, 
+    pub exact_repay: bool,
+    // Take pool state from a librarian's socket instead of syncing pools ourselves
+    #[clap(long)]
+    pub librarian_socket_path: Option<PathBuf>,
 }
 
 pub struct RunData {
     pub sui_client: SuiClient,
     pub keystore: Keystore,
     pub key_index: usize,
+    pub atomic: bool,
+    pub flash: bool,
+    pub exact_repay: bool,
+    pub slippage_bps: u64,
+    // Cycles start from every one of these, each with its own allowance
+    pub source_coins: Vec<config::SourceCoin>,
+    // Profits from different source coins are compared in this coin
+    pub profit_coin: TypeTag,
+    // Pools we keep in sync but don't search on the events of
+    pub skip_event_pools: HashSet<ObjectID>,
+    // Allow and deny lists new pools are checked against
+    pub pools: config::PoolsConfig,
+    pub cycle_detector: CycleDetector,
 }
 
-pub async fn loop_blocks<'a>(
+pub async fn loop_blocks(
     run_data: &RunData, 
-    exchanges: &Vec<Box<dyn Exchange>>, 
-    market_graph: &mut MarketGraph<'a>,
-    source_coin: &TypeTag
+    exchanges: &mut Vec<Box<dyn Exchange>>, 
+    market_graph: &mut MarketGraph,
     // paths: Vec<Vec<&TypeTag>>
 ) -> Result<()> {
 
@@ -100,72 +141,24 @@ pub async fn loop_blocks<'a>(
         // [run_data.key_index];
         // .context(format!("No address for key index {} in keystore", run_data.key_index))?;
 
-    let run_starting_balance = run_data
-        .sui_client
-        .coin_read_api()
-        .get_balance(
-            owner_address.clone(),
-            Some(format!("{}", source_coin))
-        )
-        .await?;
-
-    println!("RUN STARTING BALANCE: {}", run_starting_balance.total_balance);
+    for source_coin in run_data.source_coins.iter() {
+        let run_starting_balance = run_data
+            .sui_client
+            .coin_read_api()
+            .get_balance(
+                owner_address.clone(),
+                Some(format!("{}", source_coin.coin_type))
+            )
+            .await?;
 
-    let pool_state_changing_event_filters = exchanges
-        .iter()
-        .flat_map(|exchange| {
-            exchange.event_filters()
-        })
-        .collect::<Vec<EventFilter>>();
+        println!("RUN STARTING BALANCE: {} {}", run_starting_balance.total_balance, source_coin.coin_type);
+    }
 
-    // println!("pool_state_changing_event_filters: {:#?}", pool_state_changing_event_filters);
+    let mut subscribe_pool_state_changing_events = subscribe_exchange_events(run_data, exchanges, None).await?;
 
-    let mut subscribe_pool_state_changing_events = run_data
-        .sui_client
-        .event_api()
-        .subscribe_event(
-            EventFilter::Any(
-                pool_state_changing_event_filters
-            )
-        )
-        .await?;
+    let mut event_struct_tag_to_pool_field = exchange_event_struct_tag_to_pool_field(exchanges);
 
-    let event_struct_tag_to_pool_field = exchanges
-        .iter()
-        .flat_map(|exchange| {
-            exchange.event_struct_tag_to_pool_field()
-        })
-        .collect::<HashMap<_, _>>();
-
-    // Very hacky skip
-    let cetus_sui_pool_id = ObjectID::from_str("0x2e041f3fd93646dcc877f783c1f2b7fa62d30271bdef1f21ef002cebf857bded")?;
-    let cetus_usdc_pool_id = ObjectID::from_str("0x5eb2dfcdd1b15d2021328258f6d5ec081e9a0cdcfa9e13a0eaeb9b5f7505ca78")?;
-    let another_pool = ObjectID::from_str("0xcf994611fd4c48e277ce3ffd4d4364c914af2c3cbb05f7bf6facd371de688630")?;
-    let otra_pool = ObjectID::from_str("0x5eb2dfcdd1b15d2021328258f6d5ec081e9a0cdcfa9e13a0eaeb9b5f7505ca78")?;
-    // let mut pool_set: HashSet<ObjectID> = HashSet::new();
-    let ignor_too = ObjectID::from_str("0x238f7e4648e62751de29c982cbf639b4225547c31db7bd866982d7d56fc2c7a8")?;
-    let delulu = ObjectID::from_str("0x79d2d20005eb8f8ad6f18008a757db50ca75d57d1aa7bcef1052e55a20b37b28")?;
-    let poro = ObjectID::from_str("0x9ddb0d269d1049caf7c872846cc6d9152618d1d3ce994fae84c1c051ee23b179")?;
-
-    let mut skip_event_pools: HashSet<ObjectID> = HashSet::new();
-    skip_event_pools.insert(cetus_sui_pool_id);
-    skip_event_pools.insert(another_pool);
-    // skip_event_pools.insert(otra_pool);
-    // skip_event_pools.insert(ignor_too);
-    // skip_event_pools.insert(delulu);
-    // skip_event_pools.insert(poro);
-    // skip_event_pools.insert(cetus_usdc_pool_id);
-    // skip_event_pools.insert(ObjectID::from_str("0x2c6fc12bf0d093b5391e7c0fed7e044d52bc14eb29f6352a3fb358e33e80729e")?);
-    // skip_event_pools.insert(ObjectID::from_str("0xd0086b7713e0487bbf5bb4a1e30a000794e570590a6041155cdbebee3cb1cb77")?);
-    skip_event_pools.insert(ObjectID::from_str("0x5af4976b871fa1813362f352fa4cada3883a96191bb7212db1bd5d13685ae305")?); 
-    // skip_event_pools.insert(ObjectID::from_str("0x517ee525c34fdfb2240342bd43fc07e1ec253c2442a7edd2482e6973700c6ef5")?);
-    // skip_event_pools.insert(ObjectID::from_str("0x238f7e4648e62751de29c982cbf639b4225547c31db7bd866982d7d56fc2c7a8")?);
-    // skip_event_pools.insert(ObjectID::from_str("0x06d8af9e6afd27262db436f0d37b304a041f710c3ea1fa4c3a9bab36b3569ad3")?);
-    // skip_event_pools.insert(ObjectID::from_str("0x238f7e4648e62751de29c982cbf639b4225547c31db7bd866982d7d56fc2c7a8")?);
-
-    // let poot = ObjectID::from_str("0x86ed41e9b4c6cce36de4970cfd4ae3e98d6281f13a1b16aa31fc73ec90079c3d")?;
-
-    let mut last_seen_pool = cetus_sui_pool_id;
+    let mut last_seen_pool: Option<ObjectID> = None;
     // let mut focus_pool: Option<ObjectID>;
 
     // let excute_pool = ;
@@ -386,62 +379,121 @@ pub async fn loop_blocks<'a>(
 
 
 
+    let mut gas_estimator = gas::GasEstimator::new();
+
+    // Pools whose local state has been fetched and kept in sync by events since.
+    // Anything else gets refetched before we search through it.
+    let mut synced_pool_ids: HashSet<ObjectID> = HashSet::new();
+
+    // Pool states as of the version we last fetched. Pools that fall out of sync
+    // without having changed on chain since are restored from here.
+    let mut pool_state_cache: sui_sdk_utils::ObjectVersionCache<PoolState> = sui_sdk_utils::ObjectVersionCache::new();
+
+    // The last event we took off the stream. Resubscribing picks up after it.
+    let mut last_event_id = None;
+
     // Equivalent to .is_some() except we can print events
-    while let Some(mut event_result) = subscribe_pool_state_changing_events.next().await {
+    while let Some(event) = subscribe_pool_state_changing_events.next().await {
 
-        // Pushes to latest event. Makes sure we dont fall behind even if our inner loop takes a while.
-        // Likely to choose the events that are most often emmitted lmaooo
+        // Drain everything that piled up while our inner loop was busy.
+        // Every event gets applied but we only search once we've caught up.
+        let mut events = vec![event];
         let mut cx = std::task::Context::from_waker(futures::task::noop_waker_ref());
         while let std::task::Poll::Ready(Some(i)) = subscribe_pool_state_changing_events.next().poll_unpin(&mut cx) {
-            event_result = i;
+            events.push(i);
         }
 
-        if let Ok(event) = event_result {
+        let mut search_pool_id = None;
+        let mut new_markets = false;
+
+        for event in events {
+            last_event_id = Some(event.id);
+
+            match market_from_pool_created_event(exchanges, &run_data.sui_client, &event).await {
+                Ok(Some(market)) => {
+                    // Fetched like any other pool the first time we search through it
+                    if run_data.pools.allows(market.pool_id()) {
+                        println!("!NEW POOL!\n    POOL: {}\n    COINS: ({}, {})", market.pool_id(), market.coin_x(), market.coin_y());
+                        market_graph.insert_market(market)?;
+                        new_markets = true;
+                    }
+                    continue;
+                },
+                Ok(None) => {},
+                Err(err) => {
+                    println!("Failed to add pool created by {}: {}", event.type_, err);
+                    continue;
+                }
+            }
+
             // // println!("Event parsed_json: {:#?}", event.parsed_json);
             // println!("New event pool id: {:#?}", event.parsed_json.get("pool").context("missing pool field")?);
             // println!("Event package id: {}", event.package_id);
 
-            let event_pool_field = event_struct_tag_to_pool_field
-                .get(&event.type_)
+            let pool_id = pool_id_from_event(exchanges, &event)?
                 .context(
                     format!(
-                        "Missing event_pool_field for StructTag {} in map",
+                        "No exchange knows the pool of event {}",
                         &event.type_
                     )
                 )?;
 
-            // println!("{:#?}", &event.parsed_json);
+            // Pools we filtered out of the graph
+            if !market_graph.pool_id_to_coin_pair.contains_key(&pool_id) {
+                continue;
+            }
 
-            let pool_id = if let Value::String(pool_id_str) = 
-                event.parsed_json.get(
-                    event_pool_field
-                ).context("loop_blocks: missing pool field")? {
-                    ObjectID::from_str(pool_id_str)?
-                } else {
-                    return Err(anyhow!("Pool field should match the Value::String variant."));
-                };
+            // Synced pools are kept in sync by their events.
+            // Any other pool is fetched whole when we need it.
+            if synced_pool_ids.contains(&pool_id) {
+                match market_graph.update_market_with_event(&pool_id, &event) {
+                    Ok(EventUpdate::Applied) | Ok(EventUpdate::Ignored) => {},
+                    Ok(EventUpdate::Inconsistent) => {
+                        println!("Pool {} out of sync with event {}", pool_id, event.type_);
+                        synced_pool_ids.remove(&pool_id);
+                    },
+                    Err(err) => {
+                        println!("Failed to apply event {} to pool {}: {}", event.type_, pool_id, err);
+                        synced_pool_ids.remove(&pool_id);
+                    }
+                }
+            }
 
-            if pool_id == last_seen_pool {
-                // last_seen_pool = pool_id;
+            if run_data.skip_event_pools.contains(&pool_id) {
                 continue;
             }
 
-            last_seen_pool = pool_id;
+            // Only print events we are not skipping
+            println!("!NEW EVENT!\n    POOL: {}\n    PACKAGE: {}\n    EVENT TYPE: {}", pool_id, event.package_id, event.type_);
 
+            search_pool_id = Some(pool_id);
+        }
+
+        // New markets can come with events we aren't subscribed to yet
+        if new_markets {
+            let new_event_struct_tag_to_pool_field = exchange_event_struct_tag_to_pool_field(exchanges);
 
+            if new_event_struct_tag_to_pool_field.len() != event_struct_tag_to_pool_field.len() {
+                // Picks up after the last event we handled so nothing in between is lost
+                subscribe_pool_state_changing_events = subscribe_exchange_events(run_data, exchanges, last_event_id).await?;
+                event_struct_tag_to_pool_field = new_event_struct_tag_to_pool_field;
 
-            if skip_event_pools.contains(&pool_id) {
+                println!("Resubscribed for new pool events");
+            }
+        }
+
+        if let Some(pool_id) = search_pool_id {
+            if last_seen_pool == Some(pool_id) {
+                // last_seen_pool = pool_id;
                 continue;
             }
 
+            last_seen_pool = Some(pool_id);
+
             // if pool_id != poot {
             //     continue;
             // }
 
-
-            // Only print events we are not skipping
-            println!("!NEW EVENT!\n    POOL: {}\n    PACKAGE: {}\n    EVENT TYPE: {}", pool_id, event.package_id, event.type_);
-
             // pool_set.insert(pool_id);
             // println!("[{:?}]", pool_set);
 
@@ -450,29 +502,13 @@ pub async fn loop_blocks<'a>(
             // exchange parsing of the pool id field but here they are both "pool"
             // We grab the cycles associate with a pool id and run our max profit calcs on every leg of the cycle.
             // We can filter by exchange per leg later but for now we're trimming off a lot of time.
-            let cycles_opt = market_graph
-                .pool_id_and_source_coin_to_cycles
-                .get(&(pool_id, source_coin.clone()));
-                // .context(format!("No cycles for (pool_id, source_coin): ({}, {})", pool_id, source_coin))?
-                
             // Not every coin pool_id, source_coin combo is going to have cycles
             // So skip if there are no cycles
-            if cycles_opt.is_none() {
+            let cycles = if let Some(cycles) = cycles_for_pool(market_graph, &pool_id, &run_data.source_coins, run_data.cycle_detector) {
+                cycles
+            } else {
                 continue;
-            }
-
-            let cycles = cycles_opt
-                .unwrap()
-                .iter()
-                .map(|vec_coins|{
-                    vec_coins
-                        .iter()
-                        .map(|coin| {
-                            (*coin).clone()
-                        })
-                        .collect::<Vec<TypeTag>>()
-                })
-                .collect::<Vec<Vec<TypeTag>>>();
+            };
 
             println!("num cycles: {}", cycles.len());
 
@@ -482,30 +518,21 @@ pub async fn loop_blocks<'a>(
 
             let mut pool_ids_to_update = HashSet::new();
             
-            // Update pool involved in the cycle
-            // PREVIOUS ARBS WILL EMIT EVENTS
-            // BUT WILL HAVE AFFECTED MULTIPLE POOLS
-            // So we can't just update the pool for which the vent was emitted
-            // We have to update all pools involved in the cycle
-            // TODO: We can make this more efficient by only updating pools that
-            // were involved in ther previous trade
+            // Events keep pools in sync. Only fetch pools involved in the
+            // cycles that we've never fetched or that fell out of sync.
             for cycle in cycles.iter() {
                 for pair in cycle[..].windows(2) {
                     let coin_a = &pair[0];
                     let coin_b = &pair[1];
     
                     let pool_ids = market_graph
-                        .graph
-                        .edge_weight(&coin_a, &coin_b)
-                        .context(format!("Missing markets for pair ({}, {})", coin_a, coin_b))?
-                        .iter()
-                        .map(|(pool_id, _)| {
-                            pool_id.clone()
-                        })
-                        .collect::<Vec<ObjectID>>();
+                        .pool_ids_between(coin_a, coin_b)
+                        .context(format!("Missing markets for pair ({}, {})", coin_a, coin_b))?;
     
                     for pool_id in pool_ids {
-                        pool_ids_to_update.insert(pool_id);
+                        if !synced_pool_ids.contains(pool_id) {
+                            pool_ids_to_update.insert(pool_id.clone());
+                        }
                     }
                 }
             }
@@ -515,164 +542,521 @@ pub async fn loop_blocks<'a>(
             println!("Measuring... (updating {} markets)", pool_ids_to_update_vec.len());
             let now = Instant::now();
 
-            let pool_id_to_object_response = sui_sdk_utils::get_object_id_to_object_response(
-                &run_data.sui_client, 
+            // Chunks retry on their own. Whatever is still failing by the deadline
+            // costs us this round rather than the whole bot.
+            let refresh_client = run_data.sui_client.with_deadline(now + MARKET_REFRESH_BUDGET);
+
+            let (current_pool_ids, stale_pool_ids) = match pool_state_cache.partition_current(
+                &refresh_client,
                 &pool_ids_to_update_vec
-            ).await?;
+            ).await {
+                Ok(partition) => partition,
+                Err(err) => {
+                    println!("Market refresh failed: {:#}", err);
+                    continue;
+                }
+            };
+            println!("{} markets unchanged since cached", current_pool_ids.len());
+
+            for pool_id in current_pool_ids {
+                let cached_pool = pool_state_cache
+                    .get(&pool_id)
+                    .context(format!("Missing cached state for pool {}", pool_id))?;
+
+                market_graph.update_market_with_pool_state(&pool_id, Some(&cached_pool.state))?;
+                synced_pool_ids.insert(pool_id);
+            }
+
+            let pool_id_to_object_response = match sui_sdk_utils::get_object_id_to_object_response(
+                &refresh_client, 
+                &stale_pool_ids
+            ).await {
+                Ok(pool_id_to_object_response) => pool_id_to_object_response,
+                Err(err) => {
+                    println!("Market refresh failed: {:#}", err);
+                    continue;
+                }
+            };
             println!("pool_id_to_object_response elapsed: {:#?} for {} object responeses", now.elapsed(), pool_id_to_object_response.len());
 
-            market_graph.update_markets_with_object_responses(
-                &run_data.sui_client, 
+            // Pools that didn't make it stay out of synced_pool_ids and get fetched again
+            if let Err(err) = market_graph.update_markets_with_object_responses(
+                &refresh_client, 
                 &pool_id_to_object_response
-            ).await?;
+            ).await {
+                println!("Market refresh failed: {:#}", err);
+                continue;
+            }
+
+            for (pool_id, object_response) in pool_id_to_object_response.iter() {
+                let pool_state = market_graph
+                    .market(pool_id)
+                    .and_then(|market| market.market_record().pool_state);
+
+                if let Some(pool_state) = pool_state {
+                    pool_state_cache.insert(object_response, pool_state)?;
+                }
+            }
+
+            synced_pool_ids.extend(pool_id_to_object_response.keys().cloned());
 
             println!("update_markets_with_object_responses elapsed: {:#?}", now.elapsed());
 
-            let mut optimized_results = cycles
-                .par_iter()
-                .map(|cycle| {
-                    arbitrage::optimize_starting_amount_in(cycle, &market_graph)
-                })
-                .collect::<Result<Vec<_>, anyhow::Error>>()?;
+            search_and_execute(
+                run_data,
+                market_graph,
+                &cycles,
+                &owner_address,
+                &mut gas_estimator
+            ).await?;
+        }
+    }
     
-            let mut used_legs_set = HashSet::new();
-
-            // Good future pattern may be an execute all trades func
-            // That takes an ordering and filtering function as args
-
-            // Sort by most profitable so that the later excluded trades
-            // are the less profitable ones. Sort in descending order.
-            optimized_results.sort_by(|a, b| {
-                b.profit.cmp(&a.profit)
-            });
-
-            // Exclude less profitable trades whose legs
-            // were already seen before. Don't want any strange effects.
-            // Later we can account for the effects of our trades and whatnot.
-            // We should also not swap back through the same pool we came
-            // Since we haven't accounted for the effects of our trade on
-            // the pool.
-            // RESULTS DEPEND ON ORDER
-
-            let filtered_optimized_results_iter = optimized_results
+    Ok(())
+}
+
+// Every event that changes a pool's state plus every pool creation.
+// Starts after cursor when there is one.
+async fn subscribe_exchange_events(
+    run_data: &RunData,
+    exchanges: &[Box<dyn Exchange>],
+    cursor: Option<EventID>
+) -> Result<impl Stream<Item = SuiEvent>> {
+    let event_filters = exchanges
+        .iter()
+        .flat_map(|exchange| {
+            exchange
+                .event_filters()
                 .into_iter()
-                .filter(|optimized_result| {
-                    println!("profit: {}", optimized_result.profit);
-
-                    if optimized_result.profit < I256::from(7_000_000u128 * optimized_result.path.len() as u128) {
-                        return false
-                    }  // Should do some gas threshold instead
-                    for leg in &optimized_result.path {
-                        if used_legs_set.contains(leg.market.pool_id()) {
-                            return false;
-                        } else {
-                            used_legs_set.insert(leg.market.pool_id());
-                        }
+                .chain(exchange.pool_created_event_filters())
+        })
+        .collect::<Vec<EventFilter>>();
+
+    let events = run_data
+        .sui_client
+        .event_api()
+        .subscribe_event_resilient(
+            EventFilter::Any(
+                event_filters
+            ),
+            cursor
+        )
+        .await?;
+
+    Ok(events)
+}
+
+fn exchange_event_struct_tag_to_pool_field(exchanges: &[Box<dyn Exchange>]) -> HashMap<StructTag, String> {
+    exchanges
+        .iter()
+        .flat_map(|exchange| {
+            exchange.event_struct_tag_to_pool_field().clone()
+        })
+        .collect()
+}
+
+// The market for a new pool from whichever exchange created it
+async fn market_from_pool_created_event(
+    exchanges: &mut [Box<dyn Exchange>],
+    sui_client: &SuiClient,
+    event: &SuiEvent
+) -> Result<Option<Box<dyn Market>>> {
+    for exchange in exchanges.iter_mut() {
+        if let Some(market) = exchange.market_from_pool_created_event(sui_client, event).await? {
+            return Ok(Some(market));
+        }
+    }
+
+    Ok(None)
+}
+
+// Same search as loop_blocks but the librarian keeps pool state in sync.
+// No event subscription and no refetching pools over RPC.
+pub async fn loop_librarian(
+    run_data: &RunData,
+    librarian_socket_path: &Path,
+    market_graph: &mut MarketGraph
+) -> Result<()> {
+    let owner_address = run_data
+        .keystore
+        .addresses()
+        .get(run_data.key_index)
+        .context(format!("No address for key index {} in keystore", run_data.key_index))?
+        .clone();
+
+    let mut subscription = Subscription::connect(librarian_socket_path).await?;
+
+    let mut gas_estimator = gas::GasEstimator::new();
+
+    // The librarian's version of every pool we hold its state for. Diffs apply on top of it.
+    let mut pool_id_to_version: HashMap<ObjectID, u64> = HashMap::new();
+
+    while let Some(message) = subscription.next_message().await? {
+
+        // Drain everything that piled up while we were busy searching.
+        // Every message gets applied but we only search once we've caught up.
+        let mut messages = vec![message];
+        while let Some(message_result) = subscription.next_message().now_or_never() {
+            if let Some(message) = message_result? {
+                messages.push(message);
+            } else {
+                break;
+            }
+        }
+
+        let mut search_pool_id = None;
+
+        for message in messages {
+            match message {
+                Message::Snapshot(market_states) => {
+                    let pool_id_to_pool_state = market_states
+                        .iter()
+                        .map(|market_state| {
+                            (market_state.pool_id.clone(), &market_state.pool_state)
+                        })
+                        .collect::<HashMap<ObjectID, &PoolState>>();
+
+                    // Pools missing from the snapshot aren't synced
+                    let pool_ids = market_graph
+                        .pool_id_to_coin_pair
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<ObjectID>>();
+
+                    for pool_id in pool_ids {
+                        market_graph.update_market_with_pool_state(
+                            &pool_id,
+                            pool_id_to_pool_state.get(&pool_id).copied()
+                        )?;
                     }
 
-                    true
-                });
+                    pool_id_to_version = market_states
+                        .iter()
+                        .map(|market_state| {
+                            (market_state.pool_id.clone(), market_state.version)
+                        })
+                        .collect();
+
+                    println!("Librarian snapshot: {} markets", pool_id_to_pool_state.len());
+                },
+                Message::Update(market_state) => {
+                    // Pools we filtered out of the graph
+                    if !market_graph.pool_id_to_coin_pair.contains_key(&market_state.pool_id) {
+                        continue;
+                    }
 
-            for mut optimized_result in filtered_optimized_results_iter {
+                    market_graph.update_market_with_pool_state(
+                        &market_state.pool_id,
+                        Some(&market_state.pool_state)
+                    )?;
+                    pool_id_to_version.insert(market_state.pool_id.clone(), market_state.version);
 
-                let start_source_coin_balance = run_data
-                    .sui_client
-                    .coin_read_api()
-                    .get_balance(
-                        owner_address.clone(),
-                        Some(format!("{}", source_coin))
-                    )
-                    .await?;
-        
-                let allowance = (start_source_coin_balance.total_balance * 4) / 5;
-
-                // Adjust and check profitibility or skip
-                if optimized_result.amount_in > allowance  {
-                    println!("profitable optimized result amount_in: {}", optimized_result.amount_in);
-                    // Skip so that we don't fail
-                    // optimized_result.amount_in = start_source_coin_balance.total_balance / 2;
-                    // panic!();
-                    // continue;
-
-                    let amount_in = allowance;
-                    let amount_out = arbitrage::amount_out(&optimized_result.path, allowance)?;
-                    let profit = I256::from(amount_out) - I256::from(amount_in);
-
-                    if profit > I256::from(10_000_000u128 * optimized_result.path.len() as u128) {
-                        optimized_result.amount_in = amount_in;
-                        optimized_result.amount_out = amount_out;
-                        optimized_result.profit = profit;
-
-                        // focus_pool = Some(pool_id);
-                    } else {
-                        // focus_pool = None;
+                    search_pool_id = Some(market_state.pool_id);
+                },
+                Message::Diff(market_state_diff) => {
+                    let pool_id = market_state_diff.pool_id;
+
+                    // Pools we filtered out of the graph
+                    if !market_graph.pool_id_to_coin_pair.contains_key(&pool_id) {
                         continue;
                     }
-                }
 
-                println!("+-----------------------------------------------------");
-                println!("| START BALANCE: {}", start_source_coin_balance.total_balance);
-                println!("| AMOUNT IN: {} {}", optimized_result.amount_in, source_coin);
-                println!("| AMOUNT OUT: {} {}", optimized_result.amount_out, source_coin);
-                println!("| RAW PROFIT: {} {}", optimized_result.profit, source_coin);
-                optimized_result.path
-                    .iter()
-                    .try_for_each(|leg| {
-                        if leg.x_to_y {
-                            println!("|    +----[POOL: {}, X_TO_Y: {}]-------------", leg.market.pool_id(), leg.x_to_y);
-                            println!("|    | {}", leg.market.coin_x());
-                            println!("|    |   ----[RATE: {}]---->", leg.market.coin_y_price().context("Missing coin_y price.")?);
-                            println!("|    | {}", leg.market.coin_y());
-                            // println!("|    +------------------------------------------------");
-                        } else {
-                            println!("|    +----[POOL: {}, X_TO_Y: {}]-------------", leg.market.pool_id(), leg.x_to_y);
-                            println!("|    | {}", leg.market.coin_y());
-                            println!("|    |   ----[RATE: {}]---->", leg.market.coin_x_price().context("Missing coin_x price.")?);
-                            println!("|    | {}", leg.market.coin_x());
-                            // println!("|    +------------------------------------------------");
+                    match pool_id_to_version.get(&pool_id) {
+                        // Already in the snapshot we were sent
+                        Some(version) if market_state_diff.version <= *version => continue,
+                        Some(version) if market_state_diff.version == *version + 1 => {},
+                        _ => {
+                            // Messages arrive in order so this shouldn't happen. Without the
+                            // version before the diff the pool waits for its next full state.
+                            println!("Missed librarian versions of pool {}", pool_id);
+                            market_graph.update_market_with_pool_state(&pool_id, None)?;
+                            pool_id_to_version.remove(&pool_id);
+                            continue;
                         }
+                    }
 
-                        Ok::<(), anyhow::Error>(())
-                    })?;
-
-                println!("|    +------------------------------------------------");
-
-                // panic!();
-
-                arbitrage::execute_arb(
-                    &run_data.sui_client,
-                    optimized_result,
-                    run_data
-                        .keystore
-                        .addresses()
-                        .get(run_data.key_index)
-                        .context(format!("No address for key index {} in keystore", run_data.key_index))?,
-                    &run_data.keystore,
-                )
-                .await?;
-
-                let end_source_coin_balance = run_data
-                    .sui_client
-                    .coin_read_api()
-                    .get_balance(
-                        owner_address.clone(),
-                        Some(format!("{}", source_coin))
-                    )
-                    .await?;
+                    let mut pool_state = market_graph
+                        .market(&pool_id)
+                        .and_then(|market| market.market_record().pool_state)
+                        .context(format!("No state to apply the diff of pool {} to", pool_id))?;
 
-                let realized_profit = end_source_coin_balance.total_balance as i128 - start_source_coin_balance.total_balance as i128;
+                    pool_state.apply_diff(&market_state_diff.diff)?;
 
-                println!("| END BALANCE: {}", end_source_coin_balance.total_balance);
-                println!("| REALIZED PROFIT: {}", realized_profit);
-                println!("+-----------------------------------------------------");
+                    market_graph.update_market_with_pool_state(&pool_id, Some(&pool_state))?;
+                    pool_id_to_version.insert(pool_id.clone(), market_state_diff.version);
 
-                if realized_profit < 0 {
-                    println!("UR DOWN IN MONEY LOSER");
-                    // return Err(anyhow!("Arb failed"));
+                    search_pool_id = Some(pool_id);
+                },
+                Message::Remove(pool_id) => {
+                    if market_graph.pool_id_to_coin_pair.contains_key(&pool_id) {
+                        market_graph.update_market_with_pool_state(&pool_id, None)?;
+                    }
+                    pool_id_to_version.remove(&pool_id);
                 }
             }
         }
+
+        if let Some(pool_id) = search_pool_id {
+            let cycles = if let Some(cycles) = cycles_for_pool(market_graph, &pool_id, &run_data.source_coins, run_data.cycle_detector) {
+                cycles
+            } else {
+                continue;
+            };
+
+            println!("!NEW UPDATE!\n    POOL: {}\n    num cycles: {}", pool_id, cycles.len());
+
+            search_and_execute(
+                run_data,
+                market_graph,
+                &cycles,
+                &owner_address,
+                &mut gas_estimator
+            ).await?;
+        }
     }
-    
+
+    Ok(())
+}
+
+// The cycles to search when a pool changes. Each starts and ends at one of the source coins.
+// Owned so that the market graph can be updated while we hold on to them.
+pub fn cycles_for_pool(
+    market_graph: &MarketGraph,
+    pool_id: &ObjectID,
+    source_coins: &[config::SourceCoin],
+    cycle_detector: CycleDetector
+) -> Option<Vec<Vec<TypeTag>>> {
+    if cycle_detector == CycleDetector::NegativeCycles {
+        return negative_cycles_for_pool(market_graph, pool_id, source_coins);
+    }
+
+    let cycles = source_coins
+        .iter()
+        .filter_map(|source_coin| {
+            market_graph
+                .pool_id_and_source_coin_to_cycles
+                .get(&(pool_id.clone(), source_coin.coin_type.clone()))
+        })
+        .flatten()
+        .map(|vec_coins|{
+            vec_coins
+                .iter()
+                .map(|coin| {
+                    market_graph.coin(*coin).clone()
+                })
+                .collect::<Vec<TypeTag>>()
+        })
+        .collect::<Vec<Vec<TypeTag>>>();
+
+    if cycles.is_empty() {
+        None
+    } else {
+        Some(cycles)
+    }
+}
+
+// Negative cycles found from a pool's coins, rotated to start at a source coin.
+// A cycle through several source coins starts at the first one configured.
+// We can only start a trade with a coin we hold so the others are dropped.
+fn negative_cycles_for_pool(
+    market_graph: &MarketGraph,
+    pool_id: &ObjectID,
+    source_coins: &[config::SourceCoin]
+) -> Option<Vec<Vec<TypeTag>>> {
+    let negative_cycles = market_graph.find_negative_cycles(pool_id);
+    let num_negative_cycles = negative_cycles.len();
+
+    let cycles = negative_cycles
+        .into_iter()
+        .filter_map(|negative_cycle| {
+            // The first coin is repeated at the end
+            let coins = &negative_cycle[..negative_cycle.len() - 1];
+            let start = source_coins
+                .iter()
+                .find_map(|source_coin| {
+                    coins.iter().position(|coin| **coin == source_coin.coin_type)
+                })?;
+
+            Some(
+                coins[start..]
+                    .iter()
+                    .chain(coins[..=start].iter())
+                    .map(|coin| {
+                        (*coin).clone()
+                    })
+                    .collect::<Vec<TypeTag>>()
+            )
+        })
+        .collect::<Vec<Vec<TypeTag>>>();
+
+    if num_negative_cycles > cycles.len() {
+        println!("Skipping {} negative cycles that don't go through a source coin", num_negative_cycles - cycles.len());
+    }
+
+    if cycles.is_empty() {
+        None
+    } else {
+        Some(cycles)
+    }
+}
+
+// Optimizes every cycle against the current state of the market graph
+// and executes the profitable ones that don't share a pool.
+async fn search_and_execute(
+    run_data: &RunData,
+    market_graph: &MarketGraph,
+    cycles: &[Vec<TypeTag>],
+    owner_address: &SuiAddress,
+    gas_estimator: &mut gas::GasEstimator
+) -> Result<()> {
+    gas_estimator.update_reference_gas_price(&run_data.sui_client).await?;
+
+    // The selected trades of a source coin can put in its allowance between them.
+    // Flash swapped trades borrow what they put in so only pool depth limits them.
+    let mut source_coin_to_allowance = HashMap::new();
+    for source_coin in run_data.source_coins.iter() {
+        if run_data.flash {
+            source_coin_to_allowance.insert(source_coin.coin_type.clone(), u128::MAX);
+            continue;
+        }
+
+        let balance = run_data
+            .sui_client
+            .coin_read_api()
+            .get_balance(
+                owner_address.clone(),
+                Some(format!("{}", source_coin.coin_type))
+            )
+            .await?;
+
+        let allowance = (balance.total_balance * source_coin.allowance_bps as u128) / 10_000;
+
+        source_coin_to_allowance.insert(source_coin.coin_type.clone(), allowance);
+    }
+
+    let optimized_results = arbitrage::select_opportunities(
+        cycles,
+        market_graph,
+        &run_data.profit_coin,
+        &source_coin_to_allowance,
+        gas_estimator,
+        run_data.atomic,
+        run_data.flash
+    )?;
+
+    // Every trade is predicted against the pools as the ones before it leave them
+    let mut simulation_context = SimulationContext::new();
+
+    for optimized_result in optimized_results {
+        // Outlives optimized_result which is moved into execution
+        let source_coin = optimized_result.source_coin.clone();
+
+        let start_source_coin_balance = run_data
+            .sui_client
+            .coin_read_api()
+            .get_balance(
+                owner_address.clone(),
+                Some(format!("{}", source_coin))
+            )
+            .await?;
+
+
+        println!("+-----------------------------------------------------");
+        println!("| START BALANCE: {}", start_source_coin_balance.total_balance);
+        println!("| AMOUNT IN: {} {}", optimized_result.amount_in, source_coin);
+        println!("| AMOUNT OUT: {} {}", optimized_result.amount_out, source_coin);
+        println!("| RAW PROFIT: {} {}", optimized_result.profit, source_coin);
+        println!("| EXPECTED GAS: {} {}", optimized_result.gas_cost, source_coin);
+        println!("| NET PROFIT: {} {}", optimized_result.net_profit(), source_coin);
+        println!("| NET PROFIT VALUE: {} {}", optimized_result.net_profit_value, run_data.profit_coin);
+        optimized_result.route
+            .iter()
+            .flatten()
+            .try_for_each(|leg| {
+                if leg.x_to_y {
+                    println!("|    +----[POOL: {}, X_TO_Y: {}]-------------", leg.market.pool_id(), leg.x_to_y);
+                    println!("|    | {}", leg.market.coin_x());
+                    println!("|    |   ----[RATE: {}]---->", leg.market.coin_y_price().context("Missing coin_y price.")?);
+                    println!("|    | {}", leg.market.coin_y());
+                    // println!("|    +------------------------------------------------");
+                } else {
+                    println!("|    +----[POOL: {}, X_TO_Y: {}]-------------", leg.market.pool_id(), leg.x_to_y);
+                    println!("|    | {}", leg.market.coin_y());
+                    println!("|    |   ----[RATE: {}]---->", leg.market.coin_x_price().context("Missing coin_x price.")?);
+                    println!("|    | {}", leg.market.coin_x());
+                    // println!("|    +------------------------------------------------");
+                }
+
+                Ok::<(), anyhow::Error>(())
+            })?;
+
+        println!("|    +------------------------------------------------");
+
+        // panic!();
+
+        let signer_address = run_data
+            .keystore
+            .addresses()
+            .get(run_data.key_index)
+            .context(format!("No address for key index {} in keystore", run_data.key_index))?
+            .clone();
+
+        if run_data.flash {
+            arbitrage::execute_arb_flash(
+                &run_data.sui_client,
+                optimized_result,
+                &signer_address,
+                &run_data.keystore,
+                run_data.slippage_bps,
+                gas_estimator,
+                &mut simulation_context,
+                run_data.exact_repay,
+            )
+            .await?;
+        } else if run_data.atomic {
+            arbitrage::execute_arb_atomic(
+                &run_data.sui_client,
+                optimized_result,
+                &signer_address,
+                &run_data.keystore,
+                run_data.slippage_bps,
+                gas_estimator,
+                &mut simulation_context,
+            )
+            .await?;
+        } else {
+            arbitrage::execute_arb(
+                &run_data.sui_client,
+                optimized_result,
+                &signer_address,
+                &run_data.keystore,
+                run_data.slippage_bps,
+                gas_estimator,
+                &mut simulation_context,
+            )
+            .await?;
+        }
+
+        let end_source_coin_balance = run_data
+            .sui_client
+            .coin_read_api()
+            .get_balance(
+                owner_address.clone(),
+                Some(format!("{}", source_coin))
+            )
+            .await?;
+
+        let realized_profit = end_source_coin_balance.total_balance as i128 - start_source_coin_balance.total_balance as i128;
+
+        println!("| END BALANCE: {}", end_source_coin_balance.total_balance);
+        println!("| REALIZED PROFIT: {}", realized_profit);
+        println!("+-----------------------------------------------------");
+
+        if realized_profit < 0 {
+            println!("UR DOWN IN MONEY LOSER");
+            // return Err(anyhow!("Arb failed"));
+        }
+    }
+
     Ok(())
 }
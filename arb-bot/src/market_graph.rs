@@ -7,18 +7,25 @@ use move_core_types::language_storage::TypeTag;
 use petgraph::algo::all_simple_paths;
 use petgraph::graphmap::DiGraphMap;
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::hash::Hash;
-use std::time::{Instant, Duration};
+use std::time::Duration;
 
 use custom_sui_sdk::SuiClient;
 
 // use rayon::prelude::*;/
 
 use sui_sdk::types::base_types::ObjectID;
-use sui_sdk::rpc_types::{SuiMoveValue, SuiObjectResponse};
+use sui_sdk::rpc_types::{SuiEvent, SuiMoveValue, SuiObjectResponse};
+
+use pool_state::PoolState;
 
 use crate::markets::*;
+use crate::simulation::SimulationContext;
+
+// Relaxations have to improve a distance by at least this much. Keeps float
+// noise on loops that break even from being mistaken for negative cycles.
+const RELAXATION_EPSILON: f64 = 1e-12;
 
 // The DirectedMarketGraph should provide pure structure
 
@@ -29,88 +36,253 @@ pub struct MarketInfo {
 
 // type MarketsInfo = Vec<MarketInfo>;
 
-pub struct MarketGraph<'data> {
-    // Node: coin type
-    // Edge: map of pool id to market info
-    pub graph: DiGraphMap<&'data TypeTag, HashMap<ObjectID, MarketInfo>>,
-    pub pool_id_to_coin_pair: HashMap<ObjectID, (&'data TypeTag, &'data TypeTag)>,
-    pub source_coin_to_cycles: HashMap<TypeTag, Vec<Vec<&'data TypeTag>>>,
-    pub pool_id_and_source_coin_to_cycles: HashMap<(ObjectID, TypeTag), Vec<Vec<&'data TypeTag>>>, // Regardless of source coin? 
+// GraphMap nodes have to be Copy so coins are nodes by their index in MarketGraph::coins
+pub type CoinNode = usize;
+
+pub struct MarketGraph {
+    // Node: coin
+    // Edge: pool ids of the markets trading from one coin to the other
+    pub graph: DiGraphMap<CoinNode, HashSet<ObjectID>>,
+    // The one market of every pool. Both directions of a pool trade against it.
+    pub pool_id_to_market: HashMap<ObjectID, MarketInfo>,
+    pub pool_id_to_coin_pair: HashMap<ObjectID, (CoinNode, CoinNode)>,
+    pub source_coin_to_cycles: HashMap<TypeTag, Vec<Vec<CoinNode>>>,
+    pub pool_id_and_source_coin_to_cycles: HashMap<(ObjectID, TypeTag), Vec<Vec<CoinNode>>>, // Regardless of source coin? 
+    // The bound each source coin's cycles were found with.
+    // Cycles through markets inserted later keep to it.
+    source_coin_to_max_intermediate_nodes: HashMap<TypeTag, usize>,
+    // Every coin we've seen a market for. Coins stay when their markets go
+    // so the nodes in cycles keep pointing at the same coin.
+    coins: Vec<TypeTag>,
+    coin_to_node: HashMap<TypeTag, CoinNode>,
 }
 
-impl <'data> MarketGraph<'data> {
-    // Not consuming. Data is external to the structure.
-    // Uses Rc as multiple edges may refer to the same market.
+impl MarketGraph {
+    // Takes ownership of the markets.
     // Also 2 edges (directional) per market.
-    pub fn new(markets: &'data [Box<dyn Market>]) -> Result<Self, anyhow::Error> {
+    pub fn new(markets: Vec<Box<dyn Market>>) -> Result<Self, anyhow::Error> {
+        let mut market_graph = MarketGraph {
+            graph: DiGraphMap::with_capacity(15000, 15000),
+            pool_id_to_market: HashMap::new(),
+            pool_id_to_coin_pair: HashMap::new(),
+            source_coin_to_cycles: HashMap::new(),
+            pool_id_and_source_coin_to_cycles: HashMap::new(),
+            source_coin_to_max_intermediate_nodes: HashMap::new(),
+            coins: Vec::new(),
+            coin_to_node: HashMap::new(),
+        };
+
+        for market in markets {
+            market_graph.insert_market(market)?;
+        }
 
-        let mut graph = DiGraphMap::<&TypeTag, HashMap<ObjectID, MarketInfo>>::with_capacity(15000, 15000);
-        let mut pool_id_to_coin_pair = HashMap::new();
+        Ok(market_graph)
+    }
 
-        markets
-            .iter()
-            .try_for_each(|market| {
-                let coin_x_node = market.coin_x();
-                let coin_y_node = market.coin_y();
-
-                // Only need to insert pool id to coin pair once
-                pool_id_to_coin_pair.insert(
-                    market.pool_id().clone(),
-                    (coin_x_node, coin_y_node)
-                );
-
-                if !graph.contains_edge(coin_x_node, coin_y_node) {
-                    graph.add_edge(
-                        coin_x_node,
-                        coin_y_node,
-                        HashMap::new()
-                    );
-                }
+    fn intern_coin(&mut self, coin: &TypeTag) -> CoinNode {
+        if let Some(node) = self.coin_to_node.get(coin) {
+            return *node;
+        }
 
-                let edge_coin_x_to_coin_y = graph.edge_weight_mut(coin_x_node, coin_y_node).context("Edge to update does not exist.")?;
-                edge_coin_x_to_coin_y.insert(
-                    market.pool_id().clone(),
-                    MarketInfo {
-                        market: dyn_clone::clone_box(&**market)
-                    }
-                );
+        let node = self.coins.len();
+        self.coins.push(coin.clone());
+        self.coin_to_node.insert(coin.clone(), node);
 
-                if !graph.contains_edge(coin_y_node, coin_x_node) {
-                    graph.add_edge(
-                        coin_y_node,
-                        coin_x_node,
-                        HashMap::new()
-                    );
+        node
+    }
+
+    pub fn coin(&self, node: CoinNode) -> &TypeTag {
+        &self.coins[node]
+    }
+
+    // Pool ids of the markets trading from orig to dest. None if there aren't any.
+    pub fn pool_ids_between(&self, orig: &TypeTag, dest: &TypeTag) -> Option<&HashSet<ObjectID>> {
+        let orig = *self.coin_to_node.get(orig)?;
+        let dest = *self.coin_to_node.get(dest)?;
+
+        self.graph.edge_weight(orig, dest)
+    }
+
+    // The markets trading from orig to dest. None if there aren't any.
+    pub fn markets_between(&self, orig: &TypeTag, dest: &TypeTag) -> Option<impl Iterator<Item = &Box<dyn Market>>> {
+        let pool_ids = self.pool_ids_between(orig, dest)?;
+
+        Some(
+            pool_ids
+                .iter()
+                .filter_map(|pool_id| {
+                    self.market(pool_id)
+                })
+        )
+    }
+
+    // What amount of from gets us in to through the best viable direct market
+    // between them, price impact included. None if there isn't one.
+    pub fn convert_amount(&self, amount: u128, from: &TypeTag, to: &TypeTag) -> Option<u128> {
+        if from == to {
+            return Some(amount);
+        }
+
+        self
+            .markets_between(from, to)?
+            .filter(|market| {
+                market.viable()
+            })
+            .map(|market| {
+                if market.coin_x() == from {
+                    market.compute_swap_x_to_y(amount).1
+                } else {
+                    market.compute_swap_y_to_x(amount).0
                 }
+            })
+            .max()
+    }
 
-                let edge_coin_y_to_coin_x = graph.edge_weight_mut(coin_y_node, coin_x_node).context("Edge to update does not exist.")?;
-                edge_coin_y_to_coin_x.insert(
-                    market.pool_id().clone(),
-                    MarketInfo {
-                        market: dyn_clone::clone_box(&**market)
-                    }
-                );
+    pub fn market(&self, pool_id: &ObjectID) -> Option<&Box<dyn Market>> {
+        self
+            .pool_id_to_market
+            .get(pool_id)
+            .map(|market_info| {
+                &market_info.market
+            })
+    }
+
+    fn market_mut(&mut self, pool_id: &ObjectID) -> Result<&mut Box<dyn Market>, anyhow::Error> {
+        self
+            .pool_id_to_market
+            .get_mut(pool_id)
+            .map(|market_info| {
+                &mut market_info.market
+            })
+            .context(format!("Missing market for pool {}", pool_id))
+    }
+
+    // Adds a market, replacing any market we had for its pool, and indexes
+    // the cycles through it for every source coin we have cycles for.
+    pub fn insert_market(&mut self, market: Box<dyn Market>) -> Result<(), anyhow::Error> {
+        let pool_id = market.pool_id().clone();
+
+        self.remove_market(&pool_id)?;
+
+        let coin_x = self.intern_coin(market.coin_x());
+        let coin_y = self.intern_coin(market.coin_y());
+
+        // Cycles through the pair only exist once it has a market
+        let new_coin_pair = !self.graph.contains_edge(coin_x, coin_y);
 
-                Ok::<(), anyhow::Error>(())
-            })?;
+        self.pool_id_to_coin_pair.insert(pool_id.clone(), (coin_x, coin_y));
+        self.pool_id_to_market.insert(pool_id.clone(), MarketInfo { market });
 
-        Ok(
-            MarketGraph {
-                graph,
-                pool_id_to_coin_pair,
-                source_coin_to_cycles: HashMap::new(),
-                pool_id_and_source_coin_to_cycles: HashMap::new()
+        for (orig, dest) in [(coin_x, coin_y), (coin_y, coin_x)] {
+            if !self.graph.contains_edge(orig, dest) {
+                self.graph.add_edge(orig, dest, HashSet::new());
             }
-        )
+
+            self
+                .graph
+                .edge_weight_mut(orig, dest)
+                .context("Edge to update does not exist.")?
+                .insert(pool_id.clone());
+        }
+
+        let source_coin_to_max_intermediate_nodes = self
+            .source_coin_to_max_intermediate_nodes
+            .iter()
+            .map(|(source_coin, max_intermediate_nodes)| {
+                (source_coin.clone(), *max_intermediate_nodes)
+            })
+            .collect::<Vec<(TypeTag, usize)>>();
+
+        for (source_coin, max_intermediate_nodes) in source_coin_to_max_intermediate_nodes {
+            if new_coin_pair {
+                let source_node = self.intern_coin(&source_coin);
+                let cycles = self.find_cycles_through_coin_pair(source_node, coin_x, coin_y, max_intermediate_nodes);
+
+                self.index_cycles(&source_coin, &cycles)?;
+
+                self
+                    .source_coin_to_cycles
+                    .entry(source_coin)
+                    .or_insert(Vec::new())
+                    .extend(cycles);
+            } else {
+                // The same cycles as the markets already between the pair
+                let pool_cycles = self
+                    .source_coin_to_cycles
+                    .get(&source_coin)
+                    .map(|cycles| {
+                        cycles_through_coin_pair(cycles, coin_x, coin_y)
+                    })
+                    .unwrap_or_default();
+
+                if !pool_cycles.is_empty() {
+                    self.pool_id_and_source_coin_to_cycles.insert((pool_id.clone(), source_coin), pool_cycles);
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    // The callee of update may not necessarily (likely won't actually) 
-    // maintain the order of vertex pairs we get by calling <GraphMap>.all_edges()
-    // We'll need to identify the vertex pairs in the parameters we pass
-    // Make more sense to iterate through all edges
-    // But markets fields to edges is one to many
+    // Drops a pool's market and, when it was the last market between its
+    // coins, every cycle through them. None if we didn't have the pool.
+    pub fn remove_market(&mut self, pool_id: &ObjectID) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        let (coin_x, coin_y) = if let Some(coin_pair) = self.pool_id_to_coin_pair.remove(pool_id) {
+            coin_pair
+        } else {
+            return Ok(None);
+        };
+
+        let market = self
+            .pool_id_to_market
+            .remove(pool_id)
+            .map(|market_info| {
+                market_info.market
+            });
+
+        for (orig, dest) in [(coin_x, coin_y), (coin_y, coin_x)] {
+            let pool_ids = self
+                .graph
+                .edge_weight_mut(orig, dest)
+                .context(format!("Missing edge from {} to {}", self.coins[orig], self.coins[dest]))?;
+
+            pool_ids.remove(pool_id);
+
+            if pool_ids.is_empty() {
+                self.graph.remove_edge(orig, dest);
+            }
+        }
+
+        self
+            .pool_id_and_source_coin_to_cycles
+            .retain(|(cycles_pool_id, _), _| {
+                cycles_pool_id != pool_id
+            });
+
+        if !self.graph.contains_edge(coin_x, coin_y) {
+            for cycles in self.source_coin_to_cycles.values_mut() {
+                cycles.retain(|cycle| {
+                    !uses_coin_pair(cycle, coin_x, coin_y)
+                });
+            }
+
+            for cycles in self.pool_id_and_source_coin_to_cycles.values_mut() {
+                cycles.retain(|cycle| {
+                    !uses_coin_pair(cycle, coin_x, coin_y)
+                });
+            }
 
-    // Now only updates provided markets
+            self
+                .pool_id_and_source_coin_to_cycles
+                .retain(|_, cycles| {
+                    !cycles.is_empty()
+                });
+        }
+
+        Ok(market)
+    }
+
+    // Only updates the markets of the pools we have responses for
     pub async fn update_markets_with_object_responses(
         &mut self, 
         sui_client: &SuiClient, 
@@ -119,94 +291,60 @@ impl <'data> MarketGraph<'data> {
 
         future::try_join_all(
             self
-            .graph
-            .all_edges_mut()
-            .map(|(_, _, markets_infos)| {
-                async {
-                    future::try_join_all(
-                        markets_infos
-                        .iter_mut()
-                        .map(|(_, market_info)| {
-                            async {
-                                let pool_id = *market_info.market.pool_id();
-                                // let object_response = pool_id_to_object_response.get(&pool_id).context("Missing fields for pool.")?;
-                                // market_info.market.update_with_object_response(sui_client, object_response).await?;
-                                if let Some(object_response) = pool_id_to_object_response.get(&pool_id) {
-                                    // let now = Instant::now();
-                                    market_info.market.update_with_object_response(sui_client, object_response).await?;
-                                    // println!("time elapsed to update {}: {:#?}", market_info.market.pool_id(), now.elapsed());
-                                }
-
-                                Ok::<(), anyhow::Error>(())
-                            }
-                        })
-                    ).await?;
-
-                    Ok::<(),  anyhow::Error>(())
-                }
+            .pool_id_to_market
+            .iter_mut()
+            .filter_map(|(pool_id, market_info)| {
+                let object_response = pool_id_to_object_response.get(pool_id)?;
+
+                Some(market_info.market.update_with_object_response(sui_client, object_response))
             })
         )
         .await?;
 
-        // for (pool_id, response) in pool_id_to_object_response {
-        //     self.update_market_with_object_response(
-        //         sui_client, 
-        //         pool_id, 
-        //         response
-        //     ).await?;
-        // }
-
         Ok(())
     }
 
-    // We may need to use a HashMap<ObjectID, MarketInfo>
-    // And or keep a map of pool ids to coin pairs
-    // In 
-
-    // so pool id -> coin pair
-    // coin pair -> markets
-    // pool id -> market
-
-    // to allow for the updating of individual markets
-    // We'll also need source and dest coins....
     pub async fn update_market_with_object_response(
         &mut self, 
         sui_client: &SuiClient, 
         pool_id: &ObjectID,
         response: &SuiObjectResponse
     ) -> Result<(), anyhow::Error>{
-        let now = Instant::now();
-
-        let (coin_a, coin_b) = self
-            .pool_id_to_coin_pair
-            .get(pool_id)
-            .context("Missing coin pair for pool.")?;
-        
-        // Update markets in one direction (this is a directed graph)
-        let a_to_b_pool_id_to_market = self
-            .graph
-            .edge_weight_mut(coin_a, coin_b)
-            .context("Missing edge between coins a and b.")?;
-
-        let a_to_b_market = a_to_b_pool_id_to_market
-            .get_mut(pool_id)
-            .context("Missing market for pool.")?;
-        
-        a_to_b_market.market.update_with_object_response(sui_client, response).await?;
+        self
+            .market_mut(pool_id)?
+            .update_with_object_response(sui_client, response)
+            .await
+    }
 
-        // Update markets in the other direction
-        let b_to_a_pool_id_to_market = self
-            .graph
-            .edge_weight_mut(coin_a, coin_b)
-            .context("Missing edge between coins b to a.")?;
+    // Errors if the pool isn't in the graph
+    pub fn update_market_with_event(
+        &mut self,
+        pool_id: &ObjectID,
+        event: &SuiEvent
+    ) -> Result<EventUpdate, anyhow::Error> {
+        self
+            .market_mut(pool_id)?
+            .update_with_event(event)
+    }
 
-        let b_to_a_market = b_to_a_pool_id_to_market
-            .get_mut(pool_id)
-            .context("Missing market for pool.")?;
+    pub fn update_market_with_pool_state(
+        &mut self,
+        pool_id: &ObjectID,
+        pool_state: Option<&PoolState>
+    ) -> Result<(), anyhow::Error> {
+        self
+            .market_mut(pool_id)?
+            .update_with_pool_state(pool_state)
+    }
 
-        b_to_a_market.market.update_with_object_response(sui_client, response).await?;
+    // Takes the pools as simulated trades left them.
+    // For when nothing else will bring the real state, like backtesting.
+    pub fn apply_simulation(&mut self, simulation_context: SimulationContext) -> Result<(), anyhow::Error> {
+        for market in simulation_context.into_markets() {
+            let pool_id = market.pool_id().clone();
 
-        println!("time elapsed to update single market: {:#?}", now.elapsed());
+            *self.market_mut(&pool_id)? = market;
+        }
 
         Ok(())
     }
@@ -217,46 +355,115 @@ impl <'data> MarketGraph<'data> {
     // in the graph.
     fn find_cycles(
         &self,
-        source_coin: &'data TypeTag,
+        source_coin: CoinNode,
         max_intermediate_nodes: usize,
-    ) -> Vec<Vec<&'data TypeTag>> {
+    ) -> Vec<Vec<CoinNode>> {
         all_simple_paths(
             &self.graph, 
             source_coin, 
             source_coin, 
             1, 
             Some(max_intermediate_nodes)
-        ).collect::<Vec<Vec<&TypeTag>>>()
+        ).collect::<Vec<Vec<CoinNode>>>()
+    }
+
+    // The cycles through source_coin that trade between coin_a and coin_b in either direction.
+    // Each is a path from source_coin to one coin of the pair then from the other back.
+    fn find_cycles_through_coin_pair(
+        &self,
+        source_coin: CoinNode,
+        coin_a: CoinNode,
+        coin_b: CoinNode,
+        max_intermediate_nodes: usize,
+    ) -> Vec<Vec<CoinNode>> {
+        let paths = |from: CoinNode, to: CoinNode| {
+            if from == to {
+                vec![vec![from]]
+            } else {
+                all_simple_paths(
+                    &self.graph,
+                    from,
+                    to,
+                    0,
+                    Some(max_intermediate_nodes)
+                ).collect::<Vec<Vec<CoinNode>>>()
+            }
+        };
+
+        let mut cycles = Vec::<Vec<CoinNode>>::new();
+
+        for (orig, dest) in [(coin_a, coin_b), (coin_b, coin_a)] {
+            let tails = paths(dest, source_coin);
+
+            for head in paths(source_coin, orig) {
+                for tail in tails.iter() {
+                    let cycle = head
+                        .iter()
+                        .chain(tail.iter())
+                        .copied()
+                        .collect::<Vec<CoinNode>>();
+
+                    let intermediate_nodes = &cycle[1..cycle.len() - 1];
+                    let unique_intermediate_nodes = intermediate_nodes.iter().collect::<HashSet<_>>();
+
+                    // Simple cycles only. A two coin cycle is found from both directions.
+                    if intermediate_nodes.len() <= max_intermediate_nodes
+                        && unique_intermediate_nodes.len() == intermediate_nodes.len()
+                        && !unique_intermediate_nodes.contains(&source_coin)
+                        && !cycles.contains(&cycle) {
+                        cycles.push(cycle);
+                    }
+                }
+            }
+        }
+
+        cycles
     }
 
     pub fn add_cycles(
         &mut self,
-        source_coin: &'data TypeTag,
+        source_coin: &TypeTag,
         max_intermediate_nodes: usize,
     ) -> Result<(), anyhow::Error>{
+        let source_node = self.intern_coin(source_coin);
+        let cycles = self.find_cycles(source_node, max_intermediate_nodes);
+
+        self.index_cycles(source_coin, &cycles)?;
+
         self
             .source_coin_to_cycles
             .insert(
                 source_coin.clone(),
-                self.find_cycles(source_coin, max_intermediate_nodes)
+                cycles
             );
 
-        let cycles = self
-            .source_coin_to_cycles
-            .get(source_coin)
-            .context("No cycles for given source coin.")?;
+        self
+            .source_coin_to_max_intermediate_nodes
+            .insert(
+                source_coin.clone(),
+                max_intermediate_nodes
+            );
 
+        Ok(())
+    }
+
+    // Adds cycles to the cycle index of every pool along them
+    fn index_cycles(
+        &mut self,
+        source_coin: &TypeTag,
+        cycles: &[Vec<CoinNode>]
+    ) -> Result<(), anyhow::Error> {
         for cycle in cycles {
             for pair in cycle[..].windows(2) {
                 let coin_a = pair[0];
                 let coin_b = pair[1];
 
-                let markets = self
+                let pool_ids = self
                     .graph
                     .edge_weight(coin_a, coin_b)
-                    .context(format!("Missing edge from {} to {}", coin_a, coin_b))?;
+                    .context(format!("Missing edge from {} to {}", self.coins[coin_a], self.coins[coin_b]))?;
 
-                for (pool_id, _) in markets {
+                for pool_id in pool_ids {
                     let pool_cycles = self.pool_id_and_source_coin_to_cycles
                         .entry((pool_id.clone(), source_coin.clone()))
                         .or_insert(Vec::new());
@@ -271,6 +478,106 @@ impl <'data> MarketGraph<'data> {
         Ok(())
     }
 
+    // The marginal rate from orig to dest of the best viable market between them, after fees.
+    // Taken from the constant product curve each market quotes like at its current price.
+    // coin_x_price and coin_y_price leave out fees and aren't quoted the same way by every exchange.
+    fn best_marginal_rate(&self, orig: &TypeTag, dest: &TypeTag) -> Option<f64> {
+        self
+            .markets_between(orig, dest)?
+            .filter(|market| {
+                market.viable()
+            })
+            .filter_map(|market| {
+                let x_to_y = (orig, dest) == (market.coin_x(), market.coin_y());
+                let curve = market.constant_product_curve(x_to_y)?;
+
+                Some(curve.fee_multiplier * curve.reserve_out / curve.reserve_in)
+            })
+            .filter(|rate| {
+                rate.is_finite() && *rate > 0.
+            })
+            .reduce(f64::max)
+    }
+
+    // Profitable loops of any length reachable from the coins of a pool.
+    // Every edge is weighted by -ln(marginal rate after fees) of its best market
+    // so a loop is profitable when its weights sum to less than zero.
+    // SPFA from the pool's coins. Every cycle in the predecessor graph is a
+    // negative cycle so the predecessor graph is checked every so often.
+    // Loops are closed, the first coin is the last, and needn't go through any source coin.
+    pub fn find_negative_cycles(&self, pool_id: &ObjectID) -> Vec<Vec<&TypeTag>> {
+        let (coin_a, coin_b) = if let Some(coin_pair) = self.pool_id_to_coin_pair.get(pool_id) {
+            *coin_pair
+        } else {
+            return vec![];
+        };
+
+        let mut distance = HashMap::<CoinNode, f64>::new();
+        let mut predecessor = HashMap::<CoinNode, CoinNode>::new();
+        let mut queue = VecDeque::new();
+        let mut queued = HashSet::new();
+
+        for coin in [coin_a, coin_b] {
+            distance.insert(coin, 0.);
+            queue.push_back(coin);
+            queued.insert(coin);
+        }
+
+        let num_nodes = self.graph.node_count();
+        // Bellman-Ford's bound. Only reached if float error keeps relaxing a loop.
+        let max_relaxations = num_nodes * self.graph.edge_count();
+        let mut num_relaxations = 0;
+
+        while let Some(orig) = queue.pop_front() {
+            queued.remove(&orig);
+
+            let orig_distance = distance[&orig];
+
+            for (_, dest, _) in self.graph.edges(orig) {
+                let weight = if let Some(rate) = self.best_marginal_rate(&self.coins[orig], &self.coins[dest]) {
+                    -rate.ln()
+                } else {
+                    continue;
+                };
+
+                let dest_distance = orig_distance + weight;
+
+                if dest_distance >= distance.get(&dest).copied().unwrap_or(f64::INFINITY) - RELAXATION_EPSILON {
+                    continue;
+                }
+
+                distance.insert(dest, dest_distance);
+                predecessor.insert(dest, orig);
+                num_relaxations += 1;
+
+                if num_relaxations % num_nodes == 0 {
+                    let cycles = predecessor_cycles(&predecessor);
+
+                    if !cycles.is_empty() || num_relaxations >= max_relaxations {
+                        return cycles
+                            .into_iter()
+                            .map(|cycle| {
+                                cycle
+                                    .into_iter()
+                                    .map(|node| {
+                                        &self.coins[node]
+                                    })
+                                    .collect()
+                            })
+                            .collect();
+                    }
+                }
+
+                if queued.insert(dest) {
+                    queue.push_back(dest);
+                }
+            }
+        }
+
+        // The queue only empties when there's no negative cycle to find
+        vec![]
+    }
+
     // pub fn pool_to_cycles(
     //     &self,
     //     source_coin: &'data TypeTag
@@ -308,6 +615,82 @@ impl <'data> MarketGraph<'data> {
     // }
 }
 
+fn uses_coin_pair(cycle: &[CoinNode], coin_a: CoinNode, coin_b: CoinNode) -> bool {
+    cycle
+        .windows(2)
+        .any(|pair| {
+            (pair[0], pair[1]) == (coin_a, coin_b) || (pair[0], pair[1]) == (coin_b, coin_a)
+        })
+}
+
+// Every cycle with as many entries as it has hops between coin_a and coin_b,
+// the way index_cycles would have indexed them for a market between the two
+fn cycles_through_coin_pair(
+    cycles: &[Vec<CoinNode>],
+    coin_a: CoinNode,
+    coin_b: CoinNode
+) -> Vec<Vec<CoinNode>> {
+    cycles
+        .iter()
+        .flat_map(|cycle| {
+            let hops = cycle
+                .windows(2)
+                .filter(|pair| {
+                    uses_coin_pair(pair, coin_a, coin_b)
+                })
+                .count();
+
+            std::iter::repeat(cycle.clone()).take(hops)
+        })
+        .collect()
+}
+
+// Every cycle of a predecessor graph, closed and in the direction of trade.
+// Each coin has one predecessor so following them from any coin either ends
+// or runs into a cycle.
+fn predecessor_cycles<N: Copy + Eq + Hash>(predecessor: &HashMap<N, N>) -> Vec<Vec<N>> {
+    let mut coin_to_walk = HashMap::<N, usize>::new();
+    let mut cycles = vec![];
+
+    for (walk, start) in predecessor.keys().enumerate() {
+        let mut coin = *start;
+
+        loop {
+            match coin_to_walk.get(&coin) {
+                // Back to a coin of this walk so it's on a new cycle
+                Some(coin_walk) if *coin_walk == walk => {
+                    let mut cycle = vec![coin];
+                    let mut cycle_coin = predecessor[&coin];
+
+                    while cycle_coin != coin {
+                        cycle.push(cycle_coin);
+                        cycle_coin = predecessor[&cycle_coin];
+                    }
+
+                    cycle.push(coin);
+                    cycle.reverse();
+
+                    cycles.push(cycle);
+                    break;
+                },
+                // Joined an earlier walk whose cycle, if any, we already have
+                Some(_) => break,
+                None => {}
+            }
+
+            coin_to_walk.insert(coin, walk);
+
+            if let Some(predecessor_coin) = predecessor.get(&coin) {
+                coin = *predecessor_coin;
+            } else {
+                break;
+            }
+        }
+    }
+
+    cycles
+}
+
 // source coin to pool to 
 
 // // // Map pool to cycle
@@ -331,4 +714,48 @@ impl <'data> MarketGraph<'data> {
 //     }
 
 //     Ok(())
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predecessor_cycles() {
+        let (a, b, c, d, e) = (TypeTag::U8, TypeTag::U16, TypeTag::U32, TypeTag::U64, TypeTag::U128);
+
+        // a -> b -> c -> a with d hanging off of c and e on its own
+        let predecessor = HashMap::from([
+            (&b, &a),
+            (&c, &b),
+            (&a, &c),
+            (&d, &c),
+            (&e, &d),
+        ]);
+
+        let cycles = predecessor_cycles(&predecessor);
+
+        assert_eq!(cycles.len(), 1);
+
+        let cycle = &cycles[0];
+        assert_eq!(cycle.len(), 4);
+        assert_eq!(cycle.first(), cycle.last());
+
+        // Every step follows a predecessor edge forward
+        for pair in cycle.windows(2) {
+            assert_eq!(predecessor[pair[1]], pair[0]);
+        }
+    }
+
+    #[test]
+    fn test_predecessor_cycles_acyclic() {
+        let (a, b, c) = (TypeTag::U8, TypeTag::U16, TypeTag::U32);
+
+        let predecessor = HashMap::from([
+            (&b, &a),
+            (&c, &b),
+        ]);
+
+        assert!(predecessor_cycles(&predecessor).is_empty());
+    }
+}
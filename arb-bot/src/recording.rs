@@ -0,0 +1,68 @@
+use anyhow::Context;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use sui_sdk::rpc_types::SuiEvent;
+
+use crate::markets::MarketRecord;
+
+// A recording is newline delimited JSON. Every market comes first with its
+// state when recording started, then the events in the order we received them.
+// Object responses alone don't carry the ticks of concentrated liquidity pools
+// so markets are recorded with the state we computed from them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Record {
+    Market(MarketRecord),
+    Event(SuiEvent),
+}
+
+pub struct RecordWriter {
+    writer: BufWriter<File>,
+}
+
+impl RecordWriter {
+    pub fn create(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = File::create(path)
+            .context(format!("Failed to create recording {}", path.display()))?;
+
+        Ok(
+            RecordWriter {
+                writer: BufWriter::new(file),
+            }
+        )
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), anyhow::Error> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), anyhow::Error> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+pub fn read_records(path: &Path) -> Result<impl Iterator<Item = Result<Record, anyhow::Error>>, anyhow::Error> {
+    let file = File::open(path)
+        .context(format!("Failed to open recording {}", path.display()))?;
+
+    let records = BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(line_index, line)| {
+            let record = serde_json::from_str(&line?)
+                .context(format!("Malformed record on line {}", line_index + 1))?;
+
+            Ok(record)
+        });
+
+    Ok(records)
+}
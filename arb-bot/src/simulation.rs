@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use sui_sdk::types::base_types::ObjectID;
+
+use crate::arbitrage::{self, DirectedLeg};
+use crate::markets::Market;
+
+// Pool state as our own trades would leave it, on top of the market graph.
+// A pool is copied out of the graph the first time a swap goes through it.
+// Forking shares the copies until one side swaps through them again.
+#[derive(Clone, Default)]
+pub struct SimulationContext {
+    pool_id_to_market: HashMap<ObjectID, Arc<Box<dyn Market>>>,
+}
+
+impl SimulationContext {
+    pub fn new() -> Self {
+        SimulationContext::default()
+    }
+
+    pub fn contains(&self, pool_id: &ObjectID) -> bool {
+        self.pool_id_to_market.contains_key(pool_id)
+    }
+
+    // The leg priced against our copy of its pool if we have one
+    pub fn leg<'v>(&'v self, leg: &DirectedLeg<'v>) -> DirectedLeg<'v> {
+        let market = self
+            .pool_id_to_market
+            .get(leg.market.pool_id())
+            .map(|market| {
+                &**market
+            })
+            .unwrap_or(leg.market);
+
+        DirectedLeg {
+            x_to_y: leg.x_to_y,
+            market
+        }
+    }
+
+    pub fn legs<'v>(&'v self, legs: &[DirectedLeg<'v>]) -> Vec<DirectedLeg<'v>> {
+        legs
+            .iter()
+            .map(|leg| {
+                self.leg(leg)
+            })
+            .collect()
+    }
+
+    pub fn route<'v>(&'v self, route: &[Vec<DirectedLeg<'v>>]) -> Vec<Vec<DirectedLeg<'v>>> {
+        route
+            .iter()
+            .map(|legs| {
+                self.legs(legs)
+            })
+            .collect()
+    }
+
+    // Our copies of every pool a swap went through
+    pub fn into_markets(self) -> impl Iterator<Item = Box<dyn Market>> {
+        self
+            .pool_id_to_market
+            .into_values()
+            .map(|market| {
+                Arc::try_unwrap(market).unwrap_or_else(|market| dyn_clone::clone_box(&**market))
+            })
+    }
+
+    // Swaps amount_in through the leg's pool and returns the amount out
+    pub fn apply_swap(&mut self, leg: &DirectedLeg, amount_in: u128) -> u128 {
+        let market = self
+            .pool_id_to_market
+            .entry(leg.market.pool_id().clone())
+            .or_insert_with(|| {
+                Arc::new(dyn_clone::clone_box(&**leg.market))
+            });
+
+        // Copies the pool if a fork still shares it
+        let market = Arc::make_mut(market);
+
+        if amount_in == 0 || !market.viable() {
+            return 0;
+        }
+
+        if leg.x_to_y {
+            market.apply_swap_x_to_y(amount_in).1
+        } else {
+            market.apply_swap_y_to_x(amount_in).0
+        }
+    }
+
+    // Swaps amount_in through every hop of the route, split across
+    // each hop's pools like route_leg_results. Returns the amount out.
+    pub fn apply_route(&mut self, route: &[Vec<DirectedLeg>], mut amount_in: u128) -> Result<u128, anyhow::Error> {
+        for legs in route {
+            let leg_amounts = arbitrage::split_amount_in(&self.legs(legs), amount_in)?;
+
+            amount_in = 0;
+
+            for (leg, (leg_amount_in, _)) in legs.iter().zip(leg_amounts) {
+                amount_in += self.apply_swap(leg, leg_amount_in);
+            }
+        }
+
+        Ok(amount_in)
+    }
+}
@@ -88,4 +88,58 @@ fn is_move_option_type(tag: &StructTag) -> bool {
     tag.address == MOVE_STDLIB_ADDRESS
         && tag.module.as_ident_str() == STD_OPTION_MODULE_NAME
         && tag.name.as_ident_str() == STD_OPTION_STRUCT_NAME
-}
\ No newline at end of file
+}
+// Reading fields out of SuiEvent::parsed_json.
+// u64 and wider integers are decimal strings. Narrower ones are numbers.
+pub mod parsed_json {
+    use super::JsonValue;
+    use anyhow::{anyhow, Context};
+    use std::str::FromStr;
+
+    fn get_field<'a>(parsed_json: &'a JsonValue, field: &str) -> Result<&'a JsonValue, anyhow::Error> {
+        parsed_json
+            .get(field)
+            .context(format!("Missing field '{}'.", field))
+    }
+
+    pub fn get_u64(parsed_json: &JsonValue, field: &str) -> Result<u64, anyhow::Error> {
+        if let JsonValue::String(str) = get_field(parsed_json, field)? {
+            Ok(u64::from_str(str)?)
+        } else {
+            Err(anyhow!("'{}' is not Value::String.", field))
+        }
+    }
+
+    pub fn get_u128(parsed_json: &JsonValue, field: &str) -> Result<u128, anyhow::Error> {
+        if let JsonValue::String(str) = get_field(parsed_json, field)? {
+            Ok(u128::from_str(str)?)
+        } else {
+            Err(anyhow!("'{}' is not Value::String.", field))
+        }
+    }
+
+    pub fn get_string(parsed_json: &JsonValue, field: &str) -> Result<String, anyhow::Error> {
+        if let JsonValue::String(str) = get_field(parsed_json, field)? {
+            Ok(str.clone())
+        } else {
+            Err(anyhow!("'{}' is not Value::String.", field))
+        }
+    }
+
+    pub fn get_bool(parsed_json: &JsonValue, field: &str) -> Result<bool, anyhow::Error> {
+        if let JsonValue::Bool(bool_inner) = get_field(parsed_json, field)? {
+            Ok(*bool_inner)
+        } else {
+            Err(anyhow!("'{}' is not Value::Bool.", field))
+        }
+    }
+
+    // Move I32 { bits: u32 }
+    pub fn get_i32(parsed_json: &JsonValue, field: &str) -> Result<i32, anyhow::Error> {
+        let bits = get_field(get_field(parsed_json, field)?, "bits")?
+            .as_u64()
+            .context(format!("'{}.bits' is not a number.", field))?;
+
+        Ok(bits as u32 as i32)
+    }
+}
@@ -9,7 +9,7 @@ use move_core_types::language_storage::TypeTag;
 use std::str::FromStr;
 use std::collections::{HashMap, BTreeMap};
 
-use sui_sdk::types::base_types::{ObjectID, ObjectType, SequenceNumber};
+use sui_sdk::types::base_types::{ObjectDigest, ObjectID, ObjectRef, ObjectType, SequenceNumber};
 use sui_sdk::rpc_types::{SuiObjectResponse, SuiObjectData, SuiObjectDataOptions, SuiParsedData, SuiMoveStruct, SuiMoveValue, SuiGetPastObjectRequest, SuiPastObjectResponse};
 
 use crate::constants::OBJECT_REQUEST_LIMIT;
@@ -181,6 +181,162 @@ pub async fn get_object_id_to_object_response(
     Ok(object_id_to_object_responses)
 }
 
+// References only. Much lighter on the full node than full content.
+pub async fn get_object_id_to_object_ref(
+    sui_client: &SuiClient, 
+    object_ids: &[ObjectID]
+) -> Result<HashMap<ObjectID, ObjectRef>, anyhow::Error> {
+    let chunked_object_responses = future::try_join_all(
+        object_ids
+        .chunks(OBJECT_REQUEST_LIMIT)
+        .map(|object_ids| {
+            async {
+                let object_responses = sui_client
+                    .read_api()
+                    .multi_get_object_with_options(
+                        object_ids.to_vec(),
+                        SuiObjectDataOptions::new()
+                    )
+                    .await?;
+
+                Ok::<Vec<SuiObjectResponse>, anyhow::Error>(object_responses)
+            }
+        })
+    )
+    .await?;
+
+    // Deleted and missing objects have no reference
+    let object_id_to_object_ref = chunked_object_responses
+        .into_iter()
+        .flatten()
+        .filter_map(|object_response| {
+            let object_ref = object_response.data?.object_ref();
+
+            Some((object_ref.0, object_ref))
+        })
+        .collect::<HashMap<ObjectID, ObjectRef>>();
+
+    Ok(object_id_to_object_ref)
+}
+
+// What we made of an object as of the version we fetched
+#[derive(Debug, Clone)]
+pub struct CachedObject<T> {
+    pub version: SequenceNumber,
+    pub digest: ObjectDigest,
+    pub state: T,
+}
+
+// Remembers objects by the version they were fetched at so that
+// ones that haven't changed since don't need fetching again.
+// Versions only come from what we fetch. Our own transactions' effects
+// aren't fed in so a pool we traded through is refetched like any other change.
+#[derive(Debug, Clone)]
+pub struct ObjectVersionCache<T> {
+    object_id_to_cached_object: HashMap<ObjectID, CachedObject<T>>,
+}
+
+impl<T> Default for ObjectVersionCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ObjectVersionCache<T> {
+    pub fn new() -> Self {
+        Self {
+            object_id_to_cached_object: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, object_id: &ObjectID) -> Option<&CachedObject<T>> {
+        self.object_id_to_cached_object.get(object_id)
+    }
+
+    // Errors if the response has no data to take the version from
+    pub fn insert(
+        &mut self,
+        object_response: &SuiObjectResponse,
+        state: T
+    ) -> Result<(), anyhow::Error> {
+        let (object_id, version, digest) = object_response
+            .data
+            .as_ref()
+            .context("Object response is missing data")?
+            .object_ref();
+
+        self.object_id_to_cached_object.insert(
+            object_id,
+            CachedObject {
+                version,
+                digest,
+                state
+            }
+        );
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, object_id: &ObjectID) -> Option<CachedObject<T>> {
+        self.object_id_to_cached_object.remove(object_id)
+    }
+
+    // Splits object_ids into those cached at their latest version and those
+    // that need a full fetch. Only asks the full node for references.
+    pub async fn partition_current(
+        &mut self,
+        sui_client: &SuiClient,
+        object_ids: &[ObjectID]
+    ) -> Result<(Vec<ObjectID>, Vec<ObjectID>), anyhow::Error> {
+        // Nothing to compare against
+        let (cached_object_ids, uncached_object_ids): (Vec<ObjectID>, Vec<ObjectID>) = object_ids
+            .iter()
+            .cloned()
+            .partition(|object_id| self.object_id_to_cached_object.contains_key(object_id));
+
+        if cached_object_ids.is_empty() {
+            return Ok((vec![], uncached_object_ids));
+        }
+
+        let object_id_to_object_ref = get_object_id_to_object_ref(sui_client, &cached_object_ids).await?;
+
+        let (current_object_ids, mut stale_object_ids) = self.partition_by_object_refs(&cached_object_ids, &object_id_to_object_ref);
+        stale_object_ids.extend(uncached_object_ids);
+
+        Ok((current_object_ids, stale_object_ids))
+    }
+
+    // Cached objects whose version and digest match the reference are current.
+    // Everything else is stale and dropped from the cache.
+    pub fn partition_by_object_refs(
+        &mut self,
+        object_ids: &[ObjectID],
+        object_id_to_object_ref: &HashMap<ObjectID, ObjectRef>
+    ) -> (Vec<ObjectID>, Vec<ObjectID>) {
+        let mut current_object_ids = vec![];
+        let mut stale_object_ids = vec![];
+
+        for object_id in object_ids.iter().cloned() {
+            let current = match (self.object_id_to_cached_object.get(&object_id), object_id_to_object_ref.get(&object_id)) {
+                (Some(cached_object), Some((_, version, digest))) => {
+                    cached_object.version == *version && cached_object.digest == *digest
+                },
+                _ => false,
+            };
+
+            if current {
+                current_object_ids.push(object_id);
+            } else {
+                // Whatever we fetch next replaces it
+                self.object_id_to_cached_object.remove(&object_id);
+                stale_object_ids.push(object_id);
+            }
+        }
+
+        (current_object_ids, stale_object_ids)
+    }
+}
+
 pub mod sui_move_value {
     use super::*;
     use ethnum::U256;
@@ -306,4 +462,71 @@ pub mod sui_move_value {
                 Err(anyhow!(format!("'{}' field does not match SuiMoveValue::UID variant.", field)))
             }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_object(cache: &mut ObjectVersionCache<u64>, object_id: ObjectID, version: u64, digest: ObjectDigest) {
+        cache.object_id_to_cached_object.insert(
+            object_id,
+            CachedObject {
+                version: SequenceNumber::from_u64(version),
+                digest,
+                state: version,
+            }
+        );
+    }
+
+    #[test]
+    fn test_partition_by_object_refs() {
+        let (current, bumped, redigested, deleted, uncached) = (
+            ObjectID::from_single_byte(1),
+            ObjectID::from_single_byte(2),
+            ObjectID::from_single_byte(3),
+            ObjectID::from_single_byte(4),
+            ObjectID::from_single_byte(5),
+        );
+        let digest = ObjectDigest::random();
+
+        let mut cache = ObjectVersionCache::new();
+        cache_object(&mut cache, current, 7, digest);
+        cache_object(&mut cache, bumped, 7, digest);
+        cache_object(&mut cache, redigested, 7, digest);
+        cache_object(&mut cache, deleted, 7, digest);
+
+        let object_id_to_object_ref = HashMap::from([
+            (current, (current, SequenceNumber::from_u64(7), digest)),
+            (bumped, (bumped, SequenceNumber::from_u64(8), digest)),
+            // Same version with different contents can't be trusted either
+            (redigested, (redigested, SequenceNumber::from_u64(7), ObjectDigest::random())),
+            (uncached, (uncached, SequenceNumber::from_u64(7), digest)),
+        ]);
+
+        let (current_object_ids, stale_object_ids) = cache.partition_by_object_refs(
+            &[current, bumped, redigested, deleted, uncached],
+            &object_id_to_object_ref
+        );
+
+        assert_eq!(current_object_ids, vec![current]);
+        assert_eq!(stale_object_ids, vec![bumped, redigested, deleted, uncached]);
+
+        // Stale entries are evicted and the current one kept as is
+        assert_eq!(cache.get(&current).map(|cached_object| cached_object.state), Some(7));
+        assert!(cache.get(&bumped).is_none());
+        assert!(cache.get(&redigested).is_none());
+        assert!(cache.get(&deleted).is_none());
+    }
+
+    #[test]
+    fn test_remove() {
+        let object_id = ObjectID::from_single_byte(1);
+
+        let mut cache = ObjectVersionCache::new();
+        cache_object(&mut cache, object_id, 3, ObjectDigest::random());
+
+        assert_eq!(cache.remove(&object_id).map(|cached_object| cached_object.state), Some(3));
+        assert!(cache.get(&object_id).is_none());
+    }
+}
@@ -15,7 +15,7 @@ use custom_sui_sdk::{
         QueryEventsRequest,
         GetDynamicFieldsRequest
     },
-    transaction_builder::{TransactionBuilder, ProgrammableObjectArg},
+    transaction_builder::{TransactionBuilder, ProgrammableObjectArg, programmable_nested_result},
     programmable_transaction_sui_json::ProgrammableTransactionArg
 };
 
@@ -43,11 +43,13 @@ use move_core_types::value::MoveValue;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::time::{Duration, Instant};
 
-use crate::{markets::{Exchange, Market}, sui_sdk_utils::get_fields_from_object_response};
+use crate::{markets::{Exchange, Market, EventUpdate, MarketRecord, ConstantProductCurve, try_parse_pool_id_from_event}, sui_sdk_utils::get_fields_from_object_response};
 use crate::sui_sdk_utils::{self, sui_move_value};
 // use crate::turbos_pool;
 use crate::fast_v3_pool;
-use crate::sui_json_utils::move_value_to_json;
+use crate::sui_json_utils::{move_value_to_json, parsed_json};
+
+use pool_state::PoolState;
 
 #[derive(Debug, Clone)]
 pub struct Turbos {
@@ -61,12 +63,15 @@ impl Turbos {
     pub fn new(original_package_id: ObjectID, package_id: ObjectID, versioned_id: ObjectID) -> Self {
        
         let mut event_struct_tag_to_pool_field = HashMap::new();
-        event_struct_tag_to_pool_field.insert(
-            StructTag::from_str(
-                &format!("{}::pool::SwapEvent", original_package_id)
-            ).expect("Turbos: failed to create event struct tag"),
-            "pool".to_string()
-        );
+        // Everything that changes the state of a pool
+        for event_name in ["SwapEvent", "MintEvent", "BurnEvent", "TogglePoolStatusEvent"] {
+            event_struct_tag_to_pool_field.insert(
+                StructTag::from_str(
+                    &format!("{}::pool::{}", original_package_id, event_name)
+                ).expect("Turbos: failed to create event struct tag"),
+                "pool".to_string()
+            );
+        }
 
         Turbos {
             original_package_id,
@@ -158,9 +163,7 @@ impl Turbos {
             .pages(
                 QueryEventsRequest {
                     query: EventFilter::MoveEventType(
-                        StructTag::from_str(
-                            &format!("{}::pool_factory::PoolCreatedEvent", self.original_package_id)
-                        )?
+                        self.pool_created_event_struct_tag()
                     ),
                     cursor: None,
                     limit: None,
@@ -174,13 +177,7 @@ impl Turbos {
         let pool_ids = pool_created_events
             .into_iter()
             .map(|pool_created_event| {
-                let parsed_json = pool_created_event.parsed_json;
-                if let Value::String(pool_id_value) = parsed_json.get("pool").context(format!("Failed to get pool for a TurbosMarket: {:#?}", parsed_json))? {
-                    // println!("turbos: pool_id: {}", pool_id_value);
-                    Ok(ObjectID::from_str(&format!("0x{}", pool_id_value))?)
-                } else {
-                    Err(anyhow!("Failed to match pattern."))
-                }
+                pool_id_from_pool_created_event(&pool_created_event)
             })
             .collect::<Result<Vec<ObjectID>, anyhow::Error>>()?;
 
@@ -189,45 +186,72 @@ impl Turbos {
         let markets = pool_id_to_object_response
             .into_iter()
             .map(|(pool_id, object_response)| {
-                // println!("{:#?}", object_response);
-                // panic!();
+                self.market_from_object_response(pool_id, &object_response)
+            })
+            .collect::<Result<Vec<Box<dyn Market>>, anyhow::Error>>()?;
 
-                let fields = sui_sdk_utils::read_fields_from_object_response(&object_response).context(format!("Missing fields for pool {}.", pool_id))?;
+        Ok(markets)
+    }
 
-                let (coin_x, coin_y, fee) = get_coin_pair_and_fee_from_object_response(&object_response)?;
+    fn pool_created_event_struct_tag(&self) -> StructTag {
+        StructTag::from_str(
+            &format!("{}::pool_factory::PoolCreatedEvent", self.original_package_id)
+        ).expect("Turbos: failed to create pool created event struct tag")
+    }
 
-                let coin_x_sqrt_price = U64F64::from_bits(
-                    u128::from_str(
-                        & if let SuiMoveValue::String(str_value) = fields
-                            .read_dynamic_field_value("sqrt_price")
-                            .context(format!("Missing field sqrt_price for coin {}", coin_x))? {
-                                str_value
-                            } else {
-                                return Err(anyhow!("sqrt_price field does not match SuiMoveValue::String value."));
-                            }
-                    )?
-                );
-        
-                let coin_y_sqrt_price = U64F64::from_num(1) / coin_x_sqrt_price;
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        vec![EventFilter::MoveEventType(self.pool_created_event_struct_tag())]
+    }
 
-                Ok(
-                    Box::new(
-                        TurbosMarket {
-                            parent_exchange: self.clone(),  // reevaluate clone
-                            coin_x,
-                            coin_y,
-                            fee,
-                            pool_id,
-                            coin_x_sqrt_price: Some(coin_x_sqrt_price),
-                            coin_y_sqrt_price: Some(coin_y_sqrt_price),
-                            computing_pool: None    // We'll grab this later so we don't have to deal with async stuff
-                        }
-                    ) as Box<dyn Market>
-                )
-            })
-            .collect::<Result<Vec<Box<dyn Market>>, anyhow::Error>>()?;
+    // The fee type is only in the pool's type so the pool has to be fetched
+    async fn market_from_pool_created_event_(&self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        if event.type_ != self.pool_created_event_struct_tag() {
+            return Ok(None);
+        }
 
-        Ok(markets)
+        let pool_id = pool_id_from_pool_created_event(event)?;
+
+        let object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &[pool_id])
+            .await?
+            .remove(&pool_id)
+            .context(format!("Missing object response for new pool {}", pool_id))?;
+
+        Ok(Some(self.market_from_object_response(pool_id, &object_response)?))
+    }
+
+    fn market_from_object_response(&self, pool_id: ObjectID, object_response: &SuiObjectResponse) -> Result<Box<dyn Market>, anyhow::Error> {
+        let fields = sui_sdk_utils::read_fields_from_object_response(object_response).context(format!("Missing fields for pool {}.", pool_id))?;
+
+        let (coin_x, coin_y, fee) = get_coin_pair_and_fee_from_object_response(object_response)?;
+
+        let coin_x_sqrt_price = U64F64::from_bits(
+            u128::from_str(
+                & if let SuiMoveValue::String(str_value) = fields
+                    .read_dynamic_field_value("sqrt_price")
+                    .context(format!("Missing field sqrt_price for coin {}", coin_x))? {
+                        str_value
+                    } else {
+                        return Err(anyhow!("sqrt_price field does not match SuiMoveValue::String value."));
+                    }
+            )?
+        );
+
+        let coin_y_sqrt_price = U64F64::from_num(1) / coin_x_sqrt_price;
+
+        Ok(
+            Box::new(
+                TurbosMarket {
+                    parent_exchange: self.clone(),  // reevaluate clone
+                    coin_x,
+                    coin_y,
+                    fee,
+                    pool_id,
+                    coin_x_sqrt_price: Some(coin_x_sqrt_price),
+                    coin_y_sqrt_price: Some(coin_y_sqrt_price),
+                    computing_pool: None    // We'll grab this later so we don't have to deal with async stuff
+                }
+            ) as Box<dyn Market>
+        )
     }
 
     async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
@@ -516,6 +540,29 @@ impl Turbos {
         Ok(tick_index_to_tick)
     }
 
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        let fee = market_record
+            .extra_type_args
+            .first()
+            .context(format!("Turbos market record for pool {} is missing its fee type", market_record.pool_id))?
+            .clone();
+
+        let mut market = TurbosMarket {
+            parent_exchange: self.clone(),
+            coin_x: market_record.coin_x.clone(),
+            coin_y: market_record.coin_y.clone(),
+            fee,
+            pool_id: market_record.pool_id.clone(),
+            coin_x_sqrt_price: None,
+            coin_y_sqrt_price: None,
+            computing_pool: None
+        };
+
+        market.update_with_pool_state(market_record.pool_state.as_ref())?;
+
+        Ok(Box::new(market))
+    }
 }
 
 #[async_trait]
@@ -536,6 +583,10 @@ impl Exchange for Turbos {
         self.event_struct_tag_to_pool_field()
     }
 
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error> {
+        try_parse_pool_id_from_event(self.event_struct_tag_to_pool_field(), event)
+    }
+
     async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
         self.get_all_markets_(sui_client).await
     }
@@ -543,6 +594,18 @@ impl Exchange for Turbos {
     async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
         self.get_pool_id_to_object_response(sui_client, markets).await
     }
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        self.market_from_record(market_record)
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        self.pool_created_event_filters()
+    }
+
+    async fn market_from_pool_created_event(&mut self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        self.market_from_pool_created_event_(sui_client, event).await
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -611,7 +674,7 @@ impl TurbosMarket {
         Ok(())
     }
 
-    fn update_with_event(&mut self, event: &SuiEvent) -> Result<(), anyhow::Error> {
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
         let type_ = &event.type_;
         let event_parsed_json = &event.parsed_json;
         let computing_pool = self
@@ -636,133 +699,107 @@ impl TurbosMarket {
                 &format!("{}::pool::TogglePoolStatusEvent", &self.parent_exchange.original_package_id)
             ).context("Turbos: failed to create event struct tag")?;
 
-        match type_ {
-            swap_event_type => {
-                let amount_a = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_a").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_a is not Value::String."))
-                    }
-                )?;
-                let amount_b = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_b").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_b is not Value::String."))
-                    }
-                )?;
-                let fee_amount = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("fee_amount").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent fee_amount is not Value::String."))
-                    }
-                )?;
-                let a_to_b = *if let serde_json::Value::Bool(bool_inner) = event_parsed_json.get("a_to_b").context("")? {
-                    bool_inner
-                } else {
-                    return Err(anyhow!("SwapEvent a_to_b is not Value::Bool."))
-                };
+        if type_ == &swap_event_type {
+            let a_to_b = parsed_json::get_bool(event_parsed_json, "a_to_b")?;
+            let tick_pre_index = parsed_json::get_i32(event_parsed_json, "tick_pre_index")?;
+            let sqrt_price = parsed_json::get_u128(event_parsed_json, "sqrt_price")?;
+            let tick_current_index = parsed_json::get_i32(event_parsed_json, "tick_current_index")?;
+            let liquidity = parsed_json::get_u128(event_parsed_json, "liquidity")?;
+
+            // We missed something if we weren't where the swap started
+            // or the swap ended on the wrong side of our price.
+            let moves_with_swap = if a_to_b {
+                sqrt_price <= computing_pool.sqrt_price
+            } else {
+                sqrt_price >= computing_pool.sqrt_price
+            };
 
-                let amount_specified = if a_to_b {
-                    amount_a + fee_amount
-                } else {
-                    amount_b + fee_amount
-                };
+            if computing_pool.tick_current_index != tick_pre_index || !moves_with_swap || !computing_pool.liquidity_sanity_check() {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-                let sqrt_price_limit = if a_to_b {
-                    fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1
-                } else {
-                    fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1
-                };
+            // Walk the price to where the swap ended. Crosses the same ticks
+            // the swap did so liquidity ends up right as well.
+            computing_pool.apply_swap(
+                a_to_b,
+                u64::MAX,
+                true,
+                sqrt_price
+            );
+
+            if computing_pool.liquidity != liquidity || computing_pool.tick_current_index != tick_current_index {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-                computing_pool.apply_swap(
-                    a_to_b,
-                    amount_specified, 
-                    true, 
-                    sqrt_price_limit
-                );
-
-            },
-            add_liq_event_type => {
-                let tick_lower_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower_index is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper_index is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity_delta").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity_delta is not Value::String."))
-                    }
-                )?;
+            let coin_x_sqrt_price = U64F64::from_bits(computing_pool.sqrt_price);
+            self.coin_x_sqrt_price = Some(coin_x_sqrt_price);
+            self.coin_y_sqrt_price = Some(U64F64::from_num(1) / coin_x_sqrt_price);
+        } else if type_ == &add_liq_event_type {
+            let tick_lower_index = parsed_json::get_i32(event_parsed_json, "tick_lower_index")?;
+            let tick_upper_index = parsed_json::get_i32(event_parsed_json, "tick_upper_index")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity_delta")?;
+
+            computing_pool.apply_add_liquidity(
+                tick_lower_index, 
+                tick_upper_index, 
+                liquidity_delta
+            );
+        } else if type_ == &remove_liq_event_type {
+            let tick_lower_index = parsed_json::get_i32(event_parsed_json, "tick_lower_index")?;
+            let tick_upper_index = parsed_json::get_i32(event_parsed_json, "tick_upper_index")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity_delta")?;
+
+            if !computing_pool.ticks.contains_key(&tick_lower_index) || !computing_pool.ticks.contains_key(&tick_upper_index) {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-                computing_pool.apply_add_liquidity(
-                    tick_lower_index, 
-                    tick_upper_index, 
-                    liquidity_delta
-                );
-            },
-            remove_liq_event_type => {
-                let tick_lower_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower_index is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper_index is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity_delta").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity_delta is not Value::String."))
-                    }
-                )?;
+            computing_pool.apply_remove_liquidity(
+                tick_lower_index, 
+                tick_upper_index, 
+                liquidity_delta
+            );
+        } else if type_ == &update_status_event_type {
+            let status = parsed_json::get_bool(event_parsed_json, "status")?;
+
+            computing_pool.apply_update_unlocked(
+                status
+            );
+        } else {
+            return Ok(EventUpdate::Ignored);
+        }
 
-                computing_pool.apply_remove_liquidity(
-                    tick_lower_index, 
-                    tick_upper_index, 
-                    liquidity_delta
-                );
-            },
-            update_status_event_type => {
-                let status = *if let serde_json::Value::Bool(bool_inner) = event_parsed_json.get("status").context("")? {
-                    bool_inner
-                } else {
-                    return Err(anyhow!("SwapEvent status is not Value::Bool."))
-                };
+        Ok(EventUpdate::Applied)
+    }
 
-                computing_pool.apply_update_unlocked(
-                    status
-                );
-            },
-            _ => {
-                // do nothing
-            }
-        }
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.computing_pool = pool_state
+            .map(|pool_state| {
+                fast_v3_pool::Pool::from_pool_state(self.pool_id.clone(), pool_state)
+            })
+            .transpose()?;
 
-        // self.version = 
+        if let Some(computing_pool) = &self.computing_pool {
+            let coin_x_sqrt_price = U64F64::from_bits(computing_pool.sqrt_price);
+
+            self.coin_x_sqrt_price = Some(coin_x_sqrt_price);
+            self.coin_y_sqrt_price = Some(U64F64::from_num(1) / coin_x_sqrt_price);
+        } else {
+            self.coin_x_sqrt_price = None;
+            self.coin_y_sqrt_price = None;
+        }
 
         Ok(())
+    }
 
+    fn market_record(&self) -> MarketRecord {
+        MarketRecord {
+            package_id: self.package_id().clone(),
+            pool_id: self.pool_id.clone(),
+            coin_x: self.coin_x.clone(),
+            coin_y: self.coin_y.clone(),
+            extra_type_args: vec![self.fee.clone()],
+            pool_state: self.computing_pool.as_ref().map(|cp| cp.to_pool_state()),
+        }
     }
 
     fn pool_id(&self) -> &ObjectID {
@@ -797,6 +834,46 @@ impl TurbosMarket {
         (swap_state.amount_a as u128, swap_state.amount_b as u128)
     }
 
+    // Moves the pool as if the swap had landed. Returns the same as compute_swap_x_to_y.
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        // Nothing to move until the pool has synced
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_x_to_y(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            computing_pool.apply_swap(
+                true, 
+                amount_specified as u64, 
+                true, 
+                fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1,
+            );
+        }
+
+        amounts
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        if self.computing_pool.is_none() {
+            return (0, 0);
+        }
+
+        let amounts = self.compute_swap_y_to_x(amount_specified);
+
+        if let Some(computing_pool) = self.computing_pool.as_mut() {
+            computing_pool.apply_swap(
+                false, 
+                amount_specified as u64, 
+                true, 
+                fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1,
+            );
+        }
+
+        amounts
+    }
+
     fn viable(&self) -> bool {
         if let Some(cp) = &self.computing_pool {
             // println!("liquidity: {}", cp.liquidity);
@@ -815,16 +892,46 @@ impl TurbosMarket {
         }
     }
 
+    // Only holds until the price crosses into the next tick
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        let cp = self.computing_pool.as_ref()?;
+
+        if cp.liquidity == 0 {
+            return None;
+        }
+
+        let (virtual_reserve_x, virtual_reserve_y) = cp.virtual_reserves();
+        let (reserve_in, reserve_out) = if x_to_y {
+            (virtual_reserve_x, virtual_reserve_y)
+        } else {
+            (virtual_reserve_y, virtual_reserve_x)
+        };
+
+        Some(
+            ConstantProductCurve {
+                reserve_in,
+                reserve_out,
+                fee_multiplier: cp.fee_multiplier(),
+                exact: false,
+            }
+        )
+    }
+
     async fn add_swap_to_programmable_transaction(
         &self,
         transaction_builder: &TransactionBuilder,
         pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
+        orig_coin: Argument, // the coin we swap in its entirety
         x_to_y: bool,
-        amount_in: u128,
         amount_out: u128,
         recipient: SuiAddress
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<Argument, anyhow::Error> {
+
+        let orig_coin_type = if x_to_y {
+            self.coin_x.clone()
+        } else {
+            self.coin_y.clone()
+        };
 
         // Arg8: &Clock
         let clock_arg = ProgrammableTransactionArg::SuiJsonValue(
@@ -873,40 +980,27 @@ impl TurbosMarket {
             SuiJsonValue::from_object_id(self.pool_id.clone())
         );
 
-        // Arg1: vector<Coin<Ty0 or Ty1>>
-        let orig_coins_args_vec = if let Some(oc) = orig_coins {
-            oc
-                .into_iter()
-                .map(|orig_coin| {
-                    ProgrammableObjectArg::ObjectID(orig_coin)
-                })
-                .collect::<Vec<ProgrammableObjectArg>>()
-        } else {
-            vec![
-                ProgrammableObjectArg::Argument(
-                    transaction_builder.programmable_split_gas_coin(pt_builder, amount_in as u64).await
-                )
-            ]
-        };
+        // Arg2: u64
+        // Whatever is in the coin we were handed. We may not know it ahead of time.
+        // Read before the coin is moved into the vector below.
+        let amount_specified_arg = ProgrammableTransactionArg::Argument(
+            transaction_builder
+                .programmable_coin_value(
+                    pt_builder,
+                    orig_coin_type.clone(),
+                    orig_coin
+                ).await?
+        );
 
+        // Arg1: vector<Coin<Ty0 or Ty1>>
         let orig_coins_arg = ProgrammableTransactionArg::Argument(
             transaction_builder
                 .programmable_make_object_vec(
                     pt_builder,
-                    orig_coins_args_vec
+                    vec![ProgrammableObjectArg::Argument(orig_coin)]
                 ).await?
         );
 
-        // Arg2: u64
-        let amount_specified_arg = ProgrammableTransactionArg::SuiJsonValue(
-            SuiJsonValue::new(
-                move_value_to_json(
-                    &MoveValue::U64(amount_in as u64)
-                )
-                .context("failed to convert MoveValue for amount_specified to JSON")?
-            )?
-        );
-
         // Arg3: u64
         // The amount out we're expecting 
         let amount_threshold_arg = ProgrammableTransactionArg::SuiJsonValue(
@@ -983,13 +1077,14 @@ impl TurbosMarket {
             SuiTypeTag::new(format!("{}", self.fee)),
         ];
 
+        // The _with_return_ variants hand back the coins instead of transferring them
         let function = if x_to_y {
-            "swap_a_b"
+            "swap_a_b_with_return_"
         } else {
-            "swap_b_a"
+            "swap_b_a_with_return_"
         };
 
-        transaction_builder.programmable_move_call(
+        let swap_result = transaction_builder.programmable_move_call(
             pt_builder,
             self.parent_exchange.package_id.clone(),
             "swap_router",
@@ -998,6 +1093,231 @@ impl TurbosMarket {
             call_args
         ).await?;
 
+        // Returns (Coin<dest>, Coin<orig>)
+        let dest_coin_out = programmable_nested_result(swap_result, 0)?;
+        let orig_coin_out = programmable_nested_result(swap_result, 1)?;
+
+        // We swapped the whole coin so there should be nothing left over
+        transaction_builder
+            .programmable_coin_destroy_zero(
+                pt_builder,
+                orig_coin_type,
+                orig_coin_out
+            ).await?;
+
+        Ok(dest_coin_out)
+    }
+
+    // pool::flash_swap pays out before we pay in. The receipt it returns
+    // has no drop so the transaction can't succeed without repaying it.
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        x_to_y: bool,
+        amount_in: u128,
+        recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        let orig_coin_type = if x_to_y {
+            self.coin_x.clone()
+        } else {
+            self.coin_y.clone()
+        };
+
+        // Arg0: &mut Pool<Ty0, Ty1, Ty2>
+        let pool_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.pool_id.clone())
+        );
+
+        // Arg1: address
+        let recipient_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::Address(
+                        AccountAddress::from(
+                            recipient
+                        )
+                    )
+                ).context("failed to convert MoveValue for recipient to JSON")?
+            )?
+        );
+
+        // Arg2: bool
+        let a_to_b_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::Bool(x_to_y)
+                )
+                .context("failed to convert MoveValue for a_to_b to JSON")?
+            )?
+        );
+
+        // Arg3: u128
+        let amount_specified_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::U128(amount_in)
+                )
+                .context("failed to convert MoveValue for amount_specified to JSON")?
+            )?
+        );
+
+        // Arg4: bool
+        let amount_specified_is_input_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::Bool(true)
+                )
+                .context("failed to convert MoveValue for amount_specified_is_input to JSON")?
+            )?
+        );
+
+        // Arg5: u128
+        let sqrt_price_limit_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::U128(
+                        if x_to_y {
+                            fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1
+                        } else {
+                            fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1
+                        }
+                    )
+                )
+                .context("failed to convert MoveValue for sqrt_price_limit to JSON")?
+            )?
+        );
+
+        // Arg6: &Clock
+        let clock_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(
+                ObjectID::from_str(CLOCK_OBJECT_ID)?
+            )
+        );
+
+        // Arg7: &Versioned
+        let versioned_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(
+                self.parent_exchange.versioned_id.clone()
+            )
+        );
+
+        let call_args = vec![
+            pool_arg,                       // Arg0
+            recipient_arg,                  // Arg1
+            a_to_b_arg,                     // Arg2
+            amount_specified_arg,           // Arg3
+            amount_specified_is_input_arg,  // Arg4
+            sqrt_price_limit_arg,           // Arg5
+            clock_arg,                      // Arg6
+            versioned_arg                   // Arg7
+        ];
+
+        let type_args = vec![
+            SuiTypeTag::new(format!("{}", self.coin_x)),
+            SuiTypeTag::new(format!("{}", self.coin_y)),
+            SuiTypeTag::new(format!("{}", self.fee)),
+        ];
+
+        let flash_swap_result = transaction_builder.programmable_move_call(
+            pt_builder,
+            self.parent_exchange.package_id.clone(),
+            "pool",
+            "flash_swap",
+            type_args,
+            call_args
+        ).await?;
+
+        // Returns (Coin<Ty0>, Coin<Ty1>, FlashSwapReceipt<Ty0, Ty1>)
+        let (orig_coin_out, dest_coin_out) = if x_to_y {
+            (programmable_nested_result(flash_swap_result, 0)?, programmable_nested_result(flash_swap_result, 1)?)
+        } else {
+            (programmable_nested_result(flash_swap_result, 1)?, programmable_nested_result(flash_swap_result, 0)?)
+        };
+        let receipt = programmable_nested_result(flash_swap_result, 2)?;
+
+        // Nothing of the side we owe is paid out
+        transaction_builder
+            .programmable_coin_destroy_zero(
+                pt_builder,
+                orig_coin_type,
+                orig_coin_out
+            ).await?;
+
+        Ok((dest_coin_out, receipt))
+    }
+
+    // We swap by amount in with no effective price limit so we owe amount_in.
+    // If the pool can't take all of it the flash swap has already aborted.
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        receipt: Argument,
+        pay_coin: Argument,
+        x_to_y: bool,
+        amount_in: u128
+    ) -> Result<(), anyhow::Error> {
+        let dest_coin_type = if x_to_y {
+            self.coin_y.clone()
+        } else {
+            self.coin_x.clone()
+        };
+
+        let repay_coin = transaction_builder
+            .programmable_split_coin(
+                pt_builder,
+                ProgrammableObjectArg::Argument(pay_coin),
+                amount_in as u64
+            ).await?;
+
+        let zero_coin = transaction_builder
+            .programmable_coin_zero(
+                pt_builder,
+                dest_coin_type
+            ).await?;
+
+        let (coin_a, coin_b) = if x_to_y {
+            (repay_coin, zero_coin)
+        } else {
+            (zero_coin, repay_coin)
+        };
+
+        // Arg0: &mut Pool<Ty0, Ty1, Ty2>
+        let pool_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.pool_id.clone())
+        );
+
+        // Arg4: &Versioned
+        let versioned_arg = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(
+                self.parent_exchange.versioned_id.clone()
+            )
+        );
+
+        let call_args = vec![
+            pool_arg,                                   // Arg0
+            ProgrammableTransactionArg::Argument(coin_a),   // Arg1: Coin<Ty0>
+            ProgrammableTransactionArg::Argument(coin_b),   // Arg2: Coin<Ty1>
+            ProgrammableTransactionArg::Argument(receipt),  // Arg3: FlashSwapReceipt<Ty0, Ty1>
+            versioned_arg                               // Arg4
+        ];
+
+        let type_args = vec![
+            SuiTypeTag::new(format!("{}", self.coin_x)),
+            SuiTypeTag::new(format!("{}", self.coin_y)),
+            SuiTypeTag::new(format!("{}", self.fee)),
+        ];
+
+        transaction_builder.programmable_move_call(
+            pt_builder,
+            self.parent_exchange.package_id.clone(),
+            "pool",
+            "repay_flash_swap",
+            type_args,
+            call_args
+        ).await?;
+
         Ok(())
     }
 
@@ -1025,6 +1345,18 @@ impl Market for TurbosMarket {
         self.update_with_object_response(sui_client, object_response).await
     }
 
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
+
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.update_with_pool_state(pool_state)
+    }
+
+    fn market_record(&self) -> MarketRecord {
+        self.market_record()
+    }
+
     fn pool_id(&self) -> &ObjectID {
         self.pool_id()
     }
@@ -1053,32 +1385,110 @@ impl Market for TurbosMarket {
         self.compute_swap_y_to_x(amount_specified)
     }
 
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_x_to_y(amount_specified)
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_y_to_x(amount_specified)
+    }
+
     fn viable(&self) -> bool {
         self.viable()
     }
 
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        self.constant_product_curve(x_to_y)
+    }
+
     async fn add_swap_to_programmable_transaction(
         &self,
         transaction_builder: &TransactionBuilder,
         pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
+        orig_coin: Argument, // split off of our own coins or the output coin of the previous leg
         x_to_y: bool,
-        amount_in: u128,
+        _amount_in: u128,
         amount_out: u128,
         recipient: SuiAddress
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<Argument, anyhow::Error> {
         self.add_swap_to_programmable_transaction(
             transaction_builder,
             pt_builder,
-            orig_coins,
+            orig_coin,
             x_to_y,
-            amount_in,
             amount_out,
             recipient
         )
         .await
     }
 
+    fn supports_exact_output(&self) -> bool {
+        false
+    }
+
+    async fn add_exact_output_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _orig_coin: Argument,
+        _x_to_y: bool,
+        _amount_out: u128,
+        _recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        Err(anyhow!("Turbos pools don't support exact output swaps"))
+    }
+
+    fn supports_flash_swap(&self) -> bool {
+        true
+    }
+
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        x_to_y: bool,
+        amount_in: u128,
+        recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        self.add_flash_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            x_to_y,
+            amount_in,
+            recipient
+        )
+        .await
+    }
+
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        receipt: Argument,
+        pay_coin: Argument,
+        x_to_y: bool,
+        amount_in: u128
+    ) -> Result<(), anyhow::Error> {
+        self.add_repay_flash_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            receipt,
+            pay_coin,
+            x_to_y,
+            amount_in
+        )
+        .await
+    }
+
+}
+
+fn pool_id_from_pool_created_event(pool_created_event: &SuiEvent) -> Result<ObjectID, anyhow::Error> {
+    let parsed_json = &pool_created_event.parsed_json;
+    if let Value::String(pool_id_value) = parsed_json.get("pool").context(format!("Failed to get pool for a TurbosMarket: {:#?}", parsed_json))? {
+        Ok(ObjectID::from_str(&format!("0x{}", pool_id_value))?)
+    } else {
+        Err(anyhow!("Failed to match pattern."))
+    }
 }
 
 fn get_coin_pair_and_fee_from_object_response (
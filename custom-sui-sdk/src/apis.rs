@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::future;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -8,6 +8,7 @@ use futures::stream;
 use futures::StreamExt;
 use futures_core::Stream;
 use jsonrpsee::core::client::Subscription;
+use jsonrpsee::ws_client::WsClient;
 
 use crate::error::{Error, SuiRpcResult};
 use crate::{RpcClient, WAIT_FOR_TX_TIMEOUT_SEC};
@@ -35,27 +36,39 @@ use sui_types::sui_system_state::sui_system_state_summary::SuiSystemStateSummary
 use sui_types::transaction::{Transaction, TransactionData, TransactionKind};
 
 const WAIT_FOR_LOCAL_EXECUTION_RETRY_COUNT: u8 = 3;
+// Reconnecting a dropped event subscription waits this long after a failed
+// attempt, doubling up to the max
+const RESUBSCRIBE_DELAY_MIN: Duration = Duration::from_secs(1);
+const RESUBSCRIBE_DELAY_MAX: Duration = Duration::from_secs(60);
+// Enough to recognize everything a backfill and the new subscription both deliver
+const SEEN_EVENT_IDS_CAPACITY: usize = 10_000;
+const BACKFILL_PAGE_SIZE: usize = 100;
+// Most transactions a full node returns from one multi get
+const MULTI_GET_TRANSACTIONS_LIMIT: usize = 50;
 
 use async_trait::async_trait;
 use page_turner::prelude::*;
 
-use governor::DefaultDirectRateLimiter;
+use crate::rate_limiter::{RequestLimiter, RequestWeight};
 
 #[derive(Debug)]
 pub struct ReadApi {
     api: Arc<RpcClient>,
-    rate_limiter: Arc<DefaultDirectRateLimiter>,
+    rate_limiter: Arc<RequestLimiter>,
+    // Requests give up once it passes
+    deadline: Option<Instant>,
 }
 
 impl ReadApi {
-    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<DefaultDirectRateLimiter>) -> Self {
+    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<RequestLimiter>, deadline: Option<Instant>) -> Self {
         Self {
             api,
             rate_limiter,
+            deadline,
         }
     }
 
-    pub fn rate_limiter(&self) -> &Arc<DefaultDirectRateLimiter> {
+    pub fn rate_limiter(&self) -> &Arc<RequestLimiter> {
         &self.rate_limiter
     }
 
@@ -66,12 +79,11 @@ impl ReadApi {
         cursor: Option<ObjectID>,
         limit: Option<usize>,
     ) -> SuiRpcResult<ObjectsPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
         Ok(self
             .api
-            .http
-            .get_owned_objects(address, query, cursor, limit)
+            .request(self.deadline, |http| http.get_owned_objects(address, query.clone(), cursor, limit))
             .await?)
     }
 
@@ -81,12 +93,11 @@ impl ReadApi {
         cursor: Option<ObjectID>,
         limit: Option<usize>,
     ) -> SuiRpcResult<DynamicFieldPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
         Ok(self
             .api
-            .http
-            .get_dynamic_fields(object_id, cursor, limit)
+            .request(self.deadline, |http| http.get_dynamic_fields(object_id, cursor, limit))
             .await?)
     }
 
@@ -96,12 +107,11 @@ impl ReadApi {
         version: SequenceNumber,
         options: SuiObjectDataOptions,
     ) -> SuiRpcResult<SuiPastObjectResponse> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
         Ok(self
             .api
-            .http
-            .try_get_past_object(object_id, version, Some(options))
+            .request(self.deadline, |http| http.try_get_past_object(object_id, version, Some(options.clone())))
             .await?)
     }
 
@@ -110,12 +120,11 @@ impl ReadApi {
         past_objects: Vec<SuiGetPastObjectRequest>,
         options: SuiObjectDataOptions,
     ) -> SuiRpcResult<Vec<SuiPastObjectResponse>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::items(past_objects.len())).await;
 
         Ok(self
             .api
-            .http
-            .try_multi_get_past_objects(past_objects, Some(options))
+            .request(self.deadline, |http| http.try_multi_get_past_objects(past_objects.clone(), Some(options.clone())))
             .await?)
     }
 
@@ -124,9 +133,9 @@ impl ReadApi {
         object_id: ObjectID,
         options: SuiObjectDataOptions,
     ) -> SuiRpcResult<SuiObjectResponse> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_object(object_id, Some(options)).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_object(object_id, Some(options.clone()))).await?)
     }
 
     pub async fn multi_get_object_with_options(
@@ -134,19 +143,18 @@ impl ReadApi {
         object_ids: Vec<ObjectID>,
         options: SuiObjectDataOptions,
     ) -> SuiRpcResult<Vec<SuiObjectResponse>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::items(object_ids.len())).await;
 
         Ok(self
             .api
-            .http
-            .multi_get_objects(object_ids, Some(options))
+            .request(self.deadline, |http| http.multi_get_objects(object_ids.clone(), Some(options.clone())))
             .await?)
     }
 
     pub async fn get_total_transaction_blocks(&self) -> SuiRpcResult<u64> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(*self.api.http.get_total_transaction_blocks().await?)
+        Ok(*self.api.request(self.deadline, |http| http.get_total_transaction_blocks()).await?)
     }
 
     pub async fn get_transaction_with_options(
@@ -154,12 +162,11 @@ impl ReadApi {
         digest: TransactionDigest,
         options: SuiTransactionBlockResponseOptions,
     ) -> SuiRpcResult<SuiTransactionBlockResponse> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
         Ok(self
             .api
-            .http
-            .get_transaction_block(digest, Some(options))
+            .request(self.deadline, |http| http.get_transaction_block(digest, Some(options.clone())))
             .await?)
     }
 
@@ -168,12 +175,11 @@ impl ReadApi {
         digests: Vec<TransactionDigest>,
         options: SuiTransactionBlockResponseOptions,
     ) -> SuiRpcResult<Vec<SuiTransactionBlockResponse>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::items(digests.len())).await;
 
         Ok(self
             .api
-            .http
-            .multi_get_transaction_blocks(digests, Some(options))
+            .request(self.deadline, |http| http.multi_get_transaction_blocks(digests.clone(), Some(options.clone())))
             .await?)
     }
 
@@ -181,9 +187,9 @@ impl ReadApi {
         &self,
         epoch: Option<BigInt<u64>>,
     ) -> SuiRpcResult<SuiCommittee> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_committee_info(epoch).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_committee_info(epoch)).await?)
     }
 
     pub async fn query_transaction_blocks(
@@ -193,20 +199,19 @@ impl ReadApi {
         limit: Option<usize>,
         descending_order: bool,
     ) -> SuiRpcResult<TransactionBlocksPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
         Ok(self
             .api
-            .http
-            .query_transaction_blocks(query, cursor, limit, Some(descending_order))
+            .request(self.deadline, |http| http.query_transaction_blocks(query.clone(), cursor, limit, Some(descending_order)))
             .await?)
     }
 
     /// Return a checkpoint
     pub async fn get_checkpoint(&self, id: CheckpointId) -> SuiRpcResult<Checkpoint> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_checkpoint(id).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_checkpoint(id)).await?)
     }
 
     /// Return paginated list of checkpoints
@@ -216,12 +221,11 @@ impl ReadApi {
         limit: Option<usize>,
         descending_order: bool,
     ) -> SuiRpcResult<CheckpointPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
         Ok(self
             .api
-            .http
-            .get_checkpoints(cursor, limit, descending_order)
+            .request(self.deadline, |http| http.get_checkpoints(cursor, limit, descending_order))
             .await?)
     }
 
@@ -229,12 +233,11 @@ impl ReadApi {
     pub async fn get_latest_checkpoint_sequence_number(
         &self,
     ) -> SuiRpcResult<CheckpointSequenceNumber> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
         Ok(*self
             .api
-            .http
-            .get_latest_checkpoint_sequence_number()
+            .request(self.deadline, |http| http.get_latest_checkpoint_sequence_number())
             .await?)
     }
 
@@ -274,32 +277,32 @@ impl ReadApi {
         &self,
         package: ObjectID,
     ) -> SuiRpcResult<BTreeMap<String, SuiMoveNormalizedModule>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
         Ok(self
             .api
-            .http
-            .get_normalized_move_modules_by_package(package)
+            .request(self.deadline, |http| http.get_normalized_move_modules_by_package(package))
             .await?)
     }
 
     // TODO(devx): we can probably cache this given an epoch
     pub async fn get_reference_gas_price(&self) -> SuiRpcResult<u64> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(*self.api.http.get_reference_gas_price().await?)
+        Ok(*self.api.request(self.deadline, |http| http.get_reference_gas_price()).await?)
     }
 
     pub async fn dry_run_transaction_block(
         &self,
         tx: TransactionData,
     ) -> SuiRpcResult<DryRunTransactionBlockResponse> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::Priority).await;
+
+        let tx_bytes = Base64::from_bytes(&bcs::to_bytes(&tx)?);
 
         Ok(self
             .api
-            .http
-            .dry_run_transaction_block(Base64::from_bytes(&bcs::to_bytes(&tx)?))
+            .request(self.deadline, |http| http.dry_run_transaction_block(tx_bytes.clone()))
             .await?)
     }
 
@@ -307,9 +310,9 @@ impl ReadApi {
         &self,
         digest: TransactionDigest,
     ) -> SuiRpcResult<SuiLoadedChildObjectsResponse> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_loaded_child_objects(digest).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_loaded_child_objects(digest)).await?)
     }
 }
 
@@ -372,14 +375,17 @@ impl PageTurner<GetDynamicFieldsRequest> for ReadApi {
 #[derive(Debug, Clone)]
 pub struct CoinReadApi {
     api: Arc<RpcClient>,
-    rate_limiter: Arc<DefaultDirectRateLimiter>,
+    rate_limiter: Arc<RequestLimiter>,
+    // Requests give up once it passes
+    deadline: Option<Instant>,
 }
 
 impl CoinReadApi {
-    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<DefaultDirectRateLimiter>) -> Self {
+    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<RequestLimiter>, deadline: Option<Instant>) -> Self {
         Self {
             api,
-            rate_limiter
+            rate_limiter,
+            deadline,
         }
     }
 
@@ -390,12 +396,11 @@ impl CoinReadApi {
         cursor: Option<ObjectID>,
         limit: Option<usize>,
     ) -> SuiRpcResult<CoinPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
         Ok(self
             .api
-            .http
-            .get_coins(owner, coin_type, cursor, limit)
+            .request(self.deadline, |http| http.get_coins(owner, coin_type.clone(), cursor, limit))
             .await?)
     }
 
@@ -405,9 +410,9 @@ impl CoinReadApi {
         cursor: Option<ObjectID>,
         limit: Option<usize>,
     ) -> SuiRpcResult<CoinPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
-        Ok(self.api.http.get_all_coins(owner, cursor, limit).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_all_coins(owner, cursor, limit)).await?)
     }
 
     pub fn get_coins_stream(
@@ -452,7 +457,7 @@ impl CoinReadApi {
         amount: u128,
         exclude: Vec<ObjectID>,
     ) -> SuiRpcResult<Vec<Coin>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
         let mut total = 0u128;
         let coins = self
@@ -478,44 +483,47 @@ impl CoinReadApi {
         owner: SuiAddress,
         coin_type: Option<String>,
     ) -> SuiRpcResult<Balance> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_balance(owner, coin_type).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_balance(owner, coin_type.clone())).await?)
     }
 
     pub async fn get_all_balances(&self, owner: SuiAddress) -> SuiRpcResult<Vec<Balance>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_all_balances(owner).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_all_balances(owner)).await?)
     }
 
     pub async fn get_coin_metadata(
         &self,
         coin_type: String,
     ) -> SuiRpcResult<Option<SuiCoinMetadata>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_coin_metadata(coin_type).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_coin_metadata(coin_type.clone())).await?)
     }
 
     pub async fn get_total_supply(&self, coin_type: String) -> SuiRpcResult<Supply> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_total_supply(coin_type).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_total_supply(coin_type.clone())).await?)
     }
 }
 
 #[derive(Clone)]
 pub struct EventApi {
     api: Arc<RpcClient>,
-    rate_limiter: Arc<DefaultDirectRateLimiter>,
+    rate_limiter: Arc<RequestLimiter>,
+    // Requests give up once it passes
+    deadline: Option<Instant>,
 }
 
 impl EventApi {
-    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<DefaultDirectRateLimiter>) -> Self {
+    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<RequestLimiter>, deadline: Option<Instant>) -> Self {
         Self {
             api,
             rate_limiter,
+            deadline,
         }
     }
 
@@ -523,7 +531,7 @@ impl EventApi {
         &self,
         filter: EventFilter,
     ) -> SuiRpcResult<impl Stream<Item = SuiRpcResult<SuiEvent>>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
         match &self.api.ws {
             Some(c) => {
@@ -536,10 +544,42 @@ impl EventApi {
         }
     }
 
+    // Like subscribe_event except the stream doesn't end when the websocket drops.
+    // It reconnects and backfills whatever it missed from the last event it yielded.
+    // Starts after cursor when there is one so a consumer can pick up where it left off.
+    pub async fn subscribe_event_resilient(
+        &self,
+        filter: EventFilter,
+        cursor: Option<EventID>,
+    ) -> SuiRpcResult<impl Stream<Item = SuiEvent>> {
+        let mut subscription = ResilientSubscription {
+            event_api: self.clone(),
+            read_api: ReadApi::new(self.api.clone(), self.rate_limiter.clone(), self.deadline),
+            filter,
+            live: None,
+            backlog: VecDeque::new(),
+            cursor,
+            seen_event_ids: SeenEventIds::new(SEEN_EVENT_IDS_CAPACITY),
+            resubscribe_delay: Duration::ZERO,
+            ws_config_index: 0,
+        };
+
+        // A bad url or filter fails here instead of being retried forever
+        subscription.resubscribe().await?;
+
+        Ok(
+            stream::unfold(subscription, |mut subscription| async move {
+                let event = subscription.next_event().await;
+                Some((event, subscription))
+            })
+            .boxed()
+        )
+    }
+
     pub async fn get_events(&self, digest: TransactionDigest) -> SuiRpcResult<Vec<SuiEvent>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
             
-        Ok(self.api.http.get_events(digest).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_events(digest)).await?)
     }
 
     pub async fn query_events(
@@ -549,12 +589,11 @@ impl EventApi {
         limit: Option<usize>,
         descending_order: bool,
     ) -> SuiRpcResult<EventPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
         Ok(self
             .api
-            .http
-            .query_events(query, cursor, limit, Some(descending_order))
+            .request(self.deadline, |http| http.query_events(query.clone(), cursor, limit, Some(descending_order)))
             .await?)
     }
 
@@ -586,6 +625,245 @@ impl EventApi {
     }
 }
 
+// Remembers the most recent event ids up to capacity
+struct SeenEventIds {
+    event_ids: HashSet<EventID>,
+    event_ids_order: VecDeque<EventID>,
+    capacity: usize,
+}
+
+impl SeenEventIds {
+    fn new(capacity: usize) -> Self {
+        SeenEventIds {
+            event_ids: HashSet::new(),
+            event_ids_order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // False if we've seen it already. The oldest event id is forgotten past capacity.
+    fn insert(&mut self, event_id: EventID) -> bool {
+        if !self.event_ids.insert(event_id) {
+            return false;
+        }
+
+        self.event_ids_order.push_back(event_id);
+        if self.event_ids_order.len() > self.capacity {
+            if let Some(oldest_event_id) = self.event_ids_order.pop_front() {
+                self.event_ids.remove(&oldest_event_id);
+            }
+        }
+
+        true
+    }
+}
+
+struct ResilientSubscription {
+    event_api: EventApi,
+    // For putting backfilled events back into chain order
+    read_api: ReadApi,
+    filter: EventFilter,
+    // Every subscription gets its own websocket. It has to outlive the subscription.
+    live: Option<(WsClient, Subscription<SuiEvent>)>,
+    // Backfilled events to yield before going back to the live ones
+    backlog: VecDeque<SuiEvent>,
+    // The last event we yielded
+    cursor: Option<EventID>,
+    seen_event_ids: SeenEventIds,
+    resubscribe_delay: Duration,
+    ws_config_index: usize,
+}
+
+impl ResilientSubscription {
+    async fn next_event(&mut self) -> SuiEvent {
+        loop {
+            let event = if let Some(event) = self.backlog.pop_front() {
+                event
+            } else if let Some((_, live)) = self.live.as_mut() {
+                match live.next().await {
+                    Some(Ok(event)) => {
+                        self.resubscribe_delay = Duration::ZERO;
+                        event
+                    },
+                    Some(Err(err)) => {
+                        println!("Event subscription failed, resubscribing: {}", err);
+                        self.live = None;
+                        continue;
+                    },
+                    None => {
+                        println!("Event subscription closed, resubscribing");
+                        self.live = None;
+                        continue;
+                    }
+                }
+            } else {
+                // The first attempt after a healthy subscription goes out right away
+                if !self.resubscribe_delay.is_zero() {
+                    tokio::time::sleep(self.resubscribe_delay).await;
+                }
+                self.resubscribe_delay = (self.resubscribe_delay * 2).clamp(RESUBSCRIBE_DELAY_MIN, RESUBSCRIBE_DELAY_MAX);
+
+                if let Err(err) = self.resubscribe().await {
+                    println!("Failed to resubscribe to events, retrying in {:?}: {}", self.resubscribe_delay, err);
+                }
+                continue;
+            };
+
+            if self.mark_seen(&event) {
+                return event;
+            }
+        }
+    }
+
+    // Subscribes before backfilling so nothing can land in between.
+    // Whatever the two have in common is dropped as we yield it.
+    async fn resubscribe(&mut self) -> SuiRpcResult<()> {
+        self.live = None;
+
+        let ws_configs = &self.event_api.api.ws_configs;
+        if ws_configs.is_empty() {
+            return Err(Error::Subscription("Subscription only supported by WebSocket client.".to_string()));
+        }
+
+        // Every attempt goes to the next websocket in case it's the node that's down
+        let ws_config = &ws_configs[self.ws_config_index % ws_configs.len()];
+        self.ws_config_index += 1;
+
+        self.event_api.rate_limiter.until_ready(RequestWeight::SINGLE).await;
+
+        let ws = ws_config.connect().await?;
+        let live: Subscription<SuiEvent> = ws.subscribe_event(self.filter.clone()).await?;
+
+        self.backlog = self.backfill().await?;
+        self.live = Some((ws, live));
+
+        Ok(())
+    }
+
+    // Everything after the cursor. Without one there's nothing to pick up from.
+    async fn backfill(&self) -> SuiRpcResult<VecDeque<SuiEvent>> {
+        let cursor = if let Some(cursor) = self.cursor {
+            cursor
+        } else {
+            return Ok(VecDeque::new());
+        };
+
+        // Full nodes don't take EventFilter::Any in queries so each of its filters is queried on its own
+        let filters = if let EventFilter::Any(filters) = &self.filter {
+            filters.clone()
+        } else {
+            vec![self.filter.clone()]
+        };
+
+        let num_filters = filters.len();
+        let mut events = vec![];
+
+        for filter in filters {
+            let mut page_cursor = Some(cursor);
+
+            loop {
+                let page = self.event_api
+                    .query_events(filter.clone(), page_cursor, Some(BACKFILL_PAGE_SIZE), false)
+                    .await?;
+
+                events.extend(page.data);
+
+                if !page.has_next_page {
+                    break;
+                }
+                page_cursor = page.next_cursor;
+            }
+        }
+
+        // A single query already comes back in chain order
+        if num_filters > 1 {
+            let tx_digest_to_position = self.tx_digest_to_position(&events).await?;
+            sort_into_chain_order(&mut events, &tx_digest_to_position)?;
+        }
+
+        Ok(events.into())
+    }
+
+    // Where each event's transaction was executed: its checkpoint and its index within the checkpoint.
+    // Timestamps can't order transactions since a checkpoint's transactions share one.
+    async fn tx_digest_to_position(
+        &self,
+        events: &[SuiEvent]
+    ) -> SuiRpcResult<HashMap<TransactionDigest, (CheckpointSequenceNumber, usize)>> {
+        let tx_digests = events
+            .iter()
+            .map(|event| {
+                event.id.tx_digest
+            })
+            .collect::<HashSet<TransactionDigest>>()
+            .into_iter()
+            .collect::<Vec<TransactionDigest>>();
+
+        let mut checkpoints = HashSet::new();
+
+        for chunk in tx_digests.chunks(MULTI_GET_TRANSACTIONS_LIMIT) {
+            let responses = self.read_api
+                .multi_get_transactions_with_options(chunk.to_vec(), SuiTransactionBlockResponseOptions::new())
+                .await?;
+
+            for response in responses {
+                let checkpoint = response.checkpoint.ok_or_else(|| {
+                    Error::DataError(format!("Transaction {} isn't in a checkpoint yet", response.digest))
+                })?;
+
+                checkpoints.insert(checkpoint);
+            }
+        }
+
+        let mut tx_digest_to_position = HashMap::new();
+
+        for checkpoint in checkpoints {
+            let checkpoint = self.read_api
+                .get_checkpoint(CheckpointId::SequenceNumber(checkpoint))
+                .await?;
+
+            tx_digest_to_position.extend(
+                checkpoint
+                    .transactions
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, tx_digest)| {
+                        (tx_digest, (checkpoint.sequence_number, index))
+                    })
+            );
+        }
+
+        Ok(tx_digest_to_position)
+    }
+
+    // False if we already yielded the event. Otherwise it becomes the cursor.
+    fn mark_seen(&mut self, event: &SuiEvent) -> bool {
+        if !self.seen_event_ids.insert(event.id) {
+            return false;
+        }
+
+        self.cursor = Some(event.id);
+
+        true
+    }
+}
+
+// Orders events by their transaction's position in the chain and then by their own order in it
+fn sort_into_chain_order(
+    events: &mut [SuiEvent],
+    tx_digest_to_position: &HashMap<TransactionDigest, (CheckpointSequenceNumber, usize)>
+) -> SuiRpcResult<()> {
+    if let Some(event) = events.iter().find(|event| !tx_digest_to_position.contains_key(&event.id.tx_digest)) {
+        return Err(Error::DataError(format!("No checkpoint position for transaction {}", event.id.tx_digest)));
+    }
+
+    events.sort_by_key(|event| {
+        (tx_digest_to_position[&event.id.tx_digest], event.id.event_seq)
+    });
+
+    Ok(())
+}
+
 pub struct QueryEventsRequest {
     pub query: EventFilter,
     pub cursor: Option<EventID>,
@@ -618,14 +896,17 @@ impl PageTurner<QueryEventsRequest> for EventApi {
 #[derive(Clone)]
 pub struct QuorumDriverApi {
     api: Arc<RpcClient>,
-    rate_limiter: Arc<DefaultDirectRateLimiter>,
+    rate_limiter: Arc<RequestLimiter>,
+    // Requests give up once it passes
+    deadline: Option<Instant>,
 }
 
 impl QuorumDriverApi {
-    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<DefaultDirectRateLimiter>) -> Self {
+    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<RequestLimiter>, deadline: Option<Instant>) -> Self {
         Self {
             api,
             rate_limiter,
+            deadline,
         }
     }
 
@@ -641,21 +922,32 @@ impl QuorumDriverApi {
         options: SuiTransactionBlockResponseOptions,
         request_type: Option<ExecuteTransactionRequestType>,
     ) -> SuiRpcResult<SuiTransactionBlockResponse> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::Priority).await;
 
         let (tx_bytes, signatures) = tx.to_tx_bytes_and_signatures();
         let request_type = request_type.unwrap_or_else(|| options.default_execution_request_type());
+        let confirmed = |response: &SuiTransactionBlockResponse| {
+            match request_type {
+                ExecuteTransactionRequestType::WaitForEffectsCert => true,
+                ExecuteTransactionRequestType::WaitForLocalExecution => {
+                    response.confirmed_local_execution == Some(true)
+                }
+            }
+        };
         let mut retry_count = 0;
         let start = Instant::now();
         while retry_count < WAIT_FOR_LOCAL_EXECUTION_RETRY_COUNT {
+            // Goes out through several fullnodes at once. The first to confirm it wins.
             let response: SuiTransactionBlockResponse = self
                 .api
-                .http
-                .execute_transaction_block(
-                    tx_bytes.clone(),
-                    signatures.clone(),
-                    Some(options.clone()),
-                    Some(request_type.clone()),
+                .submit(
+                    |http| http.execute_transaction_block(
+                        tx_bytes.clone(),
+                        signatures.clone(),
+                        Some(options.clone()),
+                        Some(request_type.clone()),
+                    ),
+                    confirmed
                 )
                 .await?;
 
@@ -685,22 +977,25 @@ impl QuorumDriverApi {
 #[derive(Debug, Clone)]
 pub struct GovernanceApi {
     api: Arc<RpcClient>,
-    rate_limiter: Arc<DefaultDirectRateLimiter>
+    rate_limiter: Arc<RequestLimiter>,
+    // Requests give up once it passes
+    deadline: Option<Instant>,
 }
 
 impl GovernanceApi {
-    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<DefaultDirectRateLimiter>) -> Self {
+    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<RequestLimiter>, deadline: Option<Instant>) -> Self {
         Self {
             api,
             rate_limiter,
+            deadline,
         }
     }
 
     /// Return all [DelegatedStake].
     pub async fn get_stakes(&self, owner: SuiAddress) -> SuiRpcResult<Vec<DelegatedStake>> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_stakes(owner).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_stakes(owner)).await?)
     }
 
     /// Return the committee information for the asked `epoch`.
@@ -709,37 +1004,40 @@ impl GovernanceApi {
         &self,
         epoch: Option<BigInt<u64>>,
     ) -> SuiRpcResult<SuiCommittee> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_committee_info(epoch).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_committee_info(epoch)).await?)
     }
 
     /// Return the latest SUI system state object on-chain.
     pub async fn get_latest_sui_system_state(&self) -> SuiRpcResult<SuiSystemStateSummary> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_latest_sui_system_state().await?)
+        Ok(self.api.request(self.deadline, |http| http.get_latest_sui_system_state()).await?)
     }
 
     /// Return the reference gas price for the network
     pub async fn get_reference_gas_price(&self) -> SuiRpcResult<u64> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(*self.api.http.get_reference_gas_price().await?)
+        Ok(*self.api.request(self.deadline, |http| http.get_reference_gas_price()).await?)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ExtendedApi {
     api: Arc<RpcClient>,
-    rate_limiter: Arc<DefaultDirectRateLimiter>
+    rate_limiter: Arc<RequestLimiter>,
+    // Requests give up once it passes
+    deadline: Option<Instant>,
 }
 
 impl ExtendedApi {
-    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<DefaultDirectRateLimiter>) -> Self {
+    pub(crate) fn new(api: Arc<RpcClient>, rate_limiter: Arc<RequestLimiter>, deadline: Option<Instant>) -> Self {
         Self {
             api,
             rate_limiter,
+            deadline,
         }
     }
 
@@ -749,15 +1047,15 @@ impl ExtendedApi {
         limit: Option<usize>,
         descending_order: Option<bool>,
     ) -> SuiRpcResult<EpochPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
-        Ok(self.api.http.get_epochs(cursor, limit, descending_order).await?)
+        Ok(self.api.request(self.deadline, |http| http.get_epochs(cursor, limit, descending_order)).await?)
     }
 
     async fn get_current_epoch(&self) -> SuiRpcResult<EpochInfo> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-        Ok(self.api.http.get_current_epoch().await?)
+        Ok(self.api.request(self.deadline, |http| http.get_current_epoch()).await?)
     }
 
     /// Return the list of queried objects. Note that this is an enhanced full node only api.
@@ -767,48 +1065,48 @@ impl ExtendedApi {
         cursor: Option<CheckpointedObjectID>,
         limit: Option<usize>,
     ) -> SuiRpcResult<QueryObjectsPage> {
-        self.rate_limiter.until_ready().await;
+        self.rate_limiter.until_ready(RequestWeight::page(limit)).await;
 
-        Ok(self.api.http.query_objects(query, cursor, limit).await?)
+        Ok(self.api.request(self.deadline, |http| http.query_objects(query.clone(), cursor, limit)).await?)
     }
 
     // pub async fn get_network_metrics(&self) -> SuiRpcResult<NetworkMetrics> {
-    //     self.rate_limiter.until_ready().await;
+    //     self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-    //     Ok(self.api.http.get_network_metrics().await?)
+    //     Ok(self.api.request(self.deadline, |http| http.get_network_metrics()).await?)
     // }
 
     // pub async fn get_move_call_metrics(&self) -> RpcResult<MoveCallMetrics> {
-    //     self.rate_limiter.until_ready().await;
+    //     self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-    //     Ok(self.api.http.get_move_call_metrics().await?)
+    //     Ok(self.api.request(self.deadline, |http| http.get_move_call_metrics()).await?)
     // }
 
     // pub async fn get_latest_address_metrics(&self) -> SUiRpcResult<AddressMetrics> {
-    //     self.rate_limiter.until_ready().await;
+    //     self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-    //     Ok(self.api.http.get_latest_address_metrics().await?)
+    //     Ok(self.api.request(self.deadline, |http| http.get_latest_address_metrics()).await?)
     // }
 
     // pub async fn get_checkpoint_address_metrics(&self, checkpoint: u64) -> SuiRpcResult<AddressMetrics> {
-    //     self.rate_limiter.until_ready().await;
+    //     self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-    //     Ok(self.api.http.get_checkpoint_address_metrics(checkpoint).await?)
+    //     Ok(self.api.request(self.deadline, |http| http.get_checkpoint_address_metrics(checkpoint)).await?)
     // }
 
     // pub async fn get_all_epoch_address_metrics(
     //     &self,
     //     descending_order: Option<bool>,
     // ) -> SuiRpcResult<Vec<AddressMetrics>> {
-    //     self.rate_limiter.until_ready().await;
+    //     self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
 
-    //     Ok(self.api.http.get_all_epoch_address_metrics(descending_order).await?)
+    //     Ok(self.api.request(self.deadline, |http| http.get_all_epoch_address_metrics(descending_order)).await?)
     // }
 
     // async fn get_total_transactions(&self) -> RpcResult<BigInt<u64>> {
-    //     self.rate_limiter.until_ready().await;
+    //     self.rate_limiter.until_ready(RequestWeight::SINGLE).await;
         
-    //     Ok(self.api.http.get_total_transactions().await?)
+    //     Ok(self.api.request(self.deadline, |http| http.get_total_transactions()).await?)
     // }
 }
 
@@ -838,4 +1136,110 @@ impl PageTurner<QueryObjectsRequest> for ExtendedApi {
             Ok(TurnedPage::last(response.data))
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use move_core_types::identifier::Identifier;
+    use move_core_types::language_storage::StructTag;
+
+    fn event(tx_digest: TransactionDigest, event_seq: u64) -> SuiEvent {
+        SuiEvent {
+            id: EventID {
+                tx_digest,
+                event_seq,
+            },
+            package_id: ObjectID::ZERO,
+            transaction_module: Identifier::new("pool").unwrap(),
+            sender: SuiAddress::ZERO,
+            type_: StructTag::from_str("0x2::pool::SwapEvent").unwrap(),
+            parsed_json: serde_json::Value::Null,
+            bcs: vec![],
+            // Every transaction in the checkpoint shares it
+            timestamp_ms: Some(1_000),
+        }
+    }
+
+    fn event_id(event_seq: u64) -> EventID {
+        EventID {
+            tx_digest: TransactionDigest::random(),
+            event_seq,
+        }
+    }
+
+    #[test]
+    fn test_sort_into_chain_order() {
+        let (tx_0, tx_1, tx_2) = (TransactionDigest::random(), TransactionDigest::random(), TransactionDigest::random());
+
+        let tx_digest_to_position = HashMap::from([
+            (tx_0, (10, 3)),
+            (tx_1, (10, 7)),
+            (tx_2, (11, 0)),
+        ]);
+
+        // One filter's events followed by another's, as the backfill collects them
+        let mut events = vec![
+            event(tx_2, 0),
+            event(tx_1, 2),
+            event(tx_0, 1),
+            event(tx_1, 0),
+            event(tx_0, 0),
+            event(tx_1, 1),
+        ];
+
+        sort_into_chain_order(&mut events, &tx_digest_to_position).unwrap();
+
+        let event_ids = events
+            .iter()
+            .map(|event| {
+                (event.id.tx_digest, event.id.event_seq)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(event_ids, vec![(tx_0, 0), (tx_0, 1), (tx_1, 0), (tx_1, 1), (tx_1, 2), (tx_2, 0)]);
+    }
+
+    #[test]
+    fn test_sort_into_chain_order_unknown_transaction() {
+        let mut events = vec![event(TransactionDigest::random(), 0)];
+
+        assert!(sort_into_chain_order(&mut events, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_seen_event_ids_dedupe() {
+        let mut seen_event_ids = SeenEventIds::new(10);
+
+        let (a, b) = (event_id(0), event_id(0));
+
+        assert!(seen_event_ids.insert(a));
+        assert!(seen_event_ids.insert(b));
+        assert!(!seen_event_ids.insert(a));
+        assert!(!seen_event_ids.insert(b));
+
+        // Same transaction, next event
+        assert!(seen_event_ids.insert(EventID { tx_digest: a.tx_digest, event_seq: 1 }));
+    }
+
+    #[test]
+    fn test_seen_event_ids_capacity() {
+        let mut seen_event_ids = SeenEventIds::new(2);
+
+        let (a, b, c) = (event_id(0), event_id(0), event_id(0));
+
+        assert!(seen_event_ids.insert(a));
+        assert!(seen_event_ids.insert(b));
+        // Pushes a out
+        assert!(seen_event_ids.insert(c));
+        assert_eq!(seen_event_ids.event_ids.len(), 2);
+
+        assert!(!seen_event_ids.insert(b));
+        assert!(!seen_event_ids.insert(c));
+        // Forgotten so it reads as new again, pushing b out
+        assert!(seen_event_ids.insert(a));
+        assert!(seen_event_ids.insert(b));
+    }
+}
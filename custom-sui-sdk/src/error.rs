@@ -1,5 +1,6 @@
 use sui_types::base_types::{SuiAddress, TransactionDigest};
 use sui_types::error::UserInputError;
+use jsonrpsee::types::error::CallError;
 use thiserror::Error;
 
 pub type SuiRpcResult<T = ()> = Result<T, Error>;
@@ -27,4 +28,113 @@ pub enum Error {
     },
     #[error("Insufficient fund for address [{address}], requested amount: {amount}")]
     InsufficientFund { address: SuiAddress, amount: u128 },
-}
\ No newline at end of file
+    #[error("Deadline passed before the request could be answered")]
+    DeadlineExceeded,
+}
+
+// Whether trying again could help
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Timeout,
+    RateLimited,
+    // Dropped connections, gateways without a node behind them and garbled responses
+    Unavailable,
+    // Fails the same way every time
+    Permanent,
+}
+
+impl ErrorKind {
+    pub fn is_transient(&self) -> bool {
+        *self != ErrorKind::Permanent
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::RpcError(err) | Error::TransactionConfirmationError(_, err) => rpc_error_kind(err),
+            Error::FailToConfirmTransactionStatus(..) | Error::DeadlineExceeded => ErrorKind::Timeout,
+            _ => ErrorKind::Permanent,
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        self.kind().is_transient()
+    }
+}
+
+// Providers rate limit with HTTP 429 or a JSON-RPC error carrying the same code
+const RATE_LIMITED_CODES: [i32; 2] = [429, -32005];
+
+pub fn rpc_error_kind(err: &jsonrpsee::core::Error) -> ErrorKind {
+    match err {
+        jsonrpsee::core::Error::RequestTimeout => ErrorKind::Timeout,
+        jsonrpsee::core::Error::Transport(transport_err) => {
+            // The transport only hands us its message
+            let message = transport_err.to_string().to_lowercase();
+
+            if message.contains("429") || message.contains("too many requests") {
+                ErrorKind::RateLimited
+            } else if message.contains("timed out") || message.contains("timeout") {
+                ErrorKind::Timeout
+            } else {
+                ErrorKind::Unavailable
+            }
+        },
+        jsonrpsee::core::Error::RestartNeeded(_) | jsonrpsee::core::Error::ParseError(_) => ErrorKind::Unavailable,
+        jsonrpsee::core::Error::Call(CallError::Custom(error_object)) if RATE_LIMITED_CODES.contains(&error_object.code()) => {
+            ErrorKind::RateLimited
+        },
+        _ => ErrorKind::Permanent,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use jsonrpsee::types::error::ErrorObject;
+
+    fn call_error(code: i32) -> jsonrpsee::core::Error {
+        jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(code, "call failed", None::<()>)))
+    }
+
+    #[test]
+    fn test_rpc_error_kind_rate_limited() {
+        let transport_err = jsonrpsee::core::Error::Transport(anyhow::anyhow!("Request rejected `429`"));
+        assert_eq!(rpc_error_kind(&transport_err), ErrorKind::RateLimited);
+
+        let transport_err = jsonrpsee::core::Error::Transport(anyhow::anyhow!("Too Many Requests"));
+        assert_eq!(rpc_error_kind(&transport_err), ErrorKind::RateLimited);
+
+        assert_eq!(rpc_error_kind(&call_error(-32005)), ErrorKind::RateLimited);
+        assert_eq!(rpc_error_kind(&call_error(429)), ErrorKind::RateLimited);
+    }
+
+    #[test]
+    fn test_rpc_error_kind_timeout() {
+        assert_eq!(rpc_error_kind(&jsonrpsee::core::Error::RequestTimeout), ErrorKind::Timeout);
+
+        let transport_err = jsonrpsee::core::Error::Transport(anyhow::anyhow!("connection timed out"));
+        assert_eq!(rpc_error_kind(&transport_err), ErrorKind::Timeout);
+
+        assert_eq!(Error::DeadlineExceeded.kind(), ErrorKind::Timeout);
+    }
+
+    #[test]
+    fn test_rpc_error_kind_unavailable() {
+        let transport_err = jsonrpsee::core::Error::Transport(anyhow::anyhow!("connection reset by peer"));
+        assert_eq!(rpc_error_kind(&transport_err), ErrorKind::Unavailable);
+
+        assert!(Error::RpcError(transport_err).is_transient());
+    }
+
+    #[test]
+    fn test_rpc_error_kind_permanent() {
+        // Invalid params
+        let err = call_error(-32602);
+        assert_eq!(rpc_error_kind(&err), ErrorKind::Permanent);
+        assert!(!Error::RpcError(err).is_transient());
+
+        assert!(!Error::DataError("bad object".to_string()).is_transient());
+    }
+}
@@ -1,9 +1,11 @@
 use std::fmt::Debug;
 use std::fmt::Formatter;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use futures::future::{self, BoxFuture};
+use futures::stream::{FuturesUnordered, StreamExt};
 use jsonrpsee::core::client::ClientT;
 use jsonrpsee::http_client::{HeaderMap, HeaderValue, HttpClient, HttpClientBuilder};
 use jsonrpsee::rpc_params;
@@ -11,6 +13,7 @@ use jsonrpsee::ws_client::{WsClient, WsClientBuilder};
 use serde_json::Value;
 
 use move_core_types::language_storage::StructTag;
+use sui_json_rpc::api::ReadApiClient;
 use sui_json_rpc::{
     CLIENT_SDK_TYPE_HEADER, CLIENT_SDK_VERSION_HEADER, CLIENT_TARGET_API_VERSION_HEADER,
 };
@@ -24,22 +27,32 @@ use sui_json_rpc_types::{
 use sui_types::base_types::{ObjectID, ObjectInfo, SuiAddress};
 
 use crate::apis::{CoinReadApi, EventApi, GovernanceApi, QuorumDriverApi, ReadApi, ExtendedApi};
-use crate::error::{Error, SuiRpcResult};
+use crate::error::{rpc_error_kind, Error, SuiRpcResult};
+use crate::retry::RetryPolicy;
 
 use crate::transaction_builder::{
     DataReader,
     TransactionBuilder
 };
 
-use governor::DefaultDirectRateLimiter;
+use crate::rate_limiter::{RequestLimiter, RequestWeight};
 
 pub mod apis;
 pub mod error;
 pub mod transaction_builder;
 pub mod programmable_transaction_sui_json;
+pub mod rate_limiter;
+pub mod retry;
 
 pub const SUI_COIN_TYPE: &str = "0x2::sui::SUI";
 const WAIT_FOR_TX_TIMEOUT_SEC: u64 = 60;
+// Fullnodes failing this many requests in a row are only tried once the healthy ones fail too
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+// Fullnodes this many checkpoints behind the furthest along are treated as unhealthy
+const MAX_CHECKPOINT_LAG: u64 = 20;
+const HEALTH_CHECK_INTERVAL_SEC: u64 = 10;
+// Transactions go out through this many fullnodes at once
+const SUBMIT_FANOUT: usize = 3;
 
 // Provides a non-OpenRPC supporting SuiClientBuilder
 // apis copied in as traits like ReadAPI cannot be implemented
@@ -50,7 +63,10 @@ const WAIT_FOR_TX_TIMEOUT_SEC: u64 = 60;
 pub struct SuiClientBuilder {
     request_timeout: Duration,
     max_concurrent_requests: usize,
-    ws_url: Option<String>,
+    ws_urls: Vec<String>,
+    // Fullnodes besides the one passed to build
+    http_urls: Vec<String>,
+    retry_policy: RetryPolicy,
     // max_requests_per_second: usize
 }
 
@@ -59,7 +75,9 @@ impl Default for SuiClientBuilder {
         Self {
             request_timeout: Duration::from_secs(60),
             max_concurrent_requests: 256,
-            ws_url: None,
+            ws_urls: vec![],
+            http_urls: vec![],
+            retry_policy: RetryPolicy::default(),
             // max_requests_per_second: 50
         }
     }
@@ -76,8 +94,26 @@ impl SuiClientBuilder {
         self
     }
 
+    // Can be given more than once. Subscriptions move on to the next one when theirs drops.
     pub fn ws_url(mut self, url: impl AsRef<str>) -> Self {
-        self.ws_url = Some(url.as_ref().to_string());
+        self.ws_urls.push(url.as_ref().to_string());
+        self
+    }
+
+    // Reads only. Transactions are never resent.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn ws_urls(mut self, urls: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.ws_urls.extend(urls.into_iter().map(|url| url.as_ref().to_string()));
+        self
+    }
+
+    // More fullnodes to route requests to and submit transactions through
+    pub fn http_urls(mut self, urls: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.http_urls.extend(urls.into_iter().map(|url| url.as_ref().to_string()));
         self
     }
 
@@ -86,7 +122,7 @@ impl SuiClientBuilder {
     //     self
     // }
 
-    pub async fn build(self, http: impl AsRef<str>, rate_limiter: &Arc<DefaultDirectRateLimiter>) -> SuiRpcResult<SuiClient> {
+    pub async fn build(self, http: impl AsRef<str>, rate_limiter: &Arc<RequestLimiter>) -> SuiRpcResult<SuiClient> {
         let client_version = env!("CARGO_PKG_VERSION");
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -100,48 +136,66 @@ impl SuiClientBuilder {
         );
         headers.insert(CLIENT_SDK_TYPE_HEADER, HeaderValue::from_static("rust"));
 
-        let ws = if let Some(url) = self.ws_url {
-            Some(
-                WsClientBuilder::default()
+        let ws_configs = self.ws_urls
+            .into_iter()
+            .map(|url| {
+                WsConfig {
+                    url,
+                    headers: headers.clone(),
+                    max_concurrent_requests: self.max_concurrent_requests,
+                    request_timeout: self.request_timeout,
+                }
+            })
+            .collect::<Vec<WsConfig>>();
+
+        // The first websocket that connects serves plain subscriptions
+        let mut ws = None;
+        let mut ws_err = None;
+        for ws_config in ws_configs.iter() {
+            match ws_config.connect().await {
+                Ok(ws_client) => {
+                    ws = Some(ws_client);
+                    break;
+                },
+                Err(err) => {
+                    println!("Failed to connect to {}: {}", ws_config.url, err);
+                    ws_err = Some(err);
+                }
+            }
+        }
+        if let (None, Some(err)) = (&ws, ws_err) {
+            return Err(err);
+        }
+
+        let http = std::iter::once(http.as_ref().to_string())
+            .chain(self.http_urls)
+            .map(|url| {
+                let client = HttpClientBuilder::default()
                     .max_request_body_size(2 << 30)
                     .max_concurrent_requests(self.max_concurrent_requests)
                     .set_headers(headers.clone())
                     .request_timeout(self.request_timeout)
-                    .build(url)
-                    .await?,
-            )
-        } else {
-            None
-        };
-
-        let http = HttpClientBuilder::default()
-            .max_request_body_size(2 << 30)
-            .max_concurrent_requests(self.max_concurrent_requests)
-            .set_headers(headers.clone())
-            .request_timeout(self.request_timeout)
-            .build(http)?;
-
-        let rpc = RpcClient { http, ws };
+                    .build(&url)?;
+
+                Ok(
+                    HttpEndpoint {
+                        url,
+                        client,
+                        health: Mutex::new(EndpointHealth::default()),
+                    }
+                )
+            })
+            .collect::<SuiRpcResult<Vec<HttpEndpoint>>>()?;
+
+        let rpc = RpcClient { http, ws, ws_configs, retry_policy: self.retry_policy };
         let api = Arc::new(rpc);
-        let read_api = Arc::new(ReadApi::new(api.clone(), rate_limiter.clone()));
-        let quorum_driver_api = QuorumDriverApi::new(api.clone(), rate_limiter.clone());
-        let event_api = EventApi::new(api.clone(), rate_limiter.clone());
-        let transaction_builder = TransactionBuilder::new(read_api.clone());
-        let coin_read_api = CoinReadApi::new(api.clone(), rate_limiter.clone());
-        let governance_api = GovernanceApi::new(api.clone(), rate_limiter.clone());
-        let extended_api = ExtendedApi::new(api.clone(), rate_limiter.clone());
 
-        Ok(SuiClient {
-            api,
-            transaction_builder,
-            read_api,
-            coin_read_api,
-            event_api,
-            quorum_driver_api,
-            governance_api,
-            extended_api
-            // rate_limiter
-        })
+        // Only worth it when there's a choice of fullnode
+        if api.http.len() > 1 {
+            tokio::spawn(check_health_until_dropped(Arc::downgrade(&api)));
+        }
+
+        Ok(SuiClient::new(api, rate_limiter, None))
     }
 
     fn parse_methods(server_spec: &Value) -> Result<Vec<String>, Error> {
@@ -177,8 +231,257 @@ pub struct SuiClient {
 }
 
 pub(crate) struct RpcClient {
-    http: HttpClient,
+    // Every fullnode we know of. Requests go to the fastest healthy one.
+    http: Vec<HttpEndpoint>,
     ws: Option<WsClient>,
+    // Opens fresh websockets for subscriptions that outlive ws
+    ws_configs: Vec<WsConfig>,
+    retry_policy: RetryPolicy,
+}
+
+struct HttpEndpoint {
+    url: String,
+    client: HttpClient,
+    health: Mutex<EndpointHealth>,
+}
+
+#[derive(Debug, Default)]
+struct EndpointHealth {
+    // Round trip of the health check, smoothed. None until the first one answers.
+    latency: Option<Duration>,
+    consecutive_failures: u32,
+    // Too far behind the other fullnodes as of the last health check
+    lagging: bool,
+}
+
+impl EndpointHealth {
+    fn healthy(&self) -> bool {
+        self.consecutive_failures < MAX_CONSECUTIVE_FAILURES && !self.lagging
+    }
+}
+
+impl HttpEndpoint {
+    fn record_success(&self) {
+        self.health.lock().unwrap().consecutive_failures = 0;
+    }
+
+    fn record_failure(&self) {
+        self.health.lock().unwrap().consecutive_failures += 1;
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let mut health = self.health.lock().unwrap();
+
+        health.latency = Some(
+            match health.latency {
+                Some(smoothed_latency) => smoothed_latency.mul_f64(0.8) + latency.mul_f64(0.2),
+                None => latency,
+            }
+        );
+    }
+}
+
+// Whether another fullnode might do better. Errors the node answered with
+// would come back the same from any other.
+fn should_fail_over(err: &jsonrpsee::core::Error) -> bool {
+    rpc_error_kind(err).is_transient()
+}
+
+impl RpcClient {
+    // Healthy fullnodes fastest first then the unhealthy ones as a last resort.
+    // Fullnodes we haven't timed yet go first so they get timed.
+    fn ranked_http(&self) -> Vec<&HttpEndpoint> {
+        let mut ranked_http = self.http
+            .iter()
+            .map(|endpoint| {
+                let health = endpoint.health.lock().unwrap();
+                (!health.healthy(), health.latency.unwrap_or(Duration::ZERO), endpoint)
+            })
+            .collect::<Vec<_>>();
+
+        ranked_http.sort_by_key(|(unhealthy, latency, _)| (*unhealthy, *latency));
+
+        ranked_http
+            .into_iter()
+            .map(|(_, _, endpoint)| endpoint)
+            .collect()
+    }
+
+    // Retries transient failures with backoff until the policy or the deadline runs out.
+    // An attempt still running at the deadline is abandoned.
+    pub(crate) async fn request<T, F>(&self, deadline: Option<Instant>, request: F) -> SuiRpcResult<T>
+    where
+        F: for<'c> Fn(&'c HttpClient) -> BoxFuture<'c, Result<T, jsonrpsee::core::Error>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            let result = match deadline {
+                Some(deadline) => {
+                    tokio::time::timeout_at(deadline.into(), self.request_once(&request))
+                        .await
+                        .map_err(|_| Error::DeadlineExceeded)?
+                },
+                None => self.request_once(&request).await,
+            };
+
+            let err = match result {
+                Ok(response) => return Ok(response),
+                Err(err) => Error::from(err),
+            };
+
+            if !err.is_transient() || attempt >= self.retry_policy.max_attempts {
+                return Err(err);
+            }
+
+            let backoff = self.retry_policy.backoff(attempt);
+
+            // No point waiting for a retry that can't finish in time
+            if let Some(deadline) = deadline {
+                if Instant::now() + backoff >= deadline {
+                    return Err(err);
+                }
+            }
+
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    // Tries the fullnodes in rank order until one answers
+    async fn request_once<T, F>(&self, request: &F) -> Result<T, jsonrpsee::core::Error>
+    where
+        F: for<'c> Fn(&'c HttpClient) -> BoxFuture<'c, Result<T, jsonrpsee::core::Error>>,
+    {
+        let mut last_err = None;
+
+        for endpoint in self.ranked_http() {
+            match request(&endpoint.client).await {
+                Err(err) if should_fail_over(&err) => {
+                    println!("Request to {} failed: {}", endpoint.url, err);
+                    endpoint.record_failure();
+                    last_err = Some(err);
+                },
+                result => {
+                    endpoint.record_success();
+                    return result;
+                }
+            }
+        }
+
+        Err(last_err.expect("RpcClient has at least one http endpoint"))
+    }
+
+    // Sends the request to the top SUBMIT_FANOUT fullnodes at once and takes the
+    // first response that's accepted. Failing that, the last response or error.
+    pub(crate) async fn submit<T, F, A>(&self, request: F, accept: A) -> Result<T, jsonrpsee::core::Error>
+    where
+        F: for<'c> Fn(&'c HttpClient) -> BoxFuture<'c, Result<T, jsonrpsee::core::Error>>,
+        A: Fn(&T) -> bool,
+    {
+        let mut pending = self.ranked_http()
+            .into_iter()
+            .take(SUBMIT_FANOUT)
+            .map(|endpoint| {
+                let response = request(&endpoint.client);
+                async move { (endpoint, response.await) }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut last_result = None;
+
+        while let Some((endpoint, result)) = pending.next().await {
+            match &result {
+                Err(err) if should_fail_over(err) => {
+                    println!("Submission to {} failed: {}", endpoint.url, err);
+                    endpoint.record_failure();
+                },
+                Ok(response) if accept(response) => {
+                    endpoint.record_success();
+                    return result;
+                },
+                _ => endpoint.record_success(),
+            }
+
+            // A response beats an error
+            if result.is_ok() || !matches!(last_result, Some(Ok(_))) {
+                last_result = Some(result);
+            }
+        }
+
+        last_result.expect("RpcClient has at least one http endpoint")
+    }
+
+    async fn check_health(&self) {
+        let checkpoints = future::join_all(
+            self.http
+                .iter()
+                .map(|endpoint| async move {
+                    let start = Instant::now();
+
+                    match endpoint.client.get_latest_checkpoint_sequence_number().await {
+                        Ok(checkpoint) => {
+                            endpoint.record_latency(start.elapsed());
+                            endpoint.record_success();
+                            Some(*checkpoint)
+                        },
+                        Err(err) => {
+                            println!("Health check of {} failed: {}", endpoint.url, err);
+                            endpoint.record_failure();
+                            None
+                        }
+                    }
+                })
+        ).await;
+
+        let latest_checkpoint = checkpoints.iter().flatten().max().copied();
+
+        for (endpoint, checkpoint) in self.http.iter().zip(checkpoints) {
+            let lagging = match (checkpoint, latest_checkpoint) {
+                (Some(checkpoint), Some(latest_checkpoint)) => checkpoint + MAX_CHECKPOINT_LAG < latest_checkpoint,
+                _ => false,
+            };
+
+            endpoint.health.lock().unwrap().lagging = lagging;
+        }
+    }
+}
+
+// Keeps every fullnode's latency and lag current until the client is dropped
+async fn check_health_until_dropped(api: Weak<RpcClient>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(HEALTH_CHECK_INTERVAL_SEC));
+
+    loop {
+        interval.tick().await;
+
+        match api.upgrade() {
+            Some(api) => api.check_health().await,
+            None => return,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct WsConfig {
+    url: String,
+    headers: HeaderMap,
+    max_concurrent_requests: usize,
+    request_timeout: Duration,
+}
+
+impl WsConfig {
+    pub(crate) async fn connect(&self) -> SuiRpcResult<WsClient> {
+        Ok(
+            WsClientBuilder::default()
+                .max_request_body_size(2 << 30)
+                .max_concurrent_requests(self.max_concurrent_requests)
+                .set_headers(self.headers.clone())
+                .request_timeout(self.request_timeout)
+                .build(&self.url)
+                .await?
+        )
+    }
 }
 
 impl Debug for RpcClient {
@@ -186,12 +489,41 @@ impl Debug for RpcClient {
         write!(
             f,
             "RPC client. Http: {:?}, Websocket: {:?}",
-            self.http, self.ws
+            self.http.iter().map(|endpoint| &endpoint.url).collect::<Vec<&String>>(),
+            self.ws
         )
     }
 }
 
 impl SuiClient {
+    fn new(api: Arc<RpcClient>, rate_limiter: &Arc<RequestLimiter>, deadline: Option<Instant>) -> Self {
+        let read_api = Arc::new(ReadApi::new(api.clone(), rate_limiter.clone(), deadline));
+        let quorum_driver_api = QuorumDriverApi::new(api.clone(), rate_limiter.clone(), deadline);
+        let event_api = EventApi::new(api.clone(), rate_limiter.clone(), deadline);
+        let transaction_builder = TransactionBuilder::new(read_api.clone());
+        let coin_read_api = CoinReadApi::new(api.clone(), rate_limiter.clone(), deadline);
+        let governance_api = GovernanceApi::new(api.clone(), rate_limiter.clone(), deadline);
+        let extended_api = ExtendedApi::new(api.clone(), rate_limiter.clone(), deadline);
+
+        SuiClient {
+            api,
+            transaction_builder,
+            read_api,
+            coin_read_api,
+            event_api,
+            quorum_driver_api,
+            governance_api,
+            extended_api
+            // rate_limiter
+        }
+    }
+
+    // Shares the connections and rate limiter but gives up on reads,
+    // retries included, once the deadline passes
+    pub fn with_deadline(&self, deadline: Instant) -> SuiClient {
+        SuiClient::new(self.api.clone(), self.read_api.rate_limiter(), Some(deadline))
+    }
+
     pub fn transaction_builder(&self) -> &TransactionBuilder {
         &self.transaction_builder
     }
@@ -222,7 +554,7 @@ impl DataReader for ReadApi {
         address: SuiAddress,
         object_type: StructTag,
     ) -> Result<Vec<ObjectInfo>, anyhow::Error> {
-        self.rate_limiter().until_ready().await;
+        self.rate_limiter().until_ready(RequestWeight::SINGLE).await;
 
         let mut result = vec![];
         let query = Some(SuiObjectResponseQuery {
@@ -262,13 +594,13 @@ impl DataReader for ReadApi {
         object_id: ObjectID,
         options: SuiObjectDataOptions,
     ) -> Result<SuiObjectResponse, anyhow::Error> {
-        self.rate_limiter().until_ready().await;
+        self.rate_limiter().until_ready(RequestWeight::SINGLE).await;
 
         Ok(self.get_object_with_options(object_id, options).await?)
     }
 
     async fn get_reference_gas_price(&self) -> Result<u64, anyhow::Error> {
-        self.rate_limiter().until_ready().await;
+        self.rate_limiter().until_ready(RequestWeight::SINGLE).await;
 
         Ok(self.get_reference_gas_price().await?)
     }
@@ -0,0 +1,117 @@
+use std::num::NonZeroU32;
+
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+
+// Items a bulk request can ask for before it weighs more than a plain request
+const ITEMS_PER_WEIGHT: usize = 10;
+// What full nodes default to when a paged request doesn't give a limit
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+// What a request draws from the quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestWeight {
+    // Draws from the priority lane instead of the shared quota
+    Priority,
+    // Draws this many cells of the shared quota
+    Shared(u32),
+}
+
+impl RequestWeight {
+    pub const SINGLE: RequestWeight = RequestWeight::Shared(1);
+
+    // A cell for every ITEMS_PER_WEIGHT items asked for and never less than one
+    pub fn items(num_items: usize) -> Self {
+        RequestWeight::Shared(((num_items + ITEMS_PER_WEIGHT - 1) / ITEMS_PER_WEIGHT).max(1) as u32)
+    }
+
+    pub fn page(limit: Option<usize>) -> Self {
+        Self::items(limit.unwrap_or(DEFAULT_PAGE_SIZE))
+    }
+}
+
+// Dry runs and executions have a quota of their own so they never queue
+// behind background fetches. Providers limit the sum of the two quotas.
+#[derive(Debug)]
+pub struct RequestLimiter {
+    shared: DefaultDirectRateLimiter,
+    shared_burst_size: NonZeroU32,
+    priority: DefaultDirectRateLimiter,
+}
+
+impl RequestLimiter {
+    pub fn new(shared_quota: Quota, priority_quota: Quota) -> Self {
+        Self {
+            shared: RateLimiter::direct(shared_quota),
+            shared_burst_size: shared_quota.burst_size(),
+            priority: RateLimiter::direct(priority_quota),
+        }
+    }
+
+    pub async fn until_ready(&self, weight: RequestWeight) {
+        match weight {
+            RequestWeight::Priority => self.priority.until_ready().await,
+            RequestWeight::Shared(cells) => {
+                for installment in installments(cells, self.shared_burst_size) {
+                    // Only fails for more cells than fit in a burst
+                    let _ = self.shared.until_n_ready(installment).await;
+                }
+            }
+        }
+    }
+}
+
+// More cells than fit in a burst could never be granted at once.
+// They're drawn a burst at a time so big requests still pay their full weight.
+fn installments(cells: u32, burst_size: NonZeroU32) -> Vec<NonZeroU32> {
+    let mut remaining = cells.max(1);
+    let mut installments = vec![];
+
+    while remaining > 0 {
+        let installment = remaining.min(burst_size.get());
+        installments.push(NonZeroU32::new(installment).unwrap());
+        remaining -= installment;
+    }
+
+    installments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_items_weight() {
+        assert_eq!(RequestWeight::items(0), RequestWeight::Shared(1));
+        assert_eq!(RequestWeight::items(1), RequestWeight::Shared(1));
+        assert_eq!(RequestWeight::items(10), RequestWeight::Shared(1));
+        assert_eq!(RequestWeight::items(11), RequestWeight::Shared(2));
+        assert_eq!(RequestWeight::items(50), RequestWeight::Shared(5));
+    }
+
+    #[test]
+    fn test_page_weight() {
+        assert_eq!(RequestWeight::page(Some(100)), RequestWeight::Shared(10));
+        assert_eq!(RequestWeight::page(Some(5)), RequestWeight::Shared(1));
+        // The full node's default page size
+        assert_eq!(RequestWeight::page(None), RequestWeight::Shared(5));
+    }
+
+    #[test]
+    fn test_installments() {
+        let burst_size = NonZeroU32::new(4).unwrap();
+        let cells = |installments: Vec<NonZeroU32>| {
+            installments
+                .iter()
+                .map(|installment| {
+                    installment.get()
+                })
+                .collect::<Vec<u32>>()
+        };
+
+        assert_eq!(cells(installments(0, burst_size)), vec![1]);
+        assert_eq!(cells(installments(3, burst_size)), vec![3]);
+        assert_eq!(cells(installments(4, burst_size)), vec![4]);
+        // Past the burst the whole weight is still drawn
+        assert_eq!(cells(installments(10, burst_size)), vec![4, 4, 2]);
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+// How read requests are retried after transient errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // Including the first
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub const NEVER: RetryPolicy = RetryPolicy {
+        max_attempts: 1,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    // The wait after the given failed attempt. Doubles every attempt and is
+    // jittered down by up to half so clients that failed together don't retry together.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+
+        backoff.mul_f64(1.0 - jitter() / 2.0)
+    }
+}
+
+// Uniform in [0, 1). Every RandomState is seeded differently which is all the randomness we need.
+fn jitter() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_range() {
+        for _ in 0..1_000 {
+            let jitter = jitter();
+            assert!((0.0..1.0).contains(&jitter), "jitter: {}", jitter);
+        }
+    }
+
+    #[test]
+    fn test_backoff_bounds() {
+        let retry_policy = RetryPolicy::default();
+
+        // Full backoff and the jittered floor at half of it
+        let bounds = [
+            (1, Duration::from_millis(200)),
+            (2, Duration::from_millis(400)),
+            (3, Duration::from_millis(800)),
+            // Capped at max_backoff
+            (6, Duration::from_secs(5)),
+            (u32::MAX, Duration::from_secs(5)),
+        ];
+
+        for (attempt, backoff) in bounds {
+            for _ in 0..100 {
+                let jittered = retry_policy.backoff(attempt);
+                assert!(jittered >= backoff / 2 && jittered <= backoff, "attempt: {}, backoff: {:?}", attempt, jittered);
+            }
+        }
+    }
+
+    #[test]
+    fn test_never_backs_off() {
+        assert_eq!(RetryPolicy::NEVER.backoff(1), Duration::ZERO);
+        assert_eq!(RetryPolicy::NEVER.backoff(5), Duration::ZERO);
+    }
+}
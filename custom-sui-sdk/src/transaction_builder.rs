@@ -38,6 +38,16 @@ pub enum ProgrammableObjectArg {
     Argument(Argument)
 }
 
+// Move calls that return a tuple give back a single Argument::Result.
+// The members of the tuple have to be addressed with Argument::NestedResult.
+pub fn programmable_nested_result(result: Argument, index: u16) -> anyhow::Result<Argument> {
+    if let Argument::Result(command_index) = result {
+        Ok(Argument::NestedResult(command_index, index))
+    } else {
+        Err(anyhow!("Expected an Argument::Result to index into, got {:?}", result))
+    }
+}
+
 
 #[async_trait]
 pub trait DataReader {
@@ -1046,10 +1056,189 @@ impl TransactionBuilder {
     ) -> Argument {
 
         let amt_arg = builder.pure(amount).unwrap();
-        
+
         builder.command(Command::SplitCoins(Argument::GasCoin, vec![amt_arg]))
     }
 
+    pub async fn programmable_split_coin(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin: ProgrammableObjectArg,
+        amount: u64
+    ) -> anyhow::Result<Argument> {
+
+        let coin_arg = match coin {
+            ProgrammableObjectArg::ObjectID(coin_object_id) => {
+                builder.obj(
+                    ObjectArg::ImmOrOwnedObject(
+                        self.get_object_ref(coin_object_id).await?
+                    )
+                )?
+            },
+            ProgrammableObjectArg::Argument(coin_arg) => {
+                coin_arg
+            }
+        };
+
+        let amt_arg = builder.pure(amount)?;
+
+        Ok(
+            builder.command(Command::SplitCoins(coin_arg, vec![amt_arg]))
+        )
+    }
+
+    // Calls into 0x2::coin directly. All arguments are results of
+    // previous commands so there is nothing to resolve.
+    pub async fn programmable_coin_value(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin_type: TypeTag,
+        coin: Argument
+    ) -> anyhow::Result<Argument> {
+        Ok(
+            builder.programmable_move_call(
+                SUI_FRAMEWORK_PACKAGE_ID,
+                Identifier::from_str("coin")?,
+                Identifier::from_str("value")?,
+                vec![coin_type],
+                vec![coin]
+            )
+        )
+    }
+
+    pub async fn programmable_coin_zero(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin_type: TypeTag
+    ) -> anyhow::Result<Argument> {
+        Ok(
+            builder.programmable_move_call(
+                SUI_FRAMEWORK_PACKAGE_ID,
+                Identifier::from_str("coin")?,
+                Identifier::from_str("zero")?,
+                vec![coin_type],
+                vec![]
+            )
+        )
+    }
+
+    // Splitting more than a coin holds aborts, so splitting off min_value
+    // and merging it straight back asserts the coin's value on chain
+    // without needing a package of our own.
+    pub async fn programmable_assert_min_value(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin: Argument,
+        min_value: u64
+    ) -> anyhow::Result<()> {
+        let min_value_arg = builder.pure(min_value)?;
+
+        let split_coin = builder.command(Command::SplitCoins(coin, vec![min_value_arg]));
+
+        builder.command(Command::MergeCoins(coin, vec![split_coin]));
+
+        Ok(())
+    }
+
+    // Aborts the whole transaction if the coin is not empty
+    pub async fn programmable_coin_destroy_zero(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin_type: TypeTag,
+        coin: Argument
+    ) -> anyhow::Result<()> {
+        builder.programmable_move_call(
+            SUI_FRAMEWORK_PACKAGE_ID,
+            Identifier::from_str("coin")?,
+            Identifier::from_str("destroy_zero")?,
+            vec![coin_type],
+            vec![coin]
+        );
+
+        Ok(())
+    }
+
+    // Like programmable_split_coin but the amount is the u64 result of a previous command
+    pub async fn programmable_split_coin_by_result(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin: Argument,
+        amount: Argument
+    ) -> anyhow::Result<Argument> {
+        Ok(
+            builder.command(Command::SplitCoins(coin, vec![amount]))
+        )
+    }
+
+    // Flash swaps pay out and take repayment in Balances rather than Coins
+    pub async fn programmable_coin_from_balance(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin_type: TypeTag,
+        balance: Argument
+    ) -> anyhow::Result<Argument> {
+        Ok(
+            builder.programmable_move_call(
+                SUI_FRAMEWORK_PACKAGE_ID,
+                Identifier::from_str("coin")?,
+                Identifier::from_str("from_balance")?,
+                vec![coin_type],
+                vec![balance]
+            )
+        )
+    }
+
+    pub async fn programmable_coin_into_balance(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin_type: TypeTag,
+        coin: Argument
+    ) -> anyhow::Result<Argument> {
+        Ok(
+            builder.programmable_move_call(
+                SUI_FRAMEWORK_PACKAGE_ID,
+                Identifier::from_str("coin")?,
+                Identifier::from_str("into_balance")?,
+                vec![coin_type],
+                vec![coin]
+            )
+        )
+    }
+
+    pub async fn programmable_balance_zero(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin_type: TypeTag
+    ) -> anyhow::Result<Argument> {
+        Ok(
+            builder.programmable_move_call(
+                SUI_FRAMEWORK_PACKAGE_ID,
+                Identifier::from_str("balance")?,
+                Identifier::from_str("zero")?,
+                vec![coin_type],
+                vec![]
+            )
+        )
+    }
+
+    // Aborts the whole transaction if the balance is not empty
+    pub async fn programmable_balance_destroy_zero(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        coin_type: TypeTag,
+        balance: Argument
+    ) -> anyhow::Result<()> {
+        builder.programmable_move_call(
+            SUI_FRAMEWORK_PACKAGE_ID,
+            Identifier::from_str("balance")?,
+            Identifier::from_str("destroy_zero")?,
+            vec![coin_type],
+            vec![balance]
+        );
+
+        Ok(())
+    }
+
     async fn resolve_and_checks_programmable_transaction_args(
         &self,
         builder: &mut ProgrammableTransactionBuilder,
@@ -2,11 +2,13 @@ use std::str::FromStr;
 use async_trait::async_trait;
 use anyhow::{anyhow, Context};
 
-use futures::{future, TryStreamExt};
+use futures::TryStreamExt;
 use page_turner::PageTurner;
 use serde_json::Value;
 use fixed::types::U64F64;
 
+use itertools::{Either, Itertools};
+
 use custom_sui_sdk::{
     SuiClient,
     apis::{
@@ -19,27 +21,24 @@ use custom_sui_sdk::{
 
 use sui_sdk::json::SuiJsonValue;
 
-use sui_sdk::types::base_types::{ObjectID, ObjectIDParseError, SuiAddress};
+use sui_sdk::types::base_types::ObjectID;
 use sui_sdk::types::dynamic_field::DynamicFieldInfo;
-use sui_sdk::rpc_types::{EventFilter, SuiEvent, SuiMoveValue, SuiObjectDataOptions, SuiMoveStruct, SuiObjectResponse, SuiTypeTag};
+use sui_sdk::rpc_types::{Checkpoint, EventFilter, SuiEvent, SuiObjectDataOptions, SuiObjectResponse, SuiTypeTag};
  
-// use sui_sdk::json_types::SuiTypeTag;
 use sui_sdk::types::programmable_transaction_builder::ProgrammableTransactionBuilder;
 
 use move_core_types::language_storage::{StructTag, TypeTag};
 use move_core_types::value::MoveValue;
 
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::format;
 
-use crate::markets::{Exchange, Market};
+use crate::markets::{Exchange, Market, MarketBuilder, EventUpdate};
+use crate::ipc::PoolState;
 use crate::sui_sdk_utils::{self, sui_move_value};
-// use crate::{cetus_pool, cetus};
 use crate::fast_v3_pool;
-use crate::sui_json_utils::move_value_to_json;
+use crate::sui_json_utils::{move_value_to_json, parsed_json};
 
-// const GLOBAL: &str = "0xdaa46292632c3c4d8f31f23ea0f9b36a28ff3677e9684980e4438403a67a3d8f";
-// const POOLS: &str = "0xf699e7f2276f5c9a75944b37a0c5b5d9ddfd2471bf6242483b03ab2887d198d0";
 const CLOCK_OBJECT_ID: &str = "0x0000000000000000000000000000000000000000000000000000000000000006";
 
 #[derive(Debug, Clone)]
@@ -53,12 +52,15 @@ pub struct Cetus {
 impl Cetus {
     pub fn new(package_id: ObjectID, periphery_id: ObjectID, global_config_id: ObjectID) -> Self {
         let mut event_struct_tag_to_pool_field = HashMap::new();
-        event_struct_tag_to_pool_field.insert(
-            StructTag::from_str(
-                &format!("{}::pool::SwapEvent", package_id)
-            ).expect("Cetus: failed to create event struct tag"),
-            "pool".to_string()
-        );
+        // Everything that changes the state of a pool
+        for event_name in ["SwapEvent", "AddLiquidityEvent", "RemoveLiquidityEvent", "UpdateFeeRateEvent"] {
+            event_struct_tag_to_pool_field.insert(
+                StructTag::from_str(
+                    &format!("{}::pool::{}", package_id, event_name)
+                ).expect("Cetus: failed to create event struct tag"),
+                "pool".to_string()
+            );
+        }
         
         Cetus {
             package_id,
@@ -86,25 +88,8 @@ impl Cetus {
         &self.global_config_id
     }
 
-    fn event_filters(&self) -> Vec<EventFilter> {
-        self
-            .event_struct_tag_to_pool_field()
-            .keys()
-            .cloned()
-            .map(|event_struct_tag| {
-                EventFilter::MoveEventType(
-                    event_struct_tag
-                )
-            })
-            .collect::<Vec<_>>()
-    }
-
-    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
-        &self.event_struct_tag_to_pool_field
-    }
-
     // Cetus has us query for events
-    async fn get_all_markets_(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
+    async fn get_all_market_builders_(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error> {
 
         let pool_created_events = sui_client
             .event_api()
@@ -124,9 +109,7 @@ impl Cetus {
             .try_collect::<Vec<SuiEvent>>()
             .await?;
 
-        // let mut markets: Vec<Box<dyn Market>> = Vec::new();
-
-        let markets = pool_created_events
+        let market_builders = pool_created_events
             .iter()
             .map(|pool_created_event| {
                 let parsed_json = &pool_created_event.parsed_json;
@@ -146,45 +129,35 @@ impl Cetus {
 
                         Ok(
                             Box::new(
-                                CetusMarket {
-                                    parent_exchange: self.clone(),
+                                CetusMarketBuilder {
+                                    exchange: self.clone(),
                                     coin_x,
                                     coin_y,
                                     pool_id,
-                                    coin_x_sqrt_price: None,
-                                    coin_y_sqrt_price: None,
-                                    computing_pool: None
                                 }
-                            ) as Box<dyn Market>
+                            ) as Box<dyn MarketBuilder>
                         )
                     } else {
                         Err(anyhow!("Failed to match pattern."))
                     }
             })
-            .collect::<Result<Vec<Box<dyn Market>> ,anyhow::Error>>()?;
+            .collect::<Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error>>()?;
 
-        Ok(markets)
+        Ok(market_builders)
     }
 
-    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
-        let pool_ids = markets
-            .iter()
-            .map(|market| {
-                *market.pool_id()
-            })
-            .collect::<Vec<ObjectID>>();
-
-        sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await
-    }
+    pub async fn checkpoint_pinned_computing_pool(
+        &self,
+        sui_client: &SuiClient,
+        pool_id: &ObjectID
+    ) -> Result<(Checkpoint, fast_v3_pool::Pool), anyhow::Error> {
+        let (checkpoint, pool_object_response, node_object_responses) = self
+            .get_checkpoint_pinned_pool_and_node_object_responses(sui_client, pool_id)
+            .await?;
 
-    pub async fn computing_pool_from_object_response(&self, sui_client: &SuiClient, response: &SuiObjectResponse) -> Result<fast_v3_pool::Pool, anyhow::Error> {
-        // println!("{:#?}", response);
+        let ticks = Self::tick_index_to_tick_from_node_object_responses(node_object_responses)?;
 
-        let id = response.data.as_ref().context("data field from object response is None")?.object_id;
-        
-        let fields = sui_sdk_utils::read_fields_from_object_response(response).context("missing fields")?;
-
-        // println!("cetus pool fields: {:#?}", fields);
+        let fields = sui_sdk_utils::read_fields_from_object_response(&pool_object_response).context("missing fields")?;
 
         let tick_spacing = sui_move_value::get_number(&fields, "tick_spacing")?;
 
@@ -205,66 +178,55 @@ impl Cetus {
             "bits"
         )? as i32;
 
-
-        let tick_manager_struct = sui_move_value::get_struct(&fields, "tick_manager")?;
-
-        let tick_manager_tick_spacing = sui_move_value::get_number(&tick_manager_struct, "tick_spacing")?;
-
-        let tick_manager_ticks_skip_list_struct = sui_move_value::get_struct(&tick_manager_struct, "ticks")?;
-
-        let tick_manager_ticks_skip_list_id = sui_move_value::get_uid(
-            &tick_manager_ticks_skip_list_struct,
-            "id"
-        )?;
-
-        let ticks = self.get_ticks(sui_client, &tick_manager_ticks_skip_list_id).await?;
-
         let is_pause = sui_move_value::get_bool(&fields, "is_pause")?;
 
         Ok(
-            fast_v3_pool::Pool {
-                id,
-                tick_spacing,
-                fee: fee_rate,
-                liquidity,
-                sqrt_price: current_sqrt_price,
-                tick_current_index: current_tick_index,
-                ticks,
-                unlocked: !is_pause
-                // fee_growth_global_a,
-                // fee_growth_global_b,
-                // fee_protocol_coin_a,
-                // fee_protocol_coin_b,
-                // tick_manager: cetus_pool::tick::TickManager {
-                //     tick_spacing: tick_manager_tick_spacing,
-                //     ticks
-                // },
-                // is_pause,
-            }
+            (
+                checkpoint,
+                fast_v3_pool::Pool {
+                    id: pool_id.clone(),
+                    tick_spacing,
+                    fee: fee_rate,
+                    liquidity,
+                    sqrt_price: current_sqrt_price,
+                    tick_current_index: current_tick_index,
+                    ticks,
+                    unlocked: !is_pause
+                }
+            )
         )
     }
 
-    async fn get_ticks(
+    // Ticks live in a skip list owned by the pool. The skip list's id never changes
+    // so we can read it unpinned and then pin the pool and its nodes together.
+    async fn get_checkpoint_pinned_pool_and_node_object_responses(
         &self,
-        sui_client: &SuiClient, 
-        ticks_skip_list_id: &ObjectID
-    ) -> Result<BTreeMap<i32, fast_v3_pool::Tick>, anyhow::Error> {
+        sui_client: &SuiClient,
+        pool_id: &ObjectID
+    ) -> Result<(Checkpoint, SuiObjectResponse, Vec<SuiObjectResponse>), anyhow::Error> {
+        let pool_object_response = sui_client
+            .read_api()
+            .get_object_with_options(
+                pool_id.clone(),
+                SuiObjectDataOptions::full_content()
+            )
+            .await?;
 
-        // let aa = sui_client
-        //     .read_api()
-        //     .get_object_with_options(
-        //         ticks_skip_list_id.clone(),
-        //         SuiObjectDataOptions::new().with_type()
-        //     )
-        //     .await?;
+        let fields = sui_sdk_utils::read_fields_from_object_response(&pool_object_response).context("missing fields")?;
 
-        // println!("ticks skip_list: {:#?}", aa);
+        let ticks_skip_list_id = sui_move_value::get_uid(
+            &sui_move_value::get_struct(
+                &sui_move_value::get_struct(&fields, "tick_manager")?,
+                "ticks"
+            )?,
+            "id"
+        )?;
 
         let skip_list_dynamic_field_infos = sui_client
             .read_api()
             .pages(
                 GetDynamicFieldsRequest {
-                    object_id: ticks_skip_list_id.clone(), // We can make this consuming if it saves time
+                    object_id: ticks_skip_list_id,
                     cursor: None,
                     limit: None,
                 }
@@ -273,26 +235,48 @@ impl Cetus {
             .try_collect::<Vec<DynamicFieldInfo>>()
             .await?;
 
-        // println!("skip_list_dynamic_field_infos len: {}", skip_list_dynamic_field_infos.len());
-        // println!("skip_list_dynamic_field_infos: {:#?}", skip_list_dynamic_field_infos);
-
         let node_object_type = format!("0xbe21a06129308e0495431d12286127897aff07a8ade3970495a4404d97f9eaaa::skip_list::Node<{}::tick::Tick>", self.package_id);
 
-        let node_object_ids = skip_list_dynamic_field_infos
+        let mut pool_and_node_object_ids = skip_list_dynamic_field_infos
             .into_iter()
             .filter(|dynamic_field_info| {
                 node_object_type == dynamic_field_info.object_type
             })
-            .map(|tick_dynamic_field_info| {
-                tick_dynamic_field_info.object_id
+            .map(|node_dynamic_field_info| {
+                node_dynamic_field_info.object_id
             })
             .collect::<Vec<ObjectID>>();
 
-        let node_object_responses = sui_sdk_utils::get_object_responses(sui_client, &node_object_ids).await?;
+        pool_and_node_object_ids.push(pool_id.clone());
 
-        // println!("{:#?}", node_object_responses);
+        let (checkpoint, pool_and_node_object_responses) = sui_sdk_utils::get_checkpoint_pinned_object_responses(
+            sui_client,
+            pool_and_node_object_ids
+        ).await?;
 
-        let tick_index_to_tick = node_object_responses
+        let (mut pool_object_responses, node_object_responses): (Vec<_>, Vec<_>) = pool_and_node_object_responses
+            .into_iter()
+            .partition_map(|response| {
+                if response.data.as_ref().expect("SuiObjectResponse's data field is None").object_id == *pool_id {
+                    Either::Left(response)
+                } else {
+                    Either::Right(response)
+                }
+            });
+
+        let pool_object_response = pool_object_responses
+            .pop()
+            .context(
+                format!("Missing a SuiObjectResponse for pool {}", pool_id)
+            )?;
+
+        Ok((checkpoint, pool_object_response, node_object_responses))
+    }
+
+    fn tick_index_to_tick_from_node_object_responses(
+        node_object_responses: Vec<SuiObjectResponse>
+    ) -> Result<BTreeMap<i32, fast_v3_pool::Tick>, anyhow::Error> {
+        node_object_responses
             .into_iter()
             .map(|node_object_response| {
                 let fields = sui_sdk_utils::read_fields_from_object_response(&node_object_response).context("Missing fields.")?;
@@ -301,10 +285,6 @@ impl Cetus {
 
                 let tick_fields = sui_move_value::get_struct(&node_fields, "value").context("cetus")?;
 
-                // println!("tick_fields: {:#?}", tick_fields);
-
-                // println!("1");
-
                 let index = sui_move_value::get_number(
                     &sui_move_value::get_struct(
                         &tick_fields, 
@@ -313,14 +293,10 @@ impl Cetus {
                     "bits"
                 )? as i32;
 
-                // println!("2");
-
                 let sqrt_price = u128::from_str(
                     &sui_move_value::get_string(&tick_fields,"sqrt_price")?
                 )?;
 
-                // println!("3");
-
                 let liquidity_net = u128::from_str(
                     &sui_move_value::get_string(
                         &sui_move_value::get_struct(
@@ -331,45 +307,21 @@ impl Cetus {
                     )?
                 )? as i128;
 
-                // println!("4");
-
                 let liquidity_gross = u128::from_str(
                     &sui_move_value::get_string(&tick_fields, "liquidity_gross")?
                 )?;
 
-                // // println!("5");
-
-                // let fee_growth_outside_a = u128::from_str(
-                //     &sui_move_value::get_string(&tick_fields,"fee_growth_outside_a")?
-                // )?;
-
-                // // println!("6");
-
-                // let fee_growth_outside_b = u128::from_str(
-                //     &sui_move_value::get_string(&tick_fields,"fee_growth_outside_b")?
-                // )?;
-
-                // println!("7");
-
-                // println!("tick_fields: {:#?}", tick_fields);
-
                 let tick = fast_v3_pool::Tick{
                     index,
                     sqrt_price,
                     liquidity_net,
                     liquidity_gross,
-                    // fee_growth_outside_a,
-                    // fee_growth_outside_b,
                 };
 
                 Ok((index, tick))
             })
-            .collect::<Result<BTreeMap<i32, fast_v3_pool::Tick>, anyhow::Error>>()?;
-
-        Ok(tick_index_to_tick)
-
+            .collect::<Result<BTreeMap<i32, fast_v3_pool::Tick>, anyhow::Error>>()
     }
-
 }
 
 #[async_trait]
@@ -379,27 +331,57 @@ impl Exchange for Cetus {
     }
 
     fn event_package_id(&self) -> &ObjectID {
-        &self.event_package_id()
+        self.event_package_id()
     }
 
+    async fn get_all_market_builders(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error> {
+        self.get_all_market_builders_(sui_client).await
+    }
+}
+
+// Coin types come from the pool's creation event so building only needs the pool's state
+#[derive(Debug, Clone)]
+pub struct CetusMarketBuilder {
+    exchange: Cetus,
+    coin_x: TypeTag,
+    coin_y: TypeTag,
+    pool_id: ObjectID,
+}
 
-    fn event_filters(&self) -> Vec<EventFilter> {
-        self.event_filters()
+#[async_trait]
+impl MarketBuilder for CetusMarketBuilder {
+    fn pool_id(&self) -> &ObjectID {
+        &self.pool_id
     }
 
     fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
-        self.event_struct_tag_to_pool_field()
+        &self.exchange.event_struct_tag_to_pool_field
     }
 
-    // Cetus has us query for events
-    async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
-        self.get_all_markets_(sui_client).await
-    }
+    async fn build_checkpoint_pinned_market(
+        &self,
+        sui_client: &SuiClient
+    ) -> Result<(Checkpoint, Box<dyn Market>), anyhow::Error> {
+        let (checkpoint, computing_pool) = self
+            .exchange
+            .checkpoint_pinned_computing_pool(sui_client, &self.pool_id)
+            .await?;
 
-    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
-        self.get_pool_id_to_object_response(sui_client, markets).await
+        Ok(
+            (
+                checkpoint,
+                Box::new(
+                    CetusMarket {
+                        parent_exchange: self.exchange.clone(),
+                        coin_x: self.coin_x.clone(),
+                        coin_y: self.coin_y.clone(),
+                        pool_id: self.pool_id.clone(),
+                        computing_pool
+                    }
+                )
+            )
+        )
     }
-
 }
 
 #[derive(Debug, Clone)]
@@ -408,9 +390,7 @@ pub struct CetusMarket {
     pub coin_x: TypeTag,
     pub coin_y: TypeTag,
     pub pool_id: ObjectID,
-    pub coin_x_sqrt_price: Option<U64F64>, // In terms of y. x / y
-    pub coin_y_sqrt_price: Option<U64F64>, // In terms of x. y / x
-    pub computing_pool: Option<fast_v3_pool::Pool>
+    pub computing_pool: fast_v3_pool::Pool
 }
 
 impl CetusMarket {
@@ -422,53 +402,19 @@ impl CetusMarket {
         &self.coin_y
     }
 
-    fn coin_x_price(&self) -> Option<U64F64> {
-        if let Some(coin_x_sqrt_price) = self.coin_x_sqrt_price {
-            Some(coin_x_sqrt_price * coin_x_sqrt_price)
-        } else {
-            self.coin_x_sqrt_price
-        }
+    fn coin_x_price(&self) -> U64F64 {
+        let coin_x_sqrt_price = U64F64::from_bits(self.computing_pool.sqrt_price);
+        coin_x_sqrt_price * coin_x_sqrt_price
     }
 
-    fn coin_y_price(&self) -> Option<U64F64> {
-        if let Some(coin_y_sqrt_price) = self.coin_y_sqrt_price {
-            Some(coin_y_sqrt_price * coin_y_sqrt_price)
-        } else {
-            self.coin_y_sqrt_price
-        }
-    }
-
-    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
-        let fields = sui_sdk_utils::read_fields_from_object_response(object_response).context("Missing fields for object_response.")?;
-        // println!("cetus fields: {:#?}", fields);
-        let coin_x_sqrt_price = U64F64::from_bits(
-            u128::from_str(
-                &sui_move_value::get_string(&fields, "current_sqrt_price")?
-            )?
-        );
-
-        let coin_y_sqrt_price = U64F64::from_num(1) / coin_x_sqrt_price;
-        
-        self.coin_x_sqrt_price = Some(coin_x_sqrt_price);
-        self.coin_y_sqrt_price = Some(coin_y_sqrt_price);
-
-        // println!("coin_x<{}>: {}", self.coin_x, self.coin_x_price.unwrap());
-        // println!("coin_y<{}>: {}\n", self.coin_y, self.coin_y_price.unwrap());
-
-        self.computing_pool = Some(self.parent_exchange.computing_pool_from_object_response(sui_client, object_response).await?);
-
-        // println!("finised updating cetus pool");
-
-        Ok(())
+    fn coin_y_price(&self) -> U64F64 {
+        U64F64::from_num(1) / self.coin_x_price()
     }
 
-    fn update_with_event(&mut self, event: &SuiEvent) -> Result<(), anyhow::Error> {
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
         let type_ = &event.type_;
         let event_parsed_json = &event.parsed_json;
-        let computing_pool = self
-            .computing_pool
-            .as_mut()
-            .context("computing_pool is None")?;
+        let computing_pool = &mut self.computing_pool;
 
         // Amortize this so we only allocate these once. Cant be computed at compile time.
         let swap_event_type = StructTag::from_str(
@@ -487,127 +433,57 @@ impl CetusMarket {
                 &format!("{}::pool::UpdateFeeRateEvent", &self.parent_exchange.package_id)
             ).context("Cetus: failed to create event struct tag")?;
 
-        match type_ {
-            swap_event_type => {
-                let amount_in = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_in").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_out = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_out").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
-                let fee_amount = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("fee_amount").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent fee_amount is not Value::String."))
-                    }
-                )?;
-                let atob = *if let serde_json::Value::Bool(bool_inner) = event_parsed_json.get("atob").context("")? {
-                    bool_inner
-                } else {
-                    return Err(anyhow!("SwapEvent atob is not Value::Bool."))
-                };
-
-                let amount_specified = amount_in + fee_amount;
+        if type_ == &swap_event_type {
+            let atob = parsed_json::get_bool(event_parsed_json, "atob")?;
+            let before_sqrt_price = parsed_json::get_u128(event_parsed_json, "before_sqrt_price")?;
+            let after_sqrt_price = parsed_json::get_u128(event_parsed_json, "after_sqrt_price")?;
 
-                let sqrt_price_limit = if atob {
-                    fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1
-                } else {
-                    fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1
-                };
-
-                computing_pool.apply_swap(
-                    atob,
-                    amount_specified, 
-                    true, 
-                    sqrt_price_limit
-                );
-
-            },
-            add_liq_event_type => {
-                let tick_lower = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper_index is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity is not Value::String."))
-                    }
-                )?;
+            // We missed something if we weren't where the swap started
+            if computing_pool.sqrt_price != before_sqrt_price || !computing_pool.liquidity_sanity_check() {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-                computing_pool.apply_add_liquidity(
-                    tick_lower, 
-                    tick_upper, 
-                    liquidity_delta
-                );
-            },
-            remove_liq_event_type => {
-                let tick_lower_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity is not Value::String."))
-                    }
-                )?;
+            // Walk the price to where the swap ended. Crosses the same ticks
+            // the swap did so liquidity ends up right as well.
+            computing_pool.apply_swap(
+                atob,
+                u64::MAX,
+                true,
+                after_sqrt_price
+            );
+        } else if type_ == &add_liq_event_type {
+            let tick_lower = parsed_json::get_i32(event_parsed_json, "tick_lower")?;
+            let tick_upper = parsed_json::get_i32(event_parsed_json, "tick_upper")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity")?;
+
+            computing_pool.apply_add_liquidity(
+                tick_lower, 
+                tick_upper, 
+                liquidity_delta
+            );
+        } else if type_ == &remove_liq_event_type {
+            let tick_lower = parsed_json::get_i32(event_parsed_json, "tick_lower")?;
+            let tick_upper = parsed_json::get_i32(event_parsed_json, "tick_upper")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity")?;
+
+            if !computing_pool.ticks.contains_key(&tick_lower) || !computing_pool.ticks.contains_key(&tick_upper) {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-                computing_pool.apply_remove_liquidity(
-                    tick_lower_index, 
-                    tick_upper_index, 
-                    liquidity_delta
-                );
-            },
-            update_fee_rate_event_type => {
-                let new_fee_rate = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("new_fee_rate").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent new_fee_rate is not Value::String."))
-                    }
-                )?;
+            computing_pool.apply_remove_liquidity(
+                tick_lower, 
+                tick_upper, 
+                liquidity_delta
+            );
+        } else if type_ == &update_fee_rate_event_type {
+            let new_fee_rate = parsed_json::get_u64(event_parsed_json, "new_fee_rate")?;
 
-                computing_pool.apply_update_fee(new_fee_rate);
-            },
-            _ => {
-                // do nothing
-            }
+            computing_pool.apply_update_fee(new_fee_rate);
+        } else {
+            return Ok(EventUpdate::Ignored);
         }
 
-        Ok(())
-
+        Ok(EventUpdate::Applied)
     }
 
     fn pool_id(&self) -> &ObjectID {
@@ -622,9 +498,8 @@ impl CetusMarket {
         &self.parent_exchange.periphery_id
     }
 
-    // Better handling of computing pool being None
     fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
-        let swap_state = self.computing_pool.as_ref().unwrap().compute_swap_result(
+        let swap_state = self.computing_pool.compute_swap_result(
             true, 
             amount_specified as u64, 
             true, 
@@ -635,7 +510,7 @@ impl CetusMarket {
     }
 
     fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
-        let swap_state = self.computing_pool.as_ref().unwrap().compute_swap_result(
+        let swap_state = self.computing_pool.compute_swap_result(
             false, 
             amount_specified as u64, 
             true, 
@@ -646,16 +521,8 @@ impl CetusMarket {
     }
 
     fn viable(&self) -> bool {
-        if let Some(cp) = &self.computing_pool {
-            // println!("liquidity: {}", cp.liquidity);
-            if cp.liquidity > 0 && cp.unlocked && cp.liquidity_sanity_check() {
-                true
-            } else {
-                false
-            }
-        } else {
-            false
-        }
+        let cp = &self.computing_pool;
+        cp.liquidity > 0 && cp.unlocked && cp.liquidity_sanity_check()
     }
 
     async fn add_swap_to_programmable_trasaction(
@@ -800,7 +667,7 @@ impl CetusMarket {
 
 }
 
-#[async_trait]
+
 impl Market for CetusMarket {
     fn coin_x(&self) -> &TypeTag {
         self.coin_x()
@@ -810,18 +677,14 @@ impl Market for CetusMarket {
         self.coin_y()
     }
 
-    fn coin_x_price(&self) -> Option<U64F64> {
+    fn coin_x_price(&self) -> U64F64 {
         self.coin_x_price()
     }
 
-    fn coin_y_price(&self) -> Option<U64F64> {
+    fn coin_y_price(&self) -> U64F64 {
         self.coin_y_price()
     }
 
-    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
-        self.update_with_object_response(sui_client, object_response).await
-    }
-
     fn pool_id(&self) -> &ObjectID {
         self.pool_id()
     }
@@ -830,21 +693,13 @@ impl Market for CetusMarket {
         self.package_id()
     }
 
-    // fn router_id(&self) -> &ObjectID {
-    //     self.router_id()
-    // }
-
-    // fn compute_swap_x_to_y_mut(&mut self, amount_specified: u128) -> (u128, u128) {
-    //     let result = self.compute_swap_x_to_y_mut(amount_specified as u64);
-
-    //     (result.0 as u128, result.1 as u128)
-    // }
-
-    // fn compute_swap_y_to_x_mut(&mut self, amount_specified: u128) -> (u128, u128) {
-    //     let result = self.compute_swap_y_to_x_mut(amount_specified as u64);
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
 
-    //     (result.0 as u128, result.1 as u128)
-    // }
+    fn pool_state(&self) -> PoolState {
+        PoolState::from(&self.computing_pool)
+    }
 
     fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
         self.compute_swap_x_to_y(amount_specified)
@@ -857,29 +712,4 @@ impl Market for CetusMarket {
     fn viable(&self) -> bool {
         self.viable()
     }
-
-    async fn add_swap_to_programmable_transaction(
-        &self,
-        transaction_builder: &TransactionBuilder,
-        pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
-        x_to_y: bool,
-        amount_in: u128,
-        amount_out: u128,
-        recipient: SuiAddress
-    ) -> Result<(), anyhow::Error> {
-
-        // if !recipient.is_none() {
-        //     return Err(anyhow!("recipient should be none"));
-        // }
-
-        self.add_swap_to_programmable_trasaction(
-            transaction_builder,
-            pt_builder,
-            orig_coins,
-            x_to_y,
-            amount_in,
-            amount_out
-        ).await
-    }
 }
@@ -72,6 +72,7 @@ impl Pool {
     ) {
         let liquidity_delta_i128 = liquidity_delta as i128;
 
+        // Newly initialized ticks start empty. The deltas are added below.
         let tick_lower = self
             .ticks
             .entry(tick_lower_index)
@@ -79,8 +80,8 @@ impl Pool {
                 Tick {
                     index: tick_lower_index,
                     sqrt_price: tick_math::sqrt_price_from_tick_index(tick_lower_index),
-                    liquidity_gross: liquidity_delta,
-                    liquidity_net: -liquidity_delta_i128
+                    liquidity_gross: 0,
+                    liquidity_net: 0
                 }
             );
 
@@ -94,13 +95,18 @@ impl Pool {
                 Tick {
                     index: tick_upper_index,
                     sqrt_price: tick_math::sqrt_price_from_tick_index(tick_upper_index),
-                    liquidity_gross: liquidity_delta,
-                    liquidity_net: liquidity_delta_i128
+                    liquidity_gross: 0,
+                    liquidity_net: 0
                 }
             );
 
         tick_upper.liquidity_gross += liquidity_delta;
         tick_upper.liquidity_net -= liquidity_delta_i128;
+
+        // Position is in range so it adds to the active liquidity
+        if tick_lower_index <= self.tick_current_index && self.tick_current_index < tick_upper_index {
+            self.liquidity += liquidity_delta;
+        }
     }
 
     pub fn apply_remove_liquidity(
@@ -138,6 +144,10 @@ impl Pool {
                 .ticks
                 .remove(&tick_upper_index);
         }
+
+        if tick_lower_index <= self.tick_current_index && self.tick_current_index < tick_upper_index {
+            self.liquidity -= liquidity_delta;
+        }
     }
 
     pub fn apply_swap(
@@ -640,6 +650,43 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_add_then_remove_liquidity() {
+        let mut pool = Pool {
+            id: ObjectID::from_str("0x1").unwrap(),
+            sqrt_price: tick_math::sqrt_price_from_tick_index(100),
+            tick_current_index: 100,
+            tick_spacing: 10,
+            fee: 0,
+            unlocked: true,
+            liquidity: 0,
+            ticks: BTreeMap::new(),
+        };
+
+        // In range
+        pool.apply_add_liquidity(0, 200, 1_000);
+        assert_eq!(pool.liquidity, 1_000);
+        assert_eq!(pool.ticks[&0].liquidity_gross, 1_000);
+        assert_eq!(pool.ticks[&0].liquidity_net, 1_000);
+        assert_eq!(pool.ticks[&200].liquidity_gross, 1_000);
+        assert_eq!(pool.ticks[&200].liquidity_net, -1_000);
+
+        // Out of range. Shares the lower tick.
+        pool.apply_add_liquidity(200, 300, 500);
+        assert_eq!(pool.liquidity, 1_000);
+        assert_eq!(pool.ticks[&200].liquidity_gross, 1_500);
+        assert_eq!(pool.ticks[&200].liquidity_net, -500);
+
+        pool.apply_remove_liquidity(0, 200, 1_000);
+        assert_eq!(pool.liquidity, 0);
+        assert!(!pool.ticks.contains_key(&0));
+        assert_eq!(pool.ticks[&200].liquidity_gross, 500);
+        assert_eq!(pool.ticks[&200].liquidity_net, 500);
+
+        pool.apply_remove_liquidity(200, 300, 500);
+        assert!(pool.ticks.is_empty());
+    }
+
 }
 
 mod clmm_math {
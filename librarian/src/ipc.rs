@@ -0,0 +1,347 @@
+use anyhow::Context;
+
+use move_core_types::language_storage::TypeTag;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use sui_sdk::types::base_types::ObjectID;
+use sui_sdk::types::messages_checkpoint::CheckpointSequenceNumber;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{fast_v2_pool, fast_v3_pool, fast_cronje_pool};
+
+pub use pool_state::{PoolState, PoolStateDiff, TickState};
+
+// How many messages a slow subscriber can fall behind before it's sent a fresh snapshot
+const MESSAGE_CHANNEL_CAPACITY: usize = 4096;
+
+impl From<&fast_v3_pool::Pool> for PoolState {
+    fn from(pool: &fast_v3_pool::Pool) -> Self {
+        PoolState::ConcentratedLiquidity {
+            sqrt_price: pool.sqrt_price,
+            tick_current_index: pool.tick_current_index,
+            tick_spacing: pool.tick_spacing,
+            fee: pool.fee,
+            unlocked: pool.unlocked,
+            liquidity: pool.liquidity,
+            ticks: pool
+                .ticks
+                .values()
+                .map(|tick| {
+                    TickState {
+                        index: tick.index,
+                        sqrt_price: tick.sqrt_price,
+                        liquidity_net: tick.liquidity_net,
+                        liquidity_gross: tick.liquidity_gross,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&fast_v2_pool::Pool> for PoolState {
+    fn from(pool: &fast_v2_pool::Pool) -> Self {
+        PoolState::ConstantProduct {
+            reserve_x: pool.reserve_x,
+            reserve_y: pool.reserve_y,
+            protocol_fee: pool.protocol_fee,
+            lp_fee: pool.lp_fee,
+            unlocked: pool.unlocked,
+        }
+    }
+}
+
+impl From<&fast_cronje_pool::Pool> for PoolState {
+    fn from(pool: &fast_cronje_pool::Pool) -> Self {
+        PoolState::Stable {
+            reserve_x: pool.reserve_x,
+            reserve_y: pool.reserve_y,
+            protocol_fee: pool.protocol_fee,
+            lp_fee: pool.lp_fee,
+            scale_x: pool.scale_x,
+            scale_y: pool.scale_y,
+            unlocked: pool.unlocked,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketState {
+    pub pool_id: ObjectID,
+    pub coin_x: TypeTag,
+    pub coin_y: TypeTag,
+    // The checkpoint the market was pinned to
+    pub checkpoint: CheckpointSequenceNumber,
+    // Timestamp of the latest event applied since the pin
+    pub timestamp_ms: u64,
+    // Counts the changes published since the market was built. Set by the publisher.
+    pub version: u64,
+    pub pool_state: PoolState,
+}
+
+// A change to a market the subscriber already has
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketStateDiff {
+    pub pool_id: ObjectID,
+    pub timestamp_ms: u64,
+    // The version of the market after the diff. Applies on top of the one before it.
+    pub version: u64,
+    pub diff: PoolStateDiff,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Message {
+    // Every synced market. Replaces whatever the subscriber had.
+    Snapshot(Vec<MarketState>),
+    // The whole state of a market that was just built or changed in a way a diff can't carry
+    Update(MarketState),
+    // What changed in a market since its last version
+    Diff(MarketStateDiff),
+    // The market fell out of sync and is being rebuilt
+    Remove(ObjectID),
+}
+
+// Serves the synced markets to local subscribers over a Unix domain socket.
+// Messages are newline delimited JSON.
+#[derive(Clone)]
+pub struct Publisher {
+    pool_id_to_market_state: Arc<RwLock<HashMap<ObjectID, MarketState>>>,
+    message_sender: broadcast::Sender<Arc<Message>>,
+}
+
+impl Publisher {
+    pub fn new() -> Self {
+        let (message_sender, _) = broadcast::channel(MESSAGE_CHANNEL_CAPACITY);
+
+        Publisher {
+            pool_id_to_market_state: Arc::new(RwLock::new(HashMap::new())),
+            message_sender,
+        }
+    }
+
+    // Sends subscribers only what changed when they already have the market
+    pub fn update(&self, mut market_state: MarketState) {
+        let mut pool_id_to_market_state = self.pool_id_to_market_state
+            .write()
+            .expect("Publisher: market state lock poisoned");
+
+        let diff = pool_id_to_market_state
+            .get(&market_state.pool_id)
+            .and_then(|old_market_state| {
+                market_state.version = old_market_state.version + 1;
+
+                PoolStateDiff::between(&old_market_state.pool_state, &market_state.pool_state)
+            });
+
+        let message = if let Some(diff) = diff {
+            Message::Diff(
+                MarketStateDiff {
+                    pool_id: market_state.pool_id.clone(),
+                    timestamp_ms: market_state.timestamp_ms,
+                    version: market_state.version,
+                    diff,
+                }
+            )
+        } else {
+            Message::Update(market_state.clone())
+        };
+
+        pool_id_to_market_state.insert(market_state.pool_id.clone(), market_state);
+
+        // Sent under the lock so versions go out in order.
+        // Only fails when nobody is subscribed.
+        let _ = self.message_sender.send(Arc::new(message));
+    }
+
+    pub fn remove(&self, pool_id: &ObjectID) {
+        self.pool_id_to_market_state
+            .write()
+            .expect("Publisher: market state lock poisoned")
+            .remove(pool_id);
+
+        let _ = self.message_sender.send(Arc::new(Message::Remove(pool_id.clone())));
+    }
+
+    fn snapshot(&self) -> Message {
+        Message::Snapshot(
+            self.pool_id_to_market_state
+                .read()
+                .expect("Publisher: market state lock poisoned")
+                .values()
+                .cloned()
+                .collect()
+        )
+    }
+
+    pub async fn serve(self, socket_path: PathBuf) -> Result<(), anyhow::Error> {
+        // Clear out a socket left behind by a previous run
+        if socket_path.exists() {
+            std::fs::remove_file(&socket_path)?;
+        }
+
+        let listener = UnixListener::bind(&socket_path)
+            .context(format!("Failed to bind {}", socket_path.display()))?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let publisher = self.clone();
+
+            tokio::spawn(async move {
+                if let Err(err) = publisher.serve_subscriber(stream).await {
+                    println!("Subscriber disconnected: {}", err);
+                }
+            });
+        }
+    }
+
+    async fn serve_subscriber(&self, mut stream: UnixStream) -> Result<(), anyhow::Error> {
+        // Subscribe before taking the snapshot so no update falls in between.
+        // Subscribers skip diffs for versions the snapshot already has.
+        let mut message_receiver = self.message_sender.subscribe();
+
+        write_message(&mut stream, &self.snapshot()).await?;
+
+        loop {
+            match message_receiver.recv().await {
+                Ok(message) => {
+                    write_message(&mut stream, &message).await?;
+                },
+                Err(RecvError::Lagged(_)) => {
+                    // Whatever is still queued is older than a new snapshot would be
+                    message_receiver = self.message_sender.subscribe();
+                    write_message(&mut stream, &self.snapshot()).await?;
+                },
+                Err(RecvError::Closed) => {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+async fn write_message(stream: &mut UnixStream, message: &Message) -> Result<(), anyhow::Error> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+
+    Ok(())
+}
+
+pub struct Subscription {
+    lines: Lines<BufReader<UnixStream>>,
+}
+
+impl Subscription {
+    pub async fn connect(socket_path: &Path) -> Result<Self, anyhow::Error> {
+        let stream = UnixStream::connect(socket_path)
+            .await
+            .context(format!("Failed to connect to librarian at {}", socket_path.display()))?;
+
+        Ok(
+            Subscription {
+                lines: BufReader::new(stream).lines(),
+            }
+        )
+    }
+
+    // None once the librarian hangs up
+    pub async fn next_message(&mut self) -> Result<Option<Message>, anyhow::Error> {
+        if let Some(line) = self.lines.next_line().await? {
+            Ok(Some(serde_json::from_str(&line)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn tick(index: i32, liquidity_gross: u128) -> TickState {
+        TickState {
+            index,
+            sqrt_price: 1 << 64,
+            liquidity_net: liquidity_gross as i128,
+            liquidity_gross,
+        }
+    }
+
+    fn concentrated_liquidity(sqrt_price: u128, liquidity: u128, ticks: Vec<TickState>) -> PoolState {
+        PoolState::ConcentratedLiquidity {
+            sqrt_price,
+            tick_current_index: 0,
+            tick_spacing: 60,
+            fee: 3_000,
+            unlocked: true,
+            liquidity,
+            ticks,
+        }
+    }
+
+    fn market_state(pool_state: PoolState) -> MarketState {
+        MarketState {
+            pool_id: ObjectID::from_str("0x5").unwrap(),
+            coin_x: TypeTag::from_str("0x2::sui::SUI").unwrap(),
+            coin_y: TypeTag::from_str("0x1111::coin::COIN").unwrap(),
+            checkpoint: 100,
+            timestamp_ms: 1_700_000_000_000,
+            version: 3,
+            pool_state,
+        }
+    }
+
+    fn round_trip(message: &Message) -> Message {
+        let line = serde_json::to_string(message).unwrap();
+
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let pool_state = concentrated_liquidity(u128::MAX, 1 << 100, vec![tick(-60, 10), tick(60, 10)]);
+
+        let messages = [
+            Message::Snapshot(vec![market_state(pool_state.clone())]),
+            Message::Update(market_state(pool_state)),
+            Message::Diff(
+                MarketStateDiff {
+                    pool_id: ObjectID::from_str("0x5").unwrap(),
+                    timestamp_ms: 1_700_000_000_001,
+                    version: 4,
+                    diff: PoolStateDiff::ConcentratedLiquidity {
+                        sqrt_price: 1 << 64,
+                        tick_current_index: -1,
+                        liquidity: 5,
+                        ticks: vec![tick(-120, 0)],
+                    }
+                }
+            ),
+            Message::Diff(
+                MarketStateDiff {
+                    pool_id: ObjectID::from_str("0x6").unwrap(),
+                    timestamp_ms: 1_700_000_000_002,
+                    version: 0,
+                    diff: PoolStateDiff::Stable {
+                        reserve_x: u64::MAX,
+                        reserve_y: 0,
+                    }
+                }
+            ),
+            Message::Remove(ObjectID::from_str("0x5").unwrap()),
+        ];
+
+        for message in messages.iter() {
+            assert_eq!(&round_trip(message), message);
+        }
+    }
+}
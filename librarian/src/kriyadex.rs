@@ -2,59 +2,50 @@ use std::str::FromStr;
 use async_trait::async_trait;
 use anyhow::{anyhow, Context};
 
-use ethnum::U256;
-
-use futures::{future, TryStreamExt};
+use futures::TryStreamExt;
 use page_turner::PageTurner;
 use serde_json::Value;
-use fixed::{types::U64F64, consts::E};
+use fixed::types::U64F64;
 
 use custom_sui_sdk::{
     SuiClient,
-    apis::{
-        QueryEventsRequest,
-        GetDynamicFieldsRequest
-    },
+    apis::QueryEventsRequest,
     transaction_builder::{TransactionBuilder, ProgrammableObjectArg},
     programmable_transaction_sui_json::ProgrammableTransactionArg
 };
 
-use sui_sdk::types::{base_types::{ObjectID, ObjectIDParseError, ObjectType, SuiAddress}, object::Object};
-use sui_sdk::types::dynamic_field::DynamicFieldInfo;
+use sui_sdk::types::base_types::{ObjectID, ObjectType};
 use sui_sdk::types::programmable_transaction_builder::ProgrammableTransactionBuilder;
 use sui_sdk::rpc_types::{
+    Checkpoint,
     SuiObjectResponse, 
     EventFilter, 
     SuiEvent, 
-    SuiMoveValue, 
     SuiTypeTag
 };
  
 use sui_sdk::json::SuiJsonValue;
 
 use move_core_types::language_storage::{StructTag, TypeTag};
-use move_core_types::account_address::AccountAddress;
 use move_core_types::value::MoveValue;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::HashMap;
 
-use crate::markets::{Exchange, Market};
+use crate::markets::{Exchange, Market, MarketBuilder, EventUpdate};
+use crate::ipc::PoolState;
 use crate::sui_sdk_utils::{self, sui_move_value};
 use crate::fast_v2_pool;
 use crate::fast_cronje_pool;
-use crate::sui_json_utils::move_value_to_json;
+use crate::sui_json_utils::{move_value_to_json, parsed_json};
 
 #[derive(Debug, Clone)]
 pub struct KriyaDex {
     package_id: ObjectID,
-    event_struct_tag_to_pool_field: HashMap<StructTag, String>,
 }
 
 impl KriyaDex {
     pub fn new(package_id: ObjectID) -> Self {
-
         KriyaDex {
             package_id,
-            event_struct_tag_to_pool_field: HashMap::new(),
         }
     }
 }
@@ -68,27 +59,28 @@ impl KriyaDex {
         &self.package_id
     }
 
-    fn event_filters(&self) -> Vec<EventFilter> {
-        self
-            .event_struct_tag_to_pool_field()
-            .keys()
-            .cloned()
-            .map(|event_struct_tag| {
-                EventFilter::MoveEventType(
-                    event_struct_tag
-                )
-            })
-            .collect::<Vec<_>>()
-    }
+    // Swap events are typed by the coin going in so every pool has its own set
+    fn event_struct_tag_to_pool_field(&self, coin_x: &TypeTag, coin_y: &TypeTag) -> Result<HashMap<StructTag, String>, anyhow::Error> {
+        let mut event_struct_tag_to_pool_field = HashMap::new();
+
+        for event_name in [
+            "LiquidityAddedEvent".to_string(),
+            "LiquidityRemovedEvent".to_string(),
+            format!("SwapEvent<{}>", coin_x),
+            format!("SwapEvent<{}>", coin_y),
+        ] {
+            event_struct_tag_to_pool_field.insert(
+                StructTag::from_str(
+                    &format!("{}::spot_dex::{}", self.package_id, event_name)
+                ).context("KriyaDEX: failed to create event struct tag")?,
+                "pool_id".to_string()
+            );
+        }
 
-    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
-        &self.event_struct_tag_to_pool_field
+        Ok(event_struct_tag_to_pool_field)
     }
 
-    // Cetus has us query for events
-    async fn get_all_markets_(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
-        println!("Get all markets!");
-
+    async fn get_all_market_builders_(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error> {
         let pool_created_events = sui_client
             .event_api()
             .pages(
@@ -107,14 +99,11 @@ impl KriyaDex {
             .try_collect::<Vec<SuiEvent>>()
             .await?;
 
-        println!("kriya len pool_created_events: {}", pool_created_events.len());
-
         let pool_ids = pool_created_events
             .into_iter()
             .map(|pool_created_event| {
                 let parsed_json = pool_created_event.parsed_json;
-                if let Value::String(pool_id_value) = parsed_json.get("pool_id").context("Failed to get pool_id for a CetusMarket")? {
-                    // println!("turbos: pool_id: {}", pool_id_value);
+                if let Value::String(pool_id_value) = parsed_json.get("pool_id").context("Failed to get pool_id for a KriyaDexMarket")? {
                     Ok(ObjectID::from_str(&format!("0x{}", pool_id_value))?)
                 } else {
                     Err(anyhow!("Failed to match pattern."))
@@ -122,60 +111,49 @@ impl KriyaDex {
             })
             .collect::<Result<Vec<ObjectID>, anyhow::Error>>()?;
 
-            let pool_id_to_object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await?;
-
-            let markets = pool_id_to_object_response
-                .into_iter()
-                .map(|(pool_id, object_response)| {
-    
-                    let fields = sui_sdk_utils::read_fields_from_object_response(&object_response).context(format!("Missing fields for pool {}.", pool_id))?;
-    
-                    let (coin_x, coin_y) = get_coin_pair_from_object_response(&object_response)?;
-    
-                    // Add event filter struct tags
-                    self.event_struct_tag_to_pool_field.insert(
-                        StructTag::from_str(
-                            &format!("{}::spot_dex::SwapEvent<{}>", self.package_id, coin_x)
-                        )?,
-                        "pool_id".to_string()
-                    );
+        // The creation event doesn't have the coin types. A pool's type never changes
+        // so this doesn't need to be pinned.
+        let pool_id_to_object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await?;
 
-                    self.event_struct_tag_to_pool_field.insert(
-                        StructTag::from_str(
-                            &format!("{}::spot_dex::SwapEvent<{}>", self.package_id, coin_y)
-                        )?,
-                        "pool_id".to_string()
-                    );
-
-                    Ok(
-                        Some(
-                            Box::new(
-                                KriyaDexMarket {
-                                    parent_exchange: self.clone(),  // reevaluate clone
-                                    coin_x,
-                                    coin_y,
-                                    pool_id,
-                                    computing_pool: None    // We'll grab this later so we don't have to deal with async stuff
-                                }
-                            ) as Box<dyn Market>
-                        )
-                    )
-                })
-                .filter_map(|x| x.transpose())
-                .collect::<Result<Vec<Box<dyn Market>>, anyhow::Error>>()?;
-    
-            Ok(markets)
-    }
-
-    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
-        let pool_ids = markets
-            .iter()
-            .map(|market| {
-                *market.pool_id()
+        pool_id_to_object_response
+            .into_iter()
+            .map(|(pool_id, object_response)| {
+                let (coin_x, coin_y) = get_coin_pair_from_object_response(&object_response)?;
+                let event_struct_tag_to_pool_field = self.event_struct_tag_to_pool_field(&coin_x, &coin_y)?;
+
+                Ok(
+                    Box::new(
+                        KriyaDexMarketBuilder {
+                            exchange: self.clone(),
+                            coin_x,
+                            coin_y,
+                            pool_id,
+                            event_struct_tag_to_pool_field
+                        }
+                    ) as Box<dyn MarketBuilder>
+                )
             })
-            .collect::<Vec<ObjectID>>();
+            .collect::<Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error>>()
+    }
+
+    // All of a pool's state lives in the pool object
+    pub async fn checkpoint_pinned_computing_pool(
+        &self,
+        sui_client: &SuiClient,
+        pool_id: &ObjectID
+    ) -> Result<(Checkpoint, KriyaComputingPool), anyhow::Error> {
+        let (checkpoint, mut pool_object_responses) = sui_sdk_utils::get_checkpoint_pinned_object_responses(
+            sui_client,
+            vec![pool_id.clone()]
+        ).await?;
+
+        let pool_object_response = pool_object_responses
+            .pop()
+            .context(
+                format!("Missing a SuiObjectResponse for pool {}", pool_id)
+            )?;
 
-        sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await
+        Ok((checkpoint, self.computing_pool_from_object_response(&pool_object_response)?))
     }
 
     pub fn computing_pool_from_object_response(&self, response: &SuiObjectResponse) -> Result<KriyaComputingPool, anyhow::Error> {
@@ -270,6 +248,7 @@ impl KriyaDex {
     }
 }
 
+
 #[async_trait]
 impl Exchange for KriyaDex {
     fn package_id(&self) -> &ObjectID {
@@ -277,23 +256,56 @@ impl Exchange for KriyaDex {
     }
 
     fn event_package_id(&self) -> &ObjectID {
-        &self.event_package_id()
+        self.event_package_id()
     }
 
-    fn event_filters(&self) -> Vec<EventFilter> {
-        self.event_filters()
+    async fn get_all_market_builders(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error> {
+        self.get_all_market_builders_(sui_client).await
     }
+}
 
-    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String>{
-        self.event_struct_tag_to_pool_field()
+#[derive(Debug, Clone)]
+pub struct KriyaDexMarketBuilder {
+    exchange: KriyaDex,
+    coin_x: TypeTag,
+    coin_y: TypeTag,
+    pool_id: ObjectID,
+    event_struct_tag_to_pool_field: HashMap<StructTag, String>
+}
+
+#[async_trait]
+impl MarketBuilder for KriyaDexMarketBuilder {
+    fn pool_id(&self) -> &ObjectID {
+        &self.pool_id
     }
 
-    async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
-        self.get_all_markets_(sui_client).await
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
+        &self.event_struct_tag_to_pool_field
     }
 
-    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
-        self.get_pool_id_to_object_response(sui_client, markets).await
+    async fn build_checkpoint_pinned_market(
+        &self,
+        sui_client: &SuiClient
+    ) -> Result<(Checkpoint, Box<dyn Market>), anyhow::Error> {
+        let (checkpoint, computing_pool) = self
+            .exchange
+            .checkpoint_pinned_computing_pool(sui_client, &self.pool_id)
+            .await?;
+
+        Ok(
+            (
+                checkpoint,
+                Box::new(
+                    KriyaDexMarket {
+                        parent_exchange: self.exchange.clone(),
+                        coin_x: self.coin_x.clone(),
+                        coin_y: self.coin_y.clone(),
+                        pool_id: self.pool_id.clone(),
+                        computing_pool
+                    }
+                )
+            )
+        )
     }
 }
 
@@ -385,6 +397,32 @@ impl KriyaComputingPool {
         }
     }
 
+    fn reserve_x(
+        &self
+    ) -> u64 {
+        match self {
+            KriyaComputingPool::Uncorrelated(cp) => {
+                cp.reserve_x
+            },
+            KriyaComputingPool::Stable(cp) => {
+                cp.reserve_x
+            },
+        }
+    }
+
+    fn reserve_y(
+        &self
+    ) -> u64 {
+        match self {
+            KriyaComputingPool::Uncorrelated(cp) => {
+                cp.reserve_y
+            },
+            KriyaComputingPool::Stable(cp) => {
+                cp.reserve_y
+            },
+        }
+    }
+
     fn calc_swap_exact_amount_in(
         &self,
         amount_in: u64,
@@ -403,12 +441,12 @@ impl KriyaComputingPool {
 }
 
 #[derive(Debug, Clone)]
-struct KriyaDexMarket {
+pub struct KriyaDexMarket {
     parent_exchange: KriyaDex,
     coin_x: TypeTag,
     coin_y: TypeTag,
     pool_id: ObjectID,
-    computing_pool: Option<KriyaComputingPool>
+    computing_pool: KriyaComputingPool
 }
 
 impl KriyaDexMarket {
@@ -420,42 +458,18 @@ impl KriyaDexMarket {
         &self.coin_y
     }
 
-    fn coin_x_price(&self) -> Option<U64F64> {
-        if let Some(kcp) = &self.computing_pool {
-            Some (
-                kcp.coin_x_price()
-            )
-        } else {
-            None
-        }
-    }
-
-    fn coin_y_price(&self) -> Option<U64F64> {
-        if let Some(kcp) = &self.computing_pool {
-            Some (
-                kcp.coin_y_price()
-            )
-        } else {
-            None
-        }
+    fn coin_x_price(&self) -> U64F64 {
+        self.computing_pool.coin_x_price()
     }
 
-    // rename to "..pool_object_response"
-    // recall that we 
-    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
-        // let fields = sui_sdk_utils::read_fields_from_object_response(object_response).context("Missing fields for object_response.")?;
-
-        self.computing_pool = Some(self.parent_exchange.computing_pool_from_object_response(object_response)?);
-        Ok(())
+    fn coin_y_price(&self) -> U64F64 {
+        self.computing_pool.coin_y_price()
     }
 
-    fn update_with_event(&mut self, event: &SuiEvent) -> Result<(), anyhow::Error> {
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
         let type_ = &event.type_;
         let event_parsed_json = &event.parsed_json;
-        let computing_pool = self
-            .computing_pool
-            .as_mut()
-            .context("computing_pool is None")?;
+        let computing_pool = &mut self.computing_pool;
 
         // Amortize this so we only allocate these once. Cant be computed at compile time.
         let swap_coin_x_event_type = StructTag::from_str(
@@ -474,113 +488,53 @@ impl KriyaDexMarket {
                 &format!("{}::spot_dex::LiquidityRemovedEvent", &self.parent_exchange.package_id)
             ).context("KriyaDEX: failed to create event struct tag")?;
 
-        // let update_config_event_type = StructTag::from_str(
-        //         &format!("{}::spot_dex::ConfigUpdatedEvent", &self.parent_exchange.package_id)
-        //     ).context("KriyaDEX: failed to create event struct tag")?;
-
-        match type_ {
-            swap_coin_x_event_type => {
-                let amount_in = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_in").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_out = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_out").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
+        if type_ == &swap_coin_x_event_type || type_ == &swap_coin_y_event_type {
+            // The event is typed by the coin going in
+            let x_to_y = type_ == &swap_coin_x_event_type;
+            let amount_in = parsed_json::get_u64(event_parsed_json, "amount_in")?;
+            let amount_out = parsed_json::get_u64(event_parsed_json, "amount_out")?;
 
-                computing_pool.apply_swap_effects(
-                    true,
-                    amount_in,
-                    amount_out
-                );
+            let reserve_out = if x_to_y {
+                computing_pool.reserve_y()
+            } else {
+                computing_pool.reserve_x()
+            };
 
-            },
-            swap_coin_y_event_type => {
-                let amount_in = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_in").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_out = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_out").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
-
-                // coin x
-                let x_to_y = false;
-                
-                computing_pool.apply_swap_effects(
-                    x_to_y,
-                    amount_in,
-                    amount_out
-                );
+            // Can't pay out more than we think the pool holds
+            if amount_out > reserve_out {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-            },
-            add_liq_event_type => {
-                let amount_x = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_x").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_y = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_y").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
+            computing_pool.apply_swap_effects(
+                x_to_y,
+                amount_in,
+                amount_out
+            );
+        } else if type_ == &add_liq_event_type {
+            let amount_x = parsed_json::get_u64(event_parsed_json, "amount_x")?;
+            let amount_y = parsed_json::get_u64(event_parsed_json, "amount_y")?;
 
-                computing_pool.apply_add_liquidity_effects(
-                    amount_x,
-                    amount_y
-                );
-            },
-            remove_liq_event_type => {
-                let amount_x = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_x").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_in is not Value::String."))
-                    }
-                )?;
-                let amount_y = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_y").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_out is not Value::String."))
-                    }
-                )?;
+            computing_pool.apply_add_liquidity_effects(
+                amount_x,
+                amount_y
+            );
+        } else if type_ == &remove_liq_event_type {
+            let amount_x = parsed_json::get_u64(event_parsed_json, "amount_x")?;
+            let amount_y = parsed_json::get_u64(event_parsed_json, "amount_y")?;
 
-                computing_pool.apply_remove_liquidity_effects(
-                    amount_x,
-                    amount_y
-                );
-            },
-            // update_config_event_type => {
-            //     // return Err(anyhow!());
-            //     // This is an update to all pools....
-            // },
-            _ => {
-                // do nothing
+            if amount_x > computing_pool.reserve_x() || amount_y > computing_pool.reserve_y() {
+                return Ok(EventUpdate::Inconsistent);
             }
-        }
 
-        Ok(())
+            computing_pool.apply_remove_liquidity_effects(
+                amount_x,
+                amount_y
+            );
+        } else {
+            return Ok(EventUpdate::Ignored);
+        }
 
+        Ok(EventUpdate::Applied)
     }
 
     fn pool_id(&self) -> &ObjectID {
@@ -592,11 +546,8 @@ impl KriyaDexMarket {
     }
 
     fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
-        
         let (amount_x_delta, amount_y_delta) = self
             .computing_pool
-            .as_ref()
-            .unwrap()
             .calc_swap_exact_amount_in(
                 amount_specified as u64,
                 true
@@ -606,11 +557,8 @@ impl KriyaDexMarket {
     }
 
     fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
-        
         let (amount_x_delta, amount_y_delta) = self
             .computing_pool
-            .as_ref()
-            .unwrap()
             .calc_swap_exact_amount_in(
                 amount_specified as u64,
                 false
@@ -731,15 +679,10 @@ impl KriyaDexMarket {
     }
 
     fn viable(&self) -> bool {
-        if let Some(cp) = &self.computing_pool {
-            cp.unlocked()
-        } else {
-            false
-        }
+        self.computing_pool.unlocked()
     }
 }
 
-#[async_trait]
 impl Market for KriyaDexMarket {
     fn coin_x(&self) -> &TypeTag {
         self.coin_x()
@@ -749,18 +692,14 @@ impl Market for KriyaDexMarket {
         self.coin_y()
     }
 
-    fn coin_x_price(&self) -> Option<U64F64> {
+    fn coin_x_price(&self) -> U64F64 {
         self.coin_x_price()
     }
 
-    fn coin_y_price(&self) -> Option<U64F64> {
+    fn coin_y_price(&self) -> U64F64 {
         self.coin_y_price()
     }
 
-    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
-        self.update_with_object_response(sui_client, object_response).await
-    }
-
     fn pool_id(&self) -> &ObjectID {
         self.pool_id()
     }
@@ -769,6 +708,21 @@ impl Market for KriyaDexMarket {
         self.package_id()
     }
 
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
+
+    fn pool_state(&self) -> PoolState {
+        match &self.computing_pool {
+            KriyaComputingPool::Uncorrelated(cp) => {
+                PoolState::from(cp)
+            },
+            KriyaComputingPool::Stable(cp) => {
+                PoolState::from(cp)
+            },
+        }
+    }
+
     fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
         self.compute_swap_x_to_y(amount_specified)
     }
@@ -780,27 +734,6 @@ impl Market for KriyaDexMarket {
     fn viable(&self) -> bool {
         self.viable()
     }
-
-    async fn add_swap_to_programmable_transaction(
-        &self,
-        transaction_builder: &TransactionBuilder,
-        pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
-        x_to_y: bool,
-        amount_in: u128,
-        amount_out: u128,
-        recipient: SuiAddress
-    ) -> Result<(), anyhow::Error> {
-        self.add_swap_to_programmable_transaction(
-            transaction_builder,
-            pt_builder,
-            orig_coins,
-            x_to_y,
-            amount_in,
-            amount_out,
-        )
-        .await
-    }
 }
 
 fn get_coin_pair_from_object_response (
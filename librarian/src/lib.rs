@@ -1,39 +1,31 @@
 pub mod constants;
-// pub mod markets;
+pub mod markets;
+pub mod ipc;
+pub mod fast_v2_pool;
 pub mod fast_v3_pool;
+pub mod fast_cronje_pool;
 pub mod sui_sdk_utils;
 pub mod sui_json_utils;
+pub mod cetus;
+pub mod kriyadex;
 pub mod turbos;
 
+use anyhow::Context;
+
 use clap::Parser;
 
-use sui_sdk::rpc_types::{Checkpoint, SuiEvent};
-use std::pin::Pin;
-use std::thread;
-use crossbeam::channel::unbounded;
+use move_core_types::language_storage::StructTag;
+use sui_sdk::rpc_types::SuiEvent;
+use sui_sdk::types::base_types::ObjectID;
+use sui_sdk::types::messages_checkpoint::CheckpointSequenceNumber;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use custom_sui_sdk::SuiClient;
-use custom_sui_sdk::error::SuiRpcResult;
-use futures::{future, StreamExt};
+use futures::{FutureExt, StreamExt};
+use futures::stream::FuturesUnordered;
 use futures_core::Stream;
-use crate::turbos::*;
-
-
-// use crossbeam_channel::unbounded;
-
-// Note that we *can* parse out the pool
-// fn playback_for_market(
-//     events_cache: Vec<SuiEvent>,
-//     mut target_market: Box<dyn Market>,
-// ) -> Box<dyn Market> {
-//     events_cache
-//         .iter()
-//         .filter(|event| {
-//             // market.matches_update_event(event)
-//         })
-//         .fold(target_market, |market, event| {
-//             // market.update_with_event(event)
-//         })
-// }
+use crate::markets::{EventUpdate, Market, MarketBuilder, try_parse_pool_id_from_event};
+use crate::ipc::{MarketState, Publisher};
 
 #[derive(Parser)]
 #[clap(
@@ -43,266 +35,183 @@ use crate::turbos::*;
 )]
 
 pub struct RunDataOpts {
-    #[clap(long, default_value = "wss://sui-testnet.blastapi.io:443/25957d97-3d27-4236-8056-6b3f4eff7f0b")]
-    pub wss_url: String,
+    // Each can be given more than once. Requests go to the fastest healthy fullnode.
+    #[clap(long = "wss-url", default_values = &["wss://sui-testnet.blastapi.io:443/25957d97-3d27-4236-8056-6b3f4eff7f0b", "wss://fullnode.testnet.sui.io:443"])]
+    pub wss_urls: Vec<String>,
     // #[clap(long, default_value = "https://sui-mainnet.blastapi.io:443/338f3a96-cd39-41f8-88d9-4faefe2eee21")]
-    #[clap(long, default_value = "https://fullnode.testnet.sui.io:443")]
-    pub rpc_url: String,
+    #[clap(long = "rpc-url", default_values = &["https://fullnode.testnet.sui.io:443"])]
+    pub rpc_urls: Vec<String>,
+    // Where subscribers connect for market state
+    #[clap(long, default_value = "/tmp/librarian.sock")]
+    pub socket_path: PathBuf,
 }
 
+#[derive(Clone)]
+pub struct SyncedMarket {
+    pub market: Box<dyn Market>,
+    pub checkpoint: CheckpointSequenceNumber,
+    pub timestamp_ms: u64,
+}
 
-// pub async fn sync_markets(
-//     sui_client: SuiClient,
-//     mut events_stream: impl Stream<Item = SuiRpcResult<SuiEvent>>,
-//     market_builders_to_sync: Vec<TurbosMarketBuilder> // We'lll extend this to Box<dyn MarketBuilder>
-// ) -> Result<Vec<TurbosMarket>, anyhow::Error> {
-    
-//     tokio::pin!(events_stream);
-
-//     if let Some(event_result) = events_stream.next().await {
-//         println!("lib: {:#?}", event_result);
-//     }
-
-//     // println!("AHHHH");
-
-//     // let (fetch_markets_cue_sender, fetch_markets_cue_receiver) = unbounded();
-//     // let (fetched_markets_sender, fetched_markets_receiver) = unbounded();
-
-//     println!("pre thread");
-//     // The 
-//     // let other_client = sui_client.clone();
-//     thread::spawn(move || async move {
-//         println!("thread");
-//         // loop {
-//             if let Ok(()) = fetch_markets_cue_receiver.recv() {
-//                 let checkpoint_pinned_markets = get_checkpoint_pinned_markets(
-//                     &sui_client,
-//                     market_builders_to_sync
-//                 )
-//                 .await
-//                 .unwrap();
-    
-//                 fetched_markets_sender.send(checkpoint_pinned_markets).unwrap();
-//             } else {
-//                 panic!("Ahhhhh");
-//             }
-//         // }
-//     });
-
-//     // let mut event_cache = Vec::new();
-//     let mut start_recording = false;
-//     let mut synced_markets = Vec::new();
-
-//     while let Some(event_result) = events_stream.next().await {
-//         println!("loop!");
-//         // event_cache.push(event_result?);
-
-//         // if start_recording == false {
-//         //     println!("sending!");
-//         //     fetch_markets_cue_sender.send(())?;
-//         //     start_recording = true;
-//         // }
-
-//         // if let Ok(checkpoint_pinned_markets) = fetched_markets_receiver.recv() {
-//         //     for (checkpoint, mut market) in checkpoint_pinned_markets.into_iter() {
-//         //         // play events in event cache
-//         //         println!("Syncing {}", market.pool_id());
-//         //         for event in event_cache.iter() {
-//         //             // only play events if they match the market
-//         //             if let Some(pool_id) = market.try_parse_pool_id_from_event(event)? {
-//         //                 // Apply events after the market's checkpoint
-//         //                 if pool_id == *market.pool_id() && event.timestamp_ms.unwrap() >= checkpoint.timestamp_ms {
-//         //                     market.update_with_event(&event)?;
-//         //                 }
-//         //             }
-//         //             // skip otherwise
-//         //         }
-    
-//         //         synced_markets.push(market);
-//         //     }
-    
-//         //     // for markets 
-//         //     // synced_markets = checkpoint_pinned_markets.iter;
-//         //     break;
-//         // }
-//     }
-
-//     println!("post loop");
+impl SyncedMarket {
+    pub fn market_state(&self) -> MarketState {
+        MarketState {
+            pool_id: self.market.pool_id().clone(),
+            coin_x: self.market.coin_x().clone(),
+            coin_y: self.market.coin_y().clone(),
+            checkpoint: self.checkpoint,
+            timestamp_ms: self.timestamp_ms,
+            version: 0,
+            pool_state: self.market.pool_state(),
+        }
+    }
+}
 
-//     Ok(
-//         (
-//             synced_markets
-//         )
-//     )
-// } 
+// Every synced market across all exchanges, keyed by pool id
+#[derive(Clone, Default)]
+pub struct Book {
+    pub pool_id_to_synced_market: HashMap<ObjectID, SyncedMarket>,
+}
 
+// Builds every market pinned to a checkpoint and keeps them in sync with the events stream.
+// Events for a market that's still being built are buffered and replayed once
+// its state arrives. A market whose events stop lining up is rebuilt the same way.
+// Every change to a synced market is handed to the publisher.
 pub async fn sync_and_maintain_markets(
     sui_client: &SuiClient,
-    mut events_stream: impl Stream<Item = SuiRpcResult<SuiEvent>>,
-    market_builders_to_sync: Vec<TurbosMarketBuilder> // We'lll extend this to Box<dyn MarketBuilder>
+    events_stream: impl Stream<Item = SuiEvent>,
+    market_builders_to_sync: Vec<Box<dyn MarketBuilder>>,
+    publisher: &Publisher
 ) -> Result<(), anyhow::Error> {
     
     tokio::pin!(events_stream);
 
-    if let Some(event_result) = events_stream.next().await {
-        // println!("lib: {:#?}", event_result);
-        println!("event stream works!");
-    }
-
-    // let mut event_cache = Vec::new();
-    let mut start_recording = false;
-    // let mut synced_markets = Vec::new();
-    let mut checkpoint_pinned_markets = Vec::new();
-    
-    // We can just apply the events
-    // IF they have been applying backpressure
-    // And we don't drop any...
-    while let Some(event_result) = events_stream.next().await {
-
-        let event = event_result?;
-
-        println!("loop!");
-        if start_recording == false {
-            checkpoint_pinned_markets = get_checkpoint_pinned_markets(&sui_client, market_builders_to_sync.clone()).await?;
-            start_recording = true;
-        } else {
-            for (checkpoint, market) in checkpoint_pinned_markets.iter_mut() {
-                // play events in event cache
-                println!("Syncing {}", market.pool_id());
-                if let Some(pool_id) = market.try_parse_pool_id_from_event(&event)? {
-                    // Apply events after the market's checkpoint
-                    if pool_id == *market.pool_id() && event.timestamp_ms.unwrap() >= checkpoint.timestamp_ms {
-                        market.update_with_event(&event)?;
-                    }
-                }
-            }
-        }
-    }
-
-    println!("post loop");
-
-    Ok(())
-    // let 
-
-    // Ok(
-    //     (
-    //         checkpoint_pinne
-    //     )
-    // )
-} 
-
-async fn get_checkpoint_pinned_markets(
-    sui_client: &SuiClient,
-    market_builders_to_sync: Vec<TurbosMarketBuilder> // We'lll extend this to Box<dyn MarketBuilder>
-) -> Result<Vec<(Checkpoint, TurbosMarket)>, anyhow::Error> {
-    println!("AA");
+    let event_struct_tag_to_pool_field = market_builders_to_sync
+        .iter()
+        .flat_map(|market_builder| {
+            market_builder
+                .event_struct_tag_to_pool_field()
+                .clone()
+        })
+        .collect::<HashMap<StructTag, String>>();
 
-    future::try_join_all(
-        market_builders_to_sync
+    let pool_id_to_market_builder = market_builders_to_sync
         .into_iter()
-        .map(|market_builder| async {
-            Ok::<(Checkpoint, TurbosMarket), anyhow::Error>(
-                market_builder.build_checkpoint_pinned_market(sui_client).await?
-            )
+        .map(|market_builder| {
+            (market_builder.pool_id().clone(), market_builder)
         })
-    )
-    .await
-}
-
-// // This is our "base" stream. 
-// // It receives the events and is the source of events
-// // for all other event queues
-// pub async fn sync_markets(
-//     events_stream: impl Stream<Item = SuiRpcResult<SuiEvent>>,
-//     markets_to_sync: Vec<Box<dyn MarketBuilder>> // Builds something that implements Market, pool_id + Exchange
-//     // type_to_pool: HashMap<StructTag, Box<dyn Handler>>
-// ) -> Result<()> {
-
-//     // swap looping synced state fetcher
-//     // given that the computing pools are most importantly holders of state
-//     // the should not be Option<T> but just T?
-
-//     // Is it ok to create an empty pool but then sync it. 
-//     // No what if we miss a sync?
-//     // A pool should consist of real, if nonsynced, information to start.
-//     thread::spawn(|| {
-//         while Some(pool_to_sync) = pools_to_sync.pop() {
-//             // One of the following two.
-//             let (pool, checkpoint) = pool_to_sync.build_checkpoint_pinned();
-//             // let checkpoint = pool_to_sync.update_checkpoint_pinned();
-            
-//             // Send request for cache and receive and playback
-//             tx.send(
-//                 EventCacheRequest {
-//                     timestamp: checkpoint.timestamp,
-//                     pool_id: pool.pool_id()
-//                 }
-//             );
-
-//             // Send pool
-//             tx.send(pool)
-
-//         }
-
-//         // We could technically send all the pools.
-//         // What if we want to think modularly? 
-//         // Do we want the main book manager loop
-//         // to accept new books as they are added?
-//         // Note: This does not exactly play nice with
-//         // events like Kriyadex's where we can only subscribe if 
-//         // we know specifics about the exchange's pools.
-//         // To add a kriya pool we would have to suspend the current
-//         // stream, resubscribe, and recalibrate.
-
-//         // So we'll have to couple our book managing with our
-//         // pool selection + event substription..
-
-//         // Spinning up a new thread for every pool seems like a great
-//         // way to destroy performace. + There will be redundant event
-//         // streams for dex's like Trubos and Cetus
-
+        .collect::<HashMap<ObjectID, Box<dyn MarketBuilder>>>();
 
-//         // OR
-//         pools_to_sync.iter()
-//     });
-
-//     let mut event_cache = Vec::new();
-//     while Some(event_result) = events_stream.next(
-//         // We want to cue the synced pools request here
+    let build_market = |market_builder: Box<dyn MarketBuilder>| {
+        async move {
+            let pool_id = market_builder.pool_id().clone();
+            (pool_id, market_builder.build_checkpoint_pinned_market(sui_client).await)
+        }
+        .boxed()
+    };
 
-//         let event = event_result?;
+    let mut book = Book::default();
+    let mut pool_id_to_event_buffer: HashMap<ObjectID, Vec<SuiEvent>> = HashMap::new();
+    let mut pending_builds = FuturesUnordered::new();
 
-//         // At this point we don't know 
-//         event_cache.push();
+    for (pool_id, market_builder) in pool_id_to_market_builder.iter() {
+        pool_id_to_event_buffer.insert(pool_id.clone(), Vec::new());
+        pending_builds.push(build_market(market_builder.clone()));
+    }
 
+    loop {
+        tokio::select! {
+            event = events_stream.next() => {
+                let event = if let Some(event) = event {
+                    event
+                } else {
+                    break;
+                };
+
+                let pool_id = if let Some(pool_id) = try_parse_pool_id_from_event(&event_struct_tag_to_pool_field, &event)? {
+                    pool_id
+                } else {
+                    continue;
+                };
+
+                if let Some(event_buffer) = pool_id_to_event_buffer.get_mut(&pool_id) {
+                    event_buffer.push(event);
+                } else if let Some(synced_market) = book.pool_id_to_synced_market.get_mut(&pool_id) {
+                    match synced_market.market.update_with_event(&event)? {
+                        EventUpdate::Applied => {
+                            synced_market.timestamp_ms = event.timestamp_ms.unwrap_or(synced_market.timestamp_ms);
+                            publisher.update(synced_market.market_state());
+                        },
+                        EventUpdate::Ignored => {},
+                        EventUpdate::Inconsistent => {
+                            println!("Resyncing {}", pool_id);
+                            book.pool_id_to_synced_market.remove(&pool_id);
+                            publisher.remove(&pool_id);
+                            pool_id_to_event_buffer.insert(pool_id.clone(), Vec::new());
+                            pending_builds.push(build_market(pool_id_to_market_builder[&pool_id].clone()));
+                        }
+                    }
+                }
+            },
+            Some((pool_id, build_result)) = pending_builds.next() => {
+                let (checkpoint, market) = match build_result {
+                    Ok(checkpoint_pinned_market) => checkpoint_pinned_market,
+                    Err(err) => {
+                        // Keep buffering and try again
+                        println!("Failed to build {}: {}", pool_id, err);
+                        pending_builds.push(build_market(pool_id_to_market_builder[&pool_id].clone()));
+                        continue;
+                    }
+                };
 
-//         // If we accept pools one by one, we can clear the
-//         // event cache for every pool we receive, IF the event 
-//         // cache is waiting for a signal to move onto the next 
-//         // pool.
+                let mut synced_market = SyncedMarket {
+                    market,
+                    checkpoint: checkpoint.sequence_number,
+                    timestamp_ms: checkpoint.timestamp_ms,
+                };
 
-//         // We don't really have to worry about the cost of context switching here
+                let event_buffer = pool_id_to_event_buffer
+                    .remove(&pool_id)
+                    .unwrap_or_default();
 
-//         // Playback does slightly more complex
+                let mut consistent = true;
 
-//         // Or we can accept all pools at once.
-//         // This would simplify things. 
-//         // we could exit the loop, apply playback to all pools
-//         // and rejoin the loop.
+                for event in event_buffer.iter() {
+                    // The pinned state already includes everything up to and including its checkpoint
+                    let timestamp_ms = event.timestamp_ms.context("Event is missing timestamp_ms")?;
+                    if timestamp_ms <= checkpoint.timestamp_ms {
+                        continue;
+                    }
 
-//         // If we're not meant to accept any more pools we can break it up into stages like this
-//         if rx.try_recv() {
-//             // Sequential works but im sure we can go faster
-//             playback_for_markets();
-//             // flush event cache
-//             // then we break the while loop
-//         }
+                    match synced_market.market.update_with_event(event)? {
+                        EventUpdate::Applied => {
+                            synced_market.timestamp_ms = timestamp_ms;
+                        },
+                        EventUpdate::Ignored => {},
+                        EventUpdate::Inconsistent => {
+                            consistent = false;
+                            break;
+                        }
+                    }
+                }
 
-//     )
+                if consistent {
+                    publisher.update(synced_market.market_state());
+                    book.pool_id_to_synced_market.insert(pool_id, synced_market);
 
-//     // And then we pick up the stream again
-//     // And start keeping the book.
-// }
+                    if pool_id_to_event_buffer.is_empty() {
+                        println!("All {} markets synced", book.pool_id_to_synced_market.len());
+                    }
+                } else {
+                    println!("Resyncing {}", pool_id);
+                    pool_id_to_event_buffer.insert(pool_id.clone(), Vec::new());
+                    pending_builds.push(build_market(pool_id_to_market_builder[&pool_id].clone()));
+                }
+            }
+        }
+    }
 
+    println!("Events stream ended");
 
-// // It seems that by the nature of event subscription, doing this in stages makes sense
+    Ok(())
+}
@@ -1,10 +1,13 @@
 use librarian::*;
+use librarian::markets::Exchange;
 
+use anyhow::Context;
 use clap::Parser;
 use custom_sui_sdk::SuiClientBuilder;
 use futures::StreamExt;
 use futures_core::Stream;
-use governor::{Quota, RateLimiter};
+use custom_sui_sdk::rate_limiter::RequestLimiter;
+use governor::Quota;
 use move_core_types::language_storage::StructTag;
 use nonzero_ext::*;
 use sui_sdk::rpc_types::EventFilter;
@@ -15,10 +18,16 @@ use std::sync::Arc;
 use std::pin::Pin;
 use std::boxed::Box;
 
+const CETUS_PACKAGE_ADDRESS: &str = "0x1eabed72c53feb3805120a081dc15963c204dc8d091542592abaf7a35689b2fb";
+const CETUS_ROUTER_ADDRESS: &str = "0x2eeaab737b37137b94bfa8f841f92e36a153641119da3456dec1926b9960d9be";
+const CETUS_GLOBAL_CONFIG_ADDRESS: &str = "0xdaa46292632c3c4d8f31f23ea0f9b36a28ff3677e9684980e4438403a67a3d8f";
+
 const TURBOS_ORIGINAL_PACKAGE_ADDRESS: &str = "0x91bfbc386a41afcfd9b2533058d7e915a1d3829089cc268ff4333d54d6339ca1";
 const TURBOS_CURRENT_PACKAGE_ADDRESS: &str = "0xeb9210e2980489154cc3c293432b9a1b1300edd0d580fe2269dd9cda34baee6d";
 const TURBOS_VERSIONED_ID: &str = "0xf1cf0e81048df168ebeb1b8030fad24b3e0b53ae827c25053fff0779c1445b6f";
 
+const KRIYADEX_PACKAGE_ADDRESS: &str = "0xa0eba10b173538c8fecca1dff298e488402cc9ff374f8a12ca7758eebe830b66";
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
 
@@ -49,28 +58,55 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let run_data_opts = RunDataOpts::parse();
 
-    let rate_limiter = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(95u32))));
+    // Never executes anything so the priority lane gets next to nothing
+    let rate_limiter = Arc::new(
+        RequestLimiter::new(
+            Quota::per_second(nonzero!(94u32)),
+            Quota::per_second(nonzero!(1u32))
+        )
+    );
+
+    let (rpc_url, fallback_rpc_urls) = run_data_opts.rpc_urls
+        .split_first()
+        .context("At least one --rpc-url is needed")?;
 
     let sui_client = SuiClientBuilder::default()
-        .ws_url(
-            &run_data_opts.wss_url
+        .ws_urls(
+            &run_data_opts.wss_urls
         )
+        .http_urls(fallback_rpc_urls)
         .build(
-            &run_data_opts.rpc_url,
+            rpc_url,
             &rate_limiter
         )
         .await?;
 
-    let mut turbos = turbos::Turbos::new(
+    let cetus = cetus::Cetus::new(
+        ObjectID::from_str(CETUS_PACKAGE_ADDRESS).map_err(<ObjectIDParseError as Into<anyhow::Error>>::into)?,
+        ObjectID::from_str(CETUS_ROUTER_ADDRESS).map_err(<ObjectIDParseError as Into<anyhow::Error>>::into)?,
+        ObjectID::from_str(CETUS_GLOBAL_CONFIG_ADDRESS).map_err(<ObjectIDParseError as Into<anyhow::Error>>::into)?,
+    );
+
+    let turbos = turbos::Turbos::new(
         ObjectID::from_str(TURBOS_ORIGINAL_PACKAGE_ADDRESS).map_err(<ObjectIDParseError as Into<anyhow::Error>>::into)?, 
         ObjectID::from_str(TURBOS_CURRENT_PACKAGE_ADDRESS).map_err(<ObjectIDParseError as Into<anyhow::Error>>::into)?,
         ObjectID::from_str(TURBOS_VERSIONED_ID).map_err(<ObjectIDParseError as Into<anyhow::Error>>::into)?,
     );
 
-    let turbos_market_builders = turbos.get_all_market_builders(&sui_client).await?;
-    println!("turbos_market_builder.len(): {}", turbos_market_builders.len());
+    let kriyadex = kriyadex::KriyaDex::new(
+        ObjectID::from_str(KRIYADEX_PACKAGE_ADDRESS).map_err(<ObjectIDParseError as Into<anyhow::Error>>::into)?,
+    );
+
+    let exchanges: Vec<Box<dyn Exchange>> = vec![Box::new(cetus), Box::new(turbos), Box::new(kriyadex)];
 
-    let pool_state_changing_event_filters = turbos_market_builders
+    let mut market_builders = Vec::new();
+    for exchange in exchanges.iter() {
+        let exchange_market_builders = exchange.get_all_market_builders(&sui_client).await?;
+        println!("{} market builders for {}", exchange_market_builders.len(), exchange.package_id());
+        market_builders.extend(exchange_market_builders);
+    }
+
+    let pool_state_changing_event_filters = market_builders
         .iter()
         .flat_map(|market_builder| {
             market_builder
@@ -93,12 +129,13 @@ async fn main() -> Result<(), anyhow::Error> {
 
     println!("pool_state_changing_event_filters: {:#?}", pool_state_changing_event_filters);
 
-    let mut subscribe_pool_state_changing_events = sui_client
+    let subscribe_pool_state_changing_events = sui_client
         .event_api()
-        .subscribe_event(
+        .subscribe_event_resilient(
             EventFilter::Any(
                 pool_state_changing_event_filters
-            )
+            ),
+            None
         )
         .await?;
 
@@ -110,7 +147,16 @@ async fn main() -> Result<(), anyhow::Error> {
     //     println!("main: {:#?}", event);
     // }
 
-    sync_and_maintain_markets(&sui_client, subscribe_pool_state_changing_events, turbos_market_builders).await?;
+    let publisher = ipc::Publisher::new();
+    let serving_publisher = publisher.clone();
+    let socket_path = run_data_opts.socket_path.clone();
+    tokio::spawn(async move {
+        if let Err(err) = serving_publisher.serve(socket_path).await {
+            println!("Publisher stopped: {}", err);
+        }
+    });
+
+    sync_and_maintain_markets(&sui_client, subscribe_pool_state_changing_events, market_builders, &publisher).await?;
 
     Ok(())
 }
\ No newline at end of file
@@ -1,71 +1,80 @@
 use move_core_types::language_storage::{TypeTag, StructTag};
-use sui_sdk::{
-    types::{
-        base_types::{
-            ObjectID,
-            SuiAddress
-        },
-        programmable_transaction_builder::ProgrammableTransactionBuilder
-    }
-};
-use custom_sui_sdk::{
-    SuiClient,
-    transaction_builder::TransactionBuilder
-};
+use sui_sdk::types::base_types::ObjectID;
+use custom_sui_sdk::SuiClient;
 use async_trait::async_trait;
+use anyhow::anyhow;
 
-use std::collections::{HashSet, HashMap};
+use std::collections::HashMap;
+use std::str::FromStr;
 
 use fixed::types::U64F64;
 
-use sui_sdk::rpc_types::{EventFilter, SuiMoveValue, SuiMoveStruct, SuiObjectResponse, Checkpoint};
+use serde_json::Value;
+use sui_sdk::rpc_types::{Checkpoint, SuiEvent};
 use dyn_clone::DynClone;
 
+use crate::ipc::PoolState;
+
 #[async_trait]
 pub trait Exchange: Send + Sync {
     fn package_id(&self) -> &ObjectID;
-    fn event_filters(&self) -> Vec<EventFilter>;
-    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String>;
     fn event_package_id(&self) -> &ObjectID;
-    // async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error>; // -> Result<Vec<Box<dyn Market>>>
-    // async fn get_all_checkpoint_pinned_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error>;
-    // async fn get_pool_id_to_fields(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, BTreeMap<String, SuiMoveValue>>, anyhow::Error>;
-    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error>;
+    async fn get_all_market_builders(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error>;
 }
 
-// #[async_trait]
-// pub trait MarketBuilder: Send + Sync + DynClone {
-//     fn build_checkpoint_pinned(&self) -> (Checkpoint, impl Market);
-// }
-
+// Everything needed to build a market, without fetching its state yet.
+// Building is reference based so a market that falls out of sync can be rebuilt.
 #[async_trait]
+pub trait MarketBuilder: Send + Sync + DynClone {
+    fn pool_id(&self) -> &ObjectID;
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String>;
+    // The market's state as of the returned checkpoint.
+    async fn build_checkpoint_pinned_market(&self, sui_client: &SuiClient) -> Result<(Checkpoint, Box<dyn Market>), anyhow::Error>;
+}
+
+dyn_clone::clone_trait_object!(MarketBuilder);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventUpdate {
+    Applied,
+    // Not an event that changes our state
+    Ignored,
+    // The event doesn't line up with our state. We've missed something.
+    Inconsistent,
+}
+
 pub trait Market: Send + Sync + DynClone {
     fn coin_x(&self) -> &TypeTag;
     fn coin_y(&self) -> &TypeTag;
-    fn coin_x_price(&self) -> Option<U64F64>;
-    fn coin_y_price(&self) -> Option<U64F64>;
-    // async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error>;
-    // fn update_with_event(&mut self, event: &SuiEvent);
-    // fn 
+    fn coin_x_price(&self) -> U64F64;
+    fn coin_y_price(&self) -> U64F64;
     fn pool_id(&self) -> &ObjectID;
     fn package_id(&self) -> &ObjectID;
-    // fn compute_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128);
-    // fn compute_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128);
-    // fn compute_swap_x_to_y_mut(&mut self, amount_specified: u128) -> (u128, u128);
-    // fn compute_swap_y_to_x_mut(&mut self, amount_specified: u128) -> (u128, u128);
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error>;
+    fn pool_state(&self) -> PoolState;
     fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128);
     fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128);
-    async fn add_swap_to_programmable_transaction(
-        &self,
-        transaction_builder: &TransactionBuilder,
-        pt_builder: &mut ProgrammableTransactionBuilder,
-        orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
-        x_to_y: bool,
-        amount_in: u128,
-        amount_out: u128,
-        recipient: SuiAddress
-    ) -> Result<(), anyhow::Error>;
     fn viable(&self) -> bool;
 }
 
 dyn_clone::clone_trait_object!(Market);
+
+// None if the event isn't one that the map knows about
+pub fn try_parse_pool_id_from_event(
+    event_struct_tag_to_pool_field: &HashMap<StructTag, String>,
+    sui_event: &SuiEvent
+) -> Result<Option<ObjectID>, anyhow::Error> {
+    if let Some(pool_field_str) = event_struct_tag_to_pool_field.get(&sui_event.type_) {
+        if let Some(value) = sui_event.parsed_json.get(pool_field_str) {
+            if let Value::String(pool_id_str) = value {
+                Ok(Some(ObjectID::from_str(pool_id_str)?))
+            } else {
+                Err(anyhow!("parsed_json field should match Value::String variant"))
+            }
+        } else {
+            Err(anyhow!("Event has no such field '{}'", pool_field_str))
+        }
+    } else {
+        Ok(None)
+    }
+}
@@ -52,11 +52,10 @@ use sui_sdk::rpc_types::{
     SuiTypeTag
 };
 
-// use crate::{
-//     // markets::{Exchange, Market}, ß
-// };
+use crate::markets::{Exchange, Market, MarketBuilder, EventUpdate};
+use crate::ipc::PoolState;
 use crate::fast_v3_pool;
-use crate::sui_json_utils::move_value_to_json;
+use crate::sui_json_utils::{move_value_to_json, parsed_json};
 use crate::sui_sdk_utils::{self, sui_move_value, get_fields_from_object_response};
 
 #[derive(Debug, Clone)]
@@ -128,28 +127,34 @@ impl Turbos {
         Ok(pool_ids)
     }
 
-    pub async fn get_all_market_builders(
+    async fn get_all_market_builders_(
         &self,
         sui_client: &SuiClient
-    ) -> Result<Vec<TurbosMarketBuilder>, anyhow::Error> {
+    ) -> Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error> {
         let pool_ids = self.get_all_pool_ids(sui_client).await?;
 
+        let mut event_struct_tag_to_pool_field = HashMap::new();
+        // Everything that changes the state of a pool
+        for event_name in ["SwapEvent", "MintEvent", "BurnEvent", "TogglePoolStatusEvent"] {
+            event_struct_tag_to_pool_field.insert(
+                StructTag::from_str(
+                    &format!("{}::pool::{}", self.original_package_id, event_name)
+                ).context("Turbos: failed to create event struct tag")?,
+                "pool".to_string()
+            );
+        }
+
         Ok(
             pool_ids
                 .into_iter()
                 .map(|pool_id| {
-                    let mut event_struct_tag_to_pool_field = HashMap::new();
-                    event_struct_tag_to_pool_field.insert(
-                        StructTag::from_str(
-                            &format!("{}::pool::SwapEvent", self.original_package_id)
-                        ).expect("Turbos: failed to create event struct tag"),
-                        "pool".to_string()
-                    );
-                    TurbosMarketBuilder {
-                        exchange: self.clone(),
-                        pool_id,
-                        event_struct_tag_to_pool_field
-                    }
+                    Box::new(
+                        TurbosMarketBuilder {
+                            exchange: self.clone(),
+                            pool_id,
+                            event_struct_tag_to_pool_field: event_struct_tag_to_pool_field.clone()
+                        }
+                    ) as Box<dyn MarketBuilder>
                 })
                 .collect()
         )
@@ -402,32 +407,20 @@ impl Turbos {
     }
 }
 
-// #[async_trait]
-// impl Exchange for Turbos {
-//     fn package_id(&self) -> &ObjectID {
-//        self.package_id()
-//     }
-
-//     fn event_package_id(&self) -> &ObjectID {
-//         self.event_package_id()
-//     }
-
-//     fn event_filters(&self) -> Vec<EventFilter> {
-//         self.event_filters()
-//     }
-
-//     fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
-//         self.event_struct_tag_to_pool_field()
-//     }
+#[async_trait]
+impl Exchange for Turbos {
+    fn package_id(&self) -> &ObjectID {
+        self.package_id()
+    }
 
-//     async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
-//         self.get_all_markets_(sui_client).await
-//     }
+    fn event_package_id(&self) -> &ObjectID {
+        self.event_package_id()
+    }
 
-//     async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
-//         self.get_pool_id_to_object_response(sui_client, markets).await
-//     }
-// }
+    async fn get_all_market_builders(&self, sui_client: &SuiClient) -> Result<Vec<Box<dyn MarketBuilder>>, anyhow::Error> {
+        self.get_all_market_builders_(sui_client).await
+    }
+}
 
 // impl TurbosMarketBuilder
 
@@ -441,48 +434,30 @@ pub struct TurbosMarketBuilder {
     event_struct_tag_to_pool_field: HashMap<StructTag, String>
 }
 
-impl TurbosMarketBuilder {
-
-    // pub fn event_filters(&self) -> Vec<EventFilter> {
-    //     self
-    //         .event_struct_tag_to_pool_field
-    //         .keys()
-    //         .cloned()
-    //         .map(|event_struct_tag| {
-    //             EventFilter::MoveEventType(
-    //                 event_struct_tag
-    //             )
-    //         })
-    //         .collect::<Vec<_>>()
-    // }
-
-    // pub fn event_struct_tags(&self) -> Vec<StructTag> {
-    //     self
-    //         .event_struct_tag_to_pool_field
-    //         .keys()
-    //         .cloned()
-    //         .collect::<Vec<_>>()
-    // }
-
-    pub fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
+#[async_trait]
+impl MarketBuilder for TurbosMarketBuilder {
+    fn pool_id(&self) -> &ObjectID {
+        &self.pool_id
+    }
+
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
         &self.event_struct_tag_to_pool_field
     }
 
-    // This will consume the builder
-    // Perhaps we should make this reference based for redundancy
-    // in cas we have to rebuild
-    pub async fn build_checkpoint_pinned_market(
-        self,
+    async fn build_checkpoint_pinned_market(
+        &self,
         sui_client: &SuiClient
-    ) -> Result<(Checkpoint, TurbosMarket), anyhow::Error> {
-        self
+    ) -> Result<(Checkpoint, Box<dyn Market>), anyhow::Error> {
+        let (checkpoint, market) = self
             .exchange
             .get_checkpoint_pinned_market_with_id(
                 sui_client, 
                 self.pool_id,
-                self.event_struct_tag_to_pool_field
+                self.event_struct_tag_to_pool_field.clone()
             )
-            .await
+            .await?;
+
+        Ok((checkpoint, Box::new(market)))
     }
 }
 
@@ -522,31 +497,19 @@ impl TurbosMarket {
         self.event_struct_tag_to_pool_field.contains_key(sui_event_type)
     }
 
-    // We chould invite solutions that encourage further reasonaable optimization
-    // Like filtering before applying
-    // Rather than blindly trying to apply a change
-    pub fn try_parse_pool_id_from_event(
-        &self,
-        sui_event: &SuiEvent
-    ) -> Result<Option<ObjectID>, anyhow::Error> {
-        if let Some(pool_field_str) = self.event_struct_tag_to_pool_field.get(&sui_event.type_) {
-            if let Some(value) = sui_event.parsed_json.get(pool_field_str) {
-                if let Value::String(pool_id_str) = value {
-                    Ok(Some(ObjectID::from_str(&pool_id_str)?))
-                } else {
-                    Err(anyhow!("parsed_json field should match Value::String variant"))
-                }
-            } else {
-                Err(anyhow!("Event has no such field '{}'", pool_field_str))
-            }
-        } else {
-            Ok(None)
-        }
+    fn coin_x_price(&self) -> U64F64 {
+        let coin_x_sqrt_price = U64F64::from_bits(self.computing_pool.sqrt_price);
+        coin_x_sqrt_price * coin_x_sqrt_price
+    }
+
+    fn coin_y_price(&self) -> U64F64 {
+        U64F64::from_num(1) / self.coin_x_price()
     }
 
-    pub fn update_with_event(&mut self, event: &SuiEvent) -> Result<(), anyhow::Error> {
+    pub fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
         let type_ = &event.type_;
         let event_parsed_json = &event.parsed_json;
+        let computing_pool = &mut self.computing_pool;
 
         // Amortize this so we only allocate these once. Cant be computed at compile time.
         let swap_event_type = StructTag::from_str(
@@ -565,134 +528,72 @@ impl TurbosMarket {
                 &format!("{}::pool::TogglePoolStatusEvent", &self.parent_exchange.original_package_id)
             ).context("Turbos: failed to create event struct tag")?;
 
-        match type_ {
-            swap_event_type => {
-                let amount_a = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_a").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_a is not Value::String."))
-                    }
-                )?;
-                let amount_b = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("amount_b").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent amount_b is not Value::String."))
-                    }
-                )?;
-                let fee_amount = u64::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("fee_amount").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent fee_amount is not Value::String."))
-                    }
-                )?;
-                let a_to_b = *if let serde_json::Value::Bool(bool_inner) = event_parsed_json.get("a_to_b").context("")? {
-                    bool_inner
-                } else {
-                    return Err(anyhow!("SwapEvent a_to_b is not Value::Bool."))
-                };
-
-                let amount_specified = if a_to_b {
-                    amount_a + fee_amount
-                } else {
-                    amount_b + fee_amount
-                };
+        if type_ == &swap_event_type {
+            let a_to_b = parsed_json::get_bool(event_parsed_json, "a_to_b")?;
+            let tick_pre_index = parsed_json::get_i32(event_parsed_json, "tick_pre_index")?;
+            let sqrt_price = parsed_json::get_u128(event_parsed_json, "sqrt_price")?;
+            let tick_current_index = parsed_json::get_i32(event_parsed_json, "tick_current_index")?;
+            let liquidity = parsed_json::get_u128(event_parsed_json, "liquidity")?;
+
+            // We missed something if we weren't where the swap started
+            // or the swap ended on the wrong side of our price.
+            let moves_with_swap = if a_to_b {
+                sqrt_price <= computing_pool.sqrt_price
+            } else {
+                sqrt_price >= computing_pool.sqrt_price
+            };
 
-                let sqrt_price_limit = if a_to_b {
-                    fast_v3_pool::tick_math::MIN_SQRT_PRICE_X64 + 1
-                } else {
-                    fast_v3_pool::tick_math::MAX_SQRT_PRICE_X64 - 1
-                };
-
-                self.computing_pool.apply_swap(
-                    a_to_b,
-                    amount_specified, 
-                    true, 
-                    sqrt_price_limit
-                );
-
-            },
-            add_liq_event_type => {
-                let tick_lower_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower_index is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper_index is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity_delta").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity_delta is not Value::String."))
-                    }
-                )?;
-
-                self.computing_pool.apply_add_liquidity(
-                    tick_lower_index, 
-                    tick_upper_index, 
-                    liquidity_delta
-                );
-            },
-            remove_liq_event_type => {
-                let tick_lower_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_lower_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_lower_index is not Value::String."))
-                    }
-                )? as i32;
-                let tick_upper_index = u32::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("tick_upper_index").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent tick_upper_index is not Value::String."))
-                    }
-                )? as i32;
-                let liquidity_delta = u128::from_str(
-                    if let serde_json::Value::String(str) = event_parsed_json.get("liquidity_delta").context("")? {
-                        str
-                    } else {
-                        return Err(anyhow!("SwapEvent liquidity_delta is not Value::String."))
-                    }
-                )?;
-
-                self.computing_pool.apply_remove_liquidity(
-                    tick_lower_index, 
-                    tick_upper_index, 
-                    liquidity_delta
-                );
-            },
-            update_status_event_type => {
-                let status = *if let serde_json::Value::Bool(bool_inner) = event_parsed_json.get("status").context("")? {
-                    bool_inner
-                } else {
-                    return Err(anyhow!("SwapEvent status is not Value::Bool."))
-                };
-
-                self.computing_pool.apply_update_unlocked(
-                    status
-                );
-            },
-            _ => {
-                // do nothing
-                println!("update_with_event: did nothing.");
+            if computing_pool.tick_current_index != tick_pre_index || !moves_with_swap || !computing_pool.liquidity_sanity_check() {
+                return Ok(EventUpdate::Inconsistent);
             }
-        }
 
-        // self.version = 
+            // Walk the price to where the swap ended. Crosses the same ticks
+            // the swap did so liquidity ends up right as well.
+            computing_pool.apply_swap(
+                a_to_b,
+                u64::MAX,
+                true,
+                sqrt_price
+            );
+
+            if computing_pool.liquidity != liquidity || computing_pool.tick_current_index != tick_current_index {
+                return Ok(EventUpdate::Inconsistent);
+            }
+        } else if type_ == &add_liq_event_type {
+            let tick_lower_index = parsed_json::get_i32(event_parsed_json, "tick_lower_index")?;
+            let tick_upper_index = parsed_json::get_i32(event_parsed_json, "tick_upper_index")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity_delta")?;
+
+            computing_pool.apply_add_liquidity(
+                tick_lower_index, 
+                tick_upper_index, 
+                liquidity_delta
+            );
+        } else if type_ == &remove_liq_event_type {
+            let tick_lower_index = parsed_json::get_i32(event_parsed_json, "tick_lower_index")?;
+            let tick_upper_index = parsed_json::get_i32(event_parsed_json, "tick_upper_index")?;
+            let liquidity_delta = parsed_json::get_u128(event_parsed_json, "liquidity_delta")?;
+
+            if !computing_pool.ticks.contains_key(&tick_lower_index) || !computing_pool.ticks.contains_key(&tick_upper_index) {
+                return Ok(EventUpdate::Inconsistent);
+            }
 
-        Ok(())
+            computing_pool.apply_remove_liquidity(
+                tick_lower_index, 
+                tick_upper_index, 
+                liquidity_delta
+            );
+        } else if type_ == &update_status_event_type {
+            let status = parsed_json::get_bool(event_parsed_json, "status")?;
+
+            computing_pool.apply_update_unlocked(
+                status
+            );
+        } else {
+            return Ok(EventUpdate::Ignored);
+        }
 
+        Ok(EventUpdate::Applied)
     }
 
     pub fn pool_id(&self) -> &ObjectID {
@@ -923,83 +824,51 @@ impl TurbosMarket {
 
 }
 
-// #[async_trait]
-// impl Market for TurbosMarket {
-//     fn coin_x(&self) -> &TypeTag {
-//         self.coin_x()
-//     }
-
-//     fn coin_y(&self) -> &TypeTag {
-//         self.coin_y()
-//     }
-
-//     fn coin_x_price(&self) -> Option<U64F64> {
-//         self.coin_x_price()
-//     }
-
-//     fn coin_y_price(&self) -> Option<U64F64> {
-//         self.coin_y_price()
-//     }
-
-//     async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
-//         self.update_with_object_response(sui_client, object_response).await
-//     }
-
-//     fn pool_id(&self) -> &ObjectID {
-//         self.pool_id()
-//     }
-
-//     fn package_id(&self) -> &ObjectID {
-//         self.package_id()
-//     }
-
-//     // fn router_id(&self) -> &ObjectID {
-//     //     self.router_id()
-//     // }
-
-//     // fn compute_swap_x_to_y_mut(&mut self, amount_specified: u128) -> (u128, u128) {
-//     //     self.compute_swap_x_to_y_mut(amount_specified)
-//     // }
-
-//     // fn compute_swap_y_to_x_mut(&mut self, amount_specified: u128) -> (u128, u128) {
-//     //     self.compute_swap_y_to_x_mut(amount_specified)
-//     // }
-
-//     fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
-//         self.compute_swap_x_to_y(amount_specified)
-//     }
-
-//     fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
-//         self.compute_swap_y_to_x(amount_specified)
-//     }
-
-//     fn viable(&self) -> bool {
-//         self.viable()
-//     }
-
-//     async fn add_swap_to_programmable_transaction(
-//         &self,
-//         transaction_builder: &TransactionBuilder,
-//         pt_builder: &mut ProgrammableTransactionBuilder,
-//         orig_coins: Option<Vec<ObjectID>>, // the actual coin object in (that you own and has money)
-//         x_to_y: bool,
-//         amount_in: u128,
-//         amount_out: u128,
-//         recipient: SuiAddress
-//     ) -> Result<(), anyhow::Error> {
-//         self.add_swap_to_programmable_transaction(
-//             transaction_builder,
-//             pt_builder,
-//             orig_coins,
-//             x_to_y,
-//             amount_in,
-//             amount_out,
-//             recipient
-//         )
-//         .await
-//     }
-
-// }
+impl Market for TurbosMarket {
+    fn coin_x(&self) -> &TypeTag {
+        self.coin_x()
+    }
+
+    fn coin_y(&self) -> &TypeTag {
+        self.coin_y()
+    }
+
+    fn coin_x_price(&self) -> U64F64 {
+        self.coin_x_price()
+    }
+
+    fn coin_y_price(&self) -> U64F64 {
+        self.coin_y_price()
+    }
+
+    fn pool_id(&self) -> &ObjectID {
+        self.pool_id()
+    }
+
+    fn package_id(&self) -> &ObjectID {
+        self.package_id()
+    }
+
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
+
+    fn pool_state(&self) -> PoolState {
+        PoolState::from(&self.computing_pool)
+    }
+
+    fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
+        self.compute_swap_x_to_y(amount_specified)
+    }
+
+    fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
+        self.compute_swap_y_to_x(amount_specified)
+    }
+
+    fn viable(&self) -> bool {
+        self.viable()
+    }
+}
 
 fn get_coin_pair_and_fee_from_object_response (
     object_response: &SuiObjectResponse
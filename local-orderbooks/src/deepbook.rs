@@ -0,0 +1,905 @@
+use std::str::FromStr;
+use async_trait::async_trait;
+use anyhow::{anyhow, Context};
+
+use futures::{future, TryStreamExt};
+use page_turner::PageTurner;
+use fixed::types::U64F64;
+
+use custom_sui_sdk::{
+    SuiClient,
+    apis::{
+        QueryEventsRequest,
+        GetDynamicFieldsRequest
+    },
+    transaction_builder::{TransactionBuilder, programmable_nested_result},
+    programmable_transaction_sui_json::ProgrammableTransactionArg
+};
+
+use sui_sdk::types::base_types::{ObjectID, ObjectType, SuiAddress};
+use sui_sdk::types::dynamic_field::DynamicFieldInfo;
+use sui_sdk::types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_sdk::types::transaction::Argument;
+use sui_sdk::rpc_types::{
+    SuiObjectResponse,
+    EventFilter,
+    SuiEvent,
+    SuiMoveStruct,
+    SuiTypeTag
+};
+
+use sui_sdk::json::SuiJsonValue;
+
+use move_core_types::language_storage::{StructTag, TypeTag};
+use move_core_types::value::MoveValue;
+use std::collections::{BTreeMap, HashMap};
+
+use librarian::markets::try_parse_pool_id_from_event;
+use librarian::sui_sdk_utils::{self, sui_move_value};
+use librarian::sui_json_utils::{move_value_to_json, parsed_json};
+
+use market_traits::{Exchange, Market, EventUpdate, MarketRecord, ConstantProductCurve};
+
+use pool_state::PoolState;
+
+use crate::order_book::{OrderBook, PriceLevel, FLOAT_SCALING};
+
+const CLOCK_OBJECT_ID: &str = "0x0000000000000000000000000000000000000000000000000000000000000006";
+
+// Every event that moves an order on or off the book
+const ORDER_EVENT_NAMES: [&str; 4] = ["OrderPlaced", "OrderCanceled", "OrderFilled", "AllOrdersCanceled"];
+
+// DeepBook pools are Pool<BaseAsset, QuoteAsset> so coin_x is base and coin_y is quote.
+// x_to_y sells base into the bids and y_to_x buys base off the asks.
+#[derive(Debug, Clone)]
+pub struct DeepBook {
+    package_id: ObjectID,
+    // Swaps take an AccountCap even though taker orders never rest on the book.
+    // We reuse one we own rather than minting one per swap.
+    account_cap_id: ObjectID,
+    event_struct_tag_to_pool_field: HashMap<StructTag, String>,
+}
+
+impl DeepBook {
+    // Order events are typed by coin so they're added once we know the markets
+    pub fn new(package_id: ObjectID, account_cap_id: ObjectID) -> Self {
+        DeepBook {
+            package_id,
+            account_cap_id,
+            event_struct_tag_to_pool_field: HashMap::new(),
+        }
+    }
+}
+
+impl DeepBook {
+    pub fn package_id(&self) -> &ObjectID {
+        &self.package_id
+    }
+
+    fn event_package_id(&self) -> &ObjectID {
+        &self.package_id
+    }
+
+    fn event_filters(&self) -> Vec<EventFilter> {
+        self
+            .event_struct_tag_to_pool_field()
+            .keys()
+            .cloned()
+            .map(|event_struct_tag| {
+                EventFilter::MoveEventType(
+                    event_struct_tag
+                )
+            })
+            .collect::<Vec<_>>()
+    }
+
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
+        &self.event_struct_tag_to_pool_field
+    }
+
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error> {
+        try_parse_pool_id_from_event(self.event_struct_tag_to_pool_field(), event)
+    }
+
+    fn order_event_struct_tag(&self, event_name: &str, coin_x: &TypeTag, coin_y: &TypeTag) -> Result<StructTag, anyhow::Error> {
+        StructTag::from_str(
+            &format!("{}::clob_v2::{}<{}, {}>", self.package_id, event_name, coin_x, coin_y)
+        )
+        .context("DeepBook: failed to create event struct tag")
+    }
+
+    // Every pool announced itself with a PoolCreated event
+    async fn get_all_markets_(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
+        let pool_created_events = sui_client
+            .event_api()
+            .pages(
+                QueryEventsRequest {
+                    query: EventFilter::MoveEventType(
+                        self.pool_created_event_struct_tag()
+                    ),
+                    cursor: None,
+                    limit: None,
+                    descending_order: true,
+                }
+            )
+            .items()
+            .try_collect::<Vec<SuiEvent>>()
+            .await?;
+
+        println!("deepbook len pool_created_events: {}", pool_created_events.len());
+
+        let pool_ids = pool_created_events
+            .iter()
+            .map(|pool_created_event| {
+                pool_id_from_pool_created_event(pool_created_event)
+            })
+            .collect::<Result<Vec<ObjectID>, anyhow::Error>>()?;
+
+        let pool_id_to_object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await?;
+
+        let markets = pool_id_to_object_response
+            .into_iter()
+            .map(|(pool_id, object_response)| {
+                self.market_from_object_response(pool_id, &object_response)
+            })
+            .collect::<Result<Vec<Box<dyn Market>>, anyhow::Error>>()?;
+
+        Ok(markets)
+    }
+
+    fn pool_created_event_struct_tag(&self) -> StructTag {
+        StructTag::from_str(
+            &format!("{}::clob_v2::PoolCreated", self.package_id)
+        ).expect("DeepBook: failed to create pool created event struct tag")
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        vec![EventFilter::MoveEventType(self.pool_created_event_struct_tag())]
+    }
+
+    async fn market_from_pool_created_event_(&mut self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        if event.type_ != self.pool_created_event_struct_tag() {
+            return Ok(None);
+        }
+
+        let pool_id = pool_id_from_pool_created_event(event)?;
+
+        let object_response = sui_sdk_utils::get_object_id_to_object_response(sui_client, &[pool_id])
+            .await?
+            .remove(&pool_id)
+            .context(format!("Missing object response for new pool {}", pool_id))?;
+
+        Ok(Some(self.market_from_object_response(pool_id, &object_response)?))
+    }
+
+    // Order events are typed by coin so this adds the market's to our events
+    fn market_from_object_response(&mut self, pool_id: ObjectID, object_response: &SuiObjectResponse) -> Result<Box<dyn Market>, anyhow::Error> {
+        let (coin_x, coin_y) = get_coin_pair_from_object_response(object_response)?;
+
+        self.add_order_events(&coin_x, &coin_y)?;
+
+        Ok(
+            Box::new(
+                DeepBookMarket {
+                    parent_exchange: self.clone(),
+                    coin_x,
+                    coin_y,
+                    pool_id,
+                    order_book: None    // We'll grab this later so we don't have to deal with async stuff
+                }
+            ) as Box<dyn Market>
+        )
+    }
+
+    fn add_order_events(&mut self, coin_x: &TypeTag, coin_y: &TypeTag) -> Result<(), anyhow::Error> {
+        for event_name in ORDER_EVENT_NAMES {
+            self.event_struct_tag_to_pool_field.insert(
+                self.order_event_struct_tag(event_name, coin_x, coin_y)?,
+                "pool_id".to_string()
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
+        let pool_ids = markets
+            .iter()
+            .map(|market| {
+                *market.pool_id()
+            })
+            .collect::<Vec<ObjectID>>();
+
+        sui_sdk_utils::get_object_id_to_object_response(sui_client, &pool_ids).await
+    }
+
+    // Each side is a crit-bit tree whose leaves are price levels in a Table.
+    // Each level keeps its orders in a LinkedTable of its own.
+    pub async fn order_book_from_object_response(&self, sui_client: &SuiClient, response: &SuiObjectResponse) -> Result<OrderBook, anyhow::Error> {
+        let fields = sui_sdk_utils::read_fields_from_object_response(response).context("missing fields")?;
+
+        let taker_fee_rate = u64::from_str(
+            &sui_move_value::get_string(&fields, "taker_fee_rate")?
+        )?;
+
+        let tick_size = u64::from_str(
+            &sui_move_value::get_string(&fields, "tick_size")?
+        )?;
+
+        let lot_size = u64::from_str(
+            &sui_move_value::get_string(&fields, "lot_size")?
+        )?;
+
+        let mut order_book = OrderBook::new(taker_fee_rate, tick_size, lot_size);
+
+        let (bids, asks) = future::try_join(
+            self.get_price_levels(sui_client, &sui_move_value::get_struct(&fields, "bids")?),
+            self.get_price_levels(sui_client, &sui_move_value::get_struct(&fields, "asks")?)
+        ).await?;
+
+        order_book.bids = bids;
+        order_book.asks = asks;
+
+        Ok(order_book)
+    }
+
+    async fn get_price_levels(
+        &self,
+        sui_client: &SuiClient,
+        critbit_tree: &SuiMoveStruct
+    ) -> Result<BTreeMap<u64, PriceLevel>, anyhow::Error> {
+        let leaves_id = sui_move_value::get_uid(
+            &sui_move_value::get_struct(critbit_tree, "leaves")?,
+            "id"
+        )?;
+
+        let leaf_ids = get_dynamic_field_ids(sui_client, &leaves_id).await?;
+
+        let leaf_object_responses = sui_sdk_utils::get_object_responses(sui_client, &leaf_ids).await?;
+
+        // Field<u64, Leaf<TickLevel>>
+        let price_to_open_orders_id = leaf_object_responses
+            .iter()
+            .map(|leaf_object_response| {
+                let fields = sui_sdk_utils::read_fields_from_object_response(leaf_object_response).context("Missing fields.")?;
+
+                let leaf_fields = sui_move_value::get_struct(&fields, "value").context("deepbook")?;
+
+                let tick_level_fields = sui_move_value::get_struct(&leaf_fields, "value").context("deepbook")?;
+
+                let price = u64::from_str(
+                    &sui_move_value::get_string(&tick_level_fields, "price")?
+                )?;
+
+                let open_orders_id = sui_move_value::get_uid(
+                    &sui_move_value::get_struct(&tick_level_fields, "open_orders")?,
+                    "id"
+                )?;
+
+                Ok((price, open_orders_id))
+            })
+            .collect::<Result<Vec<(u64, ObjectID)>, anyhow::Error>>()?;
+
+        let price_levels = future::try_join_all(
+            price_to_open_orders_id
+                .iter()
+                .map(|(price, open_orders_id)| {
+                    async move {
+                        Ok::<(u64, PriceLevel), anyhow::Error>(
+                            (*price, self.get_price_level(sui_client, open_orders_id).await?)
+                        )
+                    }
+                })
+        )
+        .await?
+        .into_iter()
+        .filter(|(_, price_level)| {
+            !price_level.orders.is_empty()
+        })
+        .collect::<BTreeMap<u64, PriceLevel>>();
+
+        Ok(price_levels)
+    }
+
+    async fn get_price_level(
+        &self,
+        sui_client: &SuiClient,
+        open_orders_id: &ObjectID
+    ) -> Result<PriceLevel, anyhow::Error> {
+        let node_ids = get_dynamic_field_ids(sui_client, open_orders_id).await?;
+
+        let node_object_responses = sui_sdk_utils::get_object_responses(sui_client, &node_ids).await?;
+
+        // Field<u64, Node<u64, Order>>
+        let orders = node_object_responses
+            .iter()
+            .map(|node_object_response| {
+                let fields = sui_sdk_utils::read_fields_from_object_response(node_object_response).context("Missing fields.")?;
+
+                let node_fields = sui_move_value::get_struct(&fields, "value").context("deepbook")?;
+
+                let order_fields = sui_move_value::get_struct(&node_fields, "value").context("deepbook")?;
+
+                let order_id = u64::from_str(
+                    &sui_move_value::get_string(&order_fields, "order_id")?
+                )?;
+
+                let quantity = u64::from_str(
+                    &sui_move_value::get_string(&order_fields, "quantity")?
+                )?;
+
+                Ok((order_id, quantity))
+            })
+            .collect::<Result<BTreeMap<u64, u64>, anyhow::Error>>()?;
+
+        Ok(PriceLevel { orders })
+    }
+
+    // Records carry no order book so the market comes back unsynced
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        let mut market = DeepBookMarket {
+            parent_exchange: self.clone(),
+            coin_x: market_record.coin_x.clone(),
+            coin_y: market_record.coin_y.clone(),
+            pool_id: market_record.pool_id.clone(),
+            order_book: None
+        };
+
+        market.update_with_pool_state(market_record.pool_state.as_ref())?;
+
+        Ok(Box::new(market))
+    }
+}
+
+#[async_trait]
+impl Exchange for DeepBook {
+    fn package_id(&self) -> &ObjectID {
+        self.package_id()
+    }
+
+    fn event_package_id(&self) -> &ObjectID {
+        self.event_package_id()
+    }
+
+    fn event_filters(&self) -> Vec<EventFilter> {
+        self.event_filters()
+    }
+
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String> {
+        self.event_struct_tag_to_pool_field()
+    }
+
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error> {
+        self.pool_id_from_event(event)
+    }
+
+    async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error> {
+        self.get_all_markets_(sui_client).await
+    }
+
+    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error> {
+        self.get_pool_id_to_object_response(sui_client, markets).await
+    }
+
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error> {
+        self.market_from_record(market_record)
+    }
+
+    fn pool_created_event_filters(&self) -> Vec<EventFilter> {
+        self.pool_created_event_filters()
+    }
+
+    async fn market_from_pool_created_event(&mut self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error> {
+        self.market_from_pool_created_event_(sui_client, event).await
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DeepBookMarket {
+    parent_exchange: DeepBook,
+    coin_x: TypeTag,
+    coin_y: TypeTag,
+    pool_id: ObjectID,
+    order_book: Option<OrderBook>
+}
+
+impl DeepBookMarket {
+    fn coin_x(&self) -> &TypeTag {
+        &self.coin_x
+    }
+
+    fn coin_y(&self) -> &TypeTag {
+        &self.coin_y
+    }
+
+    // Quote we'd get for a unit of base at the best bid
+    fn coin_x_price(&self) -> Option<U64F64> {
+        let best_bid = self.order_book.as_ref()?.best_bid()?;
+
+        Some(U64F64::from_num(best_bid) / U64F64::from_num(FLOAT_SCALING))
+    }
+
+    // Base we'd get for a unit of quote at the best ask
+    fn coin_y_price(&self) -> Option<U64F64> {
+        let best_ask = self.order_book.as_ref()?.best_ask()?;
+
+        Some(U64F64::from_num(FLOAT_SCALING) / U64F64::from_num(best_ask))
+    }
+
+    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
+        self.order_book = Some(self.parent_exchange.order_book_from_object_response(sui_client, object_response).await?);
+        Ok(())
+    }
+
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        let type_ = &event.type_;
+        let event_parsed_json = &event.parsed_json;
+
+        let order_placed_event_type = self.parent_exchange.order_event_struct_tag("OrderPlaced", &self.coin_x, &self.coin_y)?;
+        let order_canceled_event_type = self.parent_exchange.order_event_struct_tag("OrderCanceled", &self.coin_x, &self.coin_y)?;
+        let order_filled_event_type = self.parent_exchange.order_event_struct_tag("OrderFilled", &self.coin_x, &self.coin_y)?;
+        let all_orders_canceled_event_type = self.parent_exchange.order_event_struct_tag("AllOrdersCanceled", &self.coin_x, &self.coin_y)?;
+
+        let order_book = self
+            .order_book
+            .as_mut()
+            .context("order_book is None")?;
+
+        if type_ == &order_placed_event_type {
+            // Only what's left after matching rests on the book. The matches come as OrderFilled.
+            let is_bid = parsed_json::get_bool(event_parsed_json, "is_bid")?;
+            let order_id = parsed_json::get_u64(event_parsed_json, "order_id")?;
+            let price = parsed_json::get_u64(event_parsed_json, "price")?;
+            let quantity = parsed_json::get_u64(event_parsed_json, "base_asset_quantity_placed")?;
+
+            order_book.insert_order(is_bid, price, order_id, quantity);
+        } else if type_ == &order_canceled_event_type {
+            if !remove_canceled_order(order_book, event_parsed_json)? {
+                return Ok(EventUpdate::Inconsistent);
+            }
+        } else if type_ == &order_filled_event_type {
+            // is_bid and order_id are the maker's
+            let is_bid = parsed_json::get_bool(event_parsed_json, "is_bid")?;
+            let order_id = parsed_json::get_u64(event_parsed_json, "order_id")?;
+            let price = parsed_json::get_u64(event_parsed_json, "price")?;
+            let quantity_remaining = parsed_json::get_u64(event_parsed_json, "base_asset_quantity_remaining")?;
+
+            if !order_book.set_order_quantity(is_bid, price, order_id, quantity_remaining) {
+                return Ok(EventUpdate::Inconsistent);
+            }
+        } else if type_ == &all_orders_canceled_event_type {
+            let orders_canceled = event_parsed_json
+                .get("orders_canceled")
+                .and_then(|orders_canceled| orders_canceled.as_array())
+                .context("Missing field 'orders_canceled'.")?;
+
+            for order_canceled in orders_canceled {
+                if !remove_canceled_order(order_book, order_canceled)? {
+                    return Ok(EventUpdate::Inconsistent);
+                }
+            }
+        } else {
+            return Ok(EventUpdate::Ignored);
+        }
+
+        Ok(EventUpdate::Applied)
+    }
+
+    // The librarian doesn't serve order books
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        if pool_state.is_some() {
+            return Err(anyhow!("DeepBook pool {} can't take pool state", self.pool_id));
+        }
+
+        self.order_book = None;
+
+        Ok(())
+    }
+
+    fn market_record(&self) -> MarketRecord {
+        MarketRecord {
+            package_id: self.package_id().clone(),
+            pool_id: self.pool_id.clone(),
+            coin_x: self.coin_x.clone(),
+            coin_y: self.coin_y.clone(),
+            extra_type_args: vec![],
+            pool_state: None,
+        }
+    }
+
+    fn pool_id(&self) -> &ObjectID {
+        &self.pool_id
+    }
+
+    fn package_id(&self) -> &ObjectID {
+        &self.parent_exchange.package_id
+    }
+
+    // Sells base. Returns (base sold, quote out).
+    // Nothing trades against a book we don't have.
+    fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
+        let (base_sold, quote_out) = if let Some(order_book) = &self.order_book {
+            order_book.calc_sell_base(saturating_u64(amount_specified))
+        } else {
+            (0, 0)
+        };
+
+        (base_sold as u128, quote_out as u128)
+    }
+
+    // Buys base. Returns (base out, quote spent).
+    fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
+        let (quote_spent, base_out) = if let Some(order_book) = &self.order_book {
+            order_book.calc_buy_base(saturating_u64(amount_specified))
+        } else {
+            (0, 0)
+        };
+
+        (base_out as u128, quote_spent as u128)
+    }
+
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        let (base_sold, quote_out) = if let Some(order_book) = &mut self.order_book {
+            order_book.apply_sell_base(saturating_u64(amount_specified))
+        } else {
+            (0, 0)
+        };
+
+        (base_sold as u128, quote_out as u128)
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        let (quote_spent, base_out) = if let Some(order_book) = &mut self.order_book {
+            order_book.apply_buy_base(saturating_u64(amount_specified))
+        } else {
+            (0, 0)
+        };
+
+        (base_out as u128, quote_spent as u128)
+    }
+
+    // Market orders only take whole lots of base and we can't round the value
+    // of a coin on chain. Sells are sized off the book to just cover amount_out
+    // and what's left of orig_coin goes to the recipient along with any change.
+    async fn add_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument, // the coin we swap in its entirety
+        x_to_y: bool,
+        amount_out: u128,
+        recipient: SuiAddress
+    ) -> Result<Argument, anyhow::Error> {
+        // Arg0: &mut Pool<Ty0, Ty1>
+        let pool = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.pool_id.clone())
+        );
+
+        // Arg1: u64
+        let client_order_id = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::new(
+                move_value_to_json(
+                    &MoveValue::U64(0)
+                )
+                .context("failed to convert MoveValue for client_order_id to JSON")?
+            )?
+        );
+
+        // Arg2: &AccountCap
+        let account_cap = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(self.parent_exchange.account_cap_id.clone())
+        );
+
+        let clock = ProgrammableTransactionArg::SuiJsonValue(
+            SuiJsonValue::from_object_id(
+                ObjectID::from_str(CLOCK_OBJECT_ID)?
+            )
+        );
+
+        let (function, call_args) = if x_to_y {
+            let base_quantity = self
+                .order_book
+                .as_ref()
+                .context("order_book is None")?
+                .base_quantity_for_quote_out(saturating_u64(amount_out))
+                .context(format!("DeepBook pool {} can't pay {} quote", self.pool_id, amount_out))?;
+
+            if base_quantity == 0 {
+                return Err(anyhow!("DeepBook pool {} can't sell zero base", self.pool_id));
+            }
+
+            // Arg3: u64
+            let quantity = ProgrammableTransactionArg::SuiJsonValue(
+                SuiJsonValue::new(
+                    move_value_to_json(
+                        &MoveValue::U64(base_quantity)
+                    )
+                    .context("failed to convert MoveValue for quantity to JSON")?
+                )?
+            );
+
+            // Arg4: Coin<Ty0>
+            let base_coin = ProgrammableTransactionArg::Argument(orig_coin);
+
+            // Arg5: Coin<Ty1>
+            let quote_coin = ProgrammableTransactionArg::Argument(
+                transaction_builder
+                    .programmable_coin_zero(
+                        pt_builder,
+                        self.coin_y.clone()
+                    ).await?
+            );
+
+            // Arg6: &Clock is last
+            (
+                "swap_exact_base_for_quote",
+                vec![pool, client_order_id, account_cap, quantity, base_coin, quote_coin, clock]
+            )
+        } else {
+            // Arg3: u64
+            // Whatever is in the coin we were handed. We may not know it ahead of time.
+            let quantity = ProgrammableTransactionArg::Argument(
+                transaction_builder
+                    .programmable_coin_value(
+                        pt_builder,
+                        self.coin_y.clone(),
+                        orig_coin
+                    ).await?
+            );
+
+            // Arg5: Coin<Ty1>
+            // It comes after Arg4: &Clock here
+            let quote_coin = ProgrammableTransactionArg::Argument(orig_coin);
+
+            (
+                "swap_exact_quote_for_base",
+                vec![pool, client_order_id, account_cap, quantity, clock, quote_coin]
+            )
+        };
+
+        let type_args = vec![
+            SuiTypeTag::new(format!("{}", self.coin_x)),
+            SuiTypeTag::new(format!("{}", self.coin_y)),
+        ];
+
+        let swap_result = transaction_builder.programmable_move_call(
+            pt_builder,
+            self.parent_exchange.package_id.clone(),
+            "clob_v2",
+            function,
+            type_args,
+            call_args
+        ).await?;
+
+        // Returns (Coin<Ty0>, Coin<Ty1>, u64)
+        let (orig_coin_out, dest_coin_out) = if x_to_y {
+            (programmable_nested_result(swap_result, 0)?, programmable_nested_result(swap_result, 1)?)
+        } else {
+            (programmable_nested_result(swap_result, 1)?, programmable_nested_result(swap_result, 0)?)
+        };
+
+        // Less than a lot or whatever the sell didn't need
+        pt_builder.transfer_arg(recipient, orig_coin_out);
+
+        // Market orders have no min out so we enforce it on the output coin ourselves
+        if amount_out > 0 {
+            transaction_builder
+                .programmable_assert_min_value(
+                    pt_builder,
+                    dest_coin_out,
+                    saturating_u64(amount_out)
+                ).await?;
+        }
+
+        Ok(dest_coin_out)
+    }
+
+    fn viable(&self) -> bool {
+        if let Some(order_book) = &self.order_book {
+            !order_book.bids.is_empty() || !order_book.asks.is_empty()
+        } else {
+            false
+        }
+    }
+
+    // A book's depth isn't a curve
+    fn constant_product_curve(&self, _x_to_y: bool) -> Option<ConstantProductCurve> {
+        None
+    }
+}
+
+#[async_trait]
+impl Market for DeepBookMarket {
+    fn coin_x(&self) -> &TypeTag {
+        self.coin_x()
+    }
+
+    fn coin_y(&self) -> &TypeTag {
+        self.coin_y()
+    }
+
+    fn coin_x_price(&self) -> Option<U64F64> {
+        self.coin_x_price()
+    }
+
+    fn coin_y_price(&self) -> Option<U64F64> {
+        self.coin_y_price()
+    }
+
+    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error> {
+        self.update_with_object_response(sui_client, object_response).await
+    }
+
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error> {
+        self.update_with_event(event)
+    }
+
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error> {
+        self.update_with_pool_state(pool_state)
+    }
+
+    fn market_record(&self) -> MarketRecord {
+        self.market_record()
+    }
+
+    fn pool_id(&self) -> &ObjectID {
+        self.pool_id()
+    }
+
+    fn package_id(&self) -> &ObjectID {
+        self.package_id()
+    }
+
+    fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128) {
+        self.compute_swap_x_to_y(amount_specified)
+    }
+
+    fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128) {
+        self.compute_swap_y_to_x(amount_specified)
+    }
+
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_x_to_y(amount_specified)
+    }
+
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128) {
+        self.apply_swap_y_to_x(amount_specified)
+    }
+
+    fn viable(&self) -> bool {
+        self.viable()
+    }
+
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve> {
+        self.constant_product_curve(x_to_y)
+    }
+
+    async fn add_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument, // split off of our own coins or the output coin of the previous leg
+        x_to_y: bool,
+        _amount_in: u128,
+        amount_out: u128,
+        recipient: SuiAddress
+    ) -> Result<Argument, anyhow::Error> {
+        self.add_swap_to_programmable_transaction(
+            transaction_builder,
+            pt_builder,
+            orig_coin,
+            x_to_y,
+            amount_out,
+            recipient
+        )
+        .await
+    }
+
+    fn supports_exact_output(&self) -> bool {
+        false
+    }
+
+    async fn add_exact_output_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _orig_coin: Argument,
+        _x_to_y: bool,
+        _amount_out: u128,
+        _recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        Err(anyhow!("DeepBook pools don't support exact output swaps"))
+    }
+
+    // DeepBook v2 only trades coins we already hold
+    fn supports_flash_swap(&self) -> bool {
+        false
+    }
+
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _x_to_y: bool,
+        _amount_in: u128,
+        _recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error> {
+        Err(anyhow!("DeepBook pools don't support flash swaps"))
+    }
+
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        _transaction_builder: &TransactionBuilder,
+        _pt_builder: &mut ProgrammableTransactionBuilder,
+        _receipt: Argument,
+        _pay_coin: Argument,
+        _x_to_y: bool,
+        _amount_in: u128
+    ) -> Result<(), anyhow::Error> {
+        Err(anyhow!("DeepBook pools don't support flash swaps"))
+    }
+}
+
+// Coin amounts are u64 on chain. Anything bigger asks for more than any coin holds.
+fn saturating_u64(amount: u128) -> u64 {
+    u64::try_from(amount).unwrap_or(u64::MAX)
+}
+
+// OrderCanceled and each of AllOrdersCanceled's orders_canceled.
+// Returns false if we didn't know the order.
+fn remove_canceled_order(order_book: &mut OrderBook, order_canceled: &serde_json::Value) -> Result<bool, anyhow::Error> {
+    let is_bid = parsed_json::get_bool(order_canceled, "is_bid")?;
+    let order_id = parsed_json::get_u64(order_canceled, "order_id")?;
+    let price = parsed_json::get_u64(order_canceled, "price")?;
+
+    Ok(order_book.remove_order(is_bid, price, order_id).is_some())
+}
+
+fn pool_id_from_pool_created_event(pool_created_event: &SuiEvent) -> Result<ObjectID, anyhow::Error> {
+    let pool_id = parsed_json::get_string(&pool_created_event.parsed_json, "pool_id")?;
+
+    Ok(ObjectID::from_str(&pool_id)?)
+}
+
+async fn get_dynamic_field_ids(sui_client: &SuiClient, object_id: &ObjectID) -> Result<Vec<ObjectID>, anyhow::Error> {
+    let dynamic_field_ids = sui_client
+        .read_api()
+        .pages(
+            GetDynamicFieldsRequest {
+                object_id: object_id.clone(),
+                cursor: None,
+                limit: None,
+            }
+        )
+        .items()
+        .try_collect::<Vec<DynamicFieldInfo>>()
+        .await?
+        .into_iter()
+        .map(|dynamic_field_info| {
+            dynamic_field_info.object_id
+        })
+        .collect::<Vec<ObjectID>>();
+
+    Ok(dynamic_field_ids)
+}
+
+// Pool<BaseAsset, QuoteAsset>
+fn get_coin_pair_from_object_response(
+    object_response: &SuiObjectResponse
+) -> Result<(TypeTag, TypeTag), anyhow::Error> {
+    let data = object_response.data.as_ref().context("Expected Some")?;
+    let type_ = data.type_.as_ref().context("Expected Some")?;
+
+    if let ObjectType::Struct(move_object_type) = type_ {
+        let type_params = move_object_type.type_params();
+
+        Ok(
+            (
+                type_params.get(0).context("Missing coin_x")?.clone(),
+                type_params.get(1).context("Missing coin_y")?.clone(),
+            )
+        )
+    } else {
+        Err(anyhow!("Does not match the ObjectType::Struct variant"))
+    }
+}
@@ -0,0 +1,6 @@
+// Local copies of on-chain order books kept in sync from pool state and order events.
+// Their markets implement arb-bot's so order books can be legs of its cycles.
+pub mod order_book;
+pub mod deepbook;
+pub use crate::order_book::*;
+pub use crate::deepbook::*;
@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+
+// DeepBook's fixed point. Prices are quote units per base unit and
+// fee rates are fractions of the quote amount, both scaled by this.
+pub const FLOAT_SCALING: u128 = 1_000_000_000;
+
+// Resting orders at one price. Makers at a price are filled oldest first
+// and order ids only go up so the map's order is the queue.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub orders: BTreeMap<u64, u64>, // order id -> base quantity left
+}
+
+impl PriceLevel {
+    pub fn quantity(&self) -> u64 {
+        self.orders.values().sum()
+    }
+}
+
+// A maker order a taker matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fill {
+    pub price: u64,
+    pub order_id: u64,
+    pub base_quantity: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBook {
+    // Takers selling base walk the bids from the top down
+    pub bids: BTreeMap<u64, PriceLevel>,
+    // Takers buying base walk the asks from the bottom up
+    pub asks: BTreeMap<u64, PriceLevel>,
+    pub taker_fee_rate: u64,
+    pub tick_size: u64,
+    // Base only trades in whole lots
+    pub lot_size: u64,
+}
+
+impl OrderBook {
+    pub fn new(taker_fee_rate: u64, tick_size: u64, lot_size: u64) -> Self {
+        OrderBook {
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            taker_fee_rate,
+            tick_size,
+            lot_size: lot_size.max(1),
+        }
+    }
+
+    fn side(&self, is_bid: bool) -> &BTreeMap<u64, PriceLevel> {
+        if is_bid {
+            &self.bids
+        } else {
+            &self.asks
+        }
+    }
+
+    fn side_mut(&mut self, is_bid: bool) -> &mut BTreeMap<u64, PriceLevel> {
+        if is_bid {
+            &mut self.bids
+        } else {
+            &mut self.asks
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<u64> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<u64> {
+        self.asks.keys().next().copied()
+    }
+
+    pub fn contains_order(&self, is_bid: bool, price: u64, order_id: u64) -> bool {
+        self.side(is_bid)
+            .get(&price)
+            .map(|level| level.orders.contains_key(&order_id))
+            .unwrap_or(false)
+    }
+
+    pub fn insert_order(&mut self, is_bid: bool, price: u64, order_id: u64, quantity: u64) {
+        if quantity == 0 {
+            return;
+        }
+
+        self.side_mut(is_bid)
+            .entry(price)
+            .or_default()
+            .orders
+            .insert(order_id, quantity);
+    }
+
+    // Returns the quantity the order had left. None if we didn't know the order.
+    pub fn remove_order(&mut self, is_bid: bool, price: u64, order_id: u64) -> Option<u64> {
+        let side = self.side_mut(is_bid);
+        let level = side.get_mut(&price)?;
+        let quantity = level.orders.remove(&order_id)?;
+
+        if level.orders.is_empty() {
+            side.remove(&price);
+        }
+
+        Some(quantity)
+    }
+
+    // Orders left with nothing come off the book.
+    // Returns false if we didn't know the order.
+    pub fn set_order_quantity(&mut self, is_bid: bool, price: u64, order_id: u64, quantity: u64) -> bool {
+        if quantity == 0 {
+            return self.remove_order(is_bid, price, order_id).is_some();
+        }
+
+        match self
+            .side_mut(is_bid)
+            .get_mut(&price)
+            .and_then(|level| level.orders.get_mut(&order_id))
+        {
+            Some(order_quantity) => {
+                *order_quantity = quantity;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Sells base into the bids. Returns (base sold, quote out after fees).
+    // Only whole lots are sold so some of quantity may be left over.
+    pub fn calc_sell_base(&self, quantity: u64) -> (u64, u64) {
+        self.match_sell_base(quantity, |_| {})
+    }
+
+    // Spends quote on the asks. Returns (quote spent including fees, base out).
+    pub fn calc_buy_base(&self, quote_quantity: u64) -> (u64, u64) {
+        self.match_buy_base(quote_quantity, |_| {})
+    }
+
+    // Takes the matched orders off the book as if the trade had landed
+    pub fn apply_sell_base(&mut self, quantity: u64) -> (u64, u64) {
+        let mut fills = vec![];
+        let amounts = self.match_sell_base(quantity, |fill| fills.push(fill));
+
+        for fill in fills {
+            self.apply_fill(true, fill);
+        }
+
+        amounts
+    }
+
+    pub fn apply_buy_base(&mut self, quote_quantity: u64) -> (u64, u64) {
+        let mut fills = vec![];
+        let amounts = self.match_buy_base(quote_quantity, |fill| fills.push(fill));
+
+        for fill in fills {
+            self.apply_fill(false, fill);
+        }
+
+        amounts
+    }
+
+    fn apply_fill(&mut self, is_bid: bool, fill: Fill) {
+        let quantity = self
+            .side(is_bid)
+            .get(&fill.price)
+            .and_then(|level| level.orders.get(&fill.order_id))
+            .copied()
+            .unwrap_or(0);
+
+        self.set_order_quantity(
+            is_bid,
+            fill.price,
+            fill.order_id,
+            quantity.saturating_sub(fill.base_quantity)
+        );
+    }
+
+    // The fewest whole lots of base whose sale pays at least quote_out after fees.
+    // None if the bids can't pay that much.
+    pub fn base_quantity_for_quote_out(&self, quote_out: u64) -> Option<u64> {
+        let mut base_sold = 0;
+        let mut quote_left = quote_out;
+
+        for (price, level) in self.bids.iter().rev() {
+            for maker_quantity in level.orders.values() {
+                if quote_left == 0 {
+                    return Some(base_sold);
+                }
+
+                let (_, quote_full) = self.sell_into_order(*price, *maker_quantity, *maker_quantity);
+
+                if quote_full < quote_left {
+                    base_sold += maker_quantity;
+                    quote_left -= quote_full;
+                    continue;
+                }
+
+                // Smallest number of lots out of this order that covers the rest
+                let (mut low, mut high) = (1, maker_quantity / self.lot_size);
+                while low < high {
+                    let mid = low + (high - low) / 2;
+                    let (_, quote) = self.sell_into_order(*price, *maker_quantity, mid * self.lot_size);
+
+                    if quote >= quote_left {
+                        high = mid;
+                    } else {
+                        low = mid + 1;
+                    }
+                }
+
+                return Some(base_sold + low * self.lot_size);
+            }
+        }
+
+        if quote_left == 0 {
+            Some(base_sold)
+        } else {
+            None
+        }
+    }
+
+    // Fees are charged per maker order and rounded up like DeepBook does
+    fn sell_into_order(&self, price: u64, maker_quantity: u64, base_left: u64) -> (u64, u64) {
+        let filled = base_left.min(maker_quantity);
+        let filled_quote = mul(filled, price);
+        let commission = mul_round_up(filled_quote, self.taker_fee_rate);
+
+        (filled, filled_quote.saturating_sub(commission))
+    }
+
+    fn match_sell_base(&self, quantity: u64, mut on_fill: impl FnMut(Fill)) -> (u64, u64) {
+        let mut base_left = quantity - quantity % self.lot_size;
+        let mut base_sold = 0;
+        let mut quote_out = 0;
+
+        'levels: for (price, level) in self.bids.iter().rev() {
+            for (order_id, maker_quantity) in level.orders.iter() {
+                if base_left == 0 {
+                    break 'levels;
+                }
+
+                let (filled, quote) = self.sell_into_order(*price, *maker_quantity, base_left);
+
+                base_left -= filled;
+                base_sold += filled;
+                quote_out += quote;
+
+                on_fill(
+                    Fill {
+                        price: *price,
+                        order_id: *order_id,
+                        base_quantity: filled,
+                    }
+                );
+            }
+        }
+
+        (base_sold, quote_out)
+    }
+
+    fn match_buy_base(&self, quote_quantity: u64, mut on_fill: impl FnMut(Fill)) -> (u64, u64) {
+        let mut quote_left = quote_quantity;
+        let mut quote_spent = 0;
+        let mut base_out = 0;
+
+        'levels: for (price, level) in self.asks.iter() {
+            for (order_id, maker_quantity) in level.orders.iter() {
+                let full_quote = mul(*maker_quantity, *price);
+                let full_cost = full_quote + mul_round_up(full_quote, self.taker_fee_rate);
+
+                let (filled, cost) = if full_cost <= quote_left {
+                    (*maker_quantity, full_cost)
+                } else {
+                    // As many whole lots as what's left pays for, fees included
+                    let quote_before_fee = div(quote_left, (FLOAT_SCALING as u64) + self.taker_fee_rate);
+                    let mut filled = div(quote_before_fee, *price);
+                    filled -= filled % self.lot_size;
+                    filled = filled.min(*maker_quantity);
+
+                    let mut cost = cost_of(filled, *price, self.taker_fee_rate);
+                    // Rounding can overshoot by a lot
+                    while filled > 0 && cost > quote_left {
+                        filled = filled.saturating_sub(self.lot_size);
+                        cost = cost_of(filled, *price, self.taker_fee_rate);
+                    }
+
+                    (filled, cost)
+                };
+
+                // Every price past this one is only worse
+                if filled == 0 {
+                    break 'levels;
+                }
+
+                quote_left -= cost;
+                quote_spent += cost;
+                base_out += filled;
+
+                on_fill(
+                    Fill {
+                        price: *price,
+                        order_id: *order_id,
+                        base_quantity: filled,
+                    }
+                );
+
+                if filled < *maker_quantity {
+                    break 'levels;
+                }
+            }
+        }
+
+        (quote_spent, base_out)
+    }
+}
+
+fn cost_of(base_quantity: u64, price: u64, taker_fee_rate: u64) -> u64 {
+    let quote = mul(base_quantity, price);
+
+    quote + mul_round_up(quote, taker_fee_rate)
+}
+
+fn mul(x: u64, y: u64) -> u64 {
+    ((x as u128 * y as u128) / FLOAT_SCALING) as u64
+}
+
+fn mul_round_up(x: u64, y: u64) -> u64 {
+    ((x as u128 * y as u128 + FLOAT_SCALING - 1) / FLOAT_SCALING) as u64
+}
+
+fn div(x: u64, y: u64) -> u64 {
+    ((x as u128 * FLOAT_SCALING) / y as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0.25% taker fee. Lots of 1_000 base.
+    fn book() -> OrderBook {
+        let mut book = OrderBook::new(2_500_000, 1_000, 1_000);
+
+        // Bids at 0.99 and 0.98 quote per base
+        book.insert_order(true, 990_000_000, 1, 10_000);
+        book.insert_order(true, 980_000_000, 2, 20_000);
+        // Asks at 1.01 and 1.02
+        book.insert_order(false, 1_010_000_000, 3, 10_000);
+        book.insert_order(false, 1_020_000_000, 4, 20_000);
+
+        book
+    }
+
+    #[test]
+    fn test_sell_walks_bids_from_the_top() {
+        let mut book = book();
+
+        // A partial lot is left over. The best bid fills entirely then 5 lots of the next.
+        let (base_sold, quote_out) = book.calc_sell_base(15_500);
+        assert_eq!(base_sold, 15_000);
+        assert_eq!(quote_out, (9_900 - 25) + (4_900 - 13));
+
+        assert_eq!(book.apply_sell_base(15_500), (base_sold, quote_out));
+        assert_eq!(book.best_bid(), Some(980_000_000));
+        assert_eq!(book.bids[&980_000_000].quantity(), 15_000);
+    }
+
+    #[test]
+    fn test_buy_stops_at_what_quote_pays_for() {
+        let mut book = book();
+
+        let (quote_spent, base_out) = book.calc_buy_base(15_000);
+        assert!(quote_spent <= 15_000);
+        // All of the best ask then whole lots of the next
+        assert_eq!(base_out % book.lot_size, 0);
+        assert!(base_out > 10_000 && base_out < 30_000);
+
+        assert_eq!(book.apply_buy_base(15_000), (quote_spent, base_out));
+        assert_eq!(book.best_ask(), Some(1_020_000_000));
+        assert_eq!(book.asks[&1_020_000_000].quantity(), 30_000 - base_out);
+    }
+
+    #[test]
+    fn test_base_quantity_for_quote_out() {
+        let book = book();
+
+        for quote_out in [1, 2_000, 9_875, 9_876, 14_000] {
+            let quantity = book.base_quantity_for_quote_out(quote_out).unwrap();
+
+            assert!(book.calc_sell_base(quantity).1 >= quote_out);
+            assert!(book.calc_sell_base(quantity - book.lot_size).1 < quote_out);
+        }
+
+        assert_eq!(book.base_quantity_for_quote_out(1_000_000), None);
+    }
+}
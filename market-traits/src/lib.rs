@@ -0,0 +1,164 @@
+// The traits arb-bot trades through. They're their own crate so exchanges
+// in crates arb-bot depends on, like local-orderbooks, can implement them.
+use move_core_types::language_storage::{TypeTag, StructTag};
+use sui_sdk::{
+    types::{
+        base_types::{
+            ObjectID,
+            SuiAddress
+        },
+        programmable_transaction_builder::ProgrammableTransactionBuilder,
+        transaction::Argument
+    }
+};
+use custom_sui_sdk::{
+    SuiClient,
+    transaction_builder::TransactionBuilder
+};
+use async_trait::async_trait;
+
+use std::collections::HashMap;
+
+use fixed::types::U64F64;
+
+use sui_sdk::rpc_types::{EventFilter, SuiEvent, SuiObjectResponse};
+use dyn_clone::DynClone;
+
+use pool_state::PoolState;
+
+use serde::{Deserialize, Serialize};
+
+#[async_trait]
+pub trait Exchange: Send + Sync {
+    fn package_id(&self) -> &ObjectID;
+    fn event_filters(&self) -> Vec<EventFilter>;
+    fn event_struct_tag_to_pool_field(&self) -> &HashMap<StructTag, String>;
+    fn event_package_id(&self) -> &ObjectID;
+    // The pool one of our events is about. None for anyone else's events.
+    fn pool_id_from_event(&self, event: &SuiEvent) -> Result<Option<ObjectID>, anyhow::Error>;
+    async fn get_all_markets(&mut self, sui_client: &SuiClient) -> Result<Vec<Box<dyn Market>>, anyhow::Error>; // -> Result<Vec<Box<dyn Market>>>
+    // async fn get_pool_id_to_fields(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, BTreeMap<String, SuiMoveValue>>, anyhow::Error>;
+    async fn get_pool_id_to_object_response(&self, sui_client: &SuiClient, markets: &[Box<dyn Market>]) -> Result<HashMap<ObjectID, SuiObjectResponse>, anyhow::Error>;
+    // Rebuilds a recorded market without touching the network
+    fn market_from_record(&self, market_record: &MarketRecord) -> Result<Box<dyn Market>, anyhow::Error>;
+    // Events announcing a new pool
+    fn pool_created_event_filters(&self) -> Vec<EventFilter>;
+    // The market a pool creation event announces. None for any other event.
+    // Adds the new market's events to event_struct_tag_to_pool_field.
+    async fn market_from_pool_created_event(&mut self, sui_client: &SuiClient, event: &SuiEvent) -> Result<Option<Box<dyn Market>>, anyhow::Error>;
+}
+
+// Enough to rebuild a market and its state offline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketRecord {
+    pub package_id: ObjectID,
+    pub pool_id: ObjectID,
+    pub coin_x: TypeTag,
+    pub coin_y: TypeTag,
+    // Type arguments of the pool past the coin pair. The fee tier on Turbos.
+    pub extra_type_args: Vec<TypeTag>,
+    // None if the market was never synced
+    pub pool_state: Option<PoolState>,
+}
+
+// A constant product curve that quotes like a market around its current price
+#[derive(Debug, Clone, Copy)]
+pub struct ConstantProductCurve {
+    pub reserve_in: f64,
+    pub reserve_out: f64,
+    // Share of the amount in left after fees
+    pub fee_multiplier: f64,
+    // The market is a constant product pool so the curve holds for any amount.
+    // Otherwise it only holds until the price crosses a tick.
+    pub exact: bool,
+}
+
+// What applying an event did to a market's local state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventUpdate {
+    Applied,
+    // The event doesn't change anything we track
+    Ignored,
+    // Local state doesn't line up with what the event says. Either we
+    // missed events or our math drifted. The pool needs to be refetched.
+    Inconsistent,
+}
+
+#[async_trait]
+pub trait Market: Send + Sync + DynClone {
+    fn coin_x(&self) -> &TypeTag;
+    fn coin_y(&self) -> &TypeTag;
+    fn coin_x_price(&self) -> Option<U64F64>;
+    fn coin_y_price(&self) -> Option<U64F64>;
+    async fn update_with_object_response(&mut self, sui_client: &SuiClient, object_response: &SuiObjectResponse) -> Result<(), anyhow::Error>;
+    fn update_with_event(&mut self, event: &SuiEvent) -> Result<EventUpdate, anyhow::Error>;
+    // State served by a librarian. None forgets the state until the librarian has it again.
+    fn update_with_pool_state(&mut self, pool_state: Option<&PoolState>) -> Result<(), anyhow::Error>;
+    fn market_record(&self) -> MarketRecord;
+    fn pool_id(&self) -> &ObjectID;
+    fn package_id(&self) -> &ObjectID;
+    // fn router_id(&self) -> &ObjectID;
+    // fn compute_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128);
+    // fn compute_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128);
+    // fn compute_swap_x_to_y_mut(&mut self, amount_specified: u128) -> (u128, u128);
+    // fn compute_swap_y_to_x_mut(&mut self, amount_specified: u128) -> (u128, u128);
+    fn compute_swap_x_to_y(&self, amount_specified: u128) -> (u128, u128);
+    fn compute_swap_y_to_x(&self, amount_specified: u128) -> (u128, u128);
+    // Applies the swap to our local state as if it had landed. Returns the same as compute_swap_*.
+    // Only for simulating. Events and object responses still bring the real state.
+    fn apply_swap_x_to_y(&mut self, amount_specified: u128) -> (u128, u128);
+    fn apply_swap_y_to_x(&mut self, amount_specified: u128) -> (u128, u128);
+    // Swaps the entire value of orig_coin and returns the output coin
+    // so that legs can be chained within a single programmable transaction.
+    // amount_in is what we predict orig_coin holds and amount_out the least we'll take.
+    async fn add_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument, // split off of our own coins or the output coin of the previous leg
+        x_to_y: bool,
+        amount_in: u128,
+        amount_out: u128,
+        recipient: SuiAddress
+    ) -> Result<Argument, anyhow::Error>;
+    // Whether the market can swap for an exact amount out
+    fn supports_exact_output(&self) -> bool;
+    // Swaps for exactly amount_out paid for out of orig_coin.
+    // Returns the output coin and what's left of orig_coin.
+    async fn add_exact_output_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        orig_coin: Argument,
+        x_to_y: bool,
+        amount_out: u128,
+        recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error>;
+    // Whether the pool pays out before being paid so a cycle can start without capital
+    fn supports_flash_swap(&self) -> bool;
+    // Borrows the output of swapping amount_in. Returns the output coin
+    // and the receipt that has to be repaid later in the same transaction.
+    async fn add_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        x_to_y: bool,
+        amount_in: u128,
+        recipient: SuiAddress
+    ) -> Result<(Argument, Argument), anyhow::Error>;
+    // Repays the receipt out of pay_coin. What isn't owed stays in pay_coin.
+    async fn add_repay_flash_swap_to_programmable_transaction(
+        &self,
+        transaction_builder: &TransactionBuilder,
+        pt_builder: &mut ProgrammableTransactionBuilder,
+        receipt: Argument,
+        pay_coin: Argument,
+        x_to_y: bool,
+        amount_in: u128
+    ) -> Result<(), anyhow::Error>;
+    fn viable(&self) -> bool;
+    // None if the market's pricing can't be approximated by one
+    fn constant_product_curve(&self, x_to_y: bool) -> Option<ConstantProductCurve>;
+}
+
+dyn_clone::clone_trait_object!(Market);
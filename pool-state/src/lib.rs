@@ -0,0 +1,274 @@
+// What it takes to compute swaps against a pool, without anything of where it came from.
+// Librarians publish it and arb-bot's markets are built from it.
+use anyhow::anyhow;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TickState {
+    pub index: i32,
+    pub sqrt_price: u128,
+    pub liquidity_net: i128,
+    pub liquidity_gross: u128,
+}
+
+// Everything needed to compute swaps against a pool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PoolState {
+    ConcentratedLiquidity {
+        sqrt_price: u128,
+        tick_current_index: i32,
+        tick_spacing: u32,
+        fee: u64,
+        unlocked: bool,
+        liquidity: u128,
+        ticks: Vec<TickState>,
+    },
+    ConstantProduct {
+        reserve_x: u64,
+        reserve_y: u64,
+        protocol_fee: u64,
+        lp_fee: u64,
+        unlocked: bool,
+    },
+    Stable {
+        reserve_x: u64,
+        reserve_y: u64,
+        protocol_fee: u64,
+        lp_fee: u64,
+        scale_x: u64,
+        scale_y: u64,
+        unlocked: bool,
+    },
+}
+
+// The fields of a pool that swaps and liquidity changes move.
+// Anything else changing is sent as a whole new state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PoolStateDiff {
+    ConcentratedLiquidity {
+        sqrt_price: u128,
+        tick_current_index: i32,
+        liquidity: u128,
+        // Ticks that changed or were added. Ticks that were removed come with no liquidity_gross.
+        ticks: Vec<TickState>,
+    },
+    ConstantProduct {
+        reserve_x: u64,
+        reserve_y: u64,
+    },
+    Stable {
+        reserve_x: u64,
+        reserve_y: u64,
+    },
+}
+
+impl PoolStateDiff {
+    // None if the change can't be expressed as a diff
+    pub fn between(old: &PoolState, new: &PoolState) -> Option<Self> {
+        match (old, new) {
+            (
+                PoolState::ConcentratedLiquidity { tick_spacing: old_tick_spacing, fee: old_fee, unlocked: old_unlocked, ticks: old_ticks, .. },
+                PoolState::ConcentratedLiquidity { sqrt_price, tick_current_index, tick_spacing, fee, unlocked, liquidity, ticks }
+            ) if (old_tick_spacing, old_fee, old_unlocked) == (tick_spacing, fee, unlocked) => {
+                let index_to_old_tick = old_ticks
+                    .iter()
+                    .map(|tick| {
+                        (tick.index, tick)
+                    })
+                    .collect::<HashMap<i32, &TickState>>();
+
+                let new_tick_indexes = ticks
+                    .iter()
+                    .map(|tick| {
+                        tick.index
+                    })
+                    .collect::<HashSet<i32>>();
+
+                let changed_ticks = ticks
+                    .iter()
+                    .filter(|tick| {
+                        index_to_old_tick.get(&tick.index) != Some(tick)
+                    })
+                    .cloned();
+
+                let removed_ticks = old_ticks
+                    .iter()
+                    .filter(|tick| {
+                        !new_tick_indexes.contains(&tick.index)
+                    })
+                    .map(|tick| {
+                        TickState {
+                            index: tick.index,
+                            sqrt_price: tick.sqrt_price,
+                            liquidity_net: 0,
+                            liquidity_gross: 0,
+                        }
+                    });
+
+                Some(
+                    PoolStateDiff::ConcentratedLiquidity {
+                        sqrt_price: *sqrt_price,
+                        tick_current_index: *tick_current_index,
+                        liquidity: *liquidity,
+                        ticks: changed_ticks.chain(removed_ticks).collect(),
+                    }
+                )
+            },
+            (
+                PoolState::ConstantProduct { protocol_fee: old_protocol_fee, lp_fee: old_lp_fee, unlocked: old_unlocked, .. },
+                PoolState::ConstantProduct { reserve_x, reserve_y, protocol_fee, lp_fee, unlocked }
+            ) if (old_protocol_fee, old_lp_fee, old_unlocked) == (protocol_fee, lp_fee, unlocked) => {
+                Some(
+                    PoolStateDiff::ConstantProduct {
+                        reserve_x: *reserve_x,
+                        reserve_y: *reserve_y,
+                    }
+                )
+            },
+            (
+                PoolState::Stable { protocol_fee: old_protocol_fee, lp_fee: old_lp_fee, scale_x: old_scale_x, scale_y: old_scale_y, unlocked: old_unlocked, .. },
+                PoolState::Stable { reserve_x, reserve_y, protocol_fee, lp_fee, scale_x, scale_y, unlocked }
+            ) if (old_protocol_fee, old_lp_fee, old_scale_x, old_scale_y, old_unlocked) == (protocol_fee, lp_fee, scale_x, scale_y, unlocked) => {
+                Some(
+                    PoolStateDiff::Stable {
+                        reserve_x: *reserve_x,
+                        reserve_y: *reserve_y,
+                    }
+                )
+            },
+            _ => None,
+        }
+    }
+}
+
+impl PoolState {
+    pub fn apply_diff(&mut self, diff: &PoolStateDiff) -> Result<(), anyhow::Error> {
+        match (self, diff) {
+            (
+                PoolState::ConcentratedLiquidity { sqrt_price, tick_current_index, liquidity, ticks, .. },
+                PoolStateDiff::ConcentratedLiquidity { sqrt_price: new_sqrt_price, tick_current_index: new_tick_current_index, liquidity: new_liquidity, ticks: changed_ticks }
+            ) => {
+                *sqrt_price = *new_sqrt_price;
+                *tick_current_index = *new_tick_current_index;
+                *liquidity = *new_liquidity;
+
+                // Ticks stay sorted by index
+                for changed_tick in changed_ticks {
+                    let position = ticks.binary_search_by_key(&changed_tick.index, |tick| tick.index);
+
+                    match (position, changed_tick.liquidity_gross) {
+                        (Ok(position), 0) => {
+                            ticks.remove(position);
+                        },
+                        (Ok(position), _) => {
+                            ticks[position] = changed_tick.clone();
+                        },
+                        (Err(_), 0) => {},
+                        (Err(position), _) => {
+                            ticks.insert(position, changed_tick.clone());
+                        }
+                    }
+                }
+            },
+            (
+                PoolState::ConstantProduct { reserve_x, reserve_y, .. },
+                PoolStateDiff::ConstantProduct { reserve_x: new_reserve_x, reserve_y: new_reserve_y }
+            )
+            | (
+                PoolState::Stable { reserve_x, reserve_y, .. },
+                PoolStateDiff::Stable { reserve_x: new_reserve_x, reserve_y: new_reserve_y }
+            ) => {
+                *reserve_x = *new_reserve_x;
+                *reserve_y = *new_reserve_y;
+            },
+            _ => {
+                return Err(anyhow!("Pool state diff is for a different kind of pool"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(index: i32, liquidity_gross: u128) -> TickState {
+        TickState {
+            index,
+            sqrt_price: 1 << 64,
+            liquidity_net: liquidity_gross as i128,
+            liquidity_gross,
+        }
+    }
+
+    fn concentrated_liquidity(sqrt_price: u128, liquidity: u128, ticks: Vec<TickState>) -> PoolState {
+        PoolState::ConcentratedLiquidity {
+            sqrt_price,
+            tick_current_index: 0,
+            tick_spacing: 60,
+            fee: 3_000,
+            unlocked: true,
+            liquidity,
+            ticks,
+        }
+    }
+
+    #[test]
+    fn test_diff_only_carries_touched_ticks() {
+        let old = concentrated_liquidity(100, 10, vec![tick(-60, 10), tick(0, 5), tick(60, 10)]);
+        let new = concentrated_liquidity(90, 15, vec![tick(-120, 5), tick(-60, 10), tick(60, 20)]);
+
+        let diff = PoolStateDiff::between(&old, &new).unwrap();
+
+        if let PoolStateDiff::ConcentratedLiquidity { ticks, .. } = &diff {
+            let mut indexes = ticks.iter().map(|tick| tick.index).collect::<Vec<i32>>();
+            indexes.sort();
+
+            // -60 didn't change
+            assert_eq!(indexes, vec![-120, 0, 60]);
+        } else {
+            panic!("Expected a concentrated liquidity diff");
+        }
+
+        let mut applied = old.clone();
+        applied.apply_diff(&diff).unwrap();
+
+        assert_eq!(applied, new);
+    }
+
+    #[test]
+    fn test_diff_reserves() {
+        let old = PoolState::ConstantProduct { reserve_x: 100, reserve_y: 200, protocol_fee: 1, lp_fee: 2, unlocked: true };
+        let new = PoolState::ConstantProduct { reserve_x: 110, reserve_y: 182, protocol_fee: 1, lp_fee: 2, unlocked: true };
+
+        let diff = PoolStateDiff::between(&old, &new).unwrap();
+
+        assert_eq!(diff, PoolStateDiff::ConstantProduct { reserve_x: 110, reserve_y: 182 });
+
+        let mut applied = old.clone();
+        applied.apply_diff(&diff).unwrap();
+
+        assert_eq!(applied, new);
+    }
+
+    #[test]
+    fn test_no_diff_when_fees_change() {
+        let old = PoolState::ConstantProduct { reserve_x: 100, reserve_y: 200, protocol_fee: 1, lp_fee: 2, unlocked: true };
+        let new = PoolState::ConstantProduct { reserve_x: 100, reserve_y: 200, protocol_fee: 1, lp_fee: 3, unlocked: true };
+
+        assert_eq!(PoolStateDiff::between(&old, &new), None);
+        assert_eq!(PoolStateDiff::between(&old, &concentrated_liquidity(1, 1, vec![])), None);
+    }
+
+    #[test]
+    fn test_apply_diff_to_other_kind_of_pool() {
+        let mut pool_state = concentrated_liquidity(1, 1, vec![]);
+
+        assert!(pool_state.apply_diff(&PoolStateDiff::Stable { reserve_x: 1, reserve_y: 1 }).is_err());
+    }
+}